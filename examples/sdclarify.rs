@@ -0,0 +1,80 @@
+//! How the crate interprets a given SRC and DST, for debugging an invocation.
+//!
+//! ``` plaintext
+//! cargo run --example sdclarify -- [--copy] [--ext EXT] SRC [DST]
+//! ```
+//!
+//! Prints the plan, see [`src_dst_clarifier::plan`]. With `--copy` every SRC is also copied
+//! to its DST as is, and the plan goes to stderr instead.
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Write},
+    process::ExitCode,
+};
+
+use src_dst_clarifier::{ioers::transfer, SrcDstConfig};
+
+const USAGE: &str = "usage: sdclarify [--copy] [--ext EXT] SRC [DST]";
+
+fn main() -> ExitCode {
+    let mut copy = false;
+    let mut extension = OsString::from("out");
+    let mut paths = Vec::new();
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--copy") => copy = true,
+            Some("--ext") => match args.next() {
+                Some(ext) => extension = ext,
+                None => return usage(),
+            },
+            Some("-h" | "--help") => {
+                println!("{USAGE}");
+                return ExitCode::SUCCESS;
+            }
+            _ => paths.push(arg),
+        }
+    }
+    let (src, dst) = match paths.as_slice() {
+        [src] => (src, None),
+        [src, dst] => (src, Some(dst)),
+        _ => return usage(),
+    };
+
+    match run(src, dst, &extension, copy) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("sdclarify: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(src: &OsString, dst: Option<&OsString>, extension: &OsString, copy: bool) -> io::Result<()> {
+    let config = SrcDstConfig::new(extension);
+    let plan = match config.plan(src, dst) {
+        Ok(plan) => plan.map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        Err(e) => return Err(e),
+    };
+    match copy {
+        true => plan.write_to(io::stderr().lock())?,
+        false => return plan.write_to(io::stdout().lock()),
+    }
+
+    for dir in plan.tnamed_dir.iter().chain(&plan.dst_dirs) {
+        fs::create_dir_all(dir)?;
+    }
+    for (src, dst) in &plan.pairs {
+        let copied = transfer(&mut *src.input(), &mut *dst.output_for(src))
+            .map_err(|e| io::Error::new(e.kind(), format!("{src} -> {dst}: {e}")))?;
+        writeln!(io::stderr(), "{copied:>12}  {src} -> {dst}")?;
+    }
+    Ok(())
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{USAGE}");
+    ExitCode::from(2)
+}
@@ -0,0 +1,292 @@
+//! Async counterparts of [`Input`](crate::ioers::Input) and [`Output`](crate::ioers::Output),
+//! behind the `tokio` feature.
+//!
+//! ```no_run
+//! # async fn run() -> std::io::Result<()> {
+//! use src_dst_clarifier::{async_io::AsyncClarifiedIo, SrcDstConfig};
+//! use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//!
+//! let pairs = SrcDstConfig::new("txt").parse("notes", Some("out")).unwrap().unwrap();
+//! pairs.create_tnamed_dir()?;
+//! for pair in pairs {
+//!     let AsyncClarifiedIo { mut input, mut output } = pair.into();
+//!     let mut content = Vec::new();
+//!     input.reader().await?.read_to_end(&mut content).await?;
+//!     let mut writer = output.writer().await?;
+//!     writer.write_all(&content.to_ascii_uppercase()).await?;
+//!     writer.shutdown().await?;
+//!     drop(writer);
+//!     output.finish().await?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    future::Future,
+    io,
+    path::PathBuf,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncWrite, BufReader, BufWriter, ReadBuf},
+};
+
+use crate::{
+    audit::{self, Operation},
+    ioers::StdinError,
+    Dst, Src,
+};
+
+pub type AsyncReader = Box<dyn AsyncRead + Send + Unpin>;
+pub type AsyncWriter = Box<dyn AsyncWrite + Send + Unpin>;
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+pub trait AsyncInput: Send {
+    fn reader(&mut self) -> BoxFuture<'_, io::Result<AsyncReader>>;
+
+    /// Called after the processing succeeded and the reader has been dropped.
+    fn finish(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+pub trait AsyncOutput: Send {
+    /// Shut the writer down before [`Self::finish`], or buffered data may be lost.
+    fn writer(&mut self) -> BoxFuture<'_, io::Result<AsyncWriter>>;
+
+    /// Called after the processing succeeded and the writer has been dropped.
+    fn finish(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Called after the processing failed. Implementors should remove partial output here.
+    fn abort(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Like [`ReadFile`](crate::ioers::ReadFile).
+#[derive(Debug, Clone)]
+pub struct AsyncReadFile {
+    path: PathBuf,
+}
+
+impl AsyncReadFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl AsyncInput for AsyncReadFile {
+    fn reader(&mut self) -> BoxFuture<'_, io::Result<AsyncReader>> {
+        Box::pin(async move {
+            let file = fs::File::open(&self.path).await;
+            let file = audit::record(Operation::Open, &self.path, file)?;
+            Ok(Box::new(BufReader::new(file)) as AsyncReader)
+        })
+    }
+}
+
+/// Like [`WriteFile`](crate::ioers::WriteFile), the partial file is removed on abort.
+#[derive(Debug, Clone)]
+pub struct AsyncWriteFile {
+    path: PathBuf,
+    created: bool,
+}
+
+impl AsyncWriteFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            created: false,
+        }
+    }
+}
+
+impl AsyncOutput for AsyncWriteFile {
+    fn writer(&mut self) -> BoxFuture<'_, io::Result<AsyncWriter>> {
+        Box::pin(async move {
+            crate::check_writable(&self.path)?;
+            let file = fs::File::create(&self.path).await;
+            let file = audit::record(Operation::Create, &self.path, file)?;
+            self.created = true;
+            Ok(Box::new(BufWriter::new(file)) as AsyncWriter)
+        })
+    }
+
+    fn abort(&mut self) -> BoxFuture<'_, io::Result<()>> {
+        Box::pin(async move {
+            if self.created {
+                self.created = false;
+                let removed = fs::remove_file(&self.path).await;
+                audit::record(Operation::Remove, &self.path, removed)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Like [`ReadStdin`](crate::ioers::ReadStdin), timeouts are left to the caller
+/// (`tokio::time::timeout`).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncReadStdin {
+    pub max_bytes: Option<u64>,
+}
+
+impl AsyncInput for AsyncReadStdin {
+    fn reader(&mut self) -> BoxFuture<'_, io::Result<AsyncReader>> {
+        let stdin = tokio::io::stdin();
+        let reader: AsyncReader = match self.max_bytes {
+            None => Box::new(stdin),
+            Some(limit) => Box::new(LimitedStdin {
+                inner: stdin,
+                limit,
+                read: 0,
+            }),
+        };
+        Box::pin(async { Ok(reader) })
+    }
+}
+
+struct LimitedStdin {
+    inner: tokio::io::Stdin,
+    limit: u64,
+    read: u64,
+}
+
+impl AsyncRead for LimitedStdin {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        ready!(Pin::new(&mut self.inner).poll_read(cx, buf))?;
+        self.read += (buf.filled().len() - before) as u64;
+        Poll::Ready(match self.read > self.limit {
+            true => Err(StdinError::TooLarge { limit: self.limit }.into()),
+            false => Ok(()),
+        })
+    }
+}
+
+/// Like [`WriteStdout`](crate::ioers::WriteStdout).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncWriteStdout;
+
+impl AsyncOutput for AsyncWriteStdout {
+    fn writer(&mut self) -> BoxFuture<'_, io::Result<AsyncWriter>> {
+        Box::pin(async { Ok(Box::new(tokio::io::stdout()) as AsyncWriter) })
+    }
+}
+
+/// Remote and socket paths have no async implementation yet.
+struct Unsupported;
+
+impl AsyncInput for Unsupported {
+    fn reader(&mut self) -> BoxFuture<'_, io::Result<AsyncReader>> {
+        Box::pin(async { Err(unsupported()) })
+    }
+}
+
+impl AsyncOutput for Unsupported {
+    fn writer(&mut self) -> BoxFuture<'_, io::Result<AsyncWriter>> {
+        Box::pin(async { Err(unsupported()) })
+    }
+}
+
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "only files and stdio have async IO",
+    )
+}
+
+/// Like [`ClarifiedIo`](crate::ioers::ClarifiedIo).
+pub struct AsyncClarifiedIo {
+    pub input: Box<dyn AsyncInput>,
+    pub output: Box<dyn AsyncOutput>,
+}
+
+impl From<(Src, Dst)> for AsyncClarifiedIo {
+    fn from((src, dst): (Src, Dst)) -> Self {
+        Self {
+            input: src.async_input(),
+            output: dst.async_output(),
+        }
+    }
+}
+
+impl Src {
+    pub fn async_input(&self) -> Box<dyn AsyncInput> {
+        match self {
+            Src::Stdin => Box::new(AsyncReadStdin::default()),
+            Src::File(path) => Box::new(AsyncReadFile::new(path)),
+            #[cfg(feature = "sftp")]
+            Src::Remote(_) => Box::new(Unsupported),
+            #[cfg(all(unix, feature = "unix-socket"))]
+            Src::UnixSocket(_) => Box::new(Unsupported),
+        }
+    }
+}
+
+impl Dst {
+    pub fn async_output(&self) -> Box<dyn AsyncOutput> {
+        match self {
+            Dst::Stdout => Box::new(AsyncWriteStdout),
+            Dst::File(path) => Box::new(AsyncWriteFile::new(path)),
+            #[cfg(feature = "sftp")]
+            Dst::Remote(_) => Box::new(Unsupported),
+            #[cfg(all(unix, feature = "unix-socket"))]
+            Dst::UnixSocket(_) => Box::new(Unsupported),
+            Dst::Entry(_) => Box::new(Unsupported),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn round_trip() {
+        let root = ScratchDir::new("async");
+        fs::write(root.join("a.txt"), "hello").unwrap();
+        let pair = (Src::File(root.join("a.txt")), Dst::File(root.join("b.txt")));
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let AsyncClarifiedIo {
+                mut input,
+                mut output,
+            } = pair.into();
+            let mut content = Vec::new();
+            let mut reader = input.reader().await.unwrap();
+            reader.read_to_end(&mut content).await.unwrap();
+            let mut writer = output.writer().await.unwrap();
+            writer.write_all(&content).await.unwrap();
+            writer.shutdown().await.unwrap();
+            drop(writer);
+            output.finish().await.unwrap();
+
+            let mut aborted = AsyncWriteFile::new(root.join("c.txt"));
+            aborted.writer().await.unwrap();
+            aborted.abort().await.unwrap();
+        });
+
+        assert_eq!(fs::read_to_string(root.join("b.txt")).unwrap(), "hello");
+        assert!(!root.join("c.txt").exists());
+    }
+}
@@ -0,0 +1,155 @@
+//! A record of every filesystem operation this crate performs, for security reviews.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use src_dst_clarifier::audit::{self, AuditLog};
+//!
+//! let log = Arc::new(AuditLog::default());
+//! audit::set_sink(Some(log.clone()));
+//! // ... parse and run, then review `log.take()`.
+//! audit::set_sink(None);
+//! ```
+//!
+//! Metadata queries (`exists`, `canonicalize`...) and listing directories are not recorded.
+
+use std::{
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    time::SystemTime,
+};
+
+static SINK: RwLock<Option<Arc<dyn AuditSink>>> = RwLock::new(None);
+
+/// Process-wide, like [`set_read_only`](crate::set_read_only). `None` by default.
+pub fn set_sink(sink: Option<Arc<dyn AuditSink>>) {
+    *SINK.write().unwrap_or_else(|e| e.into_inner()) = sink;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    Open,
+    Create,
+    CreateDir,
+    /// To [`AuditEvent::to`].
+    Rename,
+    /// To [`AuditEvent::to`].
+    Copy,
+    Remove,
+    RemoveDir,
+    /// Of [`AuditEvent::to`] at the path.
+    HardLink,
+    /// To [`AuditEvent::to`] at the path.
+    Symlink,
+    MakeFifo,
+    /// A Unix domain socket.
+    Bind,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditEvent {
+    pub time: SystemTime,
+    pub operation: Operation,
+    pub path: PathBuf,
+    pub to: Option<PathBuf>,
+    pub result: Result<(), io::ErrorKind>,
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?} '{}'", self.operation, self.path.display())?;
+        if let Some(to) = &self.to {
+            write!(f, " -> '{}'", to.display())?;
+        }
+        match self.result {
+            Ok(()) => f.write_str(": ok"),
+            Err(kind) => write!(f, ": {kind}"),
+        }
+    }
+}
+
+pub trait AuditSink: Send + Sync {
+    /// Called synchronously right after each operation, keep it quick.
+    fn record(&self, event: AuditEvent);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(AuditEvent) + Send + Sync,
+{
+    fn record(&self, event: AuditEvent) {
+        self(event)
+    }
+}
+
+/// Keeps every event, in the order performed.
+#[derive(Debug, Default)]
+pub struct AuditLog(Mutex<Vec<AuditEvent>>);
+
+impl AuditLog {
+    pub fn take(&self) -> Vec<AuditEvent> {
+        std::mem::take(&mut self.0.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+}
+
+impl AuditSink for AuditLog {
+    fn record(&self, event: AuditEvent) {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).push(event);
+    }
+}
+
+/// Passes `result` through, after recording it.
+pub(crate) fn record<T>(operation: Operation, path: &Path, result: io::Result<T>) -> io::Result<T> {
+    emit(operation, path, None, &result);
+    result
+}
+
+/// [`record`] for operations with a second path.
+pub(crate) fn record_to<T>(
+    operation: Operation,
+    path: &Path,
+    to: &Path,
+    result: io::Result<T>,
+) -> io::Result<T> {
+    emit(operation, path, Some(to), &result);
+    result
+}
+
+fn emit<T>(operation: Operation, path: &Path, to: Option<&Path>, result: &io::Result<T>) {
+    let sink = SINK.read().unwrap_or_else(|e| e.into_inner());
+    if let Some(sink) = &*sink {
+        sink.record(AuditEvent {
+            time: SystemTime::now(),
+            operation,
+            path: path.to_owned(),
+            to: to.map(Path::to_path_buf),
+            result: result.as_ref().map(drop).map_err(io::Error::kind),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioers::{Output, WriteFile};
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn records() {
+        let root = ScratchDir::new("audit");
+        let path = root.join("out.txt");
+
+        let log = Arc::new(AuditLog::default());
+        set_sink(Some(log.clone()));
+        let mut output = WriteFile::new(&path);
+        output.writer().unwrap();
+        output.abort().unwrap();
+        set_sink(None);
+
+        // 其他测试可能同时在跑
+        let events: Vec<_> = log.take().into_iter().filter(|e| e.path == path).collect();
+        let operations: Vec<_> = events.iter().map(|e| e.operation).collect();
+        assert_eq!(operations, [Operation::Create, Operation::Remove]);
+        assert!(events.iter().all(|e| e.result.is_ok()));
+    }
+}
@@ -0,0 +1,348 @@
+//! Assembling [`SrcDstPairs`] from an explicit SRC and DST, for the combinations the table of
+//! [`SrcDstConfig::parse`] does not produce:
+//!
+//! ``` plaintext
+//! PairSource::Files { root, names }   PairDrain::Stdout        every file, one after another
+//! PairSource::Stdin                   PairDrain::Archive(..)   one entry named by `entry_name`
+//! PairSource::Dir(..)                 PairDrain::Dir(..)       like `parse`, but never time-based named
+//! ```
+//!
+//! Naming of DSTs, [`SrcDstConfig::overwrite`] and the usual checks are applied as by `parse`.
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    collation, naming, spill, ArchiveEntry, ClarifyError, Drain, Dst, FileList, HeldSrcDir,
+    OverwritePolicy, Source, Src, SrcDstConfig, SrcDstError, SrcDstPairs,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairSource {
+    Stdin,
+    File(PathBuf),
+    /// Walked like a batch SRC of [`SrcDstConfig::parse`].
+    Dir(PathBuf),
+    /// An explicit list of files, relative to `root`. Yielded in [`SrcDstConfig::collation`],
+    /// not in the order given, and filtered by [`SrcDstConfig::source_filter`] as if walked.
+    Files {
+        root: PathBuf,
+        names: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PairDrain {
+    Stdout,
+    /// For a single SRC.
+    File(PathBuf),
+    /// An existing directory, DSTs are named after their SRC.
+    Dir(PathBuf),
+    /// Every SRC as an entry of one archive, `None` for stdout, see [`Dst::Entry`].
+    Archive(Option<PathBuf>),
+}
+
+/// See [`SrcDstConfig::builder`].
+#[derive(Debug, Clone)]
+pub struct PairsBuilder<'a> {
+    config: &'a SrcDstConfig,
+    source: Option<PairSource>,
+    drain: Option<PairDrain>,
+    entry_name: Option<PathBuf>,
+}
+
+impl SrcDstConfig {
+    /// The lower-level way to [`SrcDstPairs`], see [`crate::builder`].
+    ///
+    /// ```no_run
+    /// use src_dst_clarifier::{builder::{PairDrain, PairSource}, SrcDstConfig};
+    ///
+    /// let pairs = SrcDstConfig::new("txt")
+    ///     .builder()
+    ///     .source(PairSource::Files {
+    ///         root: "logs".into(),
+    ///         names: vec!["a.log".into(), "b.log".into()],
+    ///     })
+    ///     .drain(PairDrain::Stdout)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn builder(&self) -> PairsBuilder<'_> {
+        PairsBuilder {
+            config: self,
+            source: None,
+            drain: None,
+            entry_name: None,
+        }
+    }
+}
+
+impl<'a> PairsBuilder<'a> {
+    pub fn source(mut self, source: PairSource) -> Self {
+        self.source = Some(source);
+        self
+    }
+
+    pub fn drain(mut self, drain: PairDrain) -> Self {
+        self.drain = Some(drain);
+        self
+    }
+
+    /// The name in the archive of a single SRC, by default its file name,
+    /// required for [`PairSource::Stdin`].
+    pub fn entry_name<P: Into<PathBuf>>(mut self, name: P) -> Self {
+        self.entry_name = Some(name.into());
+        self
+    }
+
+    pub fn get_source(&self) -> Option<&PairSource> {
+        self.source.as_ref()
+    }
+
+    pub fn get_drain(&self) -> Option<&PairDrain> {
+        self.drain.as_ref()
+    }
+
+    /// [`io::ErrorKind::InvalidInput`](std::io::ErrorKind::InvalidInput) when SRC or DST is
+    /// missing, or they cannot be combined, e.g. many SRCs into one file.
+    pub fn build(self) -> Result<SrcDstPairs, ClarifyError> {
+        let config = self.config;
+        let filesystem = config.fs();
+        let invalid = |msg: &str| {
+            let e = std::io::Error::new(std::io::ErrorKind::InvalidInput, msg.to_owned());
+            ClarifyError::from(crate::EnvironmentError(e))
+        };
+        let (Some(source), Some(drain)) = (self.source, self.drain) else {
+            return Err(invalid("both SRC and DST have to be specified"));
+        };
+
+        match &source {
+            PairSource::Stdin if !config.stdio.from_stdin() => {
+                return Err(SrcDstError::DisallowFromStdin.into())
+            }
+            PairSource::File(path) if !filesystem.is_file(path) => {
+                return Err(SrcDstError::SrcNotExist.into())
+            }
+            PairSource::Dir(path) if !filesystem.is_dir(path) => {
+                return Err(SrcDstError::SrcNotExist.into())
+            }
+            _ => (),
+        }
+        match &drain {
+            PairDrain::Stdout | PairDrain::Archive(None) => config.check_stdout()?,
+            PairDrain::Dir(dir) if !filesystem.is_dir(dir) => {
+                return Err(SrcDstError::DstDirNotExist.into())
+            }
+            _ => (),
+        }
+
+        let mut held = HeldSrcDir::default();
+        let (src, dst) = match (source, drain) {
+            (PairSource::Dir(dir), drain) => (Source::Files(config.walk(&dir, &mut held)?), drain),
+            (PairSource::Files { root, names }, drain) => {
+                let escapes = |name: &PathBuf| {
+                    !name
+                        .components()
+                        .all(|component| matches!(component, Component::Normal(_)))
+                };
+                if names.iter().any(escapes) {
+                    return Err(invalid("listed SRC names have to be relative to the root"));
+                }
+                let order =
+                    collation::Order::of(&config.collation)?.with_traversal(config.traversal);
+                let mut spiller = spill::Spiller::new(config.sort_spill_threshold, order);
+                for name in names {
+                    if config.source_filter.accepts(&root.join(&name)) {
+                        spiller.push(OsString::from(name))?;
+                    }
+                }
+                let names = spiller.finish()?;
+                (Source::Files(FileList { root, names }), drain)
+            }
+            (single, PairDrain::Archive(archive)) => {
+                let src = match single {
+                    PairSource::File(path) => Src::File(path),
+                    _ => Src::Stdin,
+                };
+                let name = match (self.entry_name, &src) {
+                    (Some(name), _) => name,
+                    (None, Src::File(path)) => PathBuf::from(path.file_name().unwrap_or_default()),
+                    (None, _) => return Err(invalid("an entry name is required for stdin")),
+                };
+                if let Some(archive) = &archive {
+                    if config.overwrite == OverwritePolicy::Error && filesystem.is_file(archive) {
+                        return Err(SrcDstError::DstExists.into());
+                    }
+                }
+                let entry = Dst::Entry(ArchiveEntry { archive, name });
+                let mut pairs = SrcDstPairs::planned(vec![(src, entry)], None);
+                pairs.filesystem = config.filesystem.clone();
+                return Ok(pairs);
+            }
+            (single, drain) => {
+                let src = match single {
+                    PairSource::File(path) => Source::File(path),
+                    _ => Source::Stdin,
+                };
+                let dst = match (drain, &src) {
+                    (PairDrain::Dir(dir), Source::File(path)) => {
+                        let mut dst = PathBuf::new();
+                        crate::batch_dst(
+                            &mut dst,
+                            &dir,
+                            path.file_name().unwrap_or_default().as_ref(),
+                            None,
+                            crate::passthrough_extension(&config.passthrough, path)
+                                .or(config.output_extension.as_deref()),
+                            config.name_policy,
+                            config.collapse_duplicate_extensions,
+                        );
+                        PairDrain::File(dst)
+                    }
+                    (PairDrain::Dir(_), _) => {
+                        return Err(invalid("stdin can not be named into a directory"))
+                    }
+                    (drain, _) => drain,
+                };
+                (src, dst)
+            }
+        };
+
+        let dst = match (dst, &src) {
+            (PairDrain::Stdout, _) => Drain::Stdout,
+            (PairDrain::File(_), Source::Files(_)) => return Err(SrcDstError::ManyToOne.into()),
+            (PairDrain::File(path) | PairDrain::Dir(path), _) => Drain::Single(path),
+            (PairDrain::Archive(archive), _) => Drain::Archive(archive),
+        };
+        if let (Source::File(src), Drain::Single(dst)) = (&src, &dst) {
+            if src == dst && !config.inplace.allows() {
+                return Err(SrcDstError::Inplaced.into());
+            }
+        }
+
+        let mut pairs = SrcDstPairs {
+            src,
+            dst,
+            tnamed_dir: false,
+            tnamed_file: false,
+            held,
+            collapse_extensions: config.collapse_duplicate_extensions,
+            output_extension: config.output_extension.clone(),
+            passthrough: config.passthrough.clone(),
+            name_policy: config.name_policy,
+            buckets: config.dst_buckets,
+            flatten: config.flatten,
+            filesystem: config.filesystem.clone(),
+            planned_dirs: Vec::new(),
+            overwrite: config.overwrite,
+            incremental: config.incremental,
+            conflict_resolver: config.conflict_resolver.clone(),
+            resolved: std::collections::HashMap::new(),
+            probe: config.probe.clone(),
+            up_to_date: 0,
+            scratch: Dst::Stdout,
+            yielded: HashSet::new(),
+            copy_numbers: naming::CopyNumbers::default(),
+            unsettled: Vec::new(),
+            finished: false,
+        };
+        pairs.apply_overwrite_policy()?;
+        Ok(pairs)
+    }
+}
+
+impl PairDrain {
+    /// Of the DST file, directory or archive.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            PairDrain::File(path) | PairDrain::Dir(path) | PairDrain::Archive(Some(path)) => {
+                Some(path)
+            }
+            PairDrain::Stdout | PairDrain::Archive(None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn combinations() {
+        let config = SrcDstConfig::new("txt");
+        let root = PathBuf::from("/logs");
+        let pairs: Vec<_> = config
+            .builder()
+            .source(PairSource::Files {
+                root: root.clone(),
+                names: vec!["b.log".into(), "a.log".into()],
+            })
+            .drain(PairDrain::Stdout)
+            .build()
+            .unwrap()
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                (Src::File(root.join("a.log")), Dst::Stdout),
+                (Src::File(root.join("b.log")), Dst::Stdout),
+            ]
+        );
+
+        let builder = config
+            .builder()
+            .source(PairSource::Stdin)
+            .drain(PairDrain::Archive(None));
+        assert_eq!(builder.get_drain().and_then(PairDrain::path), None);
+        assert!(builder.clone().build().is_err());
+        let pairs: Vec<_> = builder.entry_name("in.txt").build().unwrap().collect();
+        assert_eq!(
+            pairs,
+            [(
+                Src::Stdin,
+                Dst::Entry(ArchiveEntry {
+                    archive: None,
+                    name: "in.txt".into()
+                })
+            )]
+        );
+
+        let escaping = config.builder().source(PairSource::Files {
+            root,
+            names: vec!["../etc/passwd".into()],
+        });
+        assert!(escaping.drain(PairDrain::Stdout).build().is_err());
+
+        let mut memory = crate::vfs::MemoryFs::new("/logs");
+        memory
+            .add_file_with("a.log", "keep")
+            .add_file_with("b.log", "skip");
+        let mut config = SrcDstConfig::new("txt");
+        config.filesystem = Some(crate::shared::Shared(std::sync::Arc::new(memory)));
+        config.source_filter.exclude.push("c.*".into());
+        config.probe = Some(crate::probe::Probe::new(
+            4,
+            |_: &Path, head: &[u8]| match head {
+                b"keep" => crate::probe::ProbeDecision::Keep,
+                _ => crate::probe::ProbeDecision::Skip,
+            },
+        ));
+        for drain in [PairDrain::Stdout, PairDrain::Archive(None)] {
+            let srcs: Vec<_> = config
+                .builder()
+                .source(PairSource::Files {
+                    root: "/logs".into(),
+                    names: vec!["a.log".into(), "b.log".into(), "c.log".into()],
+                })
+                .drain(drain)
+                .build()
+                .unwrap()
+                .map(|(src, _)| src)
+                .collect();
+            assert_eq!(srcs, [Src::File("/logs/a.log".into())]);
+        }
+    }
+}
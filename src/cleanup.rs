@@ -0,0 +1,340 @@
+//! Best-effort removal of temporary artifacts left behind when the process stops early.
+//!
+//! ``` plaintext
+//! TempGuard dropped          =>  removed, unless kept
+//! std::process::exit         =>  removed by the hook of install_exit_hook
+//! SIGKILL, power loss...     =>  removed by cleanup_stale in a later run
+//! ```
+//!
+//! The temporary siblings of [`WriteFileAtomic`](crate::ioers::WriteFileAtomic), spooled stdin,
+//! spilled SRC names, the time-based named directory of [`Runner`](crate::run::Runner)
+//! and the scratch directories of [`Task`](crate::run::Task) are all guarded.
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use crate::audit::{self, Operation};
+
+/// 按注册顺序，删除时倒过来，先删目录里的文件。
+static PENDING: Mutex<BTreeMap<u64, (PathBuf, ArtifactKind)>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    File,
+    /// Only removed if still empty.
+    EmptyDir,
+    /// Removed with everything in it.
+    Tree,
+}
+
+/// Removes the artifact on drop unless [`Self::keep`] is called, and is registered until then
+/// for [`remove_pending`].
+#[derive(Debug)]
+pub struct TempGuard {
+    id: u64,
+    path: PathBuf,
+    kind: ArtifactKind,
+    armed: bool,
+}
+
+impl TempGuard {
+    pub fn file<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(path.into(), ArtifactKind::File)
+    }
+
+    pub fn empty_dir<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(path.into(), ArtifactKind::EmptyDir)
+    }
+
+    pub fn tree<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(path.into(), ArtifactKind::Tree)
+    }
+
+    fn new(path: PathBuf, kind: ArtifactKind) -> Self {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        lock().insert(id, (path.clone(), kind));
+        Self {
+            id,
+            path,
+            kind,
+            armed: true,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// E.g. once renamed into place.
+    pub fn keep(mut self) {
+        self.armed = false;
+    }
+
+    /// Unlike dropping it, reports the error.
+    pub fn remove(mut self) -> io::Result<()> {
+        self.armed = false;
+        remove(&self.path, self.kind)
+    }
+}
+
+impl Drop for TempGuard {
+    fn drop(&mut self) {
+        lock().remove(&self.id);
+        if self.armed {
+            let _ = remove(&self.path, self.kind);
+        }
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, BTreeMap<u64, (PathBuf, ArtifactKind)>> {
+    PENDING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn remove(path: &Path, kind: ArtifactKind) -> io::Result<()> {
+    match kind {
+        ArtifactKind::File => audit::record(Operation::Remove, path, fs::remove_file(path)),
+        ArtifactKind::EmptyDir => audit::record(Operation::RemoveDir, path, fs::remove_dir(path)),
+        ArtifactKind::Tree => audit::record(Operation::RemoveDir, path, fs::remove_dir_all(path)),
+    }
+}
+
+/// Removes every artifact whose [`TempGuard`] has not been dropped yet, e.g. right before
+/// [`std::process::exit`]. The guards then do nothing. Returns how many were removed.
+pub fn remove_pending() -> usize {
+    let pending = std::mem::take(&mut *lock());
+    pending
+        .into_values()
+        .rev()
+        .filter(|(path, kind)| remove(path, *kind).is_ok())
+        .count()
+}
+
+/// Calls [`remove_pending`] when the process exits through [`std::process::exit`] or by
+/// returning from `main`. Only on Unix, does nothing elsewhere. Any number of calls install it once.
+pub fn install_exit_hook() {
+    #[cfg(unix)]
+    {
+        extern "C" fn hook() {
+            // 别的线程可能正拿着锁，宁可不删也不能卡住退出
+            if let Ok(mut pending) = PENDING.try_lock() {
+                for (path, kind) in std::mem::take(&mut *pending).into_values().rev() {
+                    let _ = remove(&path, kind);
+                }
+            }
+        }
+        static ONCE: std::sync::Once = std::sync::Once::new();
+        ONCE.call_once(|| unsafe {
+            libc::atexit(hook);
+        });
+    }
+}
+
+/// See [`cleanup_stale`].
+#[derive(Debug, Default)]
+pub struct Cleanup {
+    pub removed: Vec<PathBuf>,
+    /// What could not be listed or removed, with the error. The rest was cleaned up anyway.
+    pub failed: Vec<(PathBuf, io::Error)>,
+}
+
+/// Removes the leftovers of crashed runs within `dst_root` (recursively, symlinks not followed),
+/// and the spooled stdin, spilled SRC names and scratch directories in [`std::env::temp_dir`].
+/// Fails only if `dst_root` itself can not be listed.
+///
+/// Artifacts whose process is still running are left alone. Other platforms than Unix can't
+/// tell, so there only those untouched for a day are removed.
+pub fn cleanup_stale<P: AsRef<Path>>(dst_root: P) -> io::Result<Cleanup> {
+    let mut cleanup = Cleanup::default();
+    let root = dst_root.as_ref();
+    let mut dirs = vec![root.to_owned()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match typed_entries(&dir, &mut cleanup.failed) {
+            Err(e) if dir == root => return Err(e),
+            Err(e) => {
+                cleanup.failed.push((dir, e));
+                continue;
+            }
+            Ok(entries) => entries,
+        };
+        for (entry, file_type) in entries {
+            if file_type.is_dir() {
+                dirs.push(entry.path());
+            } else if file_type.is_file() {
+                let name = entry.file_name();
+                let pid = name.to_str().and_then(atomic_owner);
+                remove_stale(entry.path(), pid, ArtifactKind::File, &mut cleanup);
+            }
+        }
+    }
+    let temp = std::env::temp_dir();
+    let entries = match typed_entries(&temp, &mut cleanup.failed) {
+        Ok(entries) => entries,
+        Err(e) => {
+            cleanup.failed.push((temp, e));
+            Vec::new()
+        }
+    };
+    for (entry, file_type) in entries {
+        let name = entry.file_name();
+        let pid = name.to_str().and_then(scratch_owner);
+        let kind = match file_type {
+            kind if kind.is_file() => ArtifactKind::File,
+            kind if kind.is_dir() => ArtifactKind::Tree,
+            _ => continue,
+        };
+        if pid.is_some() {
+            remove_stale(entry.path(), pid, kind, &mut cleanup);
+        }
+    }
+    Ok(cleanup)
+}
+
+/// The entries of `dir` with their type, those whose type can not be told in `failed`.
+fn typed_entries(
+    dir: &Path,
+    failed: &mut Vec<(PathBuf, io::Error)>,
+) -> io::Result<Vec<(fs::DirEntry, fs::FileType)>> {
+    let mut typed = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        match entry.and_then(|entry| Ok((entry.file_type()?, entry))) {
+            Ok((file_type, entry)) => typed.push((entry, file_type)),
+            Err(e) => failed.push((dir.to_owned(), e)),
+        }
+    }
+    Ok(typed)
+}
+
+/// The temporary siblings of [`WriteFileAtomic`](crate::ioers::WriteFileAtomic) writing `dst`
+/// that crashed runs left behind, sorted. Those of running processes are left out,
+/// see [`cleanup_stale`].
+pub fn stale_partials<P: AsRef<Path>>(dst: P) -> io::Result<Vec<PathBuf>> {
+    let dst = dst.as_ref();
+    let (Some(dir), Some(dst_name)) = (dst.parent(), dst.file_name()) else {
+        return Ok(Vec::new());
+    };
+    let dir = match dir.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => dir,
+    };
+    let entries = match fs::read_dir(dir) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        entries => entries?,
+    };
+    let mut partials = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some((of, pid)) = name.to_str().and_then(atomic_parts) else {
+            continue;
+        };
+        let path = dir.join(&name);
+        let ours = pid == std::process::id();
+        if of == dst_name && !ours && entry.file_type()?.is_file() && !running(pid, &path) {
+            partials.push(path);
+        }
+    }
+    partials.sort();
+    Ok(partials)
+}
+
+fn remove_stale(path: PathBuf, pid: Option<u32>, kind: ArtifactKind, cleanup: &mut Cleanup) {
+    let Some(pid) = pid else {
+        return;
+    };
+    if pid == std::process::id() || running(pid, &path) {
+        return;
+    }
+    match remove(&path, kind) {
+        Ok(()) => cleanup.removed.push(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (), // 别人刚删掉
+        Err(e) => cleanup.failed.push((path, e)),
+    }
+}
+
+/// `.<name>.<pid>-<n>.tmp`, see [`WriteFileAtomic`](crate::ioers::WriteFileAtomic).
+fn atomic_owner(name: &str) -> Option<u32> {
+    atomic_parts(name).map(|(_, pid)| pid)
+}
+
+/// The name of DST, and the pid.
+fn atomic_parts(name: &str) -> Option<(&str, u32)> {
+    let rest = name.strip_prefix('.')?.strip_suffix(".tmp")?;
+    let (of, owner) = rest.rsplit_once('.')?;
+    Some((of, numbered_owner(owner)?))
+}
+
+/// `sdc-spool-<pid>-<n>`, `sdc-sort-<pid>-<n>` and `sdc-scratch-<pid>-<n>`.
+fn scratch_owner(name: &str) -> Option<u32> {
+    let owner = name
+        .strip_prefix("sdc-spool-")
+        .or_else(|| name.strip_prefix("sdc-sort-"))
+        .or_else(|| name.strip_prefix("sdc-scratch-"))?;
+    numbered_owner(owner)
+}
+
+/// `<pid>-<n>`
+fn numbered_owner(owner: &str) -> Option<u32> {
+    let (pid, n) = owner.split_once('-')?;
+    n.parse::<u64>().ok()?;
+    pid.parse().ok()
+}
+
+#[cfg(unix)]
+fn running(pid: u32, _: &Path) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    match unsafe { libc::kill(pid, 0) } {
+        0 => true,
+        _ => io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+    }
+}
+
+#[cfg(not(unix))]
+fn running(_: u32, path: &Path) -> bool {
+    let day = std::time::Duration::from_secs(24 * 60 * 60);
+    fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .map_or(true, |modified| {
+            modified.elapsed().map_or(true, |age| age < day)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn guards_and_stale() {
+        let root = ScratchDir::new("cleanup");
+        fs::create_dir_all(root.join("sub")).unwrap();
+
+        let guard = TempGuard::file(root.join("a.tmp"));
+        fs::write(guard.path(), b"").unwrap();
+        drop(guard);
+        assert!(!root.join("a.tmp").exists());
+        let kept = TempGuard::file(root.join("b"));
+        fs::write(kept.path(), b"").unwrap();
+        kept.keep();
+        assert!(root.join("b").exists());
+
+        let dead = root.join("sub/.out.png.4294967295-0.tmp");
+        let ours = root.join(format!(".out.png.{}-0.tmp", std::process::id()));
+        for path in [&dead, &ours] {
+            fs::write(path, b"partial").unwrap();
+        }
+        let cleanup = cleanup_stale(&root).unwrap();
+        assert_eq!(cleanup.removed, [dead]);
+        assert!(cleanup_stale(root.join("missing")).is_err());
+        assert!(ours.exists() && root.join("b").exists());
+    }
+}
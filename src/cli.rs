@@ -0,0 +1,147 @@
+//! [`clap`] integration, enabled by the `clap` feature.
+//!
+//! ``` ignore
+//! use clap::Parser;
+//! use src_dst_clarifier::{cli::SrcDstArgs, SrcDstConfig};
+//!
+//! #[derive(Parser)]
+//! struct Cli {
+//!     #[command(flatten)]
+//!     paths: SrcDstArgs,
+//! }
+//!
+//! let pairs = match Cli::parse().paths.clarify(&SrcDstConfig::new("png"))? {
+//!     Ok(pairs) => pairs,
+//!     Err(e) => e.exit(), // 用法错误，退出码 2
+//! };
+//! ```
+
+use std::{io, path::PathBuf};
+
+use clap::error::ErrorKind;
+
+use crate::{
+    Culprit, InplacePolicy, OverwritePolicy, SrcDstConfig, SrcDstError, SrcDstPairs, StdioPolicy,
+};
+
+/// The usual SRC and DST arguments, to be flattened into a parser.
+#[derive(clap::Args, Debug, Clone, PartialEq, Eq)]
+pub struct SrcDstArgs {
+    /// Input file or directory, `-` for stdin
+    #[arg(value_name = "SRC")]
+    pub src: PathBuf,
+
+    /// Output file or directory, `-` for stdout
+    #[arg(value_name = "DST", conflicts_with = "output")]
+    pub dst: Option<PathBuf>,
+
+    /// Same as DST
+    #[arg(short, long, value_name = "DST")]
+    pub output: Option<PathBuf>,
+
+    /// Refuse to read from stdin
+    #[arg(long)]
+    pub no_stdin: bool,
+
+    /// Refuse to write to stdout
+    #[arg(long)]
+    pub no_stdout: bool,
+
+    /// Allow DST to be SRC itself
+    #[arg(long)]
+    pub inplace: bool,
+
+    /// What to do with DSTs that already exist
+    #[arg(long, value_enum, value_name = "POLICY")]
+    pub overwrite: Option<OverwritePolicy>,
+}
+
+impl SrcDstArgs {
+    pub fn dst(&self) -> Option<&PathBuf> {
+        self.output.as_ref().or(self.dst.as_ref())
+    }
+
+    /// `config` with the flags applied, those not given are left as they are.
+    pub fn apply(&self, config: &SrcDstConfig) -> SrcDstConfig {
+        let mut config = config.clone();
+        let (from_stdin, to_stdout) = (config.stdio.from_stdin(), config.stdio.to_stdout());
+        config.stdio = StdioPolicy::of(from_stdin && !self.no_stdin, to_stdout && !self.no_stdout);
+        if self.inplace {
+            config.inplace = InplacePolicy::Atomic;
+        }
+        if let Some(overwrite) = self.overwrite {
+            config.overwrite = overwrite;
+        }
+        config
+    }
+
+    /// Like [`SrcDstConfig::parse`] with the flags applied, usage errors being [`clap::Error`]s
+    /// that exit with code 2 (`Error::format` them with the command for the usage line).
+    pub fn clarify(&self, config: &SrcDstConfig) -> io::Result<Result<SrcDstPairs, clap::Error>> {
+        let parsed = self.apply(config).parse(&self.src, self.dst());
+        Ok(parsed?.map_err(|e| self.usage_error(e)))
+    }
+
+    pub fn usage_error(&self, error: SrcDstError) -> clap::Error {
+        let shown = |path: Option<&PathBuf>| match path {
+            Some(path) => format!("'{}'", path.display()),
+            None => String::from("(none)"),
+        };
+        let (kind, culprit) = match error.culprit() {
+            Culprit::Src => (
+                ErrorKind::ValueValidation,
+                format!("SRC {}", shown(Some(&self.src))),
+            ),
+            Culprit::Dst => (
+                ErrorKind::ValueValidation,
+                format!("DST {}", shown(self.dst())),
+            ),
+            Culprit::Both => (
+                ErrorKind::ArgumentConflict,
+                format!(
+                    "SRC {} and DST {}",
+                    shown(Some(&self.src)),
+                    shown(self.dst())
+                ),
+            ),
+        };
+        clap::Error::raw(
+            kind,
+            format!("{error}: {culprit}\n\n  tip: {}\n", error.help()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn clarify() {
+        let root = ScratchDir::new("cli");
+        fs::create_dir_all(root.join("frames")).unwrap();
+        fs::write(root.join("out.mp4"), b"").unwrap();
+        let args = SrcDstArgs {
+            src: root.join("frames"),
+            dst: None,
+            output: Some(root.join("out.mp4")),
+            no_stdin: true,
+            no_stdout: false,
+            inplace: false,
+            overwrite: Some(OverwritePolicy::Error),
+        };
+        let config = args.apply(&SrcDstConfig::new("png"));
+        assert_eq!(config.stdio, StdioPolicy::StdoutOnly);
+        assert_eq!(config.overwrite, OverwritePolicy::Error);
+
+        let e = args
+            .clarify(&SrcDstConfig::new("png"))
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::ValueValidation);
+        assert!(e.to_string().contains("out.mp4"));
+    }
+}
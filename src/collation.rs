@@ -0,0 +1,185 @@
+//! How batch SRC names are ordered, see [`SrcDstConfig::collation`](crate::SrcDstConfig::collation).
+//!
+//! ``` plaintext
+//! Bytewise     Anna  Bertil  Åsa
+//! Locale(en)   Anna  Åsa  Bertil     (feature `icu`)
+//! ```
+//!
+//! And across the sub-directories walked into by [`SrcDstConfig::max_depth`](crate::SrcDstConfig::max_depth):
+//!
+//! ``` plaintext
+//! DepthFirst     a/deep/two  a/one  m  z/x
+//! BreadthFirst   m  a/one  z/x  a/deep/two
+//! DirsFirst      a/deep/two  a/one  z/x  m
+//! ```
+
+use std::{cmp::Ordering, ffi::OsStr, fmt, io, path::Path};
+
+use crate::{batch_order, tree_order};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum Collation {
+    /// [`batch_order`], the same everywhere.
+    #[default]
+    Bytewise,
+    /// By the rules of a BCP 47 locale (`sv`, `de-u-co-phonebk`...) through ICU, feature `icu`.
+    /// Ties, e.g. names differing only in case when the locale ignores it, are broken bytewise.
+    Locale(String),
+}
+
+/// See [`SrcDstConfig::traversal`](crate::SrcDstConfig::traversal). Names in one directory
+/// are in [`Collation`] either way.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Traversal {
+    /// Every sub-directory as a whole, where its name sorts among the files.
+    #[default]
+    DepthFirst,
+    /// The files of every level before those of the next.
+    BreadthFirst,
+    /// Depth-first, but the sub-directories of a directory before its files.
+    DirsFirst,
+}
+
+/// A [`Collation`] ready for comparing, cheap to clone.
+#[derive(Clone, Default)]
+pub(crate) struct Order {
+    traversal: Traversal,
+    #[cfg(feature = "icu")]
+    collator: Option<std::sync::Arc<icu_collator::Collator>>,
+}
+
+impl fmt::Debug for Order {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Order(..)")
+    }
+}
+
+impl Order {
+    /// [`io::ErrorKind::Unsupported`] for a locale without the `icu` feature or ICU data for it.
+    pub(crate) fn of(collation: &Collation) -> io::Result<Self> {
+        let tag = match collation {
+            Collation::Bytewise => return Ok(Self::default()),
+            Collation::Locale(tag) => tag,
+        };
+        #[cfg(feature = "icu")]
+        {
+            let locale: icu_locid::Locale = tag.parse().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("bad locale '{tag}': {e}"),
+                )
+            })?;
+            let options = icu_collator::CollatorOptions::new();
+            let collator =
+                icu_collator::Collator::try_new(&(&locale).into(), options).map_err(|e| {
+                    io::Error::new(
+                        io::ErrorKind::Unsupported,
+                        format!("no collation for '{tag}': {e}"),
+                    )
+                })?;
+            Ok(Self {
+                traversal: Traversal::default(),
+                collator: Some(std::sync::Arc::new(collator)),
+            })
+        }
+        #[cfg(not(feature = "icu"))]
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("sorting by locale '{tag}' needs the `icu` feature"),
+        ))
+    }
+
+    /// Of two file names.
+    pub(crate) fn names(&self, a: &OsStr, b: &OsStr) -> Ordering {
+        #[cfg(feature = "icu")]
+        if let Some(collator) = &self.collator {
+            let ordering = collator.compare(&a.to_string_lossy(), &b.to_string_lossy());
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        batch_order(a.as_ref(), b.as_ref())
+    }
+
+    pub(crate) fn with_traversal(mut self, traversal: Traversal) -> Self {
+        self.traversal = traversal;
+        self
+    }
+
+    /// Of two relative paths, see [`crate::tree_order`].
+    pub(crate) fn tree(&self, a: &Path, b: &Path) -> Ordering {
+        match self.traversal {
+            Traversal::DepthFirst => tree_order(a, b, |a, b| self.names(a, b)),
+            Traversal::BreadthFirst => a
+                .iter()
+                .count()
+                .cmp(&b.iter().count())
+                .then_with(|| tree_order(a, b, |a, b| self.names(a, b))),
+            Traversal::DirsFirst => {
+                let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+                loop {
+                    match (a.next(), b.next()) {
+                        (Some(x), Some(y)) if x == y => continue,
+                        // 还有下一段的就是目录
+                        (Some(x), Some(y)) => {
+                            let (x_dir, y_dir) = (a.peek().is_some(), b.peek().is_some());
+                            return y_dir.cmp(&x_dir).then_with(|| self.names(x, y));
+                        }
+                        (x, y) => return x.is_some().cmp(&y.is_some()),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn locale() {
+        let sorted = |collation| {
+            let order = Order::of(&collation)?;
+            let mut names = ["Åsa", "Bertil", "Anna"].map(OsStr::new);
+            names.sort_by(|a, b| order.names(a, b));
+            Ok::<_, io::Error>(names)
+        };
+        assert_eq!(
+            sorted(Collation::Bytewise).unwrap(),
+            ["Anna", "Bertil", "Åsa"]
+        );
+        #[cfg(feature = "icu")]
+        assert_eq!(
+            sorted(Collation::Locale("en".into())).unwrap(),
+            ["Anna", "Åsa", "Bertil"]
+        );
+        #[cfg(not(feature = "icu"))]
+        assert_eq!(
+            sorted(Collation::Locale("en".into())).unwrap_err().kind(),
+            io::ErrorKind::Unsupported
+        );
+    }
+
+    #[test]
+    fn traversal() {
+        let sorted = |traversal| {
+            let order = Order::default().with_traversal(traversal);
+            let mut paths = ["z/x", "m", "a/one", "a/deep/two"].map(Path::new);
+            paths.sort_by(|a, b| order.tree(a, b));
+            paths.map(|path| path.to_str().unwrap())
+        };
+        assert_eq!(
+            sorted(Traversal::DepthFirst),
+            ["a/deep/two", "a/one", "m", "z/x"]
+        );
+        assert_eq!(
+            sorted(Traversal::BreadthFirst),
+            ["m", "a/one", "z/x", "a/deep/two"]
+        );
+        assert_eq!(
+            sorted(Traversal::DirsFirst),
+            ["a/deep/two", "a/one", "z/x", "m"]
+        );
+    }
+}
@@ -0,0 +1,202 @@
+//! Older shapes of the API, one module per version, for migrating one call site at a time.
+
+pub mod v0 {
+    //! The API of 0.2, field for field and variant for variant, so that its struct literals and
+    //! exhaustive matches still compile.
+    //!
+    //! ```
+    //! # #![allow(deprecated)]
+    //! use src_dst_clarifier::compat::v0::{Dst, Src, SrcDstConfig};
+    //!
+    //! let config = SrcDstConfig {
+    //!     allow_from_stdin: true,
+    //!     allow_to_stdout: true,
+    //!     auto_tnamed_dst_file: true,
+    //!     auto_tnamed_dst_dir: true,
+    //!     default_extension: "txt".into(),
+    //!     allow_inplace: false,
+    //! };
+    //! for (src, dst) in config.parse("-", Some("-")).unwrap().unwrap() {
+    //!     match (src, dst) {
+    //!         (Src::Stdin, Dst::Stdout) => (),
+    //!         (Src::File(_), _) | (_, Dst::File(_)) => unreachable!(),
+    //!     }
+    //! }
+    //! ```
+    #![allow(deprecated)]
+
+    use std::{
+        ffi::{OsStr, OsString},
+        io,
+        path::{Path, PathBuf},
+    };
+
+    use crate::{AutoNamePolicy, InplacePolicy, SrcDstError, StdioPolicy};
+
+    /// Converts into [`crate::SrcDstConfig`], where the flags became policies:
+    ///
+    /// ``` plaintext
+    /// allow_from_stdin, allow_to_stdout           stdio: StdioPolicy::of(from_stdin, to_stdout)
+    /// auto_tnamed_dst_file, auto_tnamed_dst_dir   auto_name: AutoNamePolicy::of(files, dirs)
+    /// allow_inplace                               inplace: InplacePolicy::Atomic or Deny
+    /// ```
+    #[deprecated(
+        since = "0.3.0",
+        note = "use `SrcDstConfig` with its `stdio`, `auto_name` and `inplace` policies"
+    )]
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct SrcDstConfig {
+        pub allow_from_stdin: bool,
+        pub allow_to_stdout: bool,
+
+        pub auto_tnamed_dst_file: bool,
+        pub auto_tnamed_dst_dir: bool,
+
+        pub default_extension: OsString,
+
+        /// Disallowed by default. There may be a potential to `open` and `create` the same file at the same time.
+        pub allow_inplace: bool,
+    }
+
+    impl SrcDstConfig {
+        pub fn new<S: AsRef<OsStr>>(default_extension: S) -> Self {
+            Self {
+                allow_from_stdin: true,
+                allow_to_stdout: true,
+                auto_tnamed_dst_file: true,
+                auto_tnamed_dst_dir: true,
+                default_extension: default_extension.as_ref().to_owned(),
+                allow_inplace: false,
+            }
+        }
+
+        pub fn new_with_allow_inplace<S: AsRef<OsStr>>(default_extension: S) -> Self {
+            Self {
+                allow_inplace: true,
+                ..Self::new(default_extension)
+            }
+        }
+
+        /// [`crate::SrcDstConfig::parse`].
+        pub fn parse<P: AsRef<Path>>(
+            &self,
+            src: P,
+            dst: Option<P>,
+        ) -> io::Result<Result<SrcDstPairs, SrcDstError>> {
+            let pairs = crate::SrcDstConfig::from(self.clone()).parse(src, dst)?;
+            Ok(pairs.map(SrcDstPairs))
+        }
+    }
+
+    impl From<SrcDstConfig> for crate::SrcDstConfig {
+        fn from(old: SrcDstConfig) -> Self {
+            Self {
+                stdio: StdioPolicy::of(old.allow_from_stdin, old.allow_to_stdout),
+                auto_name: AutoNamePolicy::of(old.auto_tnamed_dst_file, old.auto_tnamed_dst_dir),
+                inplace: match old.allow_inplace {
+                    true => InplacePolicy::Atomic,
+                    false => InplacePolicy::Deny,
+                },
+                ..crate::SrcDstConfig::new(old.default_extension)
+            }
+        }
+    }
+
+    /// Exhaustive, as [`crate::Src`] was before remote and socket SRCs.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Src {
+        File(PathBuf),
+        Stdin,
+    }
+
+    /// Exhaustive, as [`crate::Dst`] was before remote, socket and archive DSTs.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Dst {
+        File(PathBuf),
+        Stdout,
+    }
+
+    impl From<Src> for crate::Src {
+        fn from(src: Src) -> Self {
+            match src {
+                Src::File(path) => Self::File(path),
+                Src::Stdin => Self::Stdin,
+            }
+        }
+    }
+
+    impl From<Dst> for crate::Dst {
+        fn from(dst: Dst) -> Self {
+            match dst {
+                Dst::File(path) => Self::File(path),
+                Dst::Stdout => Self::Stdout,
+            }
+        }
+    }
+
+    /// [`crate::SrcDstPairs`] yielding the pairs of 0.2.
+    #[derive(Debug)]
+    pub struct SrcDstPairs(pub crate::SrcDstPairs);
+
+    impl SrcDstPairs {
+        /// **Before consuming the path pair, call this method to create time-based named directory!**
+        pub fn create_tnamed_dir(&self) -> io::Result<()> {
+            self.0.create_tnamed_dir()
+        }
+
+        pub fn is_batch(&self) -> bool {
+            self.0.is_batch()
+        }
+    }
+
+    impl Iterator for SrcDstPairs {
+        type Item = (Src, Dst);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            // 0.2 的配置解析不出远程、套接字和归档
+            let (src, dst) = self.0.next()?;
+            let src = match src {
+                crate::Src::File(path) => Src::File(path),
+                crate::Src::Stdin => Src::Stdin,
+                #[allow(unreachable_patterns)] // 没有 sftp、unix-socket 时
+                src => unreachable!("{src} is not a SRC of 0.2"),
+            };
+            let dst = match dst {
+                crate::Dst::File(path) => Dst::File(path),
+                crate::Dst::Stdout => Dst::Stdout,
+                dst => unreachable!("{dst} is not a DST of 0.2"),
+            };
+            Some((src, dst))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn conversion() {
+            let old = SrcDstConfig {
+                allow_to_stdout: false,
+                auto_tnamed_dst_dir: false,
+                ..SrcDstConfig::new("txt")
+            };
+            let config = crate::SrcDstConfig::from(old.clone());
+            assert_eq!(config.stdio, StdioPolicy::StdinOnly);
+            assert_eq!(config.auto_name, AutoNamePolicy::FilesOnly);
+            assert_eq!(config.inplace, InplacePolicy::Deny);
+            assert_eq!(config.default_extension, "txt");
+
+            assert!(matches!(
+                old.parse("-", Some("-")),
+                Ok(Err(SrcDstError::DisallowToStdout))
+            ));
+            let mut pairs = SrcDstConfig::new("txt")
+                .parse("-", Some("-"))
+                .unwrap()
+                .unwrap();
+            assert_eq!(pairs.next(), Some((Src::Stdin, Dst::Stdout)));
+            assert_eq!(pairs.next(), None);
+        }
+    }
+}
@@ -0,0 +1,94 @@
+//! Deciding on every single DST collision instead of applying [`SrcDstConfig::overwrite`] to
+//! all of them, e.g. by asking the user.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use src_dst_clarifier::{
+//!     conflict::{Conflict, ConflictKind, Resolution},
+//!     shared::Shared,
+//!     SrcDstConfig,
+//! };
+//!
+//! let mut config = SrcDstConfig::new("png");
+//! config.conflict_resolver = Some(Shared(Arc::new(|conflict: Conflict| {
+//!     match conflict.kind {
+//!         ConflictKind::Duplicate => Resolution::RenameWithCounter,
+//!         ConflictKind::Exists => Resolution::Default, // 问用户，或者按配置来
+//!     }
+//! })));
+//! ```
+//!
+//! [`SrcDstConfig::overwrite`]: crate::SrcDstConfig::overwrite
+
+use std::path::{Path, PathBuf};
+
+use crate::{shared::Shared, OverwritePolicy};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConflictKind {
+    /// DST is an existing file.
+    Exists,
+    /// An earlier SRC of the batch has the same DST.
+    Duplicate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Conflict<'a> {
+    pub kind: ConflictKind,
+    /// `None` for stdin.
+    pub src: Option<&'a Path>,
+    pub dst: &'a Path,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Resolution {
+    /// As [`SrcDstConfig::overwrite`](crate::SrcDstConfig::overwrite) says.
+    Default,
+    Overwrite,
+    Skip,
+    RenameWithCounter,
+    RenameParenthesized,
+    /// This DST instead, used as is even if it exists.
+    Rename(PathBuf),
+}
+
+impl Resolution {
+    /// The policy to apply, and the DST a [`Self::Rename`] gives.
+    pub(crate) fn or_policy(
+        self,
+        configured: OverwritePolicy,
+    ) -> (OverwritePolicy, Option<PathBuf>) {
+        match self {
+            Resolution::Default => (configured, None),
+            Resolution::Overwrite => (OverwritePolicy::Overwrite, None),
+            Resolution::Skip => (OverwritePolicy::Skip, None),
+            Resolution::RenameWithCounter => (OverwritePolicy::RenameWithCounter, None),
+            Resolution::RenameParenthesized => (OverwritePolicy::RenameParenthesized, None),
+            Resolution::Rename(dst) => (OverwritePolicy::Overwrite, Some(dst)),
+        }
+    }
+}
+
+pub trait ConflictResolver: Send + Sync {
+    fn resolve(&self, conflict: Conflict<'_>) -> Resolution;
+}
+
+impl<F> ConflictResolver for F
+where
+    F: Fn(Conflict<'_>) -> Resolution + Send + Sync,
+{
+    fn resolve(&self, conflict: Conflict<'_>) -> Resolution {
+        self(conflict)
+    }
+}
+
+/// [`Resolution::Default`] without a resolver.
+pub(crate) fn consult(
+    resolver: Option<&Shared<dyn ConflictResolver>>,
+    conflict: Conflict<'_>,
+) -> Resolution {
+    match resolver {
+        Some(resolver) => resolver.0.resolve(conflict),
+        None => Resolution::Default,
+    }
+}
@@ -0,0 +1,219 @@
+//! A bound on how many files [`ReadFile`] and [`WriteFile`] keep open at once, so that
+//! parallel runs with prefetching do not run into `EMFILE`. Those beyond it wait for a
+//! descriptor to be closed.
+//!
+//! ```
+//! use src_dst_clarifier::descriptors;
+//!
+//! // 给 stdio、日志等留一些
+//! descriptors::set_budget(descriptors::rlimit_budget(32));
+//! ```
+//!
+//! An input and an output are open together while a pair is processed, so
+//! [`SrcDstPairs::process_parallel`](crate::SrcDstPairs::process_parallel) and its siblings run
+//! no more workers than the budget allows two for, see [`Budget::workers`]. Threads of your own
+//! taking descriptors should be bounded likewise, or they may wait on each other forever.
+//! [`Prefetch`](crate::prefetch::Prefetch) never takes the last [`PREFETCH_RESERVE`] of them,
+//! SRCs it can not open ahead are opened once processed instead.
+//!
+//! [`ReadFile`]: crate::ioers::ReadFile
+//! [`WriteFile`]: crate::ioers::WriteFile
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    sync::{Condvar, Mutex, MutexGuard, PoisonError},
+};
+
+static GLOBAL: Budget = Budget::new(None);
+
+/// Left by files opened ahead, for the input and output of the pair being processed.
+pub const PREFETCH_RESERVE: usize = 2;
+
+/// Process-wide, like [`audit::set_sink`](crate::audit::set_sink). Unbounded by default.
+pub fn set_budget(limit: Option<usize>) {
+    GLOBAL.set_limit(limit);
+}
+
+/// The one [`ReadFile`](crate::ioers::ReadFile) and [`WriteFile`](crate::ioers::WriteFile) use.
+pub fn global() -> &'static Budget {
+    &GLOBAL
+}
+
+/// The soft `RLIMIT_NOFILE` less `reserve`, `None` when unlimited or unknown.
+pub fn rlimit_budget(reserve: usize) -> Option<usize> {
+    #[cfg(unix)]
+    {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+            || limit.rlim_cur == libc::RLIM_INFINITY
+        {
+            return None;
+        }
+        Some(
+            usize::try_from(limit.rlim_cur)
+                .ok()?
+                .saturating_sub(reserve)
+                .max(1),
+        )
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = reserve;
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct Budget {
+    state: Mutex<State>,
+    released: Condvar,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: Option<usize>,
+    open: usize,
+}
+
+impl Budget {
+    pub const fn new(limit: Option<usize>) -> Self {
+        Self {
+            state: Mutex::new(State { limit, open: 0 }),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Lowering it below [`Self::open`] closes nothing, newcomers wait until enough are closed.
+    pub fn set_limit(&self, limit: Option<usize>) {
+        self.lock().limit = limit;
+        self.released.notify_all();
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.lock().limit
+    }
+
+    /// Descriptors currently held.
+    pub fn open(&self) -> usize {
+        self.lock().open
+    }
+
+    /// Blocks until a descriptor is available.
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut state = self.lock();
+        while state.limit.is_some_and(|limit| state.open >= limit) {
+            state = (self.released.wait(state)).unwrap_or_else(PoisonError::into_inner);
+        }
+        state.open += 1;
+        Permit(self)
+    }
+
+    pub fn try_acquire(&self) -> Option<Permit<'_>> {
+        self.try_acquire_leaving(0)
+    }
+
+    /// Like [`Self::try_acquire`], but only while `reserve` more would still be available,
+    /// for descriptors that are merely nice to have, see [`PREFETCH_RESERVE`].
+    pub fn try_acquire_leaving(&self, reserve: usize) -> Option<Permit<'_>> {
+        let mut state = self.lock();
+        let open = state.open.saturating_add(reserve);
+        match state.limit.is_some_and(|limit| open >= limit) {
+            true => None,
+            false => {
+                state.open += 1;
+                Some(Permit(self))
+            }
+        }
+    }
+
+    /// At most `threads`, but no more than can each hold an input and an output at once,
+    /// and at least one.
+    pub fn workers(&self, threads: usize) -> usize {
+        match self.limit() {
+            Some(limit) => threads.min(limit / 2).max(1),
+            None => threads,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// One descriptor, given back on drop.
+pub struct Permit<'a>(&'a Budget);
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.lock().open -= 1;
+        self.0.released.notify_one();
+    }
+}
+
+impl fmt::Debug for Permit<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Permit")
+    }
+}
+
+/// A reader or writer holding its descriptor until dropped.
+pub(crate) struct Held<T> {
+    pub(crate) inner: T,
+    pub(crate) _permit: Permit<'static>,
+}
+
+impl<T: Read> Read for Held<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<T: Write> Write for Held<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread, time::Duration};
+
+    #[test]
+    fn budget() {
+        let budget = Arc::new(Budget::new(Some(1)));
+        let first = budget.acquire();
+        assert!(budget.try_acquire().is_none());
+
+        let waiting = {
+            let budget = budget.clone();
+            thread::spawn(move || {
+                let _second = budget.acquire();
+                budget.open()
+            })
+        };
+        thread::sleep(Duration::from_millis(20));
+        assert!(!waiting.is_finished());
+        drop(first);
+        assert_eq!(waiting.join().unwrap(), 1);
+        assert_eq!(budget.open(), 0);
+        assert!(rlimit_budget(0).is_none_or(|limit| limit >= 1));
+
+        let budget = Budget::new(Some(3));
+        let ahead = budget.try_acquire_leaving(PREFETCH_RESERVE).unwrap();
+        assert!(budget.try_acquire_leaving(PREFETCH_RESERVE).is_none());
+        let (_input, _output) = (budget.acquire(), budget.acquire());
+        drop(ahead);
+        assert_eq!(budget.workers(8), 1);
+        assert_eq!(Budget::new(Some(9)).workers(8), 4);
+        assert_eq!(Budget::new(None).workers(8), 8);
+    }
+}
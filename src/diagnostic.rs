@@ -0,0 +1,90 @@
+//! [`miette`] integration, enabled by the `miette` feature.
+
+use std::fmt;
+
+use miette::{Diagnostic, LabeledSpan, SourceCode, SourceSpan};
+
+use crate::{l10n::Message, Culprit, SrcDstError};
+
+impl Diagnostic for SrcDstError {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(format!("src_dst_clarifier::{}", self.key())))
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Some(Box::new(SrcDstError::help(self)))
+    }
+}
+
+/// A [`SrcDstError`] together with the CLI arguments, labelling the offending one.
+///
+/// ``` plaintext
+///   × unable to write multiple files to one file
+///    ╭────
+///  1 │ ./frames output.mp4
+///    ·          ─────┬────
+///    ·               ╰── DST
+///    ╰────
+///   help: specify a directory as DST
+/// ```
+#[derive(Debug, Clone)]
+pub struct ArgsDiagnostic {
+    pub error: SrcDstError,
+    args: String,
+    src: SourceSpan,
+    dst: Option<SourceSpan>,
+}
+
+impl ArgsDiagnostic {
+    pub fn new(error: SrcDstError, src: &str, dst: Option<&str>) -> Self {
+        let mut args = String::from(src);
+        let dst = dst.map(|dst| {
+            args.push(' ');
+            let span = (args.len(), dst.len()).into();
+            args.push_str(dst);
+            span
+        });
+        Self {
+            error,
+            src: (0, src.len()).into(),
+            dst,
+            args,
+        }
+    }
+}
+
+impl fmt::Display for ArgsDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.error, f)
+    }
+}
+
+impl std::error::Error for ArgsDiagnostic {}
+
+impl Diagnostic for ArgsDiagnostic {
+    fn code<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        self.error.code()
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn fmt::Display + 'a>> {
+        Diagnostic::help(&self.error)
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(&self.args)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let src = LabeledSpan::new_with_span(Some(String::from("SRC")), self.src);
+        let dst = self.dst.map_or_else(
+            // DST 未提供时，指向末尾
+            || LabeledSpan::new_with_span(Some(String::from("DST")), (self.args.len(), 0)),
+            |span| LabeledSpan::new_with_span(Some(String::from("DST")), span),
+        );
+        Some(match self.error.culprit() {
+            Culprit::Src => Box::new([src].into_iter()),
+            Culprit::Dst => Box::new([dst].into_iter()),
+            Culprit::Both => Box::new([src, dst].into_iter()),
+        })
+    }
+}
@@ -0,0 +1,65 @@
+//! Where a plan was made or a run happened, recorded in [`Plan::environment`](crate::plan::Plan::environment)
+//! and [`RunReport::environment`](crate::run::RunReport::environment), so that saved files explain
+//! themselves when shared.
+
+use std::{fmt, path::PathBuf};
+
+use crate::vfs::FileSystem;
+
+/// Empty by default, e.g. for plans written before this was recorded.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Environment {
+    /// Of this crate.
+    pub crate_version: String,
+    /// [`std::env::consts::OS`].
+    pub os: String,
+    /// [`std::env::consts::ARCH`].
+    pub arch: String,
+    /// Relative SRC and DST were resolved against this. `None` if it could not be read.
+    pub cwd: Option<PathBuf>,
+}
+
+impl Environment {
+    pub fn capture() -> Self {
+        Self::capture_in(&crate::vfs::StdFs)
+    }
+
+    /// With the working directory of `filesystem`.
+    pub fn capture_in(filesystem: &dyn FileSystem) -> Self {
+        Self {
+            crate_version: String::from(env!("CARGO_PKG_VERSION")),
+            os: String::from(std::env::consts::OS),
+            arch: String::from(std::env::consts::ARCH),
+            cwd: filesystem.current_dir().ok(),
+        }
+    }
+}
+
+/// `src-dst-clarifier 0.2.0 on linux-x86_64 in /home/user`
+impl fmt::Display for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "src-dst-clarifier {} on {}-{}",
+            self.crate_version, self.os, self.arch
+        )?;
+        match &self.cwd {
+            Some(cwd) => write!(f, " in {}", cwd.display()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture() {
+        let memory = crate::vfs::MemoryFs::new("/work");
+        let environment = Environment::capture_in(&memory);
+        assert_eq!(environment.crate_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(environment.cwd, Some(PathBuf::from("/work")));
+        assert!(environment.to_string().ends_with(" in /work"));
+    }
+}
@@ -0,0 +1,223 @@
+//! The capture date of photos from their EXIF, feature `exif`, see [`SrcDstConfig::capture_time`](crate::SrcDstConfig::capture_time).
+//!
+//! ``` plaintext
+//! IMG_0042.jpg   (DateTimeOriginal 2021:07:04 18:30:05)   =>   20210704T183005
+//! ```
+//!
+//! JPEG and TIFF-based files (most camera raws) are understood. `DateTimeOriginal` is preferred,
+//! then `DateTimeDigitized`, then `DateTime`. EXIF has no time zone, the camera's clock is taken as is.
+
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::Path,
+};
+
+use crate::audit::{self, Operation};
+
+/// EXIF lives in the first 64 KiB of a JPEG, and near the start of a TIFF.
+const SCAN_LIMIT: u64 = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CaptureDate {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// `YYYYMMDDTHHMMSS`, without `Z` as it is local time.
+impl fmt::Display for CaptureDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}{:02}{:02}T{:02}{:02}{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+impl CaptureDate {
+    /// `YYYY:MM:DD HH:MM:SS`, the EXIF way.
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim_end_matches(['\0', ' ']);
+        let bytes = s.as_bytes();
+        if bytes.len() != 19 || bytes[4] != b':' || bytes[7] != b':' || bytes[10] != b' ' {
+            return None;
+        }
+        let num = |range: std::ops::Range<usize>| s.get(range)?.parse().ok();
+        let date = Self {
+            year: num(0..4)?,
+            month: num(5..7)? as u8,
+            day: num(8..10)? as u8,
+            hour: num(11..13)? as u8,
+            minute: num(14..16)? as u8,
+            second: num(17..19)? as u8,
+        };
+        // 没设时钟的相机会写 0000:00:00 00:00:00
+        let valid = (1..=12).contains(&date.month)
+            && (1..=31).contains(&date.day)
+            && date.hour < 24
+            && date.minute < 60
+            && date.second < 61;
+        valid.then_some(date)
+    }
+}
+
+/// `None` if `path` has no (usable) EXIF.
+pub fn capture_date<P: AsRef<Path>>(path: P) -> io::Result<Option<CaptureDate>> {
+    let path = path.as_ref();
+    from_reader(audit::record(Operation::Open, path, fs::File::open(path))?)
+}
+
+pub(crate) fn from_reader<R: Read>(reader: R) -> io::Result<Option<CaptureDate>> {
+    let mut head = Vec::new();
+    reader.take(SCAN_LIMIT).read_to_end(&mut head)?;
+    Ok(from_bytes(&head))
+}
+
+/// From the start of a JPEG or TIFF file.
+pub fn from_bytes(data: &[u8]) -> Option<CaptureDate> {
+    match data {
+        [0xff, 0xd8, ..] => from_jpeg(data),
+        [b'I', b'I', 42, 0, ..] | [b'M', b'M', 0, 42, ..] => from_tiff(data),
+        _ => None,
+    }
+}
+
+fn from_jpeg(data: &[u8]) -> Option<CaptureDate> {
+    let mut at = 2;
+    while at + 4 <= data.len() {
+        if data[at] != 0xff {
+            return None;
+        }
+        let marker = data[at + 1];
+        // 到了图像数据就不会再有 EXIF 了
+        if marker == 0xda || marker == 0xd9 {
+            return None;
+        }
+        let len = u16::from_be_bytes([data[at + 2], data[at + 3]]) as usize;
+        let segment = data.get(at + 4..at + 2 + len)?;
+        if marker == 0xe1 {
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                return from_tiff(tiff);
+            }
+        }
+        at += 2 + len;
+    }
+    None
+}
+
+fn from_tiff(tiff: &[u8]) -> Option<CaptureDate> {
+    const DATE_TIME: u16 = 0x0132;
+    const EXIF_IFD: u16 = 0x8769;
+    const DATE_TIME_ORIGINAL: u16 = 0x9003;
+    const DATE_TIME_DIGITIZED: u16 = 0x9004;
+
+    let big = tiff.starts_with(b"MM");
+    let u16_at = |at: usize| {
+        let bytes = [*tiff.get(at)?, *tiff.get(at + 1)?];
+        Some(match big {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    };
+    let u32_at = |at: usize| {
+        let bytes = tiff.get(at..at + 4)?.try_into().ok()?;
+        Some(match big {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        } as usize)
+    };
+    // (tag, value or offset) of every entry in the IFD at `at`
+    let entries = |at: usize| {
+        let count = u16_at(at).unwrap_or(0) as usize;
+        (0..count).filter_map(move |i| {
+            let entry = at + 2 + 12 * i;
+            Some((u16_at(entry)?, entry))
+        })
+    };
+    let ascii = |entry: usize| {
+        let len = u32_at(entry + 4)?;
+        let offset = u32_at(entry + 8)?;
+        let text = tiff.get(offset..offset.checked_add(len)?)?;
+        CaptureDate::parse(std::str::from_utf8(text).ok()?)
+    };
+
+    let ifd0 = u32_at(4)?;
+    let mut fallback = None;
+    for (tag, entry) in entries(ifd0) {
+        match tag {
+            DATE_TIME => fallback = fallback.or_else(|| ascii(entry)),
+            EXIF_IFD => {
+                let Some(exif) = u32_at(entry + 8) else {
+                    continue;
+                };
+                let mut digitized = None;
+                for (tag, entry) in entries(exif) {
+                    match tag {
+                        DATE_TIME_ORIGINAL => match ascii(entry) {
+                            Some(date) => return Some(date),
+                            None => continue,
+                        },
+                        DATE_TIME_DIGITIZED => digitized = ascii(entry),
+                        _ => (),
+                    }
+                }
+                if digitized.is_some() {
+                    return digitized;
+                }
+            }
+            _ => (),
+        }
+    }
+    fallback
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A little-endian TIFF with `DateTime` in IFD0 and `DateTimeOriginal` in the EXIF IFD.
+    fn tiff(original: &str) -> Vec<u8> {
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        let entry = |tiff: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32| {
+            tiff.extend(tag.to_le_bytes());
+            tiff.extend(kind.to_le_bytes());
+            tiff.extend(count.to_le_bytes());
+            tiff.extend(value.to_le_bytes());
+        };
+        // IFD0 at 8: 2 entries, EXIF IFD at 38: 1 entry, strings from 56
+        tiff.extend(2u16.to_le_bytes());
+        entry(&mut tiff, 0x0132, 2, 20, 56);
+        entry(&mut tiff, 0x8769, 4, 1, 38);
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(1u16.to_le_bytes());
+        entry(&mut tiff, 0x9003, 2, 20, 76);
+        tiff.extend(0u32.to_le_bytes());
+        tiff.extend(b"2020:01:01 00:00:00\0");
+        tiff.extend(original.as_bytes());
+        tiff.push(0);
+        tiff
+    }
+
+    #[test]
+    fn jpeg() {
+        let tiff = tiff("2021:07:04 18:30:05");
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xe1];
+        jpeg.extend((tiff.len() as u16 + 8).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(&tiff);
+        jpeg.extend([0xff, 0xda]);
+
+        let date = from_bytes(&jpeg).unwrap();
+        assert_eq!(date.to_string(), "20210704T183005");
+        assert_eq!(
+            from_bytes(&self::tiff("0000:00:00 00:00:00")).unwrap(),
+            CaptureDate::parse("2020:01:01 00:00:00").unwrap()
+        );
+        assert_eq!(from_bytes(b"\x89PNG\r\n"), None);
+    }
+}
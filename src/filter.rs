@@ -0,0 +1,107 @@
+//! Which files of a SRC directory become pairs, see [`SrcDstConfig::source_filter`].
+//!
+//! ```
+//! use std::{path::Path, sync::Arc};
+//! use src_dst_clarifier::{shared::Shared, SrcDstConfig};
+//!
+//! let mut config = SrcDstConfig::new("png");
+//! config.source_filter.extensions.push("png".into());
+//! config.source_filter.exclude.push(String::from(".*"));
+//! config.source_filter.predicate = Some(Shared(Arc::new(|path: &Path| {
+//!     path.metadata().is_ok_and(|m| m.len() > 0)
+//! })));
+//!
+//! assert!(!config.source_filter.accepts(Path::new("icons/.hidden.png")));
+//! assert!(!config.source_filter.accepts(Path::new("icons/Thumbs.db")));
+//! ```
+//!
+//! [`SrcDstConfig::source_filter`]: crate::SrcDstConfig::source_filter
+
+use std::{ffi::OsString, path::Path};
+
+use crate::{run::glob_match, shared::Shared};
+
+/// A file must pass every non-empty criterion.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceFilter {
+    /// Without the dot, ASCII case-insensitive. Empty allows every extension.
+    pub extensions: Vec<OsString>,
+    pub excluded_extensions: Vec<OsString>,
+    /// Globs (`*` and `?` only) matched against the file name. Empty includes every name.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Not saved in a [`Plan`](crate::plan::Plan).
+    pub predicate: Option<Shared<dyn SourcePredicate>>,
+    /// Asked with the path of every sub-directory before
+    /// [`SrcDstConfig::max_depth`](crate::SrcDstConfig::max_depth) walks into it, `false` skips
+    /// it with everything below, e.g. `node_modules` or `.git`. Not saved in a
+    /// [`Plan`](crate::plan::Plan) either.
+    pub descend: Option<Shared<dyn SourcePredicate>>,
+}
+
+impl SourceFilter {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    pub fn accepts(&self, path: &Path) -> bool {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let has_extension = |extensions: &[OsString]| {
+            let extension = path.extension().unwrap_or_default();
+            extensions.iter().any(|e| e.eq_ignore_ascii_case(extension))
+        };
+
+        (self.extensions.is_empty() || has_extension(&self.extensions))
+            && !has_extension(&self.excluded_extensions)
+            && (self.include.is_empty() || self.include.iter().any(|p| glob_match(p, &name)))
+            && !self.exclude.iter().any(|p| glob_match(p, &name))
+            && self.predicate.as_ref().is_none_or(|p| p.0.accepts(path))
+    }
+
+    /// Whether the walk goes into the sub-directory `dir`, see [`Self::descend`].
+    pub fn descends(&self, dir: &Path) -> bool {
+        self.descend.as_ref().is_none_or(|p| p.0.accepts(dir))
+    }
+}
+
+pub trait SourcePredicate: Send + Sync {
+    fn accepts(&self, path: &Path) -> bool;
+}
+
+impl<F> SourcePredicate for F
+where
+    F: Fn(&Path) -> bool + Send + Sync,
+{
+    fn accepts(&self, path: &Path) -> bool {
+        self(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn criteria() {
+        let mut filter = SourceFilter::default();
+        assert!(filter.is_empty() && filter.accepts(Path::new(".DS_Store")));
+
+        filter.extensions = vec!["png".into(), "jpg".into()];
+        filter.exclude.push(String::from("*-thumb.*"));
+        assert!(filter.accepts(Path::new("a/photo.PNG")));
+        assert!(!filter.accepts(Path::new("a/photo-thumb.png")));
+        assert!(!filter.accepts(Path::new("a/notes.txt")));
+        assert!(!filter.accepts(Path::new("a/png")));
+
+        filter.predicate = Some(Shared(Arc::new(|path: &Path| !path.starts_with("skip"))));
+        assert!(!filter.accepts(Path::new("skip/photo.jpg")));
+
+        assert!(filter.descends(Path::new("a/node_modules")));
+        filter.descend = Some(Shared(Arc::new(|dir: &Path| {
+            dir.file_name().is_none_or(|name| name != "node_modules")
+        })));
+        assert!(!filter.descends(Path::new("a/node_modules")));
+        assert!(filter.descends(Path::new("a/src")));
+    }
+}
@@ -0,0 +1,133 @@
+//! Separating the outputs of many pairs written to one stdout stream, so that the consumer
+//! can split them again.
+//!
+//! ``` plaintext
+//! Concatenated     <a><b>
+//! Nul              <a>\0<b>\0
+//! LengthPrefixed   <len of a, u64 big-endian><a><len of b><b>
+//! Ndjson           {"src":"a.txt","data":"..."}\n{"src":"b.bin","base64":"..."}\n
+//! ```
+//!
+//! `Ndjson` puts valid UTF-8 output into `data`, anything else into `base64`.
+
+use std::io::{self, BufRead, Write};
+
+use crate::{run::json_str, Src};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Framing {
+    /// Nothing between the outputs.
+    #[default]
+    Concatenated,
+    /// A NUL byte after every output, for outputs that never contain one, e.g. text.
+    Nul,
+    LengthPrefixed,
+    /// One JSON object per line, with the SRC.
+    Ndjson,
+}
+
+impl Framing {
+    /// The output `data` of `src` as one record.
+    pub fn write_record<W: Write>(&self, mut w: W, src: &Src, data: &[u8]) -> io::Result<()> {
+        match self {
+            Framing::Concatenated => w.write_all(data),
+            Framing::Nul => {
+                w.write_all(data)?;
+                w.write_all(b"\0")
+            }
+            Framing::LengthPrefixed => {
+                w.write_all(&(data.len() as u64).to_be_bytes())?;
+                w.write_all(data)
+            }
+            Framing::Ndjson => {
+                let src = json_str(&src.to_string());
+                match std::str::from_utf8(data) {
+                    Ok(text) => writeln!(w, r#"{{"src":{src},"data":{}}}"#, json_str(text)),
+                    Err(_) => writeln!(w, r#"{{"src":{src},"base64":"{}"}}"#, base64(data)),
+                }
+            }
+        }
+    }
+
+    /// The next record written by [`Self::write_record`], `None` at the end of the stream.
+    /// [`io::ErrorKind::Unsupported`] for `Concatenated` and `Ndjson`, which are split by
+    /// nothing or by a JSON parser respectively.
+    pub fn read_record<R: BufRead>(&self, mut r: R) -> io::Result<Option<Vec<u8>>> {
+        let mut record = Vec::new();
+        match self {
+            Framing::Nul => match r.read_until(0, &mut record)? {
+                0 => Ok(None),
+                _ if record.pop() == Some(0) => Ok(Some(record)),
+                _ => Err(io::ErrorKind::UnexpectedEof.into()),
+            },
+            Framing::LengthPrefixed => {
+                let mut len = [0; 8];
+                match r.read(&mut len[..1])? {
+                    0 => return Ok(None),
+                    _ => r.read_exact(&mut len[1..])?,
+                }
+                let len = u64::from_be_bytes(len);
+                io::copy(&mut r.take(len), &mut record)?;
+                match record.len() as u64 == len {
+                    true => Ok(Some(record)),
+                    false => Err(io::ErrorKind::UnexpectedEof.into()),
+                }
+            }
+            Framing::Concatenated | Framing::Ndjson => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("{self:?} records can not be read back"),
+            )),
+        }
+    }
+}
+
+/// Standard alphabet, padded.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char),
+                false => out.push('='),
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records() {
+        let src = Src::File("a b.txt".into());
+        for framing in [Framing::Nul, Framing::LengthPrefixed] {
+            let mut stream = Vec::new();
+            framing.write_record(&mut stream, &src, b"one").unwrap();
+            framing.write_record(&mut stream, &src, b"").unwrap();
+            let mut r = &stream[..];
+            assert_eq!(framing.read_record(&mut r).unwrap().unwrap(), b"one");
+            assert_eq!(framing.read_record(&mut r).unwrap().unwrap(), b"");
+            assert_eq!(framing.read_record(&mut r).unwrap(), None);
+        }
+
+        let mut stream = Vec::new();
+        Framing::Ndjson
+            .write_record(&mut stream, &src, b"hi\n")
+            .unwrap();
+        Framing::Ndjson
+            .write_record(&mut stream, &src, &[0xff, 0, 1, 2])
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(stream).unwrap(),
+            "{\"src\":\"a b.txt\",\"data\":\"hi\\n\"}\n{\"src\":\"a b.txt\",\"base64\":\"/wABAg==\"}\n"
+        );
+    }
+}
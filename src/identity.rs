@@ -0,0 +1,112 @@
+//! Whether two paths refer to the same directory, for the in-place checks.
+//!
+//! Canonicalization is slow on some network mounts, and some report a different path for the
+//! same directory depending on how it was reached.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use src_dst_clarifier::{identity::Normalize, shared::Shared, SrcDstConfig};
+//!
+//! let mut config = SrcDstConfig::new("png");
+//! config.path_identity = Some(Shared(Arc::new(Normalize)));
+//! ```
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+pub trait PathIdentity: Send + Sync {
+    fn same(&self, a: &Path, b: &Path) -> io::Result<bool>;
+}
+
+impl<F> PathIdentity for F
+where
+    F: Fn(&Path, &Path) -> io::Result<bool> + Send + Sync,
+{
+    fn same(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        self(a, b)
+    }
+}
+
+/// The default: symlinks are resolved. Paths that do not exist fall back to [`Normalize`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Canonicalize;
+
+impl PathIdentity for Canonicalize {
+    fn same(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        match (fs::canonicalize(a), fs::canonicalize(b)) {
+            (Ok(a), Ok(b)) => Ok(a == b),
+            _ => Normalize.same(a, b),
+        }
+    }
+}
+
+/// Same device and inode, Unix only, [`Canonicalize`] elsewhere.
+/// Paths that do not exist fall back to [`Normalize`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DevInode;
+
+impl PathIdentity for DevInode {
+    #[cfg(unix)]
+    fn same(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        match (fs::metadata(a), fs::metadata(b)) {
+            (Ok(a), Ok(b)) => Ok((a.dev(), a.ino()) == (b.dev(), b.ino())),
+            _ => Normalize.same(a, b),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn same(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        Canonicalize.same(a, b)
+    }
+}
+
+/// Lexical only, without touching the filesystem: relative paths are made absolute,
+/// `.` and `..` are resolved, symlinks are not.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Normalize;
+
+impl PathIdentity for Normalize {
+    fn same(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        Ok(normalize(a)? == normalize(b)?)
+    }
+}
+
+fn normalize(path: &Path) -> io::Result<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in std::path::absolute(path)?.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    Ok(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn strategies() {
+        let root = ScratchDir::new("identity");
+        fs::create_dir_all(root.join("a")).unwrap();
+        let dotted = root.join("a/../a/.");
+
+        assert!(Normalize.same(&root.join("a"), &dotted).unwrap());
+        assert!(!Normalize.same(&root.join("a"), &root).unwrap());
+        assert!(Canonicalize.same(&root.join("a"), &dotted).unwrap());
+        assert!(DevInode.same(&root.join("a"), &dotted).unwrap());
+        assert!(!DevInode.same(&root.join("a"), &root).unwrap());
+        assert!(DevInode
+            .same(&root.join("nope"), &root.join("a/../nope"))
+            .unwrap());
+    }
+}
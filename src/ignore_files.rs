@@ -0,0 +1,85 @@
+//! `.gitignore` and `.ignore` files met while walking a SRC directory, see
+//! [`SrcDstConfig::ignore_files`](crate::SrcDstConfig::ignore_files).
+//!
+//! Like ripgrep: the files of a directory apply to everything below it, those further down
+//! take precedence, and `.ignore` over `.gitignore` of the same directory. `.git` itself is
+//! never walked into.
+
+use std::{io, path::Path};
+
+use crate::warning::Warning;
+
+#[derive(Debug)]
+pub(crate) struct IgnoreFiles {
+    enabled: bool,
+    /// One per directory entered, the innermost last.
+    #[cfg(feature = "ignore")]
+    stack: Vec<ignore::gitignore::Gitignore>,
+}
+
+impl IgnoreFiles {
+    /// [`io::ErrorKind::Unsupported`] when enabled without the `ignore` feature.
+    pub(crate) fn new(enabled: bool) -> io::Result<Self> {
+        #[cfg(not(feature = "ignore"))]
+        if enabled {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "respecting ignore files needs the `ignore` feature",
+            ));
+        }
+        Ok(Self {
+            enabled,
+            #[cfg(feature = "ignore")]
+            stack: Vec::new(),
+        })
+    }
+
+    /// Reads the ignore files of `dir` from the real filesystem, they apply until [`Self::leave`].
+    /// Lines that cannot be parsed are warned about and left out.
+    pub(crate) fn enter(&mut self, dir: &Path, warn: &mut dyn FnMut(Warning)) {
+        #[cfg(feature = "ignore")]
+        if self.enabled {
+            let mut builder = ignore::gitignore::GitignoreBuilder::new(dir);
+            for name in [".gitignore", ".ignore"] {
+                let path = dir.join(name);
+                if !path.is_file() {
+                    continue;
+                }
+                if let Some(e) = builder.add(&path) {
+                    let error = e.to_string();
+                    warn(Warning::BadIgnoreFile { path, error });
+                }
+            }
+            let gitignore = builder.build().unwrap_or_else(|e| {
+                let (path, error) = (dir.to_owned(), e.to_string());
+                warn(Warning::BadIgnoreFile { path, error });
+                ignore::gitignore::Gitignore::empty()
+            });
+            self.stack.push(gitignore);
+        }
+        #[cfg(not(feature = "ignore"))]
+        let _ = (dir, warn);
+    }
+
+    pub(crate) fn leave(&mut self) {
+        #[cfg(feature = "ignore")]
+        self.stack.pop();
+    }
+
+    pub(crate) fn ignored(&self, path: &Path, is_dir: bool) -> bool {
+        if !self.enabled {
+            return false;
+        }
+        if is_dir && path.file_name().is_some_and(|name| name == ".git") {
+            return true;
+        }
+        #[cfg(feature = "ignore")]
+        for gitignore in self.stack.iter().rev() {
+            match gitignore.matched(path, is_dir) {
+                ignore::Match::None => continue,
+                matched => return matched.is_ignore(),
+            }
+        }
+        false
+    }
+}
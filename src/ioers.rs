@@ -0,0 +1,1714 @@
+//! Readers and writers behind [`Src`] and [`Dst`].
+//!
+//! [`Input`] and [`Output`] open their streams lazily, so nothing is touched on disk
+//! until the processing code actually asks for a reader or a writer.
+//!
+//! Both, and the streams they open, are [`Send`], so that pairs can be handed to other
+//! threads or async tasks. Most of the built-in implementors are also [`Sync`].
+
+use std::{
+    fmt, fs,
+    io::{self, Read, Write},
+    path::{Component, Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use thiserror::Error;
+
+#[cfg(feature = "tar")]
+mod archive;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+mod compressed;
+mod counting;
+mod variants;
+#[cfg(feature = "tar")]
+pub use archive::WriteArchive;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use compressed::{Codec, ReadCompressed, WriteCompressed};
+pub use counting::{transfer, ByteCounter, CountingInput, CountingOutput, ProgressCallback};
+pub use variants::Variants;
+
+use crate::{
+    audit::{self, Operation},
+    cleanup::TempGuard,
+    descriptors::{self, Held, Permit},
+    l10n::Message,
+    Dst, Src, SrcDstPairs,
+};
+
+pub trait Input: Send {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Instead of [`Self::reader`], the local file read, so that it can be copied by the OS.
+    /// `None` if there is none.
+    fn file(&mut self) -> Option<io::Result<fs::File>> {
+        None
+    }
+
+    /// Called after the processing succeeded and the reader has been dropped.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub trait Output: Send {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>>;
+
+    /// Instead of [`Self::writer`], the local file written to, so that it can be copied into
+    /// by the OS. `None`, before anything is created, if the output is more than a plain file.
+    fn file(&mut self) -> Option<io::Result<fs::File>> {
+        None
+    }
+
+    /// Called after the processing succeeded and the writer has been dropped.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called after the processing failed. Implementors should remove partial output here.
+    fn abort(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// A hint, before [`Self::writer`], that about `len` bytes will be written.
+    /// Implementors may preallocate the space, see [`preallocate`].
+    fn expect_len(&mut self, len: u64) {
+        let _ = len;
+    }
+}
+
+/// Guards against a hostile or hung upstream pipe, failing with a [`StdinError`]
+/// (retrieve it with [`io::Error::get_ref`]).
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadStdin {
+    pub max_bytes: Option<u64>,
+    /// How long a single read may wait for data.
+    ///
+    /// The blocking read is moved to a background thread, which stays blocked after a timeout.
+    pub read_timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum StdinError {
+    #[error("stdin exceeds {limit} bytes")]
+    TooLarge { limit: u64 },
+    #[error("no data from stdin within {after:?}")]
+    TimedOut { after: Duration },
+}
+
+impl Message for StdinError {
+    fn key(&self) -> &'static str {
+        match self {
+            StdinError::TooLarge { .. } => "stdin-too-large",
+            StdinError::TimedOut { .. } => "stdin-timed-out",
+        }
+    }
+
+    fn params(&self) -> Vec<(&'static str, String)> {
+        match self {
+            StdinError::TooLarge { limit } => vec![("limit", limit.to_string())],
+            StdinError::TimedOut { after } => vec![("after", format!("{after:?}"))],
+        }
+    }
+}
+
+impl From<StdinError> for io::Error {
+    fn from(e: StdinError) -> Self {
+        let kind = match e {
+            StdinError::TooLarge { .. } => io::ErrorKind::InvalidData,
+            StdinError::TimedOut { .. } => io::ErrorKind::TimedOut,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
+impl Input for ReadStdin {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        let stdin: Box<dyn Read + Send> = match self.read_timeout {
+            None => Box::new(io::stdin()),
+            Some(timeout) => Box::new(ThreadedStdin::spawn(timeout)),
+        };
+        Ok(match self.max_bytes {
+            None => stdin,
+            Some(limit) => Box::new(LimitedStdin {
+                inner: stdin,
+                limit,
+                read: 0,
+            }),
+        })
+    }
+}
+
+struct LimitedStdin {
+    inner: Box<dyn Read + Send>,
+    limit: u64,
+    read: u64,
+}
+
+impl Read for LimitedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.read += n as u64;
+        match self.read > self.limit {
+            true => Err(StdinError::TooLarge { limit: self.limit }.into()),
+            false => Ok(n),
+        }
+    }
+}
+
+struct ThreadedStdin {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    pending: io::Cursor<Vec<u8>>,
+    timeout: Duration,
+    eof: bool,
+}
+
+impl ThreadedStdin {
+    fn spawn(timeout: Duration) -> Self {
+        let (tx, rx) = mpsc::sync_channel(1);
+        thread::spawn(move || {
+            let mut stdin = io::stdin().lock();
+            loop {
+                let mut chunk = vec![0; 64 << 10];
+                let result = stdin.read(&mut chunk).map(|n| {
+                    chunk.truncate(n);
+                    chunk
+                });
+                let stop = !matches!(&result, Ok(chunk) if !chunk.is_empty());
+                if tx.send(result).is_err() || stop {
+                    break;
+                }
+            }
+        });
+        Self {
+            chunks: rx,
+            pending: io::Cursor::default(),
+            timeout,
+            eof: false,
+        }
+    }
+}
+
+impl Read for ThreadedStdin {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.pending.read(buf)?;
+        if n > 0 || self.eof || buf.is_empty() {
+            return Ok(n);
+        }
+        match self.chunks.recv_timeout(self.timeout) {
+            Ok(Ok(chunk)) => {
+                self.eof = chunk.is_empty();
+                self.pending = io::Cursor::new(chunk);
+                self.pending.read(buf)
+            }
+            Ok(Err(e)) => Err(e),
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(StdinError::TimedOut {
+                after: self.timeout,
+            }
+            .into()),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Ok(0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReadFile {
+    path: PathBuf,
+    opened: Option<(fs::File, Permit<'static>)>,
+}
+
+impl ReadFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            opened: None,
+        }
+    }
+
+    /// `file` is used by the first [`Input::reader`], e.g. from [`crate::prefetch`].
+    /// Counted in the [`descriptors`](crate::descriptors) budget from now on, waits for it.
+    pub fn opened<P: Into<PathBuf>>(path: P, file: fs::File) -> Self {
+        Self::held(path, file, descriptors::global().acquire())
+    }
+
+    pub(crate) fn held<P: Into<PathBuf>>(path: P, file: fs::File, permit: Permit<'static>) -> Self {
+        Self {
+            path: path.into(),
+            opened: Some((file, permit)),
+        }
+    }
+}
+
+/// The clone opens the file by itself.
+impl Clone for ReadFile {
+    fn clone(&self) -> Self {
+        Self::new(&self.path)
+    }
+}
+
+impl Input for ReadFile {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        let (file, permit) = match self.opened.take() {
+            Some(opened) => opened,
+            None => {
+                let permit = descriptors::global().acquire();
+                let file = audit::record(Operation::Open, &self.path, fs::File::open(&self.path))?;
+                (file, permit)
+            }
+        };
+        advise(&file, Advice::Sequential);
+        Ok(Box::new(Held {
+            inner: io::BufReader::new(file),
+            _permit: permit,
+        }))
+    }
+
+    fn file(&mut self) -> Option<io::Result<fs::File>> {
+        Some(match self.opened.take() {
+            Some((file, _)) => Ok(file),
+            None => audit::record(Operation::Open, &self.path, fs::File::open(&self.path)),
+        })
+    }
+}
+
+/// Reads the stdout of a child process, which is spawned on [`Input::reader`].
+///
+/// A non-zero exit status is reported by [`Input::finish`].
+/// If the input is dropped before that, the child is killed.
+#[derive(Debug)]
+pub struct CommandInput {
+    command: Command,
+    child: Option<Child>,
+}
+
+impl CommandInput {
+    pub fn new(command: Command) -> Self {
+        Self {
+            command,
+            child: None,
+        }
+    }
+}
+
+impl Input for CommandInput {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        let mut child = self.command.stdout(Stdio::piped()).spawn()?;
+        let stdout = child.stdout.take().unwrap();
+        self.child = Some(child);
+        Ok(Box::new(stdout))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let Some(mut child) = self.child.take() else {
+            return Ok(());
+        };
+        let status = child.wait()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!(
+                "{:?} exited with {status}",
+                self.command.get_program()
+            ))),
+        }
+    }
+}
+
+impl Drop for CommandInput {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteStdout;
+
+impl Output for WriteStdout {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(Box::new(io::stdout()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WriteFile {
+    path: PathBuf,
+    created: bool,
+    direct: bool,
+    expected_len: Option<u64>,
+    preallocated: Option<Arc<fs::File>>,
+    metadata_of: Option<PathBuf>,
+}
+
+impl WriteFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            created: false,
+            direct: false,
+            expected_len: None,
+            preallocated: None,
+            metadata_of: None,
+        }
+    }
+
+    /// Bypasses the page cache (`O_DIRECT`) for multi-GB outputs, Linux only, buffered elsewhere.
+    /// Falls back to aligned buffered writes on filesystems without direct IO (e.g. tmpfs).
+    pub fn new_direct<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            created: false,
+            direct: true,
+            expected_len: None,
+            preallocated: None,
+            metadata_of: None,
+        }
+    }
+
+    /// On [`Output::finish`], see [`copy_metadata`].
+    pub fn preserve_metadata_of<P: Into<PathBuf>>(mut self, src: P) -> Self {
+        self.metadata_of = Some(src.into());
+        self
+    }
+}
+
+impl Output for WriteFile {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        crate::check_writable(&self.path)?;
+        let permit = descriptors::global().acquire();
+        #[cfg(target_os = "linux")]
+        if self.direct {
+            let file = audit::record(
+                Operation::Create,
+                &self.path,
+                DirectWriter::create(&self.path),
+            )?;
+            self.created = true;
+            self.preallocated = preallocated(&file, self.expected_len)?;
+            return Ok(Box::new(Held {
+                inner: DirectWriter::new(file),
+                _permit: permit,
+            }));
+        }
+        let file = audit::record(Operation::Create, &self.path, fs::File::create(&self.path))?;
+        self.created = true;
+        self.preallocated = preallocated(&file, self.expected_len)?;
+        Ok(Box::new(Held {
+            inner: io::BufWriter::new(file),
+            _permit: permit,
+        }))
+    }
+
+    /// Unless [direct](Self::new_direct).
+    fn file(&mut self) -> Option<io::Result<fs::File>> {
+        if self.direct {
+            return None;
+        }
+        Some(crate::check_writable(&self.path).and_then(|()| {
+            let file = audit::record(Operation::Create, &self.path, fs::File::create(&self.path))?;
+            self.created = true;
+            Ok(file)
+        }))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        trim(self.preallocated.take())?;
+        match (&self.metadata_of, self.created) {
+            (Some(src), true) => copy_metadata(src, &self.path),
+            _ => Ok(()),
+        }
+    }
+
+    fn expect_len(&mut self, len: u64) {
+        self.expected_len = Some(len);
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        if self.created {
+            self.created = false;
+            audit::record(Operation::Remove, &self.path, fs::remove_file(&self.path))?;
+        }
+        Ok(())
+    }
+}
+
+/// The permissions and the access and modification times of `src` onto `dst`.
+/// Ownership is not copied.
+pub fn copy_metadata<S: AsRef<Path>, D: AsRef<Path>>(src: S, dst: D) -> io::Result<()> {
+    let meta = fs::metadata(src)?;
+    let mut times = fs::FileTimes::new().set_modified(meta.modified()?);
+    if let Ok(accessed) = meta.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    // 先改时间，只读的权限会挡住后面的打开
+    fs::File::options()
+        .write(true)
+        .open(&dst)?
+        .set_times(times)?;
+    fs::set_permissions(dst, meta.permissions())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Advice {
+    /// Read-ahead more aggressively.
+    Sequential,
+    /// Start reading into the page cache now.
+    WillNeed,
+}
+
+/// A best-effort hint for the whole file (`posix_fadvise`), Linux only.
+pub(crate) fn advise(file: &fs::File, advice: Advice) {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let advice = match advice {
+            Advice::Sequential => libc::POSIX_FADV_SEQUENTIAL,
+            Advice::WillNeed => libc::POSIX_FADV_WILLNEED,
+        };
+        // SAFETY: the fd is owned by `file`.
+        unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, advice) };
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, advice);
+}
+
+/// Reserves `len` bytes for `file` without changing its length, reducing fragmentation and
+/// running out of space up front rather than hours into the processing. Linux only
+/// (`fallocate`), nothing elsewhere. Filesystems without support are ignored.
+///
+/// What is not written stays reserved until the file is truncated, as [`WriteFile`] and
+/// [`HardenedWriteFile`] do on [`Output::finish`].
+pub fn preallocate(file: &fs::File, len: Option<u64>) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    if let Some(len) = len.filter(|&len| len > 0) {
+        use std::os::unix::io::AsRawFd;
+
+        let len = libc::off_t::try_from(len).unwrap_or(libc::off_t::MAX);
+        // SAFETY: the fd is owned by `file`.
+        let result =
+            unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) };
+        if result != 0 {
+            let err = io::Error::last_os_error();
+            return match err.raw_os_error() {
+                Some(libc::EOPNOTSUPP | libc::EINVAL) => Ok(()),
+                _ => Err(err),
+            };
+        }
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (file, len);
+    Ok(())
+}
+
+/// [`preallocate`], keeping a handle to [`trim`] with if anything was reserved.
+fn preallocated(file: &fs::File, len: Option<u64>) -> io::Result<Option<Arc<fs::File>>> {
+    preallocate(file, len)?;
+    match len {
+        Some(_) => Ok(Some(Arc::new(file.try_clone()?))),
+        None => Ok(None),
+    }
+}
+
+/// Gives back what was reserved past the written length.
+fn trim(file: Option<Arc<fs::File>>) -> io::Result<()> {
+    match file {
+        Some(file) => file.set_len(file.metadata()?.len()),
+        None => Ok(()),
+    }
+}
+
+/// Direct IO needs buffers, lengths and offsets aligned to the logical block size.
+#[cfg(target_os = "linux")]
+const DIRECT_ALIGN: usize = 4096;
+#[cfg(target_os = "linux")]
+const DIRECT_CHUNK: usize = 1 << 20;
+
+/// Writes whole aligned chunks, the unaligned tail is padded on flush and truncated away.
+#[cfg(target_os = "linux")]
+struct DirectWriter {
+    file: fs::File,
+    buf: Vec<u8>,
+    /// Where the aligned chunk starts in `buf`.
+    start: usize,
+    filled: usize,
+    /// Of the chunk in the file.
+    offset: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl DirectWriter {
+    fn create(path: &Path) -> io::Result<fs::File> {
+        use std::os::unix::fs::OpenOptionsExt;
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        match options.clone().custom_flags(libc::O_DIRECT).open(path) {
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => options.open(path),
+            result => result,
+        }
+    }
+
+    fn new(file: fs::File) -> Self {
+        let buf = vec![0; DIRECT_CHUNK + DIRECT_ALIGN];
+        let start = buf.as_ptr().align_offset(DIRECT_ALIGN);
+        Self {
+            file,
+            buf,
+            start,
+            filled: 0,
+            offset: 0,
+        }
+    }
+
+    fn chunk(&mut self) -> &mut [u8] {
+        &mut self.buf[self.start..self.start + DIRECT_CHUNK]
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Write for DirectWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        let (filled, offset) = (self.filled, self.offset);
+        let n = (DIRECT_CHUNK - filled).min(data.len());
+        self.chunk()[filled..filled + n].copy_from_slice(&data[..n]);
+        self.filled += n;
+        if self.filled == DIRECT_CHUNK {
+            let chunk = &self.buf[self.start..self.start + DIRECT_CHUNK];
+            self.file.write_all_at(chunk, offset)?;
+            self.offset += DIRECT_CHUNK as u64;
+            self.filled = 0;
+        }
+        Ok(n)
+    }
+
+    /// The tail stays buffered, and is written again once the chunk is complete.
+    fn flush(&mut self) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+
+        let (filled, offset) = (self.filled, self.offset);
+        if filled > 0 {
+            let padded = filled.next_multiple_of(DIRECT_ALIGN);
+            self.chunk()[filled..padded].fill(0);
+            let tail = &self.buf[self.start..self.start + padded];
+            self.file.write_all_at(tail, offset)?;
+        }
+        self.file.set_len(offset + filled as u64)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for DirectWriter {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+/// Writes to a temporary sibling of DST, renamed over DST on [`Output::finish`], so that DST
+/// is never seen half-written, and may be SRC itself (in-place editing).
+/// The permissions of an existing DST are kept.
+#[derive(Debug)]
+pub struct WriteFileAtomic {
+    path: PathBuf,
+    temp: Option<TempGuard>,
+    resumed: Option<PathBuf>,
+}
+
+impl WriteFileAtomic {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            temp: None,
+            resumed: None,
+        }
+    }
+
+    /// Appends to `partial`, the temporary sibling a crashed run left behind,
+    /// see [`cleanup::stale_partials`](crate::cleanup::stale_partials).
+    pub fn resume<P: Into<PathBuf>>(path: P, partial: PathBuf) -> Self {
+        Self {
+            resumed: Some(partial),
+            ..Self::new(path)
+        }
+    }
+
+    /// The temporary sibling, `.<name>.<pid>-<n>.tmp`.
+    fn create_temp(&mut self) -> io::Result<fs::File> {
+        let name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        for n in 0.. {
+            let temp = self
+                .path
+                .with_file_name(format!(".{name}.{}-{n}.tmp", std::process::id()));
+            let file = fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&temp);
+            match audit::record(Operation::Create, &temp, file) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+                Ok(file) => {
+                    self.temp = Some(TempGuard::file(temp));
+                    return Ok(file);
+                }
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// A clone has not started writing, its temporary sibling is its own.
+impl Clone for WriteFileAtomic {
+    fn clone(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            temp: None,
+            resumed: self.resumed.clone(),
+        }
+    }
+}
+
+impl Output for WriteFileAtomic {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        crate::check_writable(&self.path)?;
+        if let Some(partial) = self.resumed.take() {
+            let file = fs::OpenOptions::new().append(true).open(&partial);
+            let file = audit::record(Operation::Open, &partial, file)?;
+            self.temp = Some(TempGuard::file(partial));
+            return Ok(Box::new(io::BufWriter::new(file)));
+        }
+        Ok(Box::new(io::BufWriter::new(self.create_temp()?)))
+    }
+
+    /// Unless [resumed](Self::resume).
+    fn file(&mut self) -> Option<io::Result<fs::File>> {
+        match self.resumed {
+            Some(_) => None,
+            None => Some(crate::check_writable(&self.path).and_then(|()| self.create_temp())),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let Some(guard) = self.temp.take() else {
+            return Ok(());
+        };
+        let temp = guard.path();
+        if let Ok(meta) = fs::metadata(&self.path) {
+            fs::set_permissions(temp, meta.permissions())?;
+        }
+        audit::record(Operation::Open, temp, fs::File::open(temp))?.sync_all()?;
+        audit::record_to(
+            Operation::Rename,
+            temp,
+            &self.path,
+            fs::rename(temp, &self.path),
+        )?;
+        guard.keep();
+        Ok(())
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        match self.temp.take() {
+            Some(guard) => guard.remove(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Like [`WriteFile`], hardened for shared directories (e.g. `/tmp`) against symlink attacks:
+/// DST must not exist (`O_EXCL`), is never followed if it is a symlink (`O_NOFOLLOW`),
+/// and the opened file is verified to be the regular file, with a single link, now at the path.
+///
+/// An existing DST fails with [`io::ErrorKind::AlreadyExists`], move it away first.
+#[derive(Debug, Clone)]
+pub struct HardenedWriteFile {
+    path: PathBuf,
+    created: bool,
+    expected_len: Option<u64>,
+    preallocated: Option<Arc<fs::File>>,
+}
+
+impl HardenedWriteFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            created: false,
+            expected_len: None,
+            preallocated: None,
+        }
+    }
+
+    fn open(&self) -> io::Result<fs::File> {
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NOFOLLOW | libc::O_CLOEXEC);
+        }
+        audit::record(Operation::Create, &self.path, options.open(&self.path))
+    }
+
+    fn verify(&self, file: &fs::File) -> io::Result<()> {
+        let opened = file.metadata()?;
+        let linked = fs::symlink_metadata(&self.path)?;
+        let expected = opened.is_file() && linked.is_file();
+        #[cfg(unix)]
+        let expected = {
+            use std::os::unix::fs::MetadataExt;
+            expected
+                && opened.nlink() == 1
+                && (opened.dev(), opened.ino()) == (linked.dev(), linked.ino())
+        };
+        match expected {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("'{}' was tampered with", self.path.display()),
+            )),
+        }
+    }
+}
+
+impl Output for HardenedWriteFile {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        crate::check_writable(&self.path)?;
+        let file = self.open()?;
+        self.created = true;
+        self.verify(&file)?;
+        self.preallocated = preallocated(&file, self.expected_len)?;
+        Ok(Box::new(io::BufWriter::new(file)))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        trim(self.preallocated.take())
+    }
+
+    fn expect_len(&mut self, len: u64) {
+        self.expected_len = Some(len);
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        if self.created {
+            self.created = false;
+            // 被替换成了别的东西就不要动它
+            if fs::symlink_metadata(&self.path).is_ok_and(|m| m.is_file()) {
+                audit::record(Operation::Remove, &self.path, fs::remove_file(&self.path))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Creates a named pipe at the path, then blocks in [`Output::writer`] until a reader opens it.
+/// The pipe is removed afterwards if it was created here.
+///
+/// Only supported on Unix.
+#[derive(Debug, Clone)]
+pub struct FifoOutput {
+    path: PathBuf,
+    created: bool,
+}
+
+impl FifoOutput {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            created: false,
+        }
+    }
+
+    #[cfg(unix)]
+    fn mkfifo(&mut self) -> io::Result<()> {
+        use std::{ffi::CString, os::unix::ffi::OsStrExt, os::unix::fs::FileTypeExt};
+
+        crate::check_writable(&self.path)?;
+        match fs::symlink_metadata(&self.path) {
+            Ok(meta) if meta.file_type().is_fifo() => return Ok(()),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("'{}' exists and is not a named pipe", self.path.display()),
+                ))
+            }
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            Err(_) => (),
+        }
+
+        let c_path = CString::new(self.path.as_os_str().as_bytes())?;
+        // SAFETY: `c_path` is a valid NUL-terminated string.
+        let result = match unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        };
+        audit::record(Operation::MakeFifo, &self.path, result)?;
+        self.created = true;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn mkfifo(&mut self) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "named pipes are only supported on Unix",
+        ))
+    }
+
+    fn remove(&mut self) -> io::Result<()> {
+        if self.created {
+            self.created = false;
+            audit::record(Operation::Remove, &self.path, fs::remove_file(&self.path))?;
+        }
+        Ok(())
+    }
+}
+
+impl Output for FifoOutput {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        self.mkfifo()?;
+        let pipe = fs::OpenOptions::new().write(true).open(&self.path);
+        Ok(Box::new(audit::record(Operation::Open, &self.path, pipe)?))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.remove()
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        self.remove()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum LinkStyle {
+    #[default]
+    Absolute,
+    /// Relative to the directory containing the link, survives moving both together.
+    Relative,
+}
+
+/// Creates a symlink at the path pointing to `src` in [`Output::writer`],
+/// the byte stream is discarded. Removed on abort if it was created here.
+#[derive(Debug, Clone)]
+pub struct SymlinkOutput {
+    src: PathBuf,
+    link: PathBuf,
+    style: LinkStyle,
+    created: bool,
+}
+
+impl SymlinkOutput {
+    /// `src` should be absolute, as those yielded by [`SrcDstPairs`](crate::SrcDstPairs) are.
+    pub fn new<P: Into<PathBuf>, Q: Into<PathBuf>>(src: P, link: Q, style: LinkStyle) -> Self {
+        Self {
+            src: src.into(),
+            link: link.into(),
+            style,
+            created: false,
+        }
+    }
+
+    fn target(&self) -> io::Result<PathBuf> {
+        match self.style {
+            LinkStyle::Absolute => Ok(self.src.clone()),
+            LinkStyle::Relative => {
+                let parent = self.link.parent().unwrap_or(Path::new("."));
+                let parent = match parent.as_os_str().is_empty() {
+                    true => std::env::current_dir()?,
+                    false => fs::canonicalize(parent)?,
+                };
+                Ok(relative_to(&self.src, &parent))
+            }
+        }
+    }
+}
+
+/// Lexically, both should be absolute.
+fn relative_to(path: &Path, base: &Path) -> PathBuf {
+    let path = path.components().collect::<Vec<_>>();
+    let base = base.components().collect::<Vec<_>>();
+    let common = path.iter().zip(&base).take_while(|(a, b)| a == b).count();
+    let mut relative = PathBuf::new();
+    for _ in common..base.len() {
+        relative.push(Component::ParentDir);
+    }
+    relative.extend(&path[common..]);
+    relative
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symlinks are only supported on Unix and Windows",
+    ))
+}
+
+impl Output for SymlinkOutput {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        crate::check_writable(&self.link)?;
+        let target = self.target()?;
+        audit::record_to(
+            Operation::Symlink,
+            &self.link,
+            &target,
+            symlink(&target, &self.link),
+        )?;
+        self.created = true;
+        Ok(Box::new(io::sink()))
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        if self.created {
+            self.created = false;
+            audit::record(Operation::Remove, &self.link, fs::remove_file(&self.link))?;
+        }
+        Ok(())
+    }
+}
+
+/// Accepts every write but keeps nothing, only counts the bytes meant for `target`.
+///
+/// Clones share the count, so keep one to inspect after the writer is gone.
+#[derive(Debug, Clone)]
+pub struct RecordingOutput {
+    target: Dst,
+    written: Arc<AtomicU64>,
+}
+
+impl RecordingOutput {
+    pub fn new(target: Dst) -> Self {
+        Self {
+            target,
+            written: Arc::default(),
+        }
+    }
+
+    pub fn target(&self) -> &Dst {
+        &self.target
+    }
+
+    pub fn written(&self) -> u64 {
+        self.written.load(Ordering::Relaxed)
+    }
+}
+
+impl Output for RecordingOutput {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(Box::new(Counter(self.written.clone())))
+    }
+}
+
+struct Counter(Arc<AtomicU64>);
+
+impl Write for Counter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.fetch_add(buf.len() as u64, Ordering::Relaxed);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compares the stream against the existing file at the path instead of writing it.
+///
+/// Clones share the result, so keep one to call [`Self::diff`] after [`Output::finish`].
+#[derive(Debug, Clone)]
+pub struct GoldenOutput {
+    path: PathBuf,
+    state: Arc<Mutex<Comparison>>,
+}
+
+#[derive(Debug, Default)]
+struct Comparison {
+    diff: Option<GoldenDiff>,
+    compared: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum GoldenDiff {
+    #[error("golden DST does not exist")]
+    Missing,
+    /// Also reported when either side ends early, at the length of the shorter one.
+    #[error("differs from golden DST at byte {offset}")]
+    Differs { offset: u64 },
+}
+
+impl GoldenOutput {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            state: Arc::default(),
+        }
+    }
+
+    /// `None` if identical, or nothing has been compared yet.
+    pub fn diff(&self) -> Option<GoldenDiff> {
+        self.state.lock().unwrap().diff
+    }
+}
+
+impl Output for GoldenOutput {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        let golden = match audit::record(Operation::Open, &self.path, fs::File::open(&self.path)) {
+            Ok(file) => Some(io::BufReader::new(file)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        Ok(Box::new(Comparer {
+            golden,
+            state: self.state.clone(),
+        }))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.diff.is_none() {
+            state.diff = match fs::metadata(&self.path) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => Some(GoldenDiff::Missing),
+                Err(e) => return Err(e),
+                Ok(meta) if meta.len() != state.compared => Some(GoldenDiff::Differs {
+                    offset: state.compared.min(meta.len()),
+                }),
+                Ok(_) => None,
+            };
+        }
+        Ok(())
+    }
+}
+
+struct Comparer {
+    golden: Option<io::BufReader<fs::File>>,
+    state: Arc<Mutex<Comparison>>,
+}
+
+impl Write for Comparer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.diff.is_none() {
+            let Some(golden) = &mut self.golden else {
+                state.diff = Some(GoldenDiff::Missing);
+                return Ok(buf.len());
+            };
+            let mut expected = vec![0; buf.len()];
+            let mut n = 0;
+            while n < buf.len() {
+                match golden.read(&mut expected[n..])? {
+                    0 => break,
+                    read => n += read,
+                }
+            }
+            let same = buf
+                .iter()
+                .zip(&expected[..n])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if same < buf.len() {
+                state.diff = Some(GoldenDiff::Differs {
+                    offset: state.compared + same as u64,
+                });
+            }
+            state.compared += same as u64;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Used by [`BytesOutput`] and [`SpooledStdin`] unless specified otherwise.
+pub const DEFAULT_MEMORY_LIMIT: usize = 64 << 20;
+
+/// A buffer kept in memory up to `memory_limit` bytes, then spilled to a temporary file,
+/// which is removed on drop.
+#[derive(Debug)]
+pub struct Spool {
+    memory_limit: usize,
+    memory: Vec<u8>,
+    spilled: Option<(fs::File, TempGuard)>,
+    len: u64,
+}
+
+impl Spool {
+    pub fn new(memory_limit: usize) -> Self {
+        Self {
+            memory_limit,
+            memory: Vec::new(),
+            spilled: None,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        self.spilled.is_some()
+    }
+
+    /// `None` once spilled.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self.spilled {
+            None => Some(&self.memory),
+            Some(_) => None,
+        }
+    }
+
+    /// Reads everything written so far from the beginning, independently of further writes.
+    pub fn reader(&self) -> io::Result<Box<dyn Read + Send>> {
+        match &self.spilled {
+            None => Ok(Box::new(io::Cursor::new(self.memory.clone()))),
+            Some((_, guard)) => {
+                let path = guard.path();
+                let file = audit::record(Operation::Open, path, fs::File::open(path))?;
+                Ok(Box::new(io::BufReader::new(file.take(self.len))))
+            }
+        }
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "sdc-spool-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        let mut file = audit::record(Operation::Create, &path, file)?;
+        file.write_all(&self.memory)?;
+        self.memory = Vec::new();
+        self.spilled = Some((file, TempGuard::file(path)));
+        Ok(())
+    }
+}
+
+impl Write for Spool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.spilled.is_none() && self.memory.len() + buf.len() > self.memory_limit {
+            self.spill()?;
+        }
+        match &mut self.spilled {
+            None => self.memory.extend_from_slice(buf),
+            Some((file, _)) => file.write_all(buf)?,
+        }
+        self.len += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.spilled {
+            None => Ok(()),
+            Some((file, _)) => file.flush(),
+        }
+    }
+}
+
+/// Collects the output into a [`Spool`], for processing code whose result is consumed in-process.
+///
+/// Clones share the spool, so keep one to call [`Self::spool`] after the writer is gone.
+#[derive(Debug, Clone)]
+pub struct BytesOutput {
+    spool: Arc<Mutex<Spool>>,
+}
+
+impl Default for BytesOutput {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_LIMIT)
+    }
+}
+
+impl BytesOutput {
+    pub fn new(memory_limit: usize) -> Self {
+        Self {
+            spool: Arc::new(Mutex::new(Spool::new(memory_limit))),
+        }
+    }
+
+    pub fn spool(&self) -> std::sync::MutexGuard<'_, Spool> {
+        self.spool.lock().unwrap()
+    }
+}
+
+impl Output for BytesOutput {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        Ok(Box::new(SharedSpool(self.spool.clone())))
+    }
+}
+
+struct SharedSpool(Arc<Mutex<Spool>>);
+
+impl Write for SharedSpool {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
+
+/// Reads stdin to the end into a [`Spool`] on the first [`Input::reader`], then replays it,
+/// so that stdin can be read more than once (e.g. by a retry).
+#[derive(Debug)]
+pub struct SpooledStdin {
+    spool: Spool,
+    filled: bool,
+}
+
+impl Default for SpooledStdin {
+    fn default() -> Self {
+        Self::new(DEFAULT_MEMORY_LIMIT)
+    }
+}
+
+impl SpooledStdin {
+    pub fn new(memory_limit: usize) -> Self {
+        Self {
+            spool: Spool::new(memory_limit),
+            filled: false,
+        }
+    }
+}
+
+impl Input for SpooledStdin {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        if !self.filled {
+            io::copy(&mut io::stdin().lock(), &mut self.spool)?;
+            self.filled = true;
+        }
+        self.spool.reader()
+    }
+}
+
+/// A [`SpooledStdin`] shared by its clones, so that stdin is read once for several pairs,
+/// see [`SrcDstPairs::variants`](crate::SrcDstPairs::variants). The spool goes away with the
+/// last clone.
+#[derive(Debug, Clone, Default)]
+pub struct SharedStdin(Arc<Mutex<SpooledStdin>>);
+
+impl SharedStdin {
+    pub fn new(memory_limit: usize) -> Self {
+        Self(Arc::new(Mutex::new(SpooledStdin::new(memory_limit))))
+    }
+}
+
+impl Input for SharedStdin {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).reader()
+    }
+}
+
+/// An [`Input`]-[`Output`] pair ready to be consumed.
+pub struct ClarifiedIo {
+    pub input: Box<dyn Input>,
+    pub output: Box<dyn Output>,
+}
+
+impl ClarifiedIo {
+    /// SRC to DST unchanged, see [`transfer`].
+    pub fn copy_through(&mut self) -> io::Result<u64> {
+        transfer(&mut *self.input, &mut *self.output)
+    }
+}
+
+impl From<(Src, Dst)> for ClarifiedIo {
+    fn from((src, dst): (Src, Dst)) -> Self {
+        Self {
+            input: src.input(),
+            output: dst.output(),
+        }
+    }
+}
+
+/// Makes a fresh [`Input`] for every pair, e.g. for each worker of
+/// [`SrcDstPairs::process_parallel_io`](crate::SrcDstPairs::process_parallel_io).
+pub trait InputFactory: Send + Sync {
+    fn input(&self, src: &Src) -> Box<dyn Input>;
+}
+
+impl<F> InputFactory for F
+where
+    F: Fn(&Src) -> Box<dyn Input> + Send + Sync,
+{
+    fn input(&self, src: &Src) -> Box<dyn Input> {
+        self(src)
+    }
+}
+
+/// See [`InputFactory`].
+pub trait OutputFactory: Send + Sync {
+    fn output(&self, src: &Src, dst: &Dst) -> Box<dyn Output>;
+}
+
+impl<F> OutputFactory for F
+where
+    F: Fn(&Src, &Dst) -> Box<dyn Output> + Send + Sync,
+{
+    fn output(&self, src: &Src, dst: &Dst) -> Box<dyn Output> {
+        self(src, dst)
+    }
+}
+
+/// [`Src::input`] and [`Dst::output_for`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultIo;
+
+impl InputFactory for DefaultIo {
+    fn input(&self, src: &Src) -> Box<dyn Input> {
+        src.input()
+    }
+}
+
+impl OutputFactory for DefaultIo {
+    fn output(&self, src: &Src, dst: &Dst) -> Box<dyn Output> {
+        dst.output_for(src)
+    }
+}
+
+/// One recipe for the [`ClarifiedIo`] of every pair, cheap to clone into each thread.
+#[derive(Clone)]
+pub struct IoFactory {
+    pub input: Arc<dyn InputFactory>,
+    pub output: Arc<dyn OutputFactory>,
+}
+
+impl IoFactory {
+    pub fn clarify(&self, src: &Src, dst: &Dst) -> ClarifiedIo {
+        ClarifiedIo {
+            input: self.input.input(src),
+            output: self.output.output(src, dst),
+        }
+    }
+}
+
+/// Both [`DefaultIo`].
+impl Default for IoFactory {
+    fn default() -> Self {
+        Self {
+            input: Arc::new(DefaultIo),
+            output: Arc::new(DefaultIo),
+        }
+    }
+}
+
+impl fmt::Debug for IoFactory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("IoFactory(..)")
+    }
+}
+
+impl Src {
+    pub fn input(&self) -> Box<dyn Input> {
+        match self {
+            Src::Stdin => Box::new(ReadStdin::default()),
+            Src::File(path) => Box::new(ReadFile::new(path)),
+            #[cfg(feature = "sftp")]
+            Src::Remote(remote) => Box::new(crate::remote::SftpInput::new(remote.clone())),
+            #[cfg(all(unix, feature = "unix-socket"))]
+            Src::UnixSocket(socket) => {
+                Box::new(crate::socket::UnixSocketInput::new(socket.clone()))
+            }
+        }
+    }
+
+    /// The whole content, [`io::ErrorKind::FileTooLarge`] beyond `limit` bytes.
+    pub fn read_to_vec(&self, limit: usize) -> io::Result<Vec<u8>> {
+        let mut input = self.input();
+        let mut content = Vec::new();
+        // 多读一个字节才知道是不是超了
+        (input.reader()?)
+            .take(limit as u64 + 1)
+            .read_to_end(&mut content)?;
+        if content.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                format!("larger than {limit} bytes"),
+            ));
+        }
+        input.finish()?;
+        Ok(content)
+    }
+}
+
+impl SrcDstPairs {
+    /// For in-memory processing of small files: every SRC read fully, see [`Src::read_to_vec`].
+    /// Errors name their SRC, and the pairs after them are still yielded.
+    pub fn map_read_to_vec(self, limit: usize) -> ReadToVec {
+        ReadToVec { pairs: self, limit }
+    }
+}
+
+/// See [`SrcDstPairs::map_read_to_vec`].
+#[derive(Debug)]
+pub struct ReadToVec {
+    pairs: SrcDstPairs,
+    limit: usize,
+}
+
+impl Iterator for ReadToVec {
+    type Item = io::Result<(Vec<u8>, Dst)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (src, dst) = self.pairs.next()?;
+        Some(match src.read_to_vec(self.limit) {
+            Ok(content) => Ok((content, dst)),
+            Err(e) => Err(io::Error::new(e.kind(), format!("{src}: {e}"))),
+        })
+    }
+}
+
+impl Dst {
+    /// [`WriteFileAtomic`] when DST is SRC, otherwise [`Self::output`].
+    pub fn output_for(&self, src: &Src) -> Box<dyn Output> {
+        match (src, self) {
+            (Src::File(src), Dst::File(path)) if src == path => {
+                Box::new(WriteFileAtomic::new(path))
+            }
+            _ => self.output(),
+        }
+    }
+
+    pub fn output(&self) -> Box<dyn Output> {
+        match self {
+            Dst::Stdout => Box::new(WriteStdout),
+            Dst::File(path) => Box::new(WriteFile::new(path)),
+            #[cfg(feature = "sftp")]
+            Dst::Remote(remote) => Box::new(crate::remote::SftpOutput::new(remote.clone())),
+            #[cfg(all(unix, feature = "unix-socket"))]
+            Dst::UnixSocket(socket) => {
+                Box::new(crate::socket::UnixSocketOutput::new(socket.clone()))
+            }
+            Dst::Entry(_) => Box::new(ArchiveMember),
+        }
+    }
+
+    /// Completes a pair in one call. File DSTs are written through [`WriteFileAtomic`], so
+    /// they are either replaced whole or left untouched. [`SrcDstConfig::overwrite`](crate::SrcDstConfig::overwrite)
+    /// has already been applied when the pair was yielded.
+    pub fn write_all(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut output: Box<dyn Output> = match self {
+            Dst::File(path) => Box::new(WriteFileAtomic::new(path)),
+            _ => self.output(),
+        };
+        output.expect_len(bytes.len() as u64);
+        let written = (|| {
+            let mut writer = output.writer()?;
+            writer.write_all(bytes)?;
+            writer.flush()
+        })();
+        match written {
+            Ok(()) => output.finish(),
+            Err(e) => {
+                let _ = output.abort(); // 原本的错误更重要
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Entries have no output of their own, they go through the one archive writer.
+struct ArchiveMember;
+
+impl Output for ArchiveMember {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "archive entries are written through WriteArchive",
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[cfg(unix)]
+    #[test]
+    fn command_input() {
+        let mut input = CommandInput::new(Command::new("echo"));
+        let mut buf = String::new();
+        input.reader().unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "\n");
+        input.finish().unwrap();
+
+        let mut input = CommandInput::new(Command::new("false"));
+        input
+            .reader()
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .unwrap();
+        assert!(input.finish().is_err());
+    }
+
+    #[test]
+    fn spool() {
+        let mut spool = Spool::new(4);
+        spool.write_all(b"abc").unwrap();
+        assert_eq!(spool.as_bytes(), Some(&b"abc"[..]));
+        spool.write_all(b"def").unwrap();
+        assert!(spool.is_spilled());
+
+        let mut buf = String::new();
+        spool.reader().unwrap().read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "abcdef");
+    }
+
+    #[test]
+    fn relative_link() {
+        assert_eq!(
+            relative_to(Path::new("/data/in/a.png"), Path::new("/data/out/sorted")),
+            PathBuf::from("../../in/a.png")
+        );
+        assert_eq!(
+            relative_to(Path::new("/data/a.png"), Path::new("/data")),
+            PathBuf::from("a.png")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardened_write_file() {
+        let root = ScratchDir::new("hardened");
+        fs::write(root.join("victim"), b"keep").unwrap();
+        std::os::unix::fs::symlink(root.join("victim"), root.join("out.txt")).unwrap();
+
+        let mut output = HardenedWriteFile::new(root.join("out.txt"));
+        assert!(output.writer().is_err());
+        output.abort().unwrap();
+        assert_eq!(fs::read(root.join("victim")).unwrap(), b"keep");
+
+        let mut output = HardenedWriteFile::new(root.join("new.txt"));
+        output.writer().unwrap().write_all(b"fresh").unwrap();
+        assert_eq!(fs::read(root.join("new.txt")).unwrap(), b"fresh");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preserve_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = ScratchDir::new("metadata");
+        let src = root.join("in.txt");
+        fs::write(&src, b"in").unwrap();
+        let mtime = std::time::SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        let file = fs::File::options().write(true).open(&src).unwrap();
+        file.set_modified(mtime).unwrap();
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o400)).unwrap();
+
+        let mut output = WriteFile::new(root.join("out.txt")).preserve_metadata_of(&src);
+        output.writer().unwrap().write_all(b"out").unwrap();
+        output.finish().unwrap();
+        let meta = fs::metadata(root.join("out.txt")).unwrap();
+        assert_eq!(meta.modified().unwrap(), mtime);
+        assert_eq!(meta.permissions().mode() & 0o777, 0o400);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn direct_write_file() {
+        let root = ScratchDir::new("direct");
+        let content: Vec<u8> = (0..DIRECT_CHUNK + 5000).map(|n| n as u8).collect();
+
+        let mut output = WriteFile::new_direct(root.join("out.bin"));
+        let mut writer = output.writer().unwrap();
+        writer.write_all(&content[..100]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read(root.join("out.bin")).unwrap(), &content[..100]);
+        writer.write_all(&content[100..]).unwrap();
+        drop(writer);
+        assert_eq!(fs::read(root.join("out.bin")).unwrap(), content);
+    }
+
+    #[test]
+    fn preallocated() {
+        let root = ScratchDir::new("prealloc");
+        let mut output = WriteFile::new(root.join("out.txt"));
+        output.expect_len(1 << 20);
+        output.writer().unwrap().write_all(b"short").unwrap();
+        output.finish().unwrap();
+        assert_eq!(fs::read(root.join("out.txt")).unwrap(), b"short");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let reserved = fs::metadata(root.join("out.txt")).unwrap().blocks() * 512;
+            assert!(reserved < 1 << 20, "{reserved} bytes still reserved");
+        }
+    }
+
+    #[test]
+    fn thread_safe() {
+        fn send_sync<T: Send + Sync>() {}
+        fn send<T: Send>() {}
+
+        send_sync::<ReadStdin>();
+        send_sync::<ReadFile>();
+        send_sync::<WriteStdout>();
+        send_sync::<WriteFile>();
+        send_sync::<WriteFileAtomic>();
+        send_sync::<HardenedWriteFile>();
+        send_sync::<RecordingOutput>();
+        send_sync::<GoldenOutput>();
+        send_sync::<BytesOutput>();
+        send::<ClarifiedIo>();
+    }
+
+    #[test]
+    fn read_to_vec() {
+        let root = ScratchDir::new("read-to-vec");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/a.txt"), b"small").unwrap();
+        fs::write(root.join("src/b.txt"), b"far too large").unwrap();
+
+        let read: Vec<_> = crate::SrcDstConfig::new("txt")
+            .parse(root.join("src"), Some(root.clone()))
+            .unwrap()
+            .unwrap()
+            .map_read_to_vec(8)
+            .collect();
+        assert_eq!(read[0].as_ref().unwrap().0, b"small");
+        let e = read[1].as_ref().unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::FileTooLarge);
+        assert!(e.to_string().contains("b.txt"));
+    }
+
+    #[test]
+    fn dst_write_all() {
+        let root = ScratchDir::new("write-all");
+        let path = root.join("out.txt");
+        let dst = Dst::File(path.clone());
+        dst.write_all(b"first").unwrap();
+        dst.write_all(b"second").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+    }
+}
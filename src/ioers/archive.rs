@@ -0,0 +1,195 @@
+//! Packing [`Dst::Entry`](crate::Dst::Entry)s into one tar archive, feature `tar`.
+//!
+//! Only the ustar format and regular files; zip is not supported.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    path::{Component, Path},
+    time::UNIX_EPOCH,
+};
+
+use super::Output;
+
+const BLOCK: usize = 512;
+
+/// Writes entries one after another into the stream of `inner`, e.g. the output of
+/// [`SrcDstPairs::archive`](crate::SrcDstPairs::archive).
+///
+/// The end-of-archive marker is written in [`Self::finish`], so an error there is not lost in a drop.
+pub struct WriteArchive {
+    inner: Box<dyn Output>,
+    writer: Option<Box<dyn Write + Send>>,
+}
+
+impl WriteArchive {
+    pub fn new(inner: Box<dyn Output>) -> Self {
+        Self {
+            inner,
+            writer: None,
+        }
+    }
+
+    /// Exactly `len` bytes are taken from `data`, with mode `0644` and no modification time.
+    pub fn append<P: AsRef<Path>>(
+        &mut self,
+        name: P,
+        len: u64,
+        data: &mut dyn Read,
+    ) -> io::Result<()> {
+        self.append_with(name.as_ref(), len, 0o644, 0, data)
+    }
+
+    /// Keeps the permissions (on Unix) and the modification time of `path`.
+    pub fn append_file<N: AsRef<Path>, P: AsRef<Path>>(
+        &mut self,
+        name: N,
+        path: P,
+    ) -> io::Result<()> {
+        let mut file = fs::File::open(path)?;
+        let meta = file.metadata()?;
+        #[cfg(unix)]
+        let mode = std::os::unix::fs::PermissionsExt::mode(&meta.permissions()) & 0o7777;
+        #[cfg(not(unix))]
+        let mode = 0o644;
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        self.append_with(name.as_ref(), meta.len(), mode, mtime, &mut file)
+    }
+
+    fn append_with(
+        &mut self,
+        name: &Path,
+        len: u64,
+        mode: u32,
+        mtime: u64,
+        data: &mut dyn Read,
+    ) -> io::Result<()> {
+        let header = header(name, len, mode, mtime)?;
+        let writer = self.writer()?;
+        writer.write_all(&header)?;
+        let copied = io::copy(&mut data.take(len), writer)?;
+        if copied != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("entry {name:?} is shorter than {len} bytes"),
+            ));
+        }
+        let padding = (BLOCK - (len % BLOCK as u64) as usize) % BLOCK;
+        writer.write_all(&[0; BLOCK][..padding])
+    }
+
+    fn writer(&mut self) -> io::Result<&mut Box<dyn Write + Send>> {
+        if self.writer.is_none() {
+            self.writer = Some(self.inner.writer()?);
+        }
+        Ok(self.writer.as_mut().unwrap())
+    }
+
+    /// Also of `inner`. An archive without any entry is still a valid one.
+    pub fn finish(mut self) -> io::Result<()> {
+        let writer = self.writer()?;
+        writer.write_all(&[0; 2 * BLOCK])?;
+        writer.flush()?;
+        drop(self.writer.take());
+        self.inner.finish()
+    }
+
+    pub fn abort(mut self) -> io::Result<()> {
+        drop(self.writer.take());
+        self.inner.abort()
+    }
+}
+
+fn header(name: &Path, len: u64, mode: u32, mtime: u64) -> io::Result<[u8; BLOCK]> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidInput, msg);
+
+    let mut parts = Vec::new();
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => parts.push(
+                part.to_str()
+                    .ok_or_else(|| invalid(format!("entry name {name:?} is not UTF-8")))?,
+            ),
+            Component::CurDir => (),
+            _ => return Err(invalid(format!("entry name {name:?} is not relative"))),
+        }
+    }
+    let full = parts.join("/");
+    // 放不下 100 字节时，从某个 `/` 处拆成 prefix 和 name
+    let (prefix, short) = match full.len() {
+        0 => return Err(invalid(format!("entry name {name:?} is empty"))),
+        1..=100 => ("", full.as_str()),
+        _ => full
+            .match_indices('/')
+            .map(|(i, _)| (&full[..i], &full[i + 1..]))
+            .find(|(prefix, short)| prefix.len() <= 155 && short.len() <= 100)
+            .ok_or_else(|| invalid(format!("entry name {name:?} is too long for tar")))?,
+    };
+    if len >= 8 << 30 {
+        return Err(invalid(format!("entry {name:?} is too large for tar")));
+    }
+
+    let mut header = [0; BLOCK];
+    let mut put = |at: usize, bytes: &[u8]| header[at..at + bytes.len()].copy_from_slice(bytes);
+    put(0, short.as_bytes());
+    put(100, format!("{mode:07o}\0").as_bytes());
+    put(108, b"0000000\0"); // uid
+    put(116, b"0000000\0"); // gid
+    put(124, format!("{len:011o}\0").as_bytes());
+    put(
+        136,
+        format!("{:011o}\0", mtime.min(0o77777777777)).as_bytes(),
+    );
+    put(148, b"        "); // 校验和先按空格算
+    put(156, b"0");
+    put(257, b"ustar\0");
+    put(263, b"00");
+    put(345, prefix.as_bytes());
+    let checksum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioers::BytesOutput;
+
+    #[test]
+    fn ustar() {
+        let mut broken = WriteArchive::new(Box::new(BytesOutput::default()));
+        assert!(broken.append("b", 3, &mut &b"no"[..]).is_err());
+        assert!(broken.append("../b", 0, &mut io::empty()).is_err());
+        assert!(broken.append("f".repeat(101), 0, &mut io::empty()).is_err());
+
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        let bytes = BytesOutput::default();
+        let mut archive = WriteArchive::new(Box::new(bytes.clone()));
+        archive.append("sub/a.txt", 5, &mut &b"hello"[..]).unwrap();
+        archive.append(&long, 0, &mut io::empty()).unwrap();
+        archive.finish().unwrap();
+        let tar = bytes.spool().as_bytes().unwrap().to_vec();
+        assert_eq!(tar.len(), 5 * BLOCK);
+        assert_eq!(&tar[..9], b"sub/a.txt");
+        assert_eq!(&tar[257..263], b"ustar\0");
+        assert_eq!(&tar[BLOCK..BLOCK + 5], b"hello");
+        assert_eq!(&tar[2 * BLOCK..2 * BLOCK + 90], "f".repeat(90).as_bytes());
+        assert_eq!(
+            &tar[2 * BLOCK + 345..2 * BLOCK + 465],
+            "d".repeat(120).as_bytes()
+        );
+
+        let checksum: u32 = tar[..BLOCK]
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| u32::from(if (148..156).contains(&i) { b' ' } else { b }))
+            .sum();
+        let stored = std::str::from_utf8(&tar[148..154]).unwrap();
+        assert_eq!(u32::from_str_radix(stored, 8).unwrap(), checksum);
+        assert!(tar[3 * BLOCK..].iter().all(|&b| b == 0));
+    }
+}
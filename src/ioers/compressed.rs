@@ -0,0 +1,217 @@
+//! Transparent (de)compression, see [`ClarifiedIo::compressed`].
+
+use std::{
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use super::{ClarifiedIo, Input, Output};
+use crate::{Dst, Src};
+
+/// Compression formats recognized by their extension, each behind its own feature.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    /// `gz`, feature `gzip`.
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// `zst`, feature `zstd`.
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Codec {
+    /// From the last extension, matched ASCII case-insensitively.
+    /// `None` if it isn't one of an enabled codec.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let extension = path.as_ref().extension()?;
+        #[cfg(feature = "gzip")]
+        if extension.eq_ignore_ascii_case("gz") {
+            return Some(Codec::Gzip);
+        }
+        #[cfg(feature = "zstd")]
+        if extension.eq_ignore_ascii_case("zst") {
+            return Some(Codec::Zstd);
+        }
+        let _ = extension;
+        None
+    }
+}
+
+/// Decompresses the stream of `inner`.
+pub struct ReadCompressed {
+    inner: Box<dyn Input>,
+    codec: Codec,
+}
+
+impl ReadCompressed {
+    pub fn new(inner: Box<dyn Input>, codec: Codec) -> Self {
+        Self { inner, codec }
+    }
+}
+
+impl Input for ReadCompressed {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        let reader = self.inner.reader()?;
+        match self.codec {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Ok(Box::new(flate2::read::MultiGzDecoder::new(reader))),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Ok(Box::new(zstd::stream::read::Decoder::new(reader)?)),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()
+    }
+}
+
+/// Compresses into the stream of `inner`, at the default level of the codec.
+///
+/// The trailer is written in [`Output::finish`], so an error there is not lost in a drop.
+pub struct WriteCompressed {
+    inner: Box<dyn Output>,
+    codec: Codec,
+    encoder: Arc<Mutex<Option<Encoder>>>,
+}
+
+enum Encoder {
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<Box<dyn Write + Send>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, Box<dyn Write + Send>>),
+}
+
+impl Encoder {
+    fn as_write(&mut self) -> &mut dyn Write {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder,
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+
+    fn finish(self) -> io::Result<Box<dyn Write + Send>> {
+        match self {
+            #[cfg(feature = "gzip")]
+            Encoder::Gzip(encoder) => encoder.finish(),
+            #[cfg(feature = "zstd")]
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl WriteCompressed {
+    pub fn new(inner: Box<dyn Output>, codec: Codec) -> Self {
+        Self {
+            inner,
+            codec,
+            encoder: Arc::default(),
+        }
+    }
+}
+
+impl Output for WriteCompressed {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        let writer = self.inner.writer()?;
+        let encoder = match self.codec {
+            #[cfg(feature = "gzip")]
+            Codec::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => Encoder::Zstd(zstd::stream::write::Encoder::new(writer, 0)?),
+        };
+        *self.encoder.lock().unwrap() = Some(encoder);
+        Ok(Box::new(SharedEncoder(self.encoder.clone())))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let encoder = self.encoder.lock().unwrap().take();
+        if let Some(encoder) = encoder {
+            encoder.finish()?.flush()?;
+        }
+        self.inner.finish()
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        drop(self.encoder.lock().unwrap().take());
+        self.inner.abort()
+    }
+}
+
+struct SharedEncoder(Arc<Mutex<Option<Encoder>>>);
+
+impl Write for SharedEncoder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut *self.0.lock().unwrap() {
+            Some(encoder) => encoder.as_write().write(buf),
+            None => Err(io::Error::other("compressed output already finished")),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut *self.0.lock().unwrap() {
+            Some(encoder) => encoder.as_write().flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl ClarifiedIo {
+    /// Like `From<(Src, Dst)>`, but SRC and DST files with the extension of a [`Codec`]
+    /// are decompressed and compressed.
+    pub fn compressed(src: Src, dst: Dst) -> Self {
+        let mut io = Self::from((src.clone(), dst.clone()));
+        if let Some(codec) = match &src {
+            Src::File(path) => Codec::from_path(path),
+            _ => None,
+        } {
+            io.input = Box::new(ReadCompressed::new(io.input, codec));
+        }
+        if let Some(codec) = match &dst {
+            Dst::File(path) => Codec::from_path(path),
+            _ => None,
+        } {
+            io.output = Box::new(WriteCompressed::new(io.output, codec));
+        }
+        io
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn round_trip() {
+        let root = ScratchDir::new("compressed");
+        let mut names = vec!["plain.txt"];
+        #[cfg(feature = "gzip")]
+        names.push("a.txt.gz");
+        #[cfg(feature = "zstd")]
+        names.push("a.txt.ZST");
+
+        for name in names {
+            let path = root.join(name);
+            let mut io = ClarifiedIo::compressed(Src::Stdin, Dst::File(path.clone()));
+            io.output.writer().unwrap().write_all(b"hello").unwrap();
+            io.output.finish().unwrap();
+            assert_eq!(
+                fs::read(&path).unwrap() == b"hello",
+                Codec::from_path(&path).is_none()
+            );
+
+            let mut io = ClarifiedIo::compressed(Src::File(path), Dst::Stdout);
+            let mut buf = Vec::new();
+            io.input.reader().unwrap().read_to_end(&mut buf).unwrap();
+            assert_eq!(buf, b"hello");
+        }
+        assert_eq!(Codec::from_path(PathBuf::from("a.gz.txt")), None);
+    }
+}
@@ -0,0 +1,245 @@
+//! Byte counting and progress reporting around any [`Input`] or [`Output`].
+
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use super::{Input, Output};
+
+/// Called with the bytes so far.
+pub trait ProgressCallback: Send + Sync {
+    fn progress(&self, bytes: u64);
+}
+
+impl<F> ProgressCallback for F
+where
+    F: Fn(u64) + Send + Sync,
+{
+    fn progress(&self, bytes: u64) {
+        self(bytes)
+    }
+}
+
+/// Shared by the wrapper and every stream it opened, so the total stays readable after those
+/// are gone.
+#[derive(Clone, Default)]
+pub struct ByteCounter(Arc<Shared>);
+
+#[derive(Default)]
+struct Shared {
+    total: AtomicU64,
+    progress: Option<Progress>,
+}
+
+struct Progress {
+    callback: Arc<dyn ProgressCallback>,
+    every: Duration,
+    last: Mutex<Option<Instant>>,
+}
+
+impl fmt::Debug for ByteCounter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ByteCounter").field(&self.total()).finish()
+    }
+}
+
+impl ByteCounter {
+    /// `callback` is invoked at most once per `every` while bytes flow, and once more with the
+    /// final total when the wrapper finishes.
+    pub fn with_progress<C: ProgressCallback + 'static>(every: Duration, callback: C) -> Self {
+        Self(Arc::new(Shared {
+            total: AtomicU64::new(0),
+            progress: Some(Progress {
+                callback: Arc::new(callback),
+                every,
+                last: Mutex::new(None),
+            }),
+        }))
+    }
+
+    pub fn total(&self) -> u64 {
+        self.0.total.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, n: usize) {
+        let total = self.0.total.fetch_add(n as u64, Ordering::Relaxed) + n as u64;
+        let Some(progress) = &self.0.progress else {
+            return;
+        };
+        let mut last = progress.last.lock().unwrap_or_else(|e| e.into_inner());
+        // 第一次先等满一个间隔，免得小文件也刷一次
+        let now = Instant::now();
+        match *last {
+            None => *last = Some(now),
+            Some(at) if now.duration_since(at) >= progress.every => {
+                *last = Some(now);
+                drop(last);
+                progress.callback.progress(total);
+            }
+            Some(_) => (),
+        }
+    }
+
+    fn report(&self) {
+        if let Some(progress) = &self.0.progress {
+            progress.callback.progress(self.total());
+        }
+    }
+}
+
+/// Counts the bytes read from `inner`.
+pub struct CountingInput {
+    inner: Box<dyn Input>,
+    counter: ByteCounter,
+}
+
+impl CountingInput {
+    pub fn new(inner: Box<dyn Input>, counter: ByteCounter) -> Self {
+        Self { inner, counter }
+    }
+
+    pub fn counter(&self) -> &ByteCounter {
+        &self.counter
+    }
+}
+
+impl Input for CountingInput {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        let inner = self.inner.reader()?;
+        Ok(Box::new(Counting {
+            inner,
+            counter: self.counter.clone(),
+        }))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.counter.report();
+        self.inner.finish()
+    }
+}
+
+/// Counts the bytes written to `inner`.
+pub struct CountingOutput {
+    inner: Box<dyn Output>,
+    counter: ByteCounter,
+}
+
+impl CountingOutput {
+    pub fn new(inner: Box<dyn Output>, counter: ByteCounter) -> Self {
+        Self { inner, counter }
+    }
+
+    pub fn counter(&self) -> &ByteCounter {
+        &self.counter
+    }
+}
+
+impl Output for CountingOutput {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        let inner = self.inner.writer()?;
+        Ok(Box::new(Counting {
+            inner,
+            counter: self.counter.clone(),
+        }))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.counter.report();
+        self.inner.finish()
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        self.inner.abort()
+    }
+
+    fn expect_len(&mut self, len: u64) {
+        self.inner.expect_len(len);
+    }
+}
+
+struct Counting<T> {
+    inner: T,
+    counter: ByteCounter,
+}
+
+impl<R: Read> Read for Counting<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.counter.add(n);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counting<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.counter.add(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Copies the whole of `input` into `output` and finishes both, or aborts `output` on failure.
+/// Returns the bytes copied. Wrap either side in a counting one for progress.
+pub fn transfer(input: &mut dyn Input, output: &mut dyn Output) -> io::Result<u64> {
+    let copied = (|| {
+        let mut reader = input.reader()?;
+        let mut writer = output.writer()?;
+        let copied = io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        drop((reader, writer));
+        input.finish()?;
+        Ok(copied)
+    })();
+    match copied {
+        Ok(copied) => output.finish().map(|()| copied),
+        Err(e) => {
+            let _ = output.abort(); // 原本的错误更重要
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioers::BytesOutput;
+
+    struct Bytes(Vec<u8>);
+
+    impl Input for Bytes {
+        fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+            Ok(Box::new(io::Cursor::new(self.0.clone())))
+        }
+    }
+
+    #[test]
+    fn progress() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let sink = reports.clone();
+        let counter = ByteCounter::with_progress(Duration::ZERO, move |bytes| {
+            sink.lock().unwrap().push(bytes)
+        });
+        let bytes = BytesOutput::default();
+        let mut output = CountingOutput::new(Box::new(bytes.clone()), counter.clone());
+        let mut input =
+            CountingInput::new(Box::new(Bytes(vec![7; 100_000])), ByteCounter::default());
+
+        assert_eq!(transfer(&mut input, &mut output).unwrap(), 100_000);
+        assert_eq!(input.counter().total(), 100_000);
+        assert_eq!(counter.total(), 100_000);
+        assert_eq!(bytes.spool().as_bytes().unwrap().len(), 100_000);
+        let reports = reports.lock().unwrap();
+        assert!(reports.len() > 1 && reports.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(reports.last(), Some(&100_000));
+    }
+}
@@ -0,0 +1,207 @@
+//! One SRC to several DSTs differing only in extension, see [`SrcDstPairs::variants`].
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+};
+
+use super::{Input, SharedStdin};
+use crate::{ArchiveEntry, Dst, Src, SrcDstError, SrcDstPairError, SrcDstPairs};
+
+impl SrcDstPairs {
+    /// Every pair once per extension, e.g. to emit both `.png` and `.webp` from one SRC. The
+    /// DSTs get that extension; stdout and socket DSTs have no name to vary and come once.
+    /// Varied file DSTs go through [`SrcDstConfig::overwrite`] and the conflict resolver like
+    /// the others, see [`Variants::error`].
+    ///
+    /// [`SrcDstConfig::overwrite`]: crate::SrcDstConfig::overwrite
+    ///
+    /// Stdin is read once into a [`SharedStdin`] handed to all of its pairs, which lives until
+    /// the iterator moved on and the last of their inputs has been dropped.
+    pub fn variants<S: AsRef<OsStr>>(self, extensions: &[S]) -> Variants {
+        Variants {
+            pairs: self,
+            extensions: extensions
+                .iter()
+                .map(|ext| ext.as_ref().to_owned())
+                .collect(),
+            current: None,
+            stdin: None,
+            error: None,
+        }
+    }
+}
+
+/// See [`SrcDstPairs::variants`].
+#[derive(Debug)]
+pub struct Variants {
+    pairs: SrcDstPairs,
+    extensions: Vec<OsString>,
+    /// The pair being varied, and the index of the next extension.
+    current: Option<(Src, Dst, usize)>,
+    stdin: Option<SharedStdin>,
+    error: Option<SrcDstError>,
+}
+
+impl Variants {
+    /// A varied DST that already exists under [`OverwritePolicy::Error`], which ended the
+    /// iteration early. Those of the pairs themselves are in [`Self::take_unsettled`].
+    ///
+    /// [`OverwritePolicy::Error`]: crate::OverwritePolicy::Error
+    pub fn error(&self) -> Option<&SrcDstError> {
+        self.error.as_ref()
+    }
+
+    /// See [`SrcDstPairs::take_unsettled`].
+    pub fn take_unsettled(&mut self) -> Vec<SrcDstPairError> {
+        self.pairs.take_unsettled()
+    }
+}
+
+impl Iterator for Variants {
+    type Item = (Src, Dst, Box<dyn Input>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.error.is_some() {
+                return None;
+            }
+            if let Some((src, dst, next)) = &mut self.current {
+                if let Some(extension) = self.extensions.get(*next) {
+                    let varied = with_extension(dst, extension);
+                    *next = match varied {
+                        Some(_) => *next + 1,
+                        None => self.extensions.len(), // 没有名字可变，只给一次
+                    };
+                    let mut varied = varied.unwrap_or_else(|| dst.clone());
+                    let same = varied == *dst; // 和原来的一样的已经检查过了
+                    if let (Dst::File(path), false) = (&mut varied, same) {
+                        let from = match &*src {
+                            Src::File(path) => Some(path.as_path()),
+                            _ => None,
+                        };
+                        match self.pairs.settle(from, path, false) {
+                            Ok(true) => (),
+                            Ok(false) => continue,
+                            Err(e) => {
+                                self.error = Some(e);
+                                return None;
+                            }
+                        }
+                    }
+                    let (src, dst) = (src.clone(), varied);
+                    let input: Box<dyn Input> = match (&src, &self.stdin) {
+                        (Src::Stdin, Some(stdin)) => Box::new(stdin.clone()),
+                        _ => src.input(),
+                    };
+                    return Some((src, dst, input));
+                }
+            }
+            let (src, dst) = self.pairs.next()?;
+            // 换到下一个 SRC 时放掉上一份 spool
+            self.stdin = matches!(src, Src::Stdin).then(SharedStdin::default);
+            self.current = Some((src, dst, 0));
+        }
+    }
+}
+
+fn with_extension(dst: &Dst, extension: &OsStr) -> Option<Dst> {
+    let named = |path: &PathBuf| path.with_extension(extension);
+    match dst {
+        Dst::File(path) => Some(Dst::File(named(path))),
+        Dst::Entry(ArchiveEntry { archive, name }) => Some(Dst::Entry(ArchiveEntry {
+            archive: archive.clone(),
+            name: named(name),
+        })),
+        #[cfg(feature = "sftp")]
+        Dst::Remote(remote) => {
+            let mut remote = remote.clone();
+            remote.path = named(&PathBuf::from(&remote.path))
+                .to_string_lossy()
+                .into_owned();
+            Some(Dst::Remote(remote))
+        }
+        #[cfg(all(unix, feature = "unix-socket"))]
+        Dst::UnixSocket(_) => None,
+        Dst::Stdout => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::{PairDrain, PairSource},
+        SrcDstConfig,
+    };
+
+    #[test]
+    fn variants() {
+        let config = SrcDstConfig::new("png");
+        let pairs = config
+            .builder()
+            .source(PairSource::Files {
+                root: PathBuf::from("/in"),
+                names: vec!["a.jpg".into(), "b.jpg".into()],
+            })
+            .drain(PairDrain::Archive(Some("/out.tar".into())))
+            .build()
+            .unwrap();
+        let names: Vec<_> = pairs
+            .variants(&["png", "webp"])
+            .map(|(_, dst, _)| dst.to_string())
+            .collect();
+        assert_eq!(names.len(), 4);
+        assert!(names[0].ends_with("a.png") && names[1].ends_with("a.webp"));
+        assert!(names[3].ends_with("b.webp"));
+
+        let stdin = config
+            .builder()
+            .source(PairSource::Stdin)
+            .drain(PairDrain::Stdout)
+            .build()
+            .unwrap();
+        let pairs: Vec<_> = stdin.variants(&["png", "webp"]).collect();
+        assert_eq!(pairs.len(), 1);
+        assert_eq!((&pairs[0].0, &pairs[0].1), (&Src::Stdin, &Dst::Stdout));
+    }
+
+    #[test]
+    fn variants_collide() {
+        use crate::{vfs, OverwritePolicy};
+
+        let mut fs = vfs::MemoryFs::new("/");
+        fs.add_dir("/out").add_file("/out/a.webp");
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(crate::shared::Shared(std::sync::Arc::new(fs)));
+        let varied = |config: &SrcDstConfig| {
+            let stdin = config
+                .builder()
+                .source(PairSource::Stdin)
+                .drain(PairDrain::File("/out/a.png".into()))
+                .build()
+                .unwrap();
+            stdin.variants(&["png", "webp", "avif"])
+        };
+
+        // 一次读进来的 stdin 给了每个 DST
+        config.overwrite = OverwritePolicy::RenameWithCounter;
+        let mut pairs = varied(&config);
+        let yielded: Vec<_> = pairs.by_ref().collect();
+        let shared = &pairs.stdin.as_ref().unwrap().0;
+        assert_eq!(std::sync::Arc::strong_count(shared), 1 + yielded.len());
+        let dsts: Vec<_> = (yielded.iter())
+            .inspect(|(src, _, _)| assert_eq!(*src, Src::Stdin))
+            .map(|(_, dst, _)| dst.to_string())
+            .collect();
+        assert_eq!(dsts, ["/out/a.png", "/out/a-1.webp", "/out/a.avif"]);
+
+        config.overwrite = OverwritePolicy::Skip;
+        assert_eq!(varied(&config).count(), 2);
+
+        config.overwrite = OverwritePolicy::Error;
+        let mut erred = varied(&config);
+        assert_eq!(erred.by_ref().count(), 1);
+        assert!(matches!(erred.error(), Some(SrcDstError::DstExists)));
+    }
+}
@@ -0,0 +1,41 @@
+//! Stable message keys so that downstream CLIs can localize user-facing messages.
+//!
+//! ```
+//! use src_dst_clarifier::{l10n::Message, SrcDstError};
+//!
+//! let zh = |key: &str| match key {
+//!     "many-to-one" => Some(String::from("无法将多个文件写入同一个文件")),
+//!     _ => None,
+//! };
+//! assert_eq!(SrcDstError::ManyToOne.localize(zh), "无法将多个文件写入同一个文件");
+//! assert_eq!(SrcDstError::Inplaced.localize(zh), SrcDstError::Inplaced.to_string());
+//! ```
+
+use std::fmt;
+
+pub trait Message: fmt::Display {
+    /// Never changes once released, even if the English wording does.
+    fn key(&self) -> &'static str;
+
+    /// Named values to be interpolated as `{name}` into a translation.
+    fn params(&self) -> Vec<(&'static str, String)> {
+        Vec::new()
+    }
+
+    /// Looks `key` up in `catalog` and fills in the params, falls back to [`fmt::Display`].
+    fn localize<F>(&self, catalog: F) -> String
+    where
+        F: Fn(&str) -> Option<String>,
+        Self: Sized,
+    {
+        match catalog(self.key()) {
+            None => self.to_string(),
+            Some(mut template) => {
+                for (name, value) in self.params() {
+                    template = template.replace(&format!("{{{name}}}"), &value);
+                }
+                template
+            }
+        }
+    }
+}
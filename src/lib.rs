@@ -1,52 +1,435 @@
 use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
     ffi::{OsStr, OsString},
-    fs, io,
+    fmt, fs, io,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    time::{Duration, SystemTime},
 };
 
 use thiserror::Error;
 
 use kalavor::Katetime;
 
+use conflict::{Conflict, ConflictKind, Resolution};
+use probe::ProbeDecision;
+use shared::Shared;
+
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod audit;
+pub mod builder;
+pub mod cleanup;
+#[cfg(feature = "clap")]
+pub mod cli;
+pub mod collation;
+pub mod compat;
+pub mod conflict;
+pub mod descriptors;
+#[cfg(feature = "miette")]
+pub mod diagnostic;
+pub mod digest;
+pub mod environment;
+#[cfg(feature = "exif")]
+pub mod exif;
+pub mod filter;
+pub mod framing;
+pub mod identity;
+mod ignore_files;
+pub mod ioers;
+pub mod l10n;
+pub mod listing;
+pub mod merge;
+pub mod naming;
+pub mod parallel;
+pub mod plan;
+pub mod prefetch;
+pub mod probe;
+pub mod profile;
+pub mod remote;
+pub mod resolver;
+pub mod run;
+#[cfg(test)]
+mod scratch;
+pub mod shared;
+pub mod sidecar;
+pub mod size_class;
+#[cfg(unix)]
+pub mod snapshot;
+pub mod socket;
+mod spill;
+#[cfg(feature = "status-server")]
+pub mod status;
+pub mod template;
+#[cfg(feature = "test-util")]
+pub mod testing;
+pub mod undo;
+pub mod units;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+pub mod vfs;
+pub mod warning;
+pub mod wrap;
+
 /// Use single hyphen (`-`) as path to indicate IO from Stdio.
+/// A file named `-` is still reachable as `./-`, or with [`Self::dash_is_stdio`] turned off.
 ///
 /// # Notes
 ///
-/// - Auto time-based unique naming (`auto_tnamed_dst_`) only takes effect when DST is not provided.
+/// - Auto time-based unique naming ([`Self::auto_name`]) only takes effect when DST is not provided.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct SrcDstConfig {
-    pub allow_from_stdin: bool,
-    pub allow_to_stdout: bool,
+    pub stdio: StdioPolicy,
+    /// [`SrcDstError::StdoutIsTty`] instead of writing (binary) output to an interactive terminal.
+    pub deny_stdout_tty: bool,
+    /// `-` means stdio (the default), otherwise it is an ordinary path,
+    /// e.g. for arguments given after `--`.
+    pub dash_is_stdio: bool,
+
+    pub auto_name: AutoNamePolicy,
 
-    pub auto_tnamed_dst_file: bool,
-    pub auto_tnamed_dst_dir: bool,
+    /// The file name of time-based named DST files, expanded by [`template::Tokens`]:
+    /// `{name}`, `{stem}` and `{ext}` of SRC (`stdin` for stdin), `{default_ext}`, `{datetime}`
+    /// (the time-based part), `{counter}` (counting from 1 in this process) and `{random}`
+    /// (8 hex digits, not reproducible). `None` (the default) is `{stem}-{datetime}.{default_ext}`,
+    /// where `{stem}` is the whole name if SRC has another extension.
+    pub tnamed_file_template: Option<OsString>,
+    /// The same for time-based named DST directories, where SRC is the directory.
+    /// `None` (the default) is `{name}-{datetime}`.
+    pub tnamed_dir_template: Option<OsString>,
+    /// Also `{meta.<field>}` from the [`sidecar::Sidecar`] of SRC in the templates above,
+    /// e.g. `{meta.title}` from `photo.jpg.json`. A name expanding to nothing, `.` or `..` is an
+    /// error.
+    pub sidecar_tokens: bool,
 
     pub default_extension: OsString,
 
-    /// Disallowed by default. There may be a potential to `open` and `create` the same file at the same time.
-    pub allow_inplace: bool,
+    /// For format conversions: replaces the extension of every DST file name generated from SRC,
+    /// in batch mode, into a DST directory and for time-based names (where it also replaces
+    /// [`Self::default_extension`], and the SRC extension is dropped). Empty removes it.
+    pub output_extension: Option<OsString>,
+    /// File SRCs whose name matches any of these globs (`*` and `?` only) keep their own
+    /// extension in the DST names generated for them, instead of [`Self::output_extension`] or
+    /// a [`Self::probe`]d one. For [`Runner::passthrough`](run::Runner::passthrough), which
+    /// copies them as is: with the same globs here, the name actually written is the one
+    /// [`Self::overwrite`], [`Self::conflict_resolver`] and [`Self::incremental`] see.
+    pub passthrough: Vec<String>,
+
+    /// Denied by default. There may be a potential to `open` and `create` the same file at the same time.
+    pub inplace: InplacePolicy,
+
+    /// Enabled by default. Generated DST names ending with a repeated extension
+    /// (`photo.png.png`) are collapsed (`photo.png`), see [`collapse_duplicate_extension`].
+    pub collapse_duplicate_extensions: bool,
+
+    /// Applied to DST file names in batch mode only. Names it makes equal are numbered like
+    /// `<stem>-1.<ext>` rather than overwriting each other.
+    pub name_policy: naming::NamePolicy,
+    /// Batch DSTs go into subdirectories of theirs, see [`naming::Buckets`]. They are listed by
+    /// [`SrcDstPairs::dst_dirs`] like the others.
+    pub dst_buckets: Option<naming::Buckets>,
+    /// Batch DSTs go right into the DST directory instead of mirroring the sub-directories
+    /// walked into by [`Self::max_depth`], with clashing names told apart as given. See
+    /// [`run::Runner::manifest`] for where each one came from.
+    pub flatten: Option<naming::Flatten>,
+
+    /// Skips guessing from the filesystem whether DST is meant to be a file or a directory.
+    pub dst_kind: DstKind,
+
+    /// Packs a SRC directory into a single file (or stdout) instead of [`SrcDstError::ManyToOne`]:
+    /// every DST is then a [`Dst::Entry`] of [`SrcDstPairs::archive`], see
+    /// `ioers::WriteArchive` (feature `tar`). A DST that does not exist is the archive too.
+    pub allow_many_to_one: bool,
+
+    /// When DST is not provided, every DST goes next to its SRC file as `<stem>.<suffix>.<ext>`
+    /// (e.g. `photo.converted.png` for `converted`), instead of the time-based naming. The
+    /// [`Self::auto_name`] is not checked then, stdin is still time-based named. SRC files
+    /// already named so are left out of a batch, as outputs of an earlier run. With an empty
+    /// suffix, a DST that would be its own SRC is [`SrcDstError::Inplaced`] unless
+    /// [`InplacePolicy::Atomic`], one that would be another SRC always.
+    pub sibling_suffix: Option<OsString>,
+    /// Asked for a DST when it is not provided, instead of the time-based naming.
+    /// [`Self::auto_name`] is not checked then. Not saved in a [`plan::Plan`].
+    pub dst_resolver: Option<Shared<dyn resolver::DstResolver>>,
+
+    /// For reproducible pipelines: time-based names use [`Self::fixed_time`], falling back to
+    /// `SOURCE_DATE_EPOCH`, as `YYYYMMDDTHHMMSSZ`. Without either, the SHA-256 of SRC content is
+    /// used instead (`1970...` for stdin). The batch order is always stable, see [`batch_order`].
+    pub deterministic: bool,
+    pub fixed_time: Option<SystemTime>,
+    /// For photo imports: time-based names use when SRC was taken, the EXIF capture date with
+    /// the `exif` feature (see `exif::capture_date`), otherwise its modification time (as
+    /// `YYYYMMDDTHHMMSSZ`), instead of now. Takes precedence over [`Self::deterministic`].
+    pub capture_time: bool,
+
+    /// Where auto-named DSTs go when [`std::env::current_dir`] is unavailable
+    /// (deleted, or restricted environments).
+    pub cwd_fallback: CwdFallback,
+
+    /// Applied by [`Self::join_dst`] to DST names from untrusted sources.
+    pub containment: naming::Containment,
+
+    /// Every DST, including user-specified ones, must resolve under this (existing) directory,
+    /// otherwise [`SrcDstError::DstOutsideConfinement`]. Symlinks are followed. Stdout is allowed,
+    /// remote DSTs are not.
+    pub confine_to: Option<PathBuf>,
+
+    /// Receives non-fatal conditions, nothing is printed by this crate. Not saved in a [`plan::Plan`].
+    pub warnings: Option<Shared<dyn warning::WarningSink>>,
+
+    /// Unix only, ignored elsewhere: enumerate a SRC directory through a handle held from parsing
+    /// on, and have [`run::Runner`] open its files relative to it, see [`snapshot`].
+    pub hold_src_dir: bool,
+
+    /// Levels of sub-directories walked into when SRC is a directory, 0 (the default) for none.
+    /// DSTs mirror the sub-directory structure, see [`SrcDstPairs::create_dst_dirs`].
+    /// [`Self::hold_src_dir`] only applies when this is 0.
+    pub max_depth: usize,
+    /// Symlinks met while walking a SRC directory. Not applied to [`Self::hold_src_dir`]
+    /// and [`Self::listing_cache`], which always skip them.
+    pub symlinks: SymlinkPolicy,
+    /// Leave out what `.gitignore` and `.ignore` files in a SRC directory and below ignore, and
+    /// `.git` directories, like ripgrep. On by default with the `ignore` feature, an
+    /// [`io::ErrorKind::Unsupported`] error without it. Not applied to [`Self::hold_src_dir`]
+    /// and [`Self::listing_cache`] either.
+    pub ignore_files: bool,
+
+    /// When a SRC directory has more files than this, their names are sorted into runs in the
+    /// temporary directory and merged lazily (see [`SrcDstPairs::spill_error`]), instead of sorted
+    /// in memory. 0 (the default) never spills.
+    pub sort_spill_threshold: usize,
+    /// The order batch SRCs are yielded in, bytewise by default (see [`batch_order`]).
+    pub collation: collation::Collation,
+    /// The order batch SRCs of different sub-directories are yielded in, when [`Self::max_depth`]
+    /// walks into them, depth-first by default.
+    pub traversal: collation::Traversal,
+
+    /// An existing directory where listings of SRC directories are cached between runs,
+    /// skipping the scan of those that did not change, see [`listing`]. Only when
+    /// [`Self::max_depth`] is 0 and [`Self::hold_src_dir`] is off. Warnings of the scan are
+    /// not repeated for cached listings.
+    pub listing_cache: Option<PathBuf>,
+
+    /// What parsing reads of the filesystem goes through, `None` is [`vfs::StdFs`]. Except for
+    /// [`Self::hold_src_dir`], [`Self::listing_cache`] and [`Self::deterministic`] hashing,
+    /// which always read the real one. Not saved in a [`plan::Plan`].
+    pub filesystem: Option<Shared<dyn vfs::FileSystem>>,
+
+    /// Files of a SRC directory that do not pass are left out of the batch.
+    pub source_filter: filter::SourceFilter,
+
+    /// What to do with generated or specified DST files that already exist.
+    pub overwrite: OverwritePolicy,
+    /// Asked first about every existing or duplicate DST. Not saved in a [`plan::Plan`].
+    pub conflict_resolver: Option<Shared<dyn conflict::ConflictResolver>>,
+    /// For repeated runs, like `make`: file SRCs whose DST file was modified after them are not
+    /// yielded at all (and not subject to [`Self::overwrite`]), see [`SrcDstPairs::up_to_date`].
+    /// DST names are compared after [`Self::output_extension`] and the like.
+    pub incremental: bool,
+    /// Decides on the extension of each batch DST, or skips the SRC, by the first bytes of it.
+    /// May be called more than once per SRC, e.g. with [`OverwritePolicy::Error`].
+    /// Not saved in a [`plan::Plan`].
+    pub probe: Option<probe::Probe>,
+
+    /// Decides whether DST is SRC (or the directory of SRC) for the in-place checks.
+    /// `None` is [`identity::Canonicalize`]. Not saved in a [`plan::Plan`].
+    pub path_identity: Option<Shared<dyn identity::PathIdentity>>,
+}
+
+/// Whether `-` may stand for stdin and stdout, see [`SrcDstConfig::dash_is_stdio`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StdioPolicy {
+    #[default]
+    Allow,
+    /// [`SrcDstError::DisallowToStdout`] for stdout.
+    StdinOnly,
+    /// [`SrcDstError::DisallowFromStdin`] for stdin.
+    StdoutOnly,
+    Deny,
+}
+
+impl StdioPolicy {
+    /// Allowing what is given.
+    pub fn of(from_stdin: bool, to_stdout: bool) -> Self {
+        match (from_stdin, to_stdout) {
+            (true, true) => StdioPolicy::Allow,
+            (true, false) => StdioPolicy::StdinOnly,
+            (false, true) => StdioPolicy::StdoutOnly,
+            (false, false) => StdioPolicy::Deny,
+        }
+    }
+
+    pub fn from_stdin(self) -> bool {
+        matches!(self, StdioPolicy::Allow | StdioPolicy::StdinOnly)
+    }
+
+    pub fn to_stdout(self) -> bool {
+        matches!(self, StdioPolicy::Allow | StdioPolicy::StdoutOnly)
+    }
+}
+
+/// What gets a time-based name when DST is not provided, see [`SrcDstConfig::parse`].
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum AutoNamePolicy {
+    #[default]
+    FilesAndDirs,
+    /// [`SrcDstError::ForbidAutoTnamedDstDir`] for a SRC directory.
+    FilesOnly,
+    /// [`SrcDstError::ForbidAutoTnamedDstFile`] for a SRC file or stdin.
+    DirsOnly,
+    Never,
+}
+
+impl AutoNamePolicy {
+    /// Allowing what is given.
+    pub fn of(files: bool, dirs: bool) -> Self {
+        match (files, dirs) {
+            (true, true) => AutoNamePolicy::FilesAndDirs,
+            (true, false) => AutoNamePolicy::FilesOnly,
+            (false, true) => AutoNamePolicy::DirsOnly,
+            (false, false) => AutoNamePolicy::Never,
+        }
+    }
+
+    pub fn files(self) -> bool {
+        matches!(
+            self,
+            AutoNamePolicy::FilesAndDirs | AutoNamePolicy::FilesOnly
+        )
+    }
+
+    pub fn dirs(self) -> bool {
+        matches!(
+            self,
+            AutoNamePolicy::FilesAndDirs | AutoNamePolicy::DirsOnly
+        )
+    }
+}
+
+/// Whether DST may be SRC itself (also when specified as SRC), or the directory of a SRC
+/// directory.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum InplacePolicy {
+    /// [`SrcDstError::Inplaced`].
+    #[default]
+    Deny,
+    /// Written through [`ioers::WriteFileAtomic`] by [`Dst::output_for`] and [`run::Runner`].
+    Atomic,
+}
+
+impl InplacePolicy {
+    pub fn allows(self) -> bool {
+        match self {
+            InplacePolicy::Deny => false,
+            InplacePolicy::Atomic => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub enum CwdFallback {
+    /// Return the error.
+    #[default]
+    Fail,
+    /// [`std::env::temp_dir`].
+    TempDir,
+    Path(PathBuf),
+}
+
+/// Time-based names are unique anyway, see [`SrcDstPairs::allocate_tnamed`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+pub enum OverwritePolicy {
+    /// [`SrcDstError::DstExists`] on parsing, batch DSTs are all checked up front.
+    Error,
+    #[default]
+    Overwrite,
+    /// The pair is not yielded.
+    Skip,
+    /// `photo.png` => `photo-1.png`, `photo-2.png`... until a free name is found.
+    RenameWithCounter,
+    /// `photo.png` => `photo (2).png`, `photo (3).png`... like file managers do, skipping the
+    /// numbers already in the directory, for repeated imports into the same DST directory.
+    RenameParenthesized,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum SymlinkPolicy {
+    /// Walked as what they point to, broken ones are skipped.
+    Follow,
+    /// With a [`warning::Warning::SkippedSymlink`].
+    #[default]
+    Skip,
+    /// Walking fails with [`io::ErrorKind::InvalidInput`].
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum DstKind {
+    /// A DST that exists as a directory is a directory, otherwise it is a file.
+    #[default]
+    Guess,
+    /// E.g. the answer of a "Save As" dialog. [`SrcDstError::ManyToOne`] if SRC is a directory.
+    File,
+    /// [`SrcDstError::DstDirNotExist`] if it is not an existing directory.
+    Dir,
 }
 
 impl SrcDstConfig {
     pub fn new<S: AsRef<OsStr>>(default_extension: S) -> Self {
         Self {
-            allow_from_stdin: true,
-            allow_to_stdout: true,
-            auto_tnamed_dst_file: true,
-            auto_tnamed_dst_dir: true,
+            stdio: StdioPolicy::Allow,
+            deny_stdout_tty: false,
+            dash_is_stdio: true,
+            auto_name: AutoNamePolicy::FilesAndDirs,
+            tnamed_file_template: None,
+            tnamed_dir_template: None,
+            sidecar_tokens: false,
+            output_extension: None,
+            passthrough: Vec::new(),
             default_extension: default_extension.as_ref().to_owned(),
-            allow_inplace: false,
+            inplace: InplacePolicy::Deny,
+            collapse_duplicate_extensions: true,
+            name_policy: naming::NamePolicy::default(),
+            dst_buckets: None,
+            flatten: None,
+            dst_kind: DstKind::Guess,
+            allow_many_to_one: false,
+            sibling_suffix: None,
+            dst_resolver: None,
+            deterministic: false,
+            fixed_time: None,
+            capture_time: false,
+            cwd_fallback: CwdFallback::Fail,
+            containment: naming::Containment::Reject,
+            confine_to: None,
+            warnings: None,
+            hold_src_dir: false,
+            max_depth: 0,
+            symlinks: SymlinkPolicy::Skip,
+            ignore_files: cfg!(feature = "ignore"),
+            sort_spill_threshold: 0,
+            collation: collation::Collation::Bytewise,
+            traversal: collation::Traversal::DepthFirst,
+            listing_cache: None,
+            filesystem: None,
+            source_filter: filter::SourceFilter::default(),
+            overwrite: OverwritePolicy::Overwrite,
+            conflict_resolver: None,
+            incremental: false,
+            probe: None,
+            path_identity: None,
         }
     }
 
     pub fn new_with_allow_inplace<S: AsRef<OsStr>>(default_extension: S) -> Self {
         Self {
-            allow_from_stdin: true,
-            allow_to_stdout: true,
-            auto_tnamed_dst_file: true,
-            auto_tnamed_dst_dir: true,
-            default_extension: default_extension.as_ref().to_owned(),
-            allow_inplace: true,
+            inplace: InplacePolicy::Atomic,
+            ..Self::new(default_extension)
         }
     }
 
@@ -59,19 +442,253 @@ impl SrcDstConfig {
     /// Dir                ×      ×      ✓           4
     /// ```
     ///
-    /// 1. `allow_from_stdin`.
-    /// 2. `allow_to_stdout`.
-    /// 3. `auto_tnamed_dst_file`.
-    /// 4. `auto_tnamed_dst_dir`.
-    ///     Note: A directory with specified name will not be created automatically
-    ///     (an error will be returned if it does not exist).
-    /// 5. Note that [`std::env::current_dir`] will be used as output directory.
+    /// 1. [`StdioPolicy::from_stdin`].
+    /// 2. [`StdioPolicy::to_stdout`], and `deny_stdout_tty`.
+    /// 3. [`AutoNamePolicy::files`].
+    /// 4. [`AutoNamePolicy::dirs`].
+    ///    Note: A directory with specified name will not be created automatically
+    ///    (an error will be returned if it does not exist).
+    ///    The root directory (`/`, `C:\`) has no parent, its DST goes into the current directory.
+    /// 5. Note that [`std::env::current_dir`] will be used as output directory,
+    ///    see also [`Self::cwd_fallback`].
     /// 6. *Stdio will be always treated as a file.*
+    ///
+    /// Which column DST falls in is guessed from the filesystem, unless [`Self::dst_kind`] is set.
     pub fn parse<P: AsRef<Path>>(
         &self,
         src: P,
         dst: Option<P>,
     ) -> io::Result<Result<SrcDstPairs, SrcDstError>> {
+        let src = src.as_ref();
+        match self.try_parse(src, dst.as_ref().map(AsRef::as_ref)) {
+            Ok(pairs) => Ok(Ok(pairs)),
+            Err(ClarifyError::Usage(SrcDstError::SrcNotExist)) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("SRC '{}' does not exist", src.to_string_lossy()),
+            )),
+            Err(ClarifyError::Usage(e)) => Ok(Err(e)),
+            Err(ClarifyError::Environment(e)) => Err(e.0),
+        }
+    }
+
+    /// Same as [`Self::parse`], but with errors classified in a single type.
+    pub fn try_parse<P: AsRef<Path>>(
+        &self,
+        src: P,
+        dst: Option<P>,
+    ) -> Result<SrcDstPairs, ClarifyError> {
+        let pairs = self.try_parse_unconfined(src, dst)?;
+        if self.confine_to.is_some() {
+            match &pairs.src {
+                Source::Planned(planned) => {
+                    for (_, dst) in planned {
+                        match dst {
+                            Dst::Stdout => (),
+                            Dst::File(path) => self.confine(path)?,
+                            #[cfg(all(unix, feature = "unix-socket"))]
+                            Dst::UnixSocket(socket) => self.confine(&socket.path)?,
+                            Dst::Entry(ArchiveEntry { archive: None, .. }) => (),
+                            Dst::Entry(ArchiveEntry {
+                                archive: Some(archive),
+                                ..
+                            }) => self.confine(archive)?,
+                            #[cfg(feature = "sftp")]
+                            Dst::Remote(_) => return Err(SrcDstError::DstOutsideConfinement.into()),
+                        }
+                    }
+                }
+                _ => {
+                    match &pairs.dst {
+                        Drain::Single(dst) | Drain::Sibling { dir: dst, .. } => {
+                            self.confine(dst)?
+                        } // 批处理时，所有 DST 都在这个目录下
+                        Drain::Archive(Some(archive)) => self.confine(archive)?,
+                        Drain::Stdout | Drain::Archive(None) => (),
+                    }
+                }
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// Like [`Self::parse`] for several SRCs at once, e.g. `mytool a.png b.png dir/ -o out/`,
+    /// merged into planned pairs in the order given.
+    ///
+    /// With more than one distinct SRC, DST must be an existing directory or not be provided,
+    /// otherwise [`SrcDstError::ManyToOne`] or [`SrcDstError::DstDirNotExist`].
+    /// See [`Self::try_parse_many`] for the details.
+    pub fn parse_many<I, P>(
+        &self,
+        srcs: I,
+        dst: Option<P>,
+    ) -> io::Result<Result<SrcDstPairs, SrcDstError>>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let srcs: Vec<P> = srcs.into_iter().collect();
+        match self.try_parse_many(
+            srcs.iter().map(AsRef::as_ref),
+            dst.as_ref().map(AsRef::as_ref),
+        ) {
+            Ok(pairs) => Ok(Ok(pairs)),
+            Err(ClarifyError::Usage(SrcDstError::SrcNotExist)) => {
+                let src = srcs
+                    .iter()
+                    .map(AsRef::as_ref)
+                    .find(|src| !self.is_stdio(src) && !self.fs().exists(src));
+                Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "SRC '{}' does not exist",
+                        src.unwrap_or(Path::new("")).to_string_lossy()
+                    ),
+                ))
+            }
+            Err(ClarifyError::Usage(e)) => Ok(Err(e)),
+            Err(ClarifyError::Environment(e)) => Err(e.0),
+        }
+    }
+
+    /// Same as [`Self::parse_many`], but with errors classified in a single type.
+    ///
+    /// SRCs given twice (after canonicalization), or files also found in a given directory,
+    /// are yielded once. DSTs colliding across SRCs are dealt with by [`Self::overwrite`],
+    /// except that [`OverwritePolicy::Overwrite`] keeps both pairs.
+    /// Time-based named DSTs are not reserved, their directories are among
+    /// [`SrcDstPairs::dst_dirs`] instead.
+    pub fn try_parse_many<I, P>(&self, srcs: I, dst: Option<P>) -> Result<SrcDstPairs, ClarifyError>
+    where
+        I: IntoIterator<Item = P>,
+        P: AsRef<Path>,
+    {
+        let filesystem = self.fs();
+        let mut distinct = Vec::new();
+        for src in srcs {
+            let src = src.as_ref();
+            let src = match self.is_stdio(src) {
+                true => src.to_owned(),
+                false if !filesystem.exists(src) => return Err(SrcDstError::SrcNotExist.into()),
+                false => filesystem.canonicalize(src)?,
+            };
+            if !distinct.contains(&src) {
+                distinct.push(src);
+            }
+        }
+        let dst = dst.as_ref().map(AsRef::as_ref);
+        match distinct.len() {
+            0 => return Err(io::Error::new(io::ErrorKind::InvalidInput, "no SRC given").into()),
+            1 => return self.try_parse(distinct[0].as_path(), dst),
+            _ => (),
+        }
+        if let Some(dst) = dst {
+            if self.is_stdio(dst)
+                || self.dst_kind == DstKind::File
+                || filesystem.exists(dst) && !filesystem.is_dir(dst)
+            {
+                return Err(SrcDstError::ManyToOne.into());
+            } else if !filesystem.is_dir(dst) {
+                return Err(SrcDstError::DstDirNotExist.into());
+            }
+        }
+
+        let mut pairs = Vec::new();
+        let mut dirs = Vec::new();
+        let mut yielded = HashSet::new();
+        let mut taken = HashSet::new();
+        let mut numbers = naming::CopyNumbers::default();
+        for src in &distinct {
+            let mut part = self.try_parse(src.as_path(), dst)?;
+            dirs.extend(part.tnamed_dir().map(Path::to_owned));
+            dirs.extend(part.dst_dirs());
+            for (src, dst) in part.by_ref() {
+                if !yielded.insert(src.clone()) {
+                    continue;
+                }
+                let dst = match dst {
+                    Dst::File(path) if taken.contains(&path) => match self.overwrite {
+                        OverwritePolicy::Error => return Err(SrcDstError::DstExists.into()),
+                        OverwritePolicy::Overwrite => Dst::File(path),
+                        OverwritePolicy::Skip => continue,
+                        OverwritePolicy::RenameWithCounter => Dst::File(free_name(&path, |p| {
+                            occupied(filesystem, p) || taken.contains(p)
+                        })),
+                        OverwritePolicy::RenameParenthesized => {
+                            Dst::File(numbers.free_name(filesystem, &path, |p| {
+                                occupied(filesystem, p) || taken.contains(p)
+                            }))
+                        }
+                    },
+                    dst => dst,
+                };
+                if let Dst::File(path) = &dst {
+                    taken.insert(path.clone());
+                }
+                pairs.push((src, dst));
+            }
+            if let Some(e) = part.spill_error() {
+                return Err(io::Error::new(e.kind(), e.to_string()).into());
+            }
+        }
+
+        let mut many = SrcDstPairs::planned(pairs, None);
+        let mut seen = HashSet::new();
+        dirs.retain(|dir| seen.insert(dir.clone()));
+        many.planned_dirs = dirs;
+        many.filesystem = self.filesystem.clone();
+        Ok(many)
+    }
+
+    /// Checks `dst` against [`Self::confine_to`]. `dst` need not exist,
+    /// its nearest existing ancestor is canonicalized and the rest is joined lexically.
+    /// An IO error of kind `NotFound` if not even a root of it exists (in a [`Self::filesystem`]).
+    pub fn confine<P: AsRef<Path>>(&self, dst: P) -> Result<(), ClarifyError> {
+        let Some(root) = &self.confine_to else {
+            return Ok(());
+        };
+        let filesystem = self.fs();
+        let root = filesystem.canonicalize(root)?;
+        let dst = dst.as_ref();
+        let dst = match dst.is_absolute() {
+            true => dst.to_owned(),
+            false => filesystem.current_dir()?.join(dst),
+        };
+
+        // 绝对路径，一般至少根目录存在；虚拟文件系统就不一定了
+        let Some(ancestor) = dst.ancestors().find(|p| filesystem.exists(p)) else {
+            let e = format!("no ancestor of '{}' exists", dst.display());
+            return Err(io::Error::new(io::ErrorKind::NotFound, e).into());
+        };
+        let rest = dst.strip_prefix(ancestor).unwrap();
+        let base = filesystem.canonicalize(ancestor)?;
+        let resolved = match rest.as_os_str().is_empty() {
+            true => Some(base),
+            false => naming::join_contained(&base, rest, naming::Containment::Reject),
+        };
+        match resolved {
+            Some(resolved) if resolved.starts_with(&root) => Ok(()),
+            _ => Err(SrcDstError::DstOutsideConfinement.into()),
+        }
+    }
+
+    fn try_parse_unconfined<P: AsRef<Path>>(
+        &self,
+        src: P,
+        dst: Option<P>,
+    ) -> Result<SrcDstPairs, ClarifyError> {
+        #[cfg(feature = "sftp")]
+        if let Some(pairs) =
+            remote::parse_remote(self, src.as_ref(), dst.as_ref().map(AsRef::as_ref))?
+        {
+            return Ok(pairs);
+        }
+        #[cfg(all(unix, feature = "unix-socket"))]
+        if let Some(pairs) =
+            socket::parse_socket(self, src.as_ref(), dst.as_ref().map(AsRef::as_ref))?
+        {
+            return Ok(pairs);
+        }
+
         enum InnerSource {
             Stdin,
             File(PathBuf),
@@ -86,17 +703,15 @@ impl SrcDstConfig {
             NotProvided,
         }
 
+        let filesystem = self.fs();
         let src = src.as_ref();
-        let src = if src.as_os_str() == "-" {
+        let src = if self.is_stdio(src) {
             InnerSource::Stdin
-        } else if !src.exists() {
-            return Err(io::Error::new(
-                io::ErrorKind::PermissionDenied,
-                format!("SRC '{}' does not exist", src.to_string_lossy()),
-            ));
+        } else if !filesystem.exists(src) {
+            return Err(SrcDstError::SrcNotExist.into());
         } else {
-            let src = fs::canonicalize(src)?;
-            if src.is_file() {
+            let src = filesystem.canonicalize(src)?;
+            if filesystem.is_file(&src) {
                 InnerSource::File(src)
             } else {
                 InnerSource::Dir(src)
@@ -104,17 +719,30 @@ impl SrcDstConfig {
         };
 
         let dst = dst.as_ref();
+        let prompted = dst.is_none() && self.dst_resolver.is_some();
+        let sibling = match (&src, self.sibling_suffix.as_ref()) {
+            _ if dst.is_some() || prompted => None,
+            (InnerSource::Stdin, _) => None,
+            (_, suffix) => suffix,
+        };
         let mut dst = match dst {
             None => InnerDrain::NotProvided,
             Some(dst) => {
                 let dst = dst.as_ref();
-                if dst.as_os_str() == "-" {
+                if self.is_stdio(dst) {
                     InnerDrain::Stdout
-                } else if !dst.exists() {
+                } else if self.dst_kind == DstKind::File {
+                    match filesystem.exists(dst) {
+                        true => InnerDrain::File(filesystem.canonicalize(dst)?),
+                        false => InnerDrain::NotExist(dst.to_owned()),
+                    }
+                } else if self.dst_kind == DstKind::Dir && !filesystem.is_dir(dst) {
+                    return Err(SrcDstError::DstDirNotExist.into());
+                } else if !filesystem.exists(dst) {
                     InnerDrain::NotExist(dst.to_owned())
                 } else {
-                    let dst = fs::canonicalize(dst)?;
-                    if dst.is_file() {
+                    let dst = filesystem.canonicalize(dst)?;
+                    if filesystem.is_file(&dst) {
                         InnerDrain::File(dst)
                     } else {
                         InnerDrain::Dir(dst)
@@ -123,40 +751,67 @@ impl SrcDstConfig {
             }
         };
 
-        if matches!(src, InnerSource::Stdin) && !self.allow_from_stdin {
-            return Ok(Err(SrcDstError::DisallowFromStdin)); // 1
+        if matches!(src, InnerSource::Stdin) && !self.stdio.from_stdin() {
+            return Err(SrcDstError::DisallowFromStdin.into()); // 1
         }
-        if matches!(dst, InnerDrain::Stdout) && !self.allow_to_stdout {
-            return Ok(Err(SrcDstError::DisallowToStdout)); // 2
+        if matches!(dst, InnerDrain::Stdout) {
+            self.check_stdout()?; // 2
         }
-        if matches!(dst, InnerDrain::NotProvided) {
-            if matches!(src, InnerSource::Dir(_)) && !self.auto_tnamed_dst_dir {
-                return Ok(Err(SrcDstError::ForbidAutoTnamedDstDir)); // 4
-            } else if !self.auto_tnamed_dst_file {
-                return Ok(Err(SrcDstError::ForbidAutoTnamedDstFile)); // 3
+        if matches!(dst, InnerDrain::NotProvided) && !prompted && sibling.is_none() {
+            match matches!(src, InnerSource::Dir(_)) {
+                true if !self.auto_name.dirs() => {
+                    return Err(SrcDstError::ForbidAutoTnamedDstDir.into()); // 4
+                }
+                false if !self.auto_name.files() => {
+                    return Err(SrcDstError::ForbidAutoTnamedDstFile.into()); // 3
+                }
+                _ => (),
             }
         }
         if let InnerDrain::Dir(parent) = &dst {
             if let InnerSource::File(src) = &src {
-                if fs::canonicalize(parent)? == fs::canonicalize(src)?.parent().unwrap() {
+                if self.same_path(parent, src.parent().unwrap())? {
                     dst = InnerDrain::NotProvided; // 当 DST-Dir 与 SRC-File所在目录 相同时，切换至 tname
                 }
-            } else if !self.allow_inplace {
+            } else if !self.inplace.allows() {
                 if let InnerSource::Dir(src) = &src {
-                    if fs::canonicalize(parent)? == fs::canonicalize(src)? {
-                        return Ok(Err(SrcDstError::Inplaced));
+                    if self.same_path(parent, src)? {
+                        return Err(SrcDstError::Inplaced.into());
                     }
                 }
             }
         }
+        if let (InnerSource::File(src), InnerDrain::File(file)) = (&src, &mut dst) {
+            if self.same_path(src, file)? {
+                *file = src.clone(); // 原地编辑，见 ioers::WriteFileAtomic
+            }
+        }
 
         let mut tnamed = false;
+        let mut tnamed_file = false;
+        let mut held = HeldSrcDir::default();
         let (src, dst): (Source, Drain) = match src {
             InnerSource::Stdin | InnerSource::File(_) => {
-                fn dst_parent_src_name(src: &InnerSource, dst: &InnerDrain) -> io::Result<PathBuf> {
+                // 原样复制的 SRC 在命名时就保留自己的后缀
+                let passthrough = match &src {
+                    InnerSource::File(src) => {
+                        passthrough_extension(&self.passthrough, src).map(|extension| Self {
+                            output_extension: Some(extension.to_owned()),
+                            ..self.clone()
+                        })
+                    }
+                    InnerSource::Stdin | InnerSource::Dir(_) => None,
+                };
+                let config = passthrough.as_ref().unwrap_or(self);
+
+                fn dst_parent_src_name(
+                    config: &SrcDstConfig,
+                    src: &InnerSource,
+                    dst: &InnerDrain,
+                ) -> io::Result<PathBuf> {
                     let mut parent = match dst {
                         InnerDrain::Dir(parent) => parent.to_owned(),
-                        InnerDrain::NotProvided => fs::canonicalize(std::env::current_dir()?)?,
+                        InnerDrain::NotProvided => config.output_root()?,
                         _ => unreachable!(),
                     };
                     parent.push(match src {
@@ -177,212 +832,1818 @@ impl SrcDstConfig {
                     match dst {
                         InnerDrain::Stdout => Drain::Stdout,
                         InnerDrain::File(dst) => Drain::Single(dst),
-                        InnerDrain::Dir(_) => Drain::Single(dst_parent_src_name(&src, &dst)?),
+                        InnerDrain::Dir(_) => Drain::Single(
+                            config.normalize(dst_parent_src_name(config, &src, &dst)?),
+                        ),
                         InnerDrain::NotExist(dst) => Drain::Single(dst),
+                        InnerDrain::NotProvided if sibling.is_some() => {
+                            let InnerSource::File(src) = &src else {
+                                unreachable!()
+                            };
+                            let (mut dst, suffix) = (PathBuf::new(), sibling.unwrap());
+                            batch_dst(
+                                &mut dst,
+                                src.parent().unwrap(),
+                                sibling_name(src.file_name().unwrap(), suffix).as_ref(),
+                                None,
+                                config.output_extension.as_deref(),
+                                self.name_policy,
+                                self.collapse_duplicate_extensions,
+                            );
+                            if &dst == src && !self.inplace.allows() {
+                                return Err(SrcDstError::Inplaced.into());
+                            }
+                            Drain::Single(dst)
+                        }
                         InnerDrain::NotProvided => {
-                            // input.png => input-A01123-0456-0789.png
-                            // input.jpg => input.jpg-A01123-0456-0789.png
-
-                            let mut dst = dst_parent_src_name(&src, &dst)?;
-
-                            dst.extension()
-                                .and_then(|ext| Some(ext == self.default_extension))
-                                .unwrap_or(false)
-                                .then(|| dst.set_extension("")); // 如果后缀不错，那么就去掉
-                            dst.set_file_name(format!(
-                                "{}-{}{}",
-                                dst.as_os_str().to_string_lossy(),
-                                Katetime::now_datetime(),
-                                match self.default_extension.is_empty() {
-                                    true => String::with_capacity(0),
-                                    false =>
-                                        format!(".{}", self.default_extension.to_string_lossy()),
+                            tnamed_file = !prompted;
+                            let src_path = match &src {
+                                InnerSource::File(src) => Some(src.as_path()),
+                                _ => None,
+                            };
+                            let suggested = config
+                                .tnamed_file(dst_parent_src_name(config, &src, &dst)?, src_path)?;
+                            Drain::Single(match (prompted, &src) {
+                                (false, _) => suggested,
+                                (true, InnerSource::File(src)) => {
+                                    self.resolve(Some(src), false, suggested)?
                                 }
-                            ));
-
-                            Drain::Single(dst)
+                                (true, _) => self.resolve(None, false, suggested)?,
+                            })
                         }
                     },
                 )
             }
 
             InnerSource::Dir(src) => {
-                fn shallow_walk<P: AsRef<Path>>(src: P) -> io::Result<Vec<PathBuf>> {
-                    let mut files = fs::read_dir(src)?
-                        .filter_map(Result::ok)
-                        .filter_map(|p| {
-                            p.metadata()
-                                .ok()
-                                .and_then(|m| m.is_file().then(|| p.path()))
-                        })
-                        .collect::<Vec<_>>();
-                    files.sort_unstable_by(|a, b| b.cmp(a));
-                    Ok(files)
-                }
-
                 match dst {
-                    InnerDrain::Stdout => return Ok(Err(SrcDstError::ManyToOne)),
-                    InnerDrain::File(_) => return Ok(Err(SrcDstError::ManyToOne)),
-                    InnerDrain::Dir(dst) => (Source::Files(shallow_walk(src)?), Drain::Single(dst)),
-                    InnerDrain::NotExist(_) => return Ok(Err(SrcDstError::DstDirNotExist)),
+                    InnerDrain::Stdout if self.allow_many_to_one => (
+                        Source::Files(self.walk(&src, &mut held)?),
+                        Drain::Archive(None),
+                    ),
+                    InnerDrain::File(dst) | InnerDrain::NotExist(dst) if self.allow_many_to_one => {
+                        (
+                            Source::Files(self.walk(&src, &mut held)?),
+                            Drain::Archive(Some(dst)),
+                        )
+                    }
+                    InnerDrain::Stdout => return Err(SrcDstError::ManyToOne.into()),
+                    InnerDrain::File(_) => return Err(SrcDstError::ManyToOne.into()),
+                    InnerDrain::Dir(dst) => (
+                        Source::Files(self.walk(&src, &mut held)?),
+                        Drain::Single(dst),
+                    ),
+                    InnerDrain::NotExist(_) if self.dst_kind == DstKind::File => {
+                        return Err(SrcDstError::ManyToOne.into())
+                    }
+                    InnerDrain::NotExist(_) => return Err(SrcDstError::DstDirNotExist.into()),
+                    InnerDrain::NotProvided if sibling.is_some() => {
+                        let suffix = sibling.unwrap().clone();
+                        let outputs = |name: &OsStr| is_sibling_output(name, &suffix);
+                        (
+                            Source::Files(self.walk_skipping(&src, &mut held, &outputs)?),
+                            Drain::Sibling { dir: src, suffix },
+                        )
+                    }
                     InnerDrain::NotProvided => {
                         // ./inputs => ./inputs-A01123-0456-0789
-                        let mut dst = src
-                            .parent()
-                            .ok_or_else(|| {
-                                io::Error::new(
-                                    io::ErrorKind::PermissionDenied,
-                                    format!("parent directory of {src:?} are unavailable"),
-                                )
-                            })?
-                            .to_owned();
-                        dst.push(format!(
-                            "{}-{}",
-                            src.file_name().unwrap().to_string_lossy(),
-                            Katetime::now_datetime()
-                        ));
-
-                        tnamed = true;
-                        (Source::Files(shallow_walk(src)?), Drain::Single(dst))
+                        // /       => $CWD/root-A01123-0456-0789
+                        let mut dst = match src.parent() {
+                            Some(parent) => parent.to_owned(),
+                            None => self.output_root()?,
+                        };
+                        let name = src.file_name().unwrap_or(OsStr::new("root"));
+                        match &self.tnamed_dir_template {
+                            None => {
+                                let mut dir = name.to_owned();
+                                dir.push(format!("-{}", self.stamp(Some(&src))?));
+                                dst.push(dir)
+                            }
+                            Some(template) => {
+                                dst.push(self.tname(template, Path::new(name), Some(&src))?)
+                            }
+                        }
+
+                        if prompted {
+                            dst = self.resolve(Some(&src), true, dst)?;
+                        }
+                        tnamed = !prompted || !filesystem.exists(&dst);
+                        (
+                            Source::Files(self.walk(&src, &mut held)?),
+                            Drain::Single(dst),
+                        )
                     }
                 }
             }
         };
 
-        Ok(Ok(SrcDstPairs {
+        let mut pairs = SrcDstPairs {
             src,
             dst,
             tnamed_dir: tnamed,
+            tnamed_file,
+            held,
+            collapse_extensions: self.collapse_duplicate_extensions,
+            output_extension: self.output_extension.clone(),
+            passthrough: self.passthrough.clone(),
+            name_policy: self.name_policy,
+            buckets: self.dst_buckets,
+            flatten: self.flatten,
+            filesystem: self.filesystem.clone(),
+            planned_dirs: Vec::new(),
+            overwrite: self.overwrite,
+            conflict_resolver: self.conflict_resolver.clone(),
+            resolved: HashMap::new(),
+            incremental: self.incremental,
+            probe: self.probe.clone(),
+            up_to_date: 0,
+            scratch: Dst::Stdout,
+            yielded: HashSet::new(),
+            copy_numbers: naming::CopyNumbers::default(),
+            unsettled: Vec::new(),
             finished: false,
-        }))
+        };
+        pairs.check_siblings(self.inplace.allows())?;
+        pairs.apply_overwrite_policy()?;
+        Ok(pairs)
     }
-}
 
-#[non_exhaustive]
-#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
-pub enum SrcDstError {
-    #[error("disallow read from stdin")]
-    DisallowFromStdin = 1,
-    #[error("disallow write to stdout")]
-    DisallowToStdout,
-    #[error("forbid automatic time-based named DST file")]
-    ForbidAutoTnamedDstFile,
-    #[error("forbid automatic time-based named DST directory")]
-    ForbidAutoTnamedDstDir,
+    /// The batch SRCs, in reverse [`Self::collation`].
+    fn walk(&self, src: &Path, held: &mut HeldSrcDir) -> io::Result<FileList> {
+        self.walk_skipping(src, held, &|_| false)
+    }
 
-    #[error("there may be a potential to `open` and `create` the same file at the same time")]
-    Inplaced,
+    /// Also leaving out names (relative to `src`) that are `skipped`.
+    fn walk_skipping(
+        &self,
+        src: &Path,
+        held: &mut HeldSrcDir,
+        skipped: &dyn Fn(&OsStr) -> bool,
+    ) -> io::Result<FileList> {
+        let order = collation::Order::of(&self.collation)?.with_traversal(self.traversal);
+        let mut spiller = spill::Spiller::new(self.sort_spill_threshold, order);
+        let filtered = !self.source_filter.is_empty();
+        let mut push = |name: OsString| match filtered {
+            true if !self.source_filter.accepts(&src.join(&name)) => Ok(()),
+            _ if skipped(&name) => Ok(()),
+            _ => spiller.push(name),
+        };
+        self.walk_unfiltered(src, held, &mut push)?;
+        Ok(FileList {
+            root: src.to_owned(),
+            names: spiller.finish()?,
+        })
+    }
 
-    #[error("unable to write multiple files to one file")]
-    ManyToOne,
-    #[error("specified DST directory does not exist")]
-    DstDirNotExist,
-}
+    fn walk_unfiltered(
+        &self,
+        src: &Path,
+        held: &mut HeldSrcDir,
+        push: &mut dyn FnMut(OsString) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut ignores = ignore_files::IgnoreFiles::new(self.ignore_files)?;
+        if self.max_depth > 0 {
+            return deep_walk(
+                self.fs(),
+                src,
+                self.max_depth,
+                self.symlinks,
+                &|dir| self.source_filter.descends(dir),
+                &mut ignores,
+                |w| self.warn(w),
+                push,
+            );
+        }
+        #[cfg(unix)]
+        if self.hold_src_dir {
+            let dir = snapshot::HeldDir::open(src)?;
+            dir.file_names(|w| self.warn(w))?
+                .into_iter()
+                .try_for_each(push)?;
+            *held = Some(std::sync::Arc::new(dir));
+            return Ok(());
+        }
+        #[cfg(not(unix))]
+        let () = *held;
+        if let Some(cache) = &self.listing_cache {
+            let listing = match listing::Listing::load(cache, src) {
+                Ok(Some(listing)) => listing,
+                _ => {
+                    let listing = listing::Listing::scan(src, |w| self.warn(w))?;
+                    let _ = listing.save(cache, src); // 缓存只是为了加速，存不下也无妨
+                    listing
+                }
+            };
+            return listing
+                .files
+                .into_iter()
+                .try_for_each(|file| push(file.name));
+        }
+        ignores.enter(src, &mut |w| self.warn(w));
+        let mut push = |name: OsString| match ignores.ignored(&src.join(&name), false) {
+            true => Ok(()),
+            false => push(name),
+        };
+        shallow_names(self.fs(), src, self.symlinks, |w| self.warn(w), &mut push)
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Src {
-    File(PathBuf),
-    Stdin,
-}
+    pub(crate) fn warn(&self, warning: warning::Warning) {
+        if let Some(sink) = &self.warnings {
+            sink.0.warn(warning);
+        }
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
-pub enum Dst {
-    File(PathBuf),
-    Stdout,
-}
+    fn resolve(&self, src: Option<&Path>, batch: bool, suggested: PathBuf) -> io::Result<PathBuf> {
+        match &self.dst_resolver {
+            None => Ok(suggested),
+            Some(resolver) => resolver.0.resolve(&resolver::DstRequest {
+                src: src.map(Path::to_path_buf),
+                batch,
+                suggested,
+            }),
+        }
+    }
 
-#[derive(Debug)]
-pub struct SrcDstPairs {
-    src: Source,
-    dst: Drain,
+    /// `-`, see [`Self::dash_is_stdio`].
+    pub(crate) fn is_stdio(&self, path: &Path) -> bool {
+        self.dash_is_stdio && path.as_os_str() == "-"
+    }
 
-    tnamed_dir: bool,
-    finished: bool,
-}
+    /// See [`Self::stdio`] and [`Self::deny_stdout_tty`].
+    pub(crate) fn check_stdout(&self) -> Result<(), SrcDstError> {
+        use std::io::IsTerminal;
 
-impl SrcDstPairs {
-    /// **Before consuming the path pair, call this method to create time-based named directory!**
-    pub fn create_tnamed_dir(&self) -> io::Result<()> {
-        if let Drain::Single(dir) = &self.dst {
-            if self.tnamed_dir {
-                fs::create_dir(dir)?;
-            }
+        if !self.stdio.to_stdout() {
+            return Err(SrcDstError::DisallowToStdout);
+        }
+        match self.deny_stdout_tty && io::stdout().is_terminal() {
+            true => Err(SrcDstError::StdoutIsTty),
+            false => Ok(()),
         }
-        Ok(())
     }
 
-    pub fn is_batch(&self) -> bool {
-        matches!(self.src, Source::Files(_))
+    /// Joins an untrusted DST `name` onto `root`, guaranteeing the result stays inside it,
+    /// see [`naming::join_contained`].
+    pub fn join_dst<P: AsRef<Path>, N: AsRef<Path>>(
+        &self,
+        root: P,
+        name: N,
+    ) -> Result<PathBuf, SrcDstError> {
+        let (root, name) = (root.as_ref(), name.as_ref());
+        let dst = naming::join_contained(root, name, self.containment)
+            .ok_or(SrcDstError::DstEscapesRoot)?;
+        if dst != root.join(name) {
+            self.warn(warning::Warning::SanitizedName {
+                name: name.to_owned(),
+                dst: dst.clone(),
+            });
+        }
+        Ok(dst)
     }
-}
-
-impl Iterator for SrcDstPairs {
-    type Item = (Src, Dst);
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.finished {
-            return None;
+    /// The canonicalized current directory, or [`Self::cwd_fallback`].
+    pub(crate) fn output_root(&self) -> io::Result<PathBuf> {
+        let filesystem = self.fs();
+        let err = match (filesystem.current_dir()).and_then(|cwd| filesystem.canonicalize(&cwd)) {
+            Ok(cwd) => return Ok(cwd),
+            Err(e) => e,
+        };
+        match &self.cwd_fallback {
+            CwdFallback::Fail => Err(err),
+            CwdFallback::TempDir => filesystem.canonicalize(&std::env::temp_dir()),
+            CwdFallback::Path(path) => filesystem.canonicalize(path),
         }
+    }
 
-        match &self.dst {
-            Drain::Stdout => match &self.src {
-                Source::Stdin => {
-                    self.finished = true;
-                    Some((Src::Stdin, Dst::Stdout))
-                }
-                Source::File(src) => {
-                    self.finished = true;
-                    Some((Src::File(src.to_owned()), Dst::Stdout))
-                }
-                Source::Files(_) => unreachable!(),
-            },
+    pub(crate) fn fs(&self) -> &dyn vfs::FileSystem {
+        vfs::or_std(&self.filesystem)
+    }
 
-            Drain::Single(dst) => match &mut self.src {
-                Source::Stdin => {
-                    self.finished = true;
-                    Some((Src::Stdin, Dst::File(dst.to_owned())))
-                }
-                Source::File(src) => {
-                    self.finished = true;
-                    Some((Src::File(src.to_owned()), Dst::File(dst.to_owned())))
+    pub(crate) fn same_path(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        use identity::PathIdentity;
+
+        match (&self.path_identity, &self.filesystem) {
+            (Some(identity), _) => identity.0.same(a, b),
+            (None, None) => identity::Canonicalize.same(a, b),
+            (None, Some(filesystem)) => {
+                let canonical = |p| filesystem.0.canonicalize(p);
+                match (canonical(a), canonical(b)) {
+                    (Ok(a), Ok(b)) => Ok(a == b),
+                    _ => identity::Normalize.same(a, b),
                 }
-                Source::Files(srcs) => match srcs.pop() {
-                    None => None,
-                    Some(src) => {
-                        let dst = dst.join(src.file_name().unwrap());
-                        Some((Src::File(src), Dst::File(dst)))
-                    }
-                },
-            },
+            }
         }
     }
-}
 
-#[derive(Debug)]
-enum Source {
-    Stdin,
-    File(PathBuf),
-    /// 注意文件列表应该是倒过来排序的！这样就能把它们一个个 pop 出来了。
-    Files(Vec<PathBuf>),
-}
+    /// [`Self::output_extension`], or else [`Self::default_extension`].
+    fn dst_extension(&self) -> &OsStr {
+        self.output_extension
+            .as_deref()
+            .unwrap_or(&self.default_extension)
+    }
 
-#[derive(Debug)]
-enum Drain {
-    Stdout,
-    /// 注意这玩意必须手动拼接！（如果 SRC 是 [`Source::Files`] 的话）也就是文件名相同，但父目录不同。
-    Single(PathBuf),
-}
+    /// Applied to every DST file name this crate generates from SRC.
+    pub(crate) fn normalize(&self, mut dst: PathBuf) -> PathBuf {
+        if let Some(extension) = &self.output_extension {
+            dst.set_extension(extension);
+        }
+        match self.collapse_duplicate_extensions {
+            true => collapse_duplicate_extension(dst),
+            false => dst,
+        }
+    }
 
-/// 我该怎么做测试？只是简单跑一下`cargo test -- --nocapture`吗？
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// `dst` is the SRC file name joined to the output directory.
+    pub(crate) fn tnamed_file(&self, mut dst: PathBuf, src: Option<&Path>) -> io::Result<PathBuf> {
+        // input.png => input-A01123-0456-0789.png
+        // input.jpg => input.jpg-A01123-0456-0789.png
+        if let Some(template) = &self.tnamed_file_template {
+            let name = self.tname(template, dst.file_name().unwrap().as_ref(), src)?;
+            dst.set_file_name(name);
+            return Ok(self.normalize(dst));
+        }
 
-    #[test]
-    fn test() {
-        match SrcDstConfig::new("png").parse(".", None) {
-            Err(e) => println!("{e}"),
-            Ok(p) => match p {
+        let extension = self.dst_extension();
+        (dst.extension() == Some(extension) || self.output_extension.is_some())
+            .then(|| dst.set_extension("")); // 如果后缀不错（或者反正要换掉），那么就去掉
+        dst.set_file_name(format!(
+            "{}-{}{}",
+            dst.as_os_str().to_string_lossy(),
+            self.stamp(src)?,
+            match extension.is_empty() {
+                true => String::with_capacity(0),
+                false => format!(".{}", extension.to_string_lossy()),
+            }
+        ));
+        Ok(self.normalize(dst))
+    }
+
+    /// Expands a time-based name `template` for the SRC named `name`,
+    /// see [`Self::tnamed_file_template`].
+    fn tname(&self, template: &OsStr, name: &Path, src: Option<&Path>) -> io::Result<OsString> {
+        static COUNTER: AtomicU64 = AtomicU64::new(1);
+
+        let mut tokens = template::Tokens::of_src(name);
+        tokens.insert("default_ext", self.dst_extension());
+        if let (true, Some(src)) = (self.sidecar_tokens, src) {
+            tokens.insert_sidecar(src)?;
+        }
+        let uses = |token: &str| {
+            let token = format!("{{{token}}}");
+            (template.as_encoded_bytes())
+                .windows(token.len())
+                .any(|w| w == token.as_bytes())
+        };
+        if uses("datetime") {
+            tokens.insert("datetime", self.stamp(src)?); // 确定性模式下要读 SRC，用到时才算
+        }
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        tokens.insert("counter", count.to_string());
+        if uses("random") {
+            use std::hash::{BuildHasher, Hasher};
+
+            let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+            hasher.write_u64(count);
+            hasher.write_u128(
+                SystemTime::UNIX_EPOCH
+                    .elapsed()
+                    .unwrap_or_default()
+                    .as_nanos(),
+            );
+            tokens.insert("random", format!("{:08x}", hasher.finish() as u32));
+        }
+        // 元数据里的值可能是空的、`.`、`..`，不能让它指到别处
+        let name = tokens.expand(template);
+        match Path::new(&name).file_name() == Some(name.as_os_str()) {
+            true => Ok(name),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a file name", name.to_string_lossy()),
+            )),
+        }
+    }
+
+    /// The unique part of time-based names. `src` is hashed in deterministic mode if no time is set.
+    pub(crate) fn stamp(&self, src: Option<&Path>) -> io::Result<String> {
+        if let (true, Some(src)) = (self.capture_time, src) {
+            if let Some(taken) = capture_time(self.fs(), src)? {
+                return Ok(taken);
+            }
+        }
+        if !self.deterministic {
+            return Ok(Katetime::now_datetime().to_string());
+        }
+        if let Some(time) = self.fixed_time.or_else(source_date_epoch) {
+            return Ok(format_utc(time));
+        }
+        match src {
+            None => Ok(format_utc(SystemTime::UNIX_EPOCH)),
+            Some(src) if src.is_dir() => {
+                let mut hasher = digest::Sha256::new();
+                for file in shallow_walk(src, |_| ())?.iter().rev() {
+                    hasher.update(file.file_name().unwrap().to_string_lossy().as_bytes());
+                    let opened = audit::record(audit::Operation::Open, file, fs::File::open(file));
+                    hasher.update(&digest::Digest::of_reader(opened?)?.0);
+                }
+                Ok(hasher.finish().to_string()[..16].to_owned())
+            }
+            Some(src) => {
+                let opened = audit::record(audit::Operation::Open, src, fs::File::open(src))?;
+                Ok(digest::Digest::of_reader(opened)?.to_string()[..16].to_owned())
+            }
+        }
+    }
+}
+
+/// `None` for directories.
+fn capture_time(filesystem: &dyn vfs::FileSystem, src: &Path) -> io::Result<Option<String>> {
+    if filesystem.kind(src)? != vfs::EntryKind::File {
+        return Ok(None);
+    }
+    #[cfg(feature = "exif")]
+    {
+        let opened = audit::record(audit::Operation::Open, src, filesystem.open(src))?;
+        if let Some(taken) = exif::from_reader(opened)? {
+            return Ok(Some(taken.to_string()));
+        }
+    }
+    Ok(Some(format_utc(filesystem.modified(src)?)))
+}
+
+fn source_date_epoch() -> Option<SystemTime> {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(SystemTime::UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// `YYYYMMDDTHHMMSSZ`, times before 1970 are clamped.
+fn format_utc(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+/// `photo.png.png` => `photo.png`, ASCII case-insensitively. Only the file name is touched.
+pub fn collapse_duplicate_extension(mut path: PathBuf) -> PathBuf {
+    while let (Some(ext), Some(stem)) = (path.extension(), path.file_stem()) {
+        match Path::new(stem).extension() {
+            Some(inner) if inner.eq_ignore_ascii_case(ext) => path.set_extension(""),
+            _ => break,
+        };
+    }
+    path
+}
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+/// Process-wide, disabled by default. While enabled, everything in this crate that would create,
+/// write, move or remove something on disk returns [`io::ErrorKind::PermissionDenied`] instead,
+/// and [`run::Runner`] only simulates, as with [`run::Runner::dry_run`].
+/// Scratch files of [`ioers::Spool`] in the temporary directory are the only exception.
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::SeqCst);
+}
+
+pub fn is_read_only() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+pub(crate) fn check_writable(path: &Path) -> io::Result<()> {
+    match is_read_only() {
+        true => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("read-only mode, refusing to modify '{}'", path.display()),
+        )),
+        false => Ok(()),
+    }
+}
+
+#[cfg(unix)]
+pub(crate) type HeldSrcDir = Option<std::sync::Arc<snapshot::HeldDir>>;
+#[cfg(not(unix))]
+pub(crate) type HeldSrcDir = ();
+
+/// `name-2.png`, or `name-2` for a directory.
+fn numbered(path: &Path, n: u32, dir: bool) -> PathBuf {
+    let mut name = match (dir, path.file_stem()) {
+        (false, Some(stem)) => stem.to_owned(),
+        _ => path.file_name().unwrap_or_default().to_owned(),
+    };
+    name.push(format!("-{n}"));
+    if let (false, Some(ext)) = (dir, path.extension()) {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// The order pairs are yielded in batch mode, the same on every platform and filesystem:
+/// lexicographic by the (UTF-8-lossy) file name, ties broken by the raw OS bytes.
+pub fn batch_order(a: &Path, b: &Path) -> std::cmp::Ordering {
+    let name = |p: &Path| {
+        p.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+    name(a)
+        .cmp(&name(b))
+        .then_with(|| a.file_name().cmp(&b.file_name()))
+}
+
+/// The DST of the batch SRC `name` (see [`FileList::names`]) inside the DST directory `dir`,
+/// built in `buf` (keeping its capacity).
+fn batch_dst(
+    buf: &mut PathBuf,
+    dir: &Path,
+    name: &Path,
+    bucket: Option<&Path>,
+    output_extension: Option<&OsStr>,
+    name_policy: naming::NamePolicy,
+    collapse_extensions: bool,
+) {
+    buf.as_mut_os_string().clear();
+    buf.push(dir);
+    match bucket {
+        Some(bucket) => {
+            buf.extend(name.parent());
+            buf.push(bucket);
+            buf.push(name.file_name().unwrap_or_default());
+        }
+        None => buf.push(name),
+    }
+    if let Some(extension) = output_extension {
+        buf.set_extension(extension);
+    }
+    *buf = name_policy.apply(std::mem::take(buf)); // 没有规则时原样返回，不会重新分配
+    if collapse_extensions {
+        *buf = collapse_duplicate_extension(std::mem::take(buf));
+    }
+}
+
+/// `photo.jpg` as `photo.<suffix>.jpg`, see [`SrcDstConfig::sibling_suffix`].
+fn sibling_name<'a>(name: &'a OsStr, suffix: &OsStr) -> Cow<'a, OsStr> {
+    if suffix.is_empty() {
+        return Cow::Borrowed(name);
+    }
+    let name = Path::new(name);
+    let mut file_name = name.file_stem().unwrap_or_default().to_owned();
+    file_name.push(".");
+    file_name.push(suffix);
+    if let Some(extension) = name.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    Cow::Owned(name.with_file_name(file_name).into_os_string())
+}
+
+/// Named `<stem>.<suffix>.<ext>` by [`sibling_name`], whatever the extension. A SRC whose
+/// extension merely is the suffix is not one.
+fn is_sibling_output(name: &OsStr, suffix: &OsStr) -> bool {
+    let name = Path::new(name);
+    let mut marked = OsString::from(".");
+    marked.push(suffix);
+    let stem = name.file_stem().unwrap_or_default().as_encoded_bytes();
+    !suffix.is_empty() && name.extension().is_some() && stem.ends_with(marked.as_encoded_bytes())
+}
+
+/// `dst`, or the first of `stem-1.ext`, `stem-2.ext`... not `taken`.
+fn free_name(dst: &Path, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    match taken(dst) {
+        false => dst.to_owned(),
+        true => (1..)
+            .map(|n| numbered(dst, n, false))
+            .find(|dst| !taken(dst))
+            .unwrap(),
+    }
+}
+
+/// The extension a file SRC matching [`SrcDstConfig::passthrough`] keeps, empty if it has none.
+fn passthrough_extension<'a>(globs: &[String], src: &'a Path) -> Option<&'a OsStr> {
+    let name = src.file_name()?.to_str()?;
+    (globs.iter().any(|glob| run::glob_match(glob, name)))
+        .then(|| src.extension().unwrap_or_default())
+}
+
+/// An empty file dated to the epoch, left by [`run::StructureOnly::Placeholders`].
+pub(crate) fn is_placeholder(filesystem: &dyn vfs::FileSystem, path: &Path) -> bool {
+    filesystem.is_file(path)
+        && filesystem.len(path).is_ok_and(|len| len == 0)
+        && filesystem.modified(path).ok() == Some(SystemTime::UNIX_EPOCH)
+}
+
+/// Something other than a placeholder is at `path`, see [`is_placeholder`].
+fn occupied(filesystem: &dyn vfs::FileSystem, path: &Path) -> bool {
+    filesystem.exists(path) && !is_placeholder(filesystem, path)
+}
+
+/// The file `dst` was modified after `src`.
+fn up_to_date(filesystem: &dyn vfs::FileSystem, src: &Path, dst: &Path) -> bool {
+    let modified = |path| match filesystem.is_file(path) {
+        true => filesystem.modified(path),
+        false => Err(io::ErrorKind::Other.into()),
+    };
+    match (modified(src), modified(dst)) {
+        (Ok(src), Ok(dst)) => dst >= src,
+        _ => false,
+    }
+}
+
+/// 倒序，见 [`Source::Files`]。
+pub(crate) fn shallow_walk<P: AsRef<Path>>(
+    src: P,
+    warn: impl FnMut(warning::Warning),
+) -> io::Result<Vec<PathBuf>> {
+    let src = src.as_ref();
+    let mut files = Vec::new();
+    shallow_names(&vfs::StdFs, src, SymlinkPolicy::Skip, warn, &mut |name| {
+        files.push(src.join(name));
+        Ok(())
+    })?;
+    files.sort_unstable_by(|a, b| batch_order(b, a));
+    Ok(files)
+}
+
+/// The file names of [`shallow_walk`], unsorted, to `push`.
+pub(crate) fn shallow_names(
+    filesystem: &dyn vfs::FileSystem,
+    src: &Path,
+    symlinks: SymlinkPolicy,
+    mut warn: impl FnMut(warning::Warning),
+    push: &mut dyn FnMut(OsString) -> io::Result<()>,
+) -> io::Result<()> {
+    for (name, kind) in filesystem.read_dir(src)? {
+        match walked_kind(filesystem, &src.join(&name), kind, symlinks, &mut warn)? {
+            vfs::EntryKind::Symlink => (),
+            vfs::EntryKind::Dir => warn(warning::Warning::IgnoredSubdirectory(src.join(name))),
+            vfs::EntryKind::File => push(name)?,
+            vfs::EntryKind::Other => (),
+        }
+    }
+    Ok(())
+}
+
+/// [`shallow_names`] into sub-directories, up to `max_depth` levels, relative to `src`.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn deep_walk(
+    filesystem: &dyn vfs::FileSystem,
+    src: &Path,
+    max_depth: usize,
+    symlinks: SymlinkPolicy,
+    descend: &dyn Fn(&Path) -> bool,
+    ignores: &mut ignore_files::IgnoreFiles,
+    mut warn: impl FnMut(warning::Warning),
+    push: &mut dyn FnMut(OsString) -> io::Result<()>,
+) -> io::Result<()> {
+    #[allow(clippy::too_many_arguments)]
+    fn visit(
+        filesystem: &dyn vfs::FileSystem,
+        dir: &Path,
+        relative: &Path,
+        depth: usize,
+        symlinks: SymlinkPolicy,
+        descend: &dyn Fn(&Path) -> bool,
+        ignores: &mut ignore_files::IgnoreFiles,
+        push: &mut dyn FnMut(OsString) -> io::Result<()>,
+        warn: &mut dyn FnMut(warning::Warning),
+    ) -> io::Result<()> {
+        let entries = match filesystem.read_dir(dir) {
+            Ok(entries) => entries,
+            // 读不了的子目录跳过，SRC 目录本身读不了才算错
+            Err(e) if !relative.as_os_str().is_empty() => {
+                let (path, error) = (dir.to_owned(), e.to_string());
+                warn(warning::Warning::UnreadableDirectory { path, error });
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        ignores.enter(dir, warn);
+        for (name, kind) in entries {
+            let path = dir.join(&name);
+            match walked_kind(filesystem, &path, kind, symlinks, warn)? {
+                vfs::EntryKind::Symlink => (),
+                kind if ignores.ignored(&path, kind == vfs::EntryKind::Dir) => (),
+                vfs::EntryKind::Dir if depth == 0 => {
+                    warn(warning::Warning::IgnoredSubdirectory(path))
+                }
+                vfs::EntryKind::Dir if !descend(&path) => (),
+                vfs::EntryKind::Dir => {
+                    let relative = relative.join(&name);
+                    visit(
+                        filesystem,
+                        &path,
+                        &relative,
+                        depth - 1,
+                        symlinks,
+                        descend,
+                        ignores,
+                        push,
+                        warn,
+                    )?
+                }
+                vfs::EntryKind::File => push(relative.join(name).into_os_string())?,
+                vfs::EntryKind::Other => (),
+            }
+        }
+        ignores.leave();
+        Ok(())
+    }
+
+    visit(
+        filesystem,
+        src,
+        Path::new(""),
+        max_depth,
+        symlinks,
+        descend,
+        ignores,
+        push,
+        &mut warn,
+    )
+}
+
+/// `kind` of the entry `path` during a walk, with symlinks resolved by `policy`.
+/// Still [`vfs::EntryKind::Symlink`] if skipped.
+fn walked_kind(
+    filesystem: &dyn vfs::FileSystem,
+    path: &Path,
+    kind: vfs::EntryKind,
+    policy: SymlinkPolicy,
+    warn: &mut dyn FnMut(warning::Warning),
+) -> io::Result<vfs::EntryKind> {
+    if kind != vfs::EntryKind::Symlink {
+        return Ok(kind);
+    }
+    match policy {
+        SymlinkPolicy::Follow => match filesystem.kind(path) {
+            Ok(kind) => return Ok(kind),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (), // 断链
+            Err(e) => return Err(e),
+        },
+        SymlinkPolicy::Skip => (),
+        SymlinkPolicy::Error => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("symlink '{}' in SRC", path.display()),
+            ))
+        }
+    }
+    warn(warning::Warning::SkippedSymlink(path.to_owned()));
+    Ok(kind)
+}
+
+/// `names` (e.g. [`batch_order`]) component by component, so that files come before
+/// sub-directories of the same name prefix and each sub-directory stays together.
+pub(crate) fn tree_order(
+    a: &Path,
+    b: &Path,
+    names: impl Fn(&OsStr, &OsStr) -> std::cmp::Ordering,
+) -> std::cmp::Ordering {
+    let (mut a, mut b) = (a.iter(), b.iter());
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) => match names(x, y) {
+                std::cmp::Ordering::Equal => continue,
+                ordering => return ordering,
+            },
+            (x, y) => return x.is_some().cmp(&y.is_some()),
+        }
+    }
+}
+
+#[non_exhaustive]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SrcDstError {
+    #[error("disallow read from stdin")]
+    DisallowFromStdin = 1,
+    #[error("disallow write to stdout")]
+    DisallowToStdout,
+    #[error("forbid automatic time-based named DST file")]
+    ForbidAutoTnamedDstFile,
+    #[error("forbid automatic time-based named DST directory")]
+    ForbidAutoTnamedDstDir,
+
+    #[error("there may be a potential to `open` and `create` the same file at the same time")]
+    Inplaced,
+
+    #[error("unable to write multiple files to one file")]
+    ManyToOne,
+    #[error("specified DST directory does not exist")]
+    DstDirNotExist,
+
+    #[error("SRC does not exist")]
+    SrcNotExist,
+
+    #[error("DST escapes the output directory")]
+    DstEscapesRoot,
+
+    #[error("DST is outside the confined output directory")]
+    DstOutsideConfinement,
+
+    #[error("DST already exists")]
+    DstExists,
+
+    #[error("refuse to write to a terminal")]
+    StdoutIsTty,
+}
+
+/// Errors caused by the user, a bad combination of SRC and DST for example.
+/// CLIs usually print help and exit with code 2.
+pub type UsageError = SrcDstError;
+
+/// Errors caused by the environment (IO failures). CLIs usually exit with code 1.
+#[derive(Error, Debug)]
+#[error(transparent)]
+pub struct EnvironmentError(#[from] pub io::Error);
+
+#[derive(Error, Debug)]
+pub enum ClarifyError {
+    #[error(transparent)]
+    Usage(#[from] UsageError),
+    #[error(transparent)]
+    Environment(#[from] EnvironmentError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorClass {
+    Usage,
+    Environment,
+}
+
+impl ClarifyError {
+    pub fn class(&self) -> ErrorClass {
+        match self {
+            ClarifyError::Usage(_) => ErrorClass::Usage,
+            ClarifyError::Environment(_) => ErrorClass::Environment,
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self.class() {
+            ErrorClass::Usage => 2,
+            ErrorClass::Environment => 1,
+        }
+    }
+}
+
+impl From<io::Error> for ClarifyError {
+    fn from(e: io::Error) -> Self {
+        ClarifyError::Environment(EnvironmentError(e))
+    }
+}
+
+/// Which of the CLI arguments an error is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Culprit {
+    Src,
+    Dst,
+    Both,
+}
+
+impl SrcDstError {
+    pub fn culprit(&self) -> Culprit {
+        match self {
+            SrcDstError::DisallowFromStdin | SrcDstError::SrcNotExist => Culprit::Src,
+            SrcDstError::DisallowToStdout
+            | SrcDstError::StdoutIsTty
+            | SrcDstError::ForbidAutoTnamedDstFile
+            | SrcDstError::ForbidAutoTnamedDstDir
+            | SrcDstError::ManyToOne
+            | SrcDstError::DstDirNotExist
+            | SrcDstError::DstEscapesRoot
+            | SrcDstError::DstOutsideConfinement
+            | SrcDstError::DstExists => Culprit::Dst,
+            SrcDstError::Inplaced => Culprit::Both,
+        }
+    }
+
+    pub fn help(&self) -> &'static str {
+        match self {
+            SrcDstError::DisallowFromStdin => "specify an existing file or directory as SRC",
+            SrcDstError::DisallowToStdout => "specify a file or directory as DST",
+            SrcDstError::StdoutIsTty => {
+                "redirect the output to a file or a pipe, or specify a DST file"
+            }
+            SrcDstError::ForbidAutoTnamedDstFile => "specify a DST file",
+            SrcDstError::ForbidAutoTnamedDstDir => "specify a DST directory",
+            SrcDstError::Inplaced => "specify a DST directory other than SRC",
+            SrcDstError::ManyToOne => "specify a directory as DST",
+            SrcDstError::DstDirNotExist => "create the DST directory first",
+            SrcDstError::SrcNotExist => "check the SRC path",
+            SrcDstError::DstEscapesRoot => "remove `..` and absolute components from the DST name",
+            SrcDstError::DstOutsideConfinement => "choose a DST inside the confined directory",
+            SrcDstError::DstExists => "choose another DST, or move the existing one away",
+        }
+    }
+}
+
+impl l10n::Message for SrcDstError {
+    fn key(&self) -> &'static str {
+        match self {
+            SrcDstError::DisallowFromStdin => "disallow-from-stdin",
+            SrcDstError::DisallowToStdout => "disallow-to-stdout",
+            SrcDstError::ForbidAutoTnamedDstFile => "forbid-auto-tnamed-dst-file",
+            SrcDstError::ForbidAutoTnamedDstDir => "forbid-auto-tnamed-dst-dir",
+            SrcDstError::Inplaced => "inplaced",
+            SrcDstError::ManyToOne => "many-to-one",
+            SrcDstError::DstDirNotExist => "dst-dir-not-exist",
+            SrcDstError::SrcNotExist => "src-not-exist",
+            SrcDstError::DstEscapesRoot => "dst-escapes-root",
+            SrcDstError::DstOutsideConfinement => "dst-outside-confinement",
+            SrcDstError::DstExists => "dst-exists",
+            SrcDstError::StdoutIsTty => "stdout-is-tty",
+        }
+    }
+}
+
+/// Non-exhaustive, since kinds of SRC exist only behind features: matches need a wildcard arm
+/// to compile with any set of features enabled.
+///
+/// Since 0.3, [`Src::Remote`] and [`Src::UnixSocket`] do not exist without their feature,
+/// instead of failing at runtime.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Src {
+    File(PathBuf),
+    Stdin,
+    #[cfg(feature = "sftp")]
+    Remote(remote::RemotePath),
+    /// Only on Unix.
+    #[cfg(all(unix, feature = "unix-socket"))]
+    UnixSocket(socket::SocketPath),
+}
+
+/// Non-exhaustive like [`Src`], for the same reason.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Dst {
+    File(PathBuf),
+    Stdout,
+    #[cfg(feature = "sftp")]
+    Remote(remote::RemotePath),
+    /// Only on Unix.
+    #[cfg(all(unix, feature = "unix-socket"))]
+    UnixSocket(socket::SocketPath),
+    /// Only produced with [`SrcDstConfig::allow_many_to_one`].
+    Entry(ArchiveEntry),
+}
+
+/// A member of the single archive a SRC directory is packed into.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ArchiveEntry {
+    /// `None` for stdout.
+    pub archive: Option<PathBuf>,
+    /// Relative to the SRC directory, kept as is (no output extension nor name policy).
+    pub name: PathBuf,
+}
+
+/// Stdio is shown as `-`, just like it is specified.
+impl fmt::Display for Src {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Src::File(path) => write!(f, "{}", path.display()),
+            Src::Stdin => f.write_str("-"),
+            #[cfg(feature = "sftp")]
+            Src::Remote(remote) => write!(f, "{remote}"),
+            #[cfg(all(unix, feature = "unix-socket"))]
+            Src::UnixSocket(socket) => write!(f, "{socket}"),
+        }
+    }
+}
+
+/// Stdio is shown as `-`, just like it is specified.
+impl fmt::Display for Dst {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dst::File(path) => write!(f, "{}", path.display()),
+            Dst::Stdout => f.write_str("-"),
+            #[cfg(feature = "sftp")]
+            Dst::Remote(remote) => write!(f, "{remote}"),
+            #[cfg(all(unix, feature = "unix-socket"))]
+            Dst::UnixSocket(socket) => write!(f, "{socket}"),
+            Dst::Entry(ArchiveEntry { archive, name }) => match archive {
+                Some(archive) => write!(f, "{}#{}", archive.display(), name.display()),
+                None => write!(f, "-#{}", name.display()),
+            },
+        }
+    }
+}
+
+/// In batch mode, pairs are yielded in [`batch_order`] of SRC.
+#[derive(Debug)]
+pub struct SrcDstPairs {
+    src: Source,
+    dst: Drain,
+
+    tnamed_dir: bool,
+    tnamed_file: bool,
+    held: HeldSrcDir,
+    overwrite: OverwritePolicy,
+    conflict_resolver: Option<Shared<dyn conflict::ConflictResolver>>,
+    /// Of DSTs found in conflict up front, see [`OverwritePolicy::Error`].
+    resolved: HashMap<PathBuf, conflict::Resolution>,
+    incremental: bool,
+    probe: Option<probe::Probe>,
+    /// 因为已是最新而没有给出的 SRC 个数。
+    up_to_date: usize,
+    /// 给 [`Self::next_borrowed`] 反复使用的缓冲。
+    scratch: Dst,
+    /// 批量模式下已经给出的 DST，避免同名的 SRC 互相覆盖。
+    yielded: HashSet<PathBuf>,
+    copy_numbers: naming::CopyNumbers,
+    collapse_extensions: bool,
+    output_extension: Option<OsString>,
+    passthrough: Vec<String>,
+    name_policy: naming::NamePolicy,
+    buckets: Option<naming::Buckets>,
+    flatten: Option<naming::Flatten>,
+    filesystem: Option<Shared<dyn vfs::FileSystem>>,
+    /// [`plan::Plan::dst_dirs`]。
+    planned_dirs: Vec<PathBuf>,
+    /// 被 [`Iterator::next`] 略过的，见 [`SrcDstPairs::take_unsettled`]。
+    unsettled: Vec<SrcDstPairError>,
+    finished: bool,
+}
+
+impl SrcDstPairs {
+    fn planned(mut pairs: Vec<(Src, Dst)>, tnamed_dir: Option<PathBuf>) -> Self {
+        pairs.reverse();
+        Self {
+            src: Source::Planned(pairs),
+            tnamed_dir: tnamed_dir.is_some(),
+            tnamed_file: false,
+            held: HeldSrcDir::default(),
+            overwrite: OverwritePolicy::Overwrite,
+            conflict_resolver: None,
+            resolved: HashMap::new(),
+            incremental: false,
+            probe: None,
+            up_to_date: 0,
+            scratch: Dst::Stdout,
+            yielded: HashSet::new(),
+            copy_numbers: naming::CopyNumbers::default(),
+            dst: tnamed_dir.map_or(Drain::Stdout, Drain::Single),
+            collapse_extensions: false,
+            output_extension: None,
+            passthrough: Vec::new(),
+            name_policy: naming::NamePolicy::default(),
+            buckets: None,
+            flatten: None,
+            filesystem: None,
+            planned_dirs: Vec::new(),
+            unsettled: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// **Before consuming the path pair, call this method to create time-based named directory!**
+    pub fn create_tnamed_dir(&self) -> io::Result<()> {
+        if let Drain::Single(dir) = &self.dst {
+            if self.tnamed_dir {
+                check_writable(dir)?;
+                audit::record(audit::Operation::CreateDir, dir, fs::create_dir(dir))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Safe against other processes picking the same time-based name in the same second:
+    /// creates the time-based named directory, or an empty time-based named file,
+    /// retrying with `-2`, `-3`... appended to the name while it already exists.
+    /// Instead of [`Self::create_tnamed_dir`], see [`Self::tnamed_dir`] and [`Self::tnamed_file`]
+    /// for what was actually created.
+    pub fn allocate_tnamed(&mut self) -> io::Result<()> {
+        let Drain::Single(dst) = &mut self.dst else {
+            return Ok(());
+        };
+        if !self.tnamed_dir && !self.tnamed_file {
+            return Ok(());
+        }
+        check_writable(dst)?;
+        let create = |path: &Path| match self.tnamed_dir {
+            true => audit::record(audit::Operation::CreateDir, path, fs::create_dir(path)),
+            false => {
+                let file = fs::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .open(path);
+                audit::record(audit::Operation::Create, path, file.map(drop))
+            }
+        };
+        if matches!(self.src, Source::Planned(_)) {
+            return create(dst); // 规划好的 DST 已经在这个目录下了，不能改名
+        }
+
+        let original = dst.clone();
+        for n in 2.. {
+            match create(dst) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    *dst = numbered(&original, n, self.tnamed_dir);
+                }
+                result => return result,
+            }
+        }
+        unreachable!()
+    }
+
+    /// See [`SrcDstConfig::hold_src_dir`].
+    #[cfg(unix)]
+    pub fn held_dir(&self) -> Option<&std::sync::Arc<snapshot::HeldDir>> {
+        self.held.as_ref()
+    }
+
+    /// Sub-directories of the DST directory mirroring those of SRC that will be yielded,
+    /// shallowest first, see [`SrcDstConfig::max_depth`].
+    pub fn dst_dirs(&self) -> Vec<PathBuf> {
+        if let Source::Planned(_) = &self.src {
+            return self.planned_dirs.clone();
+        }
+        let (Source::Files(files), Drain::Single(dst) | Drain::Sibling { dir: dst, .. }) =
+            (&self.src, &self.dst)
+        else {
+            return Vec::new();
+        };
+        let mut relative = std::collections::BTreeSet::new();
+        let each = files.names.each(|name| {
+            let parent = Path::new(name).parent().filter(|_| self.flatten.is_none());
+            let bucketed = self.buckets.map(|buckets| {
+                let parent = parent.unwrap_or(Path::new(""));
+                parent.join(buckets.of(vfs::or_std(&self.filesystem), &files.root.join(name)))
+            });
+            let dirs = (bucketed.as_deref())
+                .or(parent)
+                .into_iter()
+                .flat_map(Path::ancestors);
+            relative.extend(
+                dirs.filter(|dir| !dir.as_os_str().is_empty())
+                    .map(Path::to_owned),
+            );
+        });
+        if each.is_err() {
+            return Vec::new();
+        }
+        relative.into_iter().map(|dir| dst.join(dir)).collect()
+    }
+
+    /// **After [`Self::create_tnamed_dir`], before consuming the path pairs**, when recursive.
+    /// Returns the directories that did not exist yet. Each is created once, and only those
+    /// not in a newly created one are looked up first.
+    pub fn create_dst_dirs(&self) -> io::Result<Vec<PathBuf>> {
+        let mut created = Vec::new();
+        let mut fresh = HashSet::new();
+        for dir in self.dst_dirs() {
+            let in_fresh = dir.parent().is_some_and(|parent| fresh.contains(parent));
+            if in_fresh || !dir.is_dir() {
+                check_writable(&dir)?;
+                audit::record(audit::Operation::CreateDir, &dir, fs::create_dir(&dir))?;
+                fresh.insert(dir.clone());
+                created.push(dir);
+            }
+        }
+        Ok(created)
+    }
+
+    /// Like [`Iterator::next`], but in batch mode DST is built in a buffer reused across calls,
+    /// instead of allocated for every pair. This saves one allocation per pair, not all of them:
+    /// the SRC is still allocated, and the DST is still copied whenever the DSTs given so far are
+    /// remembered to number colliding ones, i.e. with an [`OverwritePolicy`] other than
+    /// [`OverwritePolicy::Overwrite`], a [`SrcDstConfig::conflict_resolver`], or DSTs renamed
+    /// or flattened.
+    pub fn next_borrowed(&mut self) -> Option<(Src, &Dst)> {
+        let batch = matches!(
+            (&self.src, &self.dst),
+            (Source::Files(_), Drain::Single(_) | Drain::Sibling { .. })
+        );
+        if self.finished || !batch {
+            let (src, dst) = self.next()?;
+            self.scratch = dst;
+            return Some((src, &self.scratch));
+        }
+        let mut buf = match std::mem::replace(&mut self.scratch, Dst::Stdout) {
+            Dst::File(buf) => buf,
+            _ => PathBuf::new(),
+        };
+        let src = loop {
+            match self.next_batch(&mut buf) {
+                Some(Ok(src)) => break Some(src),
+                Some(Err(e)) => self.unsettled.push(e),
+                None => break None,
+            }
+        };
+        self.scratch = Dst::File(buf);
+        Some((Src::File(src?), &self.scratch))
+    }
+
+    /// Like [`Iterator::next`], but file SRCs are checked to still be files right before being
+    /// yielded, and an error ending the iteration early is yielded too.
+    pub fn try_next(&mut self) -> Option<Result<(Src, Dst), SrcDstPairError>> {
+        if let Some(e) = self.unsettled.pop() {
+            return Some(Err(e));
+        }
+        let next = match self.next_pair() {
+            Some(Ok(pair)) => Some(pair),
+            Some(Err(e)) => return Some(Err(e)),
+            None => None,
+        };
+        let Some((src, dst)) = next else {
+            let e = self.spill_error().filter(|_| !self.finished)?;
+            let e = io::Error::new(e.kind(), e.to_string());
+            self.finished = true;
+            return Some(Err(SrcDstPairError::Listing(e)));
+        };
+        if let Src::File(path) = &src {
+            match vfs::or_std(&self.filesystem).kind(path) {
+                Ok(vfs::EntryKind::File) => (),
+                Ok(_) => return Some(Err(SrcDstPairError::SrcGone(path.clone()))),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                    return Some(Err(SrcDstPairError::SrcGone(path.clone())))
+                }
+                Err(error) => {
+                    let path = path.clone();
+                    return Some(Err(SrcDstPairError::Src { path, error }));
+                }
+            }
+        }
+        Some(Ok((src, dst)))
+    }
+
+    /// The batch SRCs left out so far by [`Iterator::next`] and [`Self::next_borrowed`],
+    /// because their DST could not be settled, see [`SrcDstPairError::Dst`].
+    /// [`Self::try_next`] yields them instead.
+    pub fn take_unsettled(&mut self) -> Vec<SrcDstPairError> {
+        std::mem::take(&mut self.unsettled)
+    }
+
+    /// An iterator of [`Self::try_next`], for batch consumers to skip or report individual
+    /// failures instead of aborting.
+    pub fn checked(self) -> CheckedPairs {
+        CheckedPairs(self)
+    }
+
+    /// How many SRCs have been left out so far because their DST is up to date,
+    /// see [`SrcDstConfig::incremental`].
+    pub fn up_to_date(&self) -> usize {
+        self.up_to_date
+    }
+
+    /// Whether any pair goes to stdout, directly or as an archive entry. Such pairs must not be
+    /// processed concurrently, or their output interleaves, see [`Self::process_parallel`].
+    pub fn writes_to_stdout(&self) -> bool {
+        match (&self.src, &self.dst) {
+            (Source::Planned(pairs), _) => pairs.iter().any(|(_, dst)| {
+                matches!(
+                    dst,
+                    Dst::Stdout | Dst::Entry(ArchiveEntry { archive: None, .. })
+                )
+            }),
+            (_, Drain::Stdout | Drain::Archive(None)) => true,
+            (_, Drain::Single(_) | Drain::Sibling { .. } | Drain::Archive(Some(_))) => false,
+        }
+    }
+
+    /// How many pairs are left at most, as some may still be left out by [`SrcDstConfig::overwrite`]
+    /// and [`SrcDstConfig::incremental`]. Unknown when the SRC names have been spilled to disk.
+    pub fn remaining(&self) -> Option<usize> {
+        match (&self.src, self.finished) {
+            (_, true) => Some(0),
+            (Source::Planned(pairs), _) => Some(pairs.len()),
+            (Source::Files(files), _) => files.names.len(),
+            (Source::Stdin | Source::File(_), _) => Some(1),
+        }
+    }
+
+    /// Reorders the pairs not yielded yet, the least by `cmp` coming next, e.g. to bump the
+    /// files a user asked for to the front. Names spilled to disk are read back into memory.
+    pub fn reprioritize<F>(&mut self, mut cmp: F) -> io::Result<()>
+    where
+        F: FnMut(&Src, &Src) -> std::cmp::Ordering,
+    {
+        match &mut self.src {
+            // 倒序存放，所以比较也倒过来
+            Source::Planned(pairs) => pairs.sort_by(|(a, _), (b, _)| cmp(b, a)),
+            Source::Files(files) => {
+                let mut names = Vec::with_capacity(files.names.len().unwrap_or(0));
+                files
+                    .names
+                    .each(|name| names.push((Src::File(files.root.join(name)), name.to_owned())))?;
+                names.sort_by(|(a, _), (b, _)| cmp(b, a));
+                files.names =
+                    spill::Names::Memory(names.into_iter().map(|(_, name)| name).collect());
+            }
+            Source::Stdin | Source::File(_) => (),
+        }
+        Ok(())
+    }
+
+    /// Pops the next batch SRC, with its DST built in `buf`, or the error of a SRC whose DST
+    /// can not be settled.
+    fn next_batch(&mut self, buf: &mut PathBuf) -> Option<Result<PathBuf, SrcDstPairError>> {
+        let renames = self.renames();
+        loop {
+            let (Source::Files(files), Drain::Single(dir) | Drain::Sibling { dir, .. }) =
+                (&mut self.src, &self.dst)
+            else {
+                return None;
+            };
+            let suffix = match &self.dst {
+                Drain::Sibling { suffix, .. } => suffix.as_os_str(),
+                _ => OsStr::new(""),
+            };
+            let name = files.names.pop()?;
+            let probed = self
+                .probe
+                .as_ref()
+                .map(|probe| probe.decide(vfs::or_std(&self.filesystem), &files.root.join(&name)));
+            let extension = match probed {
+                Some(ProbeDecision::Skip) => continue,
+                Some(ProbeDecision::Extension(extension)) => Some(extension),
+                Some(ProbeDecision::Keep) | None => None,
+            };
+            let extension = match passthrough_extension(&self.passthrough, name.as_ref()) {
+                Some(kept) => Some(kept.to_owned()),
+                None => extension,
+            };
+            let filesystem = vfs::or_std(&self.filesystem);
+            let bucket =
+                (self.buckets).map(|buckets| buckets.of(filesystem, &files.root.join(&name)));
+            let flat = self
+                .flatten
+                .map(|_| Path::new(&name).file_name().unwrap_or_default());
+            batch_dst(
+                buf,
+                dir,
+                sibling_name(flat.unwrap_or(&name), suffix).as_ref(),
+                bucket.as_deref(),
+                extension.as_deref().or(self.output_extension.as_deref()),
+                self.name_policy,
+                self.collapse_extensions,
+            );
+            if let Some(flatten) = self.flatten {
+                *buf = flatten.free_name(buf, name.as_ref(), |dst| self.yielded.contains(dst));
+            }
+            if renames {
+                *buf = free_name(buf, |dst| self.yielded.contains(dst)); // 改名后撞名了
+            }
+            let src = files.root.join(name);
+            if self.incremental && up_to_date(vfs::or_std(&self.filesystem), &src, buf) {
+                self.up_to_date += 1;
+                continue;
+            }
+            // 解析时没有的 DST 也可能在那之后出现
+            match self.settle(Some(&src), buf, false) {
+                Ok(true) => return Some(Ok(src)),
+                Ok(false) => (),
+                Err(error) => {
+                    let dst = buf.clone();
+                    return Some(Err(SrcDstPairError::Dst { src, dst, error }));
+                }
+            }
+        }
+    }
+
+    /// Like [`Self::next_batch`], for DSTs other than a directory, with the name relative to
+    /// the root. Only [`SrcDstConfig::probe`] applies, there is no DST file to name or to
+    /// compare for [`SrcDstConfig::incremental`].
+    fn next_streamed(&mut self) -> Option<(PathBuf, OsString)> {
+        loop {
+            let Source::Files(files) = &mut self.src else {
+                return None;
+            };
+            let name = files.names.pop()?;
+            let src = files.root.join(&name);
+            let filesystem = vfs::or_std(&self.filesystem);
+            match self
+                .probe
+                .as_ref()
+                .map(|probe| probe.decide(filesystem, &src))
+            {
+                Some(ProbeDecision::Skip) => continue,
+                _ => return Some((src, name)),
+            }
+        }
+    }
+
+    /// Deals with `buf`, the DST of `src`, colliding with an existing file or a DST yielded
+    /// before, see [`SrcDstConfig::overwrite`] and [`SrcDstConfig::conflict_resolver`].
+    /// `false` to leave the pair out. Unless `checked` up front, [`OverwritePolicy::Error`]
+    /// is an error here.
+    pub(crate) fn settle(
+        &mut self,
+        src: Option<&Path>,
+        buf: &mut PathBuf,
+        checked: bool,
+    ) -> Result<bool, SrcDstError> {
+        let filesystem = vfs::or_std(&self.filesystem);
+        let taken = |dst: &Path| occupied(filesystem, dst) || self.yielded.contains(dst);
+        let resolver = self.conflict_resolver.as_ref();
+        let kind = match resolver.is_some() {
+            true if self.yielded.contains(buf.as_path()) => Some(ConflictKind::Duplicate),
+            true if occupied(filesystem, buf) => Some(ConflictKind::Exists),
+            _ => None,
+        };
+        let resolution = match kind {
+            Some(kind) => self.resolved.remove(buf.as_path()).unwrap_or_else(|| {
+                let dst = buf.as_path();
+                conflict::consult(resolver, Conflict { kind, src, dst })
+            }),
+            None => Resolution::Default,
+        };
+        let (policy, renamed) = resolution.or_policy(self.overwrite);
+        if let Some(renamed) = renamed {
+            *buf = renamed;
+        }
+        match policy {
+            OverwritePolicy::Error if !checked && taken(buf) => return Err(SrcDstError::DstExists),
+            OverwritePolicy::Error | OverwritePolicy::Overwrite => (),
+            OverwritePolicy::Skip if taken(buf) => return Ok(false),
+            OverwritePolicy::Skip => (),
+            OverwritePolicy::RenameWithCounter if taken(buf) => *buf = free_name(buf, taken),
+            OverwritePolicy::RenameWithCounter => (),
+            OverwritePolicy::RenameParenthesized if taken(buf) => {
+                *buf = self.copy_numbers.free_name(filesystem, buf, taken)
+            }
+            OverwritePolicy::RenameParenthesized => (),
+        }
+        if self.overwrite != OverwritePolicy::Overwrite
+            || resolver.is_some()
+            || self.flatten.is_some()
+            || self.renames()
+        {
+            self.yielded.insert(buf.clone());
+        }
+        Ok(true)
+    }
+
+    /// Whether batch DSTs are named otherwise than their SRC, so that two SRCs (e.g. `a.jpg` and
+    /// `a.gif` given an output extension) may get the same one, which is then numbered.
+    fn renames(&self) -> bool {
+        !self.name_policy.is_noop()
+            || self.collapse_extensions
+            || self.output_extension.is_some()
+            || self.probe.is_some()
+    }
+
+    /// See [`SrcDstConfig::sibling_suffix`], only an empty suffix can name a DST like a SRC.
+    fn check_siblings(&self, allow_inplace: bool) -> Result<(), ClarifyError> {
+        let (Source::Files(files), Drain::Sibling { suffix, .. }) = (&self.src, &self.dst) else {
+            return Ok(());
+        };
+        if !suffix.is_empty() {
+            return Ok(());
+        }
+        let mut srcs = HashSet::new();
+        files.names.each(|name| {
+            srcs.insert(PathBuf::from(name));
+        })?;
+        let mut dst = PathBuf::new();
+        for src in &srcs {
+            batch_dst(
+                &mut dst,
+                Path::new(""),
+                src,
+                None,
+                passthrough_extension(&self.passthrough, src).or(self.output_extension.as_deref()),
+                self.name_policy,
+                self.collapse_extensions,
+            );
+            match (&dst == src, srcs.contains(&dst)) {
+                (true, _) if allow_inplace => (),
+                (true, _) | (false, true) => return Err(SrcDstError::Inplaced.into()),
+                (false, false) => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_overwrite_policy(&mut self) -> Result<(), ClarifyError> {
+        let filesystem = vfs::or_std(&self.filesystem);
+        let renames = self.renames();
+        // 归档模式下 DST 是单个文件，即便 SRC 是一堆文件
+        let single_file = match (&self.src, &self.dst) {
+            (Source::Files(_), dst) => matches!(dst, Drain::Archive(_)),
+            _ => !self.tnamed_file,
+        };
+        let suffix = match &self.dst {
+            Drain::Sibling { suffix, .. } => suffix.clone(),
+            _ => OsString::new(),
+        };
+        let (Drain::Single(dst) | Drain::Sibling { dir: dst, .. } | Drain::Archive(Some(dst))) =
+            &mut self.dst
+        else {
+            return Ok(());
+        };
+        match &self.src {
+            Source::File(src) if src == dst => (), // 原地编辑
+            Source::File(src)
+                if self.incremental && up_to_date(vfs::or_std(&self.filesystem), src, dst) =>
+            {
+                self.up_to_date += 1;
+                self.finished = true;
+            }
+            _ if single_file && filesystem.is_file(dst) && !is_placeholder(filesystem, dst) => {
+                let src = match &self.src {
+                    Source::File(src) => Some(src.as_path()),
+                    _ => None,
+                };
+                let kind = ConflictKind::Exists;
+                let resolution =
+                    conflict::consult(self.conflict_resolver.as_ref(), Conflict { kind, src, dst });
+                match resolution.or_policy(self.overwrite) {
+                    (_, Some(renamed)) => *dst = renamed,
+                    (OverwritePolicy::Error, _) => return Err(SrcDstError::DstExists.into()),
+                    (OverwritePolicy::Overwrite, _) => (),
+                    (OverwritePolicy::Skip, _) => self.finished = true,
+                    (OverwritePolicy::RenameWithCounter, _) => {
+                        *dst = free_name(dst, |dst| occupied(filesystem, dst))
+                    }
+                    (OverwritePolicy::RenameParenthesized, _) => {
+                        let mut numbers = naming::CopyNumbers::default();
+                        *dst = numbers.free_name(filesystem, dst, |dst| occupied(filesystem, dst))
+                    }
+                }
+            }
+            Source::Files(files) if self.overwrite == OverwritePolicy::Error => {
+                let mut seen = HashSet::new();
+                let mut exists = false;
+                let mut resolved = HashMap::new();
+                files.names.each(|name| {
+                    let probed = self
+                        .probe
+                        .as_ref()
+                        .map(|probe| probe.decide(filesystem, &files.root.join(name)));
+                    let extension = match probed {
+                        Some(ProbeDecision::Skip) => return,
+                        Some(ProbeDecision::Extension(extension)) => Some(extension),
+                        Some(ProbeDecision::Keep) | None => None,
+                    };
+                    let extension = match passthrough_extension(&self.passthrough, name.as_ref()) {
+                        Some(kept) => Some(kept.to_owned()),
+                        None => extension,
+                    };
+                    let bucket = (self.buckets)
+                        .map(|buckets| buckets.of(filesystem, &files.root.join(name)));
+                    let mut buf = PathBuf::new();
+                    let flat = self
+                        .flatten
+                        .map(|_| Path::new(name).file_name().unwrap_or_default());
+                    batch_dst(
+                        &mut buf,
+                        dst,
+                        sibling_name(flat.unwrap_or(name), &suffix).as_ref(),
+                        bucket.as_deref(),
+                        extension.as_deref().or(self.output_extension.as_deref()),
+                        self.name_policy,
+                        self.collapse_extensions,
+                    );
+                    if let Some(flatten) = self.flatten {
+                        buf = flatten.free_name(&buf, name.as_ref(), |dst| seen.contains(dst));
+                    }
+                    if renames {
+                        buf = free_name(&buf, |dst| seen.contains(dst));
+                    }
+                    let fresh =
+                        self.incremental && up_to_date(filesystem, &files.root.join(name), &buf);
+                    let kind = match (
+                        seen.insert(buf.clone()),
+                        occupied(filesystem, &buf) && !fresh,
+                    ) {
+                        (false, _) => ConflictKind::Duplicate,
+                        (true, true) => ConflictKind::Exists,
+                        (true, false) => return,
+                    };
+                    let src = files.root.join(name);
+                    let conflict = Conflict {
+                        kind,
+                        src: Some(&src),
+                        dst: &buf,
+                    };
+                    match conflict::consult(self.conflict_resolver.as_ref(), conflict) {
+                        Resolution::Default => exists = true,
+                        resolution => {
+                            resolved.entry(buf).or_insert(resolution);
+                        }
+                    }
+                })?;
+                if exists {
+                    return Err(SrcDstError::DstExists.into());
+                }
+                self.resolved = resolved;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Reading back spilled SRC names failed, which ended the pairs early,
+    /// see [`SrcDstConfig::sort_spill_threshold`].
+    pub fn spill_error(&self) -> Option<&io::Error> {
+        match &self.src {
+            Source::Files(files) => files.names.error(),
+            _ => None,
+        }
+    }
+
+    /// The file [`Self::allocate_tnamed`] would create, if any.
+    pub fn tnamed_file(&self) -> Option<&Path> {
+        match &self.dst {
+            Drain::Single(file) if self.tnamed_file => Some(file),
+            _ => None,
+        }
+    }
+
+    /// The directory [`Self::create_tnamed_dir`] would create, if any.
+    pub fn tnamed_dir(&self) -> Option<&Path> {
+        match &self.dst {
+            Drain::Single(dir) if self.tnamed_dir => Some(dir),
+            _ => None,
+        }
+    }
+
+    /// The single file (or stdout) all entries go to, see [`SrcDstConfig::allow_many_to_one`].
+    pub fn archive(&self) -> Option<Dst> {
+        match &self.dst {
+            Drain::Archive(Some(archive)) => Some(Dst::File(archive.clone())),
+            Drain::Archive(None) => Some(Dst::Stdout),
+            _ => None,
+        }
+    }
+
+    pub fn is_batch(&self) -> bool {
+        match &self.src {
+            Source::Files(_) => true,
+            Source::Planned(pairs) => pairs.len() > 1,
+            _ => false,
+        }
+    }
+}
+
+impl SrcDstPairs {
+    /// [`Iterator::next`], with the batch SRCs whose DST can not be settled.
+    fn next_pair(&mut self) -> Option<Result<(Src, Dst), SrcDstPairError>> {
+        if self.finished {
+            return None;
+        }
+        let src = match (&mut self.src, &self.dst) {
+            (Source::Planned(pairs), _) => return pairs.pop().map(Ok),
+            (Source::Files(_), Drain::Archive(archive)) => {
+                let archive = archive.clone();
+                let (src, name) = self.next_streamed()?;
+                let name = PathBuf::from(name);
+                return Some(Ok((
+                    Src::File(src),
+                    Dst::Entry(ArchiveEntry { archive, name }),
+                )));
+            }
+            (Source::Files(_), Drain::Stdout) => {
+                let (src, _) = self.next_streamed()?;
+                return Some(Ok((Src::File(src), Dst::Stdout)));
+            }
+            (Source::Files(_), _) => {
+                let mut dst = PathBuf::new();
+                let src = self.next_batch(&mut dst)?;
+                return Some(src.map(|src| (Src::File(src), Dst::File(dst))));
+            }
+            (Source::Stdin, _) => Src::Stdin,
+            (Source::File(src), _) => Src::File(src.to_owned()),
+        };
+        self.finished = true;
+        Some(Ok(match &self.dst {
+            Drain::Stdout | Drain::Archive(None) => (src, Dst::Stdout),
+            Drain::Single(dst) | Drain::Archive(Some(dst)) => (src, Dst::File(dst.to_owned())),
+            Drain::Sibling { .. } => return None, // 只用于批处理，单个 SRC 用的是 Drain::Single
+        }))
+    }
+}
+
+/// Batch SRCs whose DST can not be settled are left out, see [`SrcDstPairs::take_unsettled`].
+impl Iterator for SrcDstPairs {
+    type Item = (Src, Dst);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.next_pair()? {
+                Ok(pair) => return Some(pair),
+                Err(e) => self.unsettled.push(e),
+            }
+        }
+    }
+}
+
+/// See [`SrcDstPairs::checked`].
+#[derive(Debug)]
+pub struct CheckedPairs(SrcDstPairs);
+
+impl Iterator for CheckedPairs {
+    type Item = Result<(Src, Dst), SrcDstPairError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.try_next()
+    }
+}
+
+/// A pair that can not be processed, the others still can.
+#[derive(Error, Debug)]
+pub enum SrcDstPairError {
+    /// Removed, or replaced by something else than a file, since the parsing.
+    #[error("SRC {0:?} no longer exists")]
+    SrcGone(PathBuf),
+    #[error("failed to check SRC {path:?}")]
+    Src {
+        path: PathBuf,
+        #[source]
+        error: io::Error,
+    },
+    /// Reading back the SRC names spilled to disk failed, see [`SrcDstConfig::sort_spill_threshold`].
+    /// The last item yielded.
+    #[error("failed to read the SRC names back")]
+    Listing(#[source] io::Error),
+    /// The DST is in the way, e.g. created since the parsing under [`OverwritePolicy::Error`].
+    #[error("failed to settle DST {dst:?} of SRC {src:?}")]
+    Dst {
+        src: PathBuf,
+        dst: PathBuf,
+        #[source]
+        error: SrcDstError,
+    },
+}
+
+impl SrcDstPairError {
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            SrcDstPairError::SrcGone(path)
+            | SrcDstPairError::Src { path, .. }
+            | SrcDstPairError::Dst { src: path, .. } => Some(path),
+            SrcDstPairError::Listing(_) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Source {
+    Stdin,
+    File(PathBuf),
+    /// 注意文件列表应该是倒过来排序的！这样就能把它们一个个 pop 出来了。
+    Files(FileList),
+    /// 同上，倒序。此时 Drain 仅用于记录 tnamed 目录。
+    Planned(Vec<(Src, Dst)>),
+}
+
+/// Batch SRCs, the root stored only once.
+#[derive(Debug)]
+struct FileList {
+    root: PathBuf,
+    /// Relative to `root`, only the file names when not recursive.
+    names: spill::Names,
+}
+
+#[derive(Debug)]
+enum Drain {
+    Stdout,
+    /// 注意这玩意必须手动拼接！（如果 SRC 是 [`Source::Files`] 的话）也就是文件名相同，但父目录不同。
+    Single(PathBuf),
+    /// 所有 SRC 打包进同一个文件，`None` 为 stdout。
+    Archive(Option<PathBuf>),
+    /// 批处理时 DST 就在 SRC 旁边，`dir` 即 SRC 目录，见 [`SrcDstConfig::sibling_suffix`]。
+    Sibling {
+        dir: PathBuf,
+        suffix: OsString,
+    },
+}
+
+/// 我该怎么做测试？只是简单跑一下`cargo test -- --nocapture`吗？
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn test() {
+        match SrcDstConfig::new("png").parse(".", None) {
+            Err(e) => println!("{e}"),
+            Ok(p) => match p {
                 Err(e) => println!("{e}"),
                 Ok(p) => {
                     let p = p.collect::<Vec<_>>();
@@ -391,4 +2652,1002 @@ mod tests {
             },
         };
     }
+
+    #[test]
+    fn collapse_duplicate_extension() {
+        let collapse = |p: &str| super::collapse_duplicate_extension(PathBuf::from(p));
+        assert_eq!(
+            collapse("out/photo.png.png"),
+            PathBuf::from("out/photo.png")
+        );
+        assert_eq!(collapse("photo.PNG.png.png"), PathBuf::from("photo.PNG"));
+        assert_eq!(collapse("photo.jpg.png"), PathBuf::from("photo.jpg.png"));
+        assert_eq!(collapse(".png.png"), PathBuf::from(".png.png"));
+    }
+
+    #[test]
+    fn batch_order() {
+        let mut names = ["b.jpg", "B.jpg", "a10.jpg", "a2.jpg", "é.jpg"].map(PathBuf::from);
+        names.sort_by(|a, b| super::batch_order(a, b));
+        assert_eq!(
+            names.map(|p| p.to_string_lossy().into_owned()),
+            ["B.jpg", "a10.jpg", "a2.jpg", "b.jpg", "é.jpg"]
+        );
+    }
+
+    #[test]
+    fn format_utc() {
+        let at = |secs| super::format_utc(SystemTime::UNIX_EPOCH + Duration::from_secs(secs));
+        assert_eq!(at(0), "19700101T000000Z");
+        assert_eq!(at(951782400), "20000229T000000Z");
+        assert_eq!(at(1697000000), "20231011T045320Z");
+    }
+
+    #[test]
+    fn confine() {
+        let root = ScratchDir::new("confine");
+        fs::create_dir_all(root.join("out")).unwrap();
+        let mut config = SrcDstConfig::new("png");
+        config.confine_to = Some(root.join("out"));
+
+        assert!(config.confine(root.join("out/a/b.png")).is_ok());
+        assert!(config.confine(root.join("out/new/../../b.png")).is_err());
+        assert!(config.confine(root.join("b.png")).is_err());
+    }
+
+    #[test]
+    fn confine_detached() {
+        // 只有 /work 底下的存在，连根目录都没有
+        struct Detached(vfs::MemoryFs);
+
+        impl vfs::FileSystem for Detached {
+            fn kind(&self, path: &Path) -> io::Result<vfs::EntryKind> {
+                match path.starts_with("/work") {
+                    true => self.0.kind(path),
+                    false => Err(io::ErrorKind::NotFound.into()),
+                }
+            }
+
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                self.kind(path)?;
+                self.0.canonicalize(path)
+            }
+
+            fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, vfs::EntryKind)>> {
+                self.0.read_dir(path)
+            }
+
+            fn current_dir(&self) -> io::Result<PathBuf> {
+                self.0.current_dir()
+            }
+        }
+
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory.add_dir("out");
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(Shared(std::sync::Arc::new(Detached(memory))));
+        config.confine_to = Some(PathBuf::from("/work/out"));
+
+        assert!(config.confine("/work/out/a.png").is_ok());
+        assert!(matches!(
+            config.confine("/elsewhere/a.png"),
+            Err(ClarifyError::Environment(EnvironmentError(e))) if e.kind() == io::ErrorKind::NotFound
+        ));
+    }
+
+    #[test]
+    fn allocate_tnamed() {
+        let root = ScratchDir::new("allocate");
+        fs::create_dir_all(root.join("frames")).unwrap();
+        let mut config = SrcDstConfig::new("png");
+        config.deterministic = true;
+        config.fixed_time = Some(SystemTime::UNIX_EPOCH);
+
+        let mut first = config.try_parse(root.join("frames"), None).unwrap();
+        let mut second = config.try_parse(root.join("frames"), None).unwrap();
+        first.allocate_tnamed().unwrap();
+        second.allocate_tnamed().unwrap();
+        let first = first.tnamed_dir().unwrap().to_owned();
+        let second = second.tnamed_dir().unwrap().to_owned();
+        assert_eq!(second, numbered(&first, 2, true));
+        assert!(second.is_dir());
+        assert_eq!(
+            numbered(Path::new("a/input-x.png"), 3, false),
+            PathBuf::from("a/input-x-3.png")
+        );
+    }
+
+    #[test]
+    fn recursive() {
+        let root = ScratchDir::new("recursive");
+        fs::create_dir_all(root.join("in/a/deep/deeper")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in [
+            "in/top.txt",
+            "in/a/one.txt",
+            "in/a/deep/two.txt",
+            "in/a/deep/deeper/no.txt",
+        ] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+
+        let mut config = SrcDstConfig::new("txt");
+        config.max_depth = 2;
+        let pairs = config
+            .try_parse(root.join("in"), Some(root.join("out")))
+            .unwrap();
+        let out = fs::canonicalize(root.join("out")).unwrap();
+        assert_eq!(pairs.dst_dirs(), [out.join("a"), out.join("a/deep")]);
+        assert_eq!(pairs.create_dst_dirs().unwrap().len(), 2);
+        let dsts: Vec<_> = pairs.map(|(_, dst)| dst).collect();
+        assert_eq!(
+            dsts,
+            [
+                Dst::File(out.join("a/deep/two.txt")),
+                Dst::File(out.join("a/one.txt")),
+                Dst::File(out.join("top.txt")),
+            ]
+        );
+    }
+
+    #[test]
+    fn overwrite_policy() {
+        let root = ScratchDir::new("overwrite");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["in/a.txt", "in/b.txt", "out/a.txt", "out/a-1.txt"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let out = fs::canonicalize(root.join("out")).unwrap();
+        let dsts = |overwrite| {
+            let mut config = SrcDstConfig::new("txt");
+            config.overwrite = overwrite;
+            let pairs = config.try_parse(root.join("in"), Some(root.join("out")))?;
+            Ok::<_, ClarifyError>(pairs.map(|(_, dst)| dst).collect::<Vec<_>>())
+        };
+
+        assert!(matches!(
+            dsts(OverwritePolicy::Error),
+            Err(ClarifyError::Usage(SrcDstError::DstExists))
+        ));
+        assert_eq!(
+            dsts(OverwritePolicy::Skip).unwrap(),
+            [Dst::File(out.join("b.txt"))]
+        );
+        assert_eq!(
+            dsts(OverwritePolicy::RenameWithCounter).unwrap(),
+            [Dst::File(out.join("a-2.txt")), Dst::File(out.join("b.txt"))]
+        );
+        assert_eq!(
+            dsts(OverwritePolicy::RenameParenthesized).unwrap(),
+            [
+                Dst::File(out.join("a (2).txt")),
+                Dst::File(out.join("b.txt"))
+            ]
+        );
+    }
+
+    #[test]
+    fn next_borrowed() {
+        let root = ScratchDir::new("borrowed");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["in/a.txt", "in/b.txt.txt", "in/c.txt"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let parse = || {
+            SrcDstConfig::new("txt")
+                .try_parse(root.join("in"), Some(root.join("out")))
+                .unwrap()
+        };
+
+        let expected: Vec<_> = parse().collect();
+        let mut pairs = parse();
+        let mut borrowed = Vec::new();
+        while let Some((src, dst)) = pairs.next_borrowed() {
+            borrowed.push((src, dst.clone()));
+        }
+        assert_eq!(borrowed, expected);
+        assert_eq!(expected.len(), 3);
+    }
+
+    #[test]
+    fn tnamed_templates() {
+        let root = ScratchDir::new("templates");
+        fs::create_dir_all(root.join("frames")).unwrap();
+        fs::write(root.join("photo.jpg"), b"").unwrap();
+        let mut config = SrcDstConfig::new("png");
+        config.deterministic = true;
+        config.fixed_time = Some(SystemTime::UNIX_EPOCH);
+        config.tnamed_file_template = Some("{stem}.{ext}-{datetime}.{default_ext}".into());
+        config.tnamed_dir_template = Some("out-{name}-{random}".into());
+
+        let mut file = config.try_parse(root.join("photo.jpg"), None).unwrap();
+        let Some((_, Dst::File(dst))) = file.next() else {
+            panic!("not a file");
+        };
+        assert_eq!(dst.file_name().unwrap(), "photo.jpg-19700101T000000Z.png");
+
+        let photo = fs::File::options()
+            .write(true)
+            .open(root.join("photo.jpg"))
+            .unwrap();
+        photo
+            .set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1625423405))
+            .unwrap();
+        config.capture_time = true;
+        let mut file = config.try_parse(root.join("photo.jpg"), None).unwrap();
+        let Some((_, Dst::File(dst))) = file.next() else {
+            panic!("not a file");
+        };
+        assert_eq!(dst.file_name().unwrap(), "photo.jpg-20210704T183005Z.png");
+        config.capture_time = false;
+
+        fs::write(root.join("photo.jpg.json"), br#"{"title": "Lake/Bled"}"#).unwrap();
+        config.sidecar_tokens = true;
+        config.tnamed_file_template = Some("{meta.title}.{default_ext}".into());
+        let mut file = config.try_parse(root.join("photo.jpg"), None).unwrap();
+        let Some((_, Dst::File(dst))) = file.next() else {
+            panic!("not a file");
+        };
+        assert_eq!(dst.file_name().unwrap(), "Lake_Bled.png");
+        for title in ["", ".", ".."] {
+            let sidecar = format!(r#"{{"title": "{title}"}}"#);
+            fs::write(root.join("photo.jpg.json"), sidecar).unwrap();
+            config.tnamed_file_template = Some("{meta.title}".into());
+            assert!(config.try_parse(root.join("photo.jpg"), None).is_err());
+        }
+        config.tnamed_file_template = Some("{meta.title}.{default_ext}".into());
+
+        let dir = config.try_parse(root.join("frames"), None).unwrap();
+        let name = dir
+            .tnamed_dir()
+            .unwrap()
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(name.starts_with("out-frames-") && name.len() == "out-frames-".len() + 8);
+    }
+
+    #[test]
+    fn collapsed_collision() {
+        let root = ScratchDir::new("collapsed");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["in/a.png", "in/a.png.png"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let out = fs::canonicalize(root.join("out")).unwrap();
+        let mut config = SrcDstConfig::new("png");
+        config.collapse_duplicate_extensions = true;
+
+        let batch = config.try_parse(root.join("in"), Some(root.join("out")));
+        assert_eq!(
+            batch.unwrap().map(|(_, dst)| dst).collect::<Vec<_>>(),
+            [Dst::File(out.join("a.png")), Dst::File(out.join("a-1.png"))]
+        );
+    }
+
+    #[test]
+    fn output_extension() {
+        let root = ScratchDir::new("extension");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["in/a.jpg", "in/b.jpeg"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let out = fs::canonicalize(root.join("out")).unwrap();
+        let mut config = SrcDstConfig::new("png");
+        config.output_extension = Some("webp".into());
+        config.deterministic = true;
+        config.fixed_time = Some(SystemTime::UNIX_EPOCH);
+
+        let batch = config.try_parse(root.join("in"), Some(root.join("out")));
+        assert_eq!(
+            batch.unwrap().map(|(_, dst)| dst).collect::<Vec<_>>(),
+            [Dst::File(out.join("a.webp")), Dst::File(out.join("b.webp"))]
+        );
+        let mut single = config.try_parse(root.join("in/a.jpg"), None).unwrap();
+        let Some((_, Dst::File(dst))) = single.next() else {
+            panic!("not a file");
+        };
+        assert_eq!(dst.file_name().unwrap(), "a-19700101T000000Z.webp");
+    }
+
+    #[test]
+    fn output_extension_collision() {
+        let root = ScratchDir::new("extension-collision");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["in/a.gif", "in/a.jpg"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let out = fs::canonicalize(root.join("out")).unwrap();
+        let mut config = SrcDstConfig::new("gif,jpg");
+        config.output_extension = Some("png".into());
+
+        let batch = config.try_parse(root.join("in"), Some(root.join("out")));
+        assert_eq!(
+            batch.unwrap().map(|(_, dst)| dst).collect::<Vec<_>>(),
+            [Dst::File(out.join("a.png")), Dst::File(out.join("a-1.png"))]
+        );
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.txt")
+            .add_file("in/sub/b.txt")
+            .add_file("out/a.txt");
+        let mut config = SrcDstConfig::new("txt");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.max_depth = 1;
+        config.overwrite = OverwritePolicy::RenameWithCounter;
+
+        let plan = config.plan("in", Some("out")).unwrap().unwrap();
+        assert_eq!(plan.dst_dirs, [PathBuf::from("/work/out/sub")]);
+        assert_eq!(
+            plan.pairs,
+            [
+                (
+                    Src::File(PathBuf::from("/work/in/a.txt")),
+                    Dst::File(PathBuf::from("/work/out/a-1.txt"))
+                ),
+                (
+                    Src::File(PathBuf::from("/work/in/sub/b.txt")),
+                    Dst::File(PathBuf::from("/work/out/sub/b.txt"))
+                ),
+            ]
+        );
+        assert_eq!(SrcDstPairs::from(plan).dst_dirs().len(), 1);
+    }
+
+    #[test]
+    fn parse_many() {
+        let root = ScratchDir::new("many");
+        fs::create_dir_all(root.join("dir")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["a.txt", "b.txt", "dir/a.txt", "dir/c.txt"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let mut config = SrcDstConfig::new("txt");
+        config.overwrite = OverwritePolicy::RenameWithCounter;
+        let srcs = ["a.txt", "b.txt", "a.txt", "dir"].map(|src| root.join(src));
+
+        let pairs = config
+            .parse_many(&srcs, Some(&root.join("out")))
+            .unwrap()
+            .unwrap();
+        let out = fs::canonicalize(root.join("out")).unwrap();
+        let dsts: Vec<_> = pairs.map(|(_, dst)| dst).collect();
+        assert_eq!(
+            dsts,
+            ["a.txt", "b.txt", "a-1.txt", "c.txt"].map(|name| Dst::File(out.join(name)))
+        );
+
+        let dash = PathBuf::from("-");
+        assert_eq!(
+            config.parse_many(&srcs, Some(&dash)).unwrap().unwrap_err(),
+            SrcDstError::ManyToOne
+        );
+    }
+
+    #[test]
+    fn checked() {
+        let root = ScratchDir::new("checked");
+        fs::create_dir_all(root.join("src")).unwrap();
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(root.join("src").join(file), b"").unwrap();
+        }
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("src"), Some(root.clone()))
+            .unwrap()
+            .unwrap();
+        fs::remove_file(root.join("src/b.txt")).unwrap(); // 解析之后被删掉了
+
+        let results: Vec<_> = pairs.checked().collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        let e = results[1].as_ref().unwrap_err();
+        assert!(matches!(e, SrcDstPairError::SrcGone(_)));
+        assert_eq!(e.path().unwrap().file_name().unwrap(), "b.txt");
+    }
+
+    #[test]
+    fn unsettled() {
+        let root = ScratchDir::new("unsettled");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(root.join("in").join(file), b"").unwrap();
+        }
+        let mut config = SrcDstConfig::new("txt");
+        config.overwrite = OverwritePolicy::Error;
+        let parse = || {
+            (config.parse(root.join("in"), Some(root.join("out"))))
+                .unwrap()
+                .unwrap()
+        };
+        let (checked, mut nexted) = (parse(), parse());
+        fs::write(root.join("out/b.txt"), b"b").unwrap(); // 解析之后才有的
+
+        let results: Vec<_> = checked.checked().collect();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        let e = results[1].as_ref().unwrap_err();
+        assert!(matches!(
+            e,
+            SrcDstPairError::Dst { dst, error: SrcDstError::DstExists, .. }
+                if *dst == root.join("out/b.txt")
+        ));
+        assert_eq!(e.path().unwrap().file_name().unwrap(), "b.txt");
+
+        assert_eq!(nexted.by_ref().count(), 2);
+        let unsettled = nexted.take_unsettled();
+        assert_eq!(unsettled.len(), 1);
+        assert!(matches!(unsettled[0], SrcDstPairError::Dst { .. }));
+    }
+
+    #[test]
+    fn many_to_one() {
+        let root = ScratchDir::new("many-to-one");
+        fs::create_dir_all(root.join("in/sub")).unwrap();
+        for file in ["in/a.txt", "in/sub/b.txt"] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        let mut config = SrcDstConfig::new("txt");
+        config.max_depth = 1;
+        let archive = root.join("in.tar");
+        assert!(config
+            .try_parse(root.join("in"), Some(archive.clone()))
+            .is_err());
+
+        config.allow_many_to_one = true;
+        let pairs = config
+            .try_parse(root.join("in"), Some(archive.clone()))
+            .unwrap();
+        assert_eq!(pairs.archive(), Some(Dst::File(archive.clone())));
+        let entries: Vec<_> = pairs.map(|(_, dst)| dst.to_string()).collect();
+        let expected = [
+            format!("{}#a.txt", archive.display()),
+            format!("{}#sub/b.txt", archive.display()),
+        ];
+        assert_eq!(entries, expected);
+
+        fs::write(&archive, b"").unwrap();
+        config.overwrite = OverwritePolicy::Error;
+        assert!(config
+            .try_parse(root.join("in"), Some(archive.clone()))
+            .is_err());
+        config.overwrite = OverwritePolicy::RenameWithCounter;
+        let pairs = config
+            .try_parse(root.join("in"), Some(archive.clone()))
+            .unwrap();
+        assert_ne!(pairs.archive(), Some(Dst::File(archive)));
+    }
+
+    #[test]
+    fn literal_dash() {
+        let root = ScratchDir::new("dash");
+        fs::write(root.join("a.txt"), b"").unwrap();
+        let mut config = SrcDstConfig::new("txt");
+        let mut pairs = config
+            .try_parse(root.join("a.txt"), Some("-".into()))
+            .unwrap();
+        assert_eq!(pairs.next().unwrap().1, Dst::Stdout);
+
+        config.dash_is_stdio = false;
+        let mut pairs = config
+            .try_parse(root.join("a.txt"), Some("-".into()))
+            .unwrap();
+        assert_eq!(pairs.next().unwrap().1, Dst::File("-".into()));
+        assert!(matches!(
+            config.try_parse(PathBuf::from("-"), None),
+            Err(ClarifyError::Usage(SrcDstError::SrcNotExist))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tnamed_dir_non_utf8() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let root = ScratchDir::new("non-utf8");
+        let frames = OsStr::from_bytes(b"frames-\xff");
+        fs::create_dir_all(root.join(frames)).unwrap();
+        let mut config = SrcDstConfig::new("png");
+        config.tnamed_dir_template = Some("out-{name}".into());
+
+        let dir = config.try_parse(root.join(frames), None).unwrap();
+        let name = dir.tnamed_dir().unwrap().file_name().unwrap();
+        assert_eq!(name.as_bytes(), b"out-frames-\xff");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlink_policy() {
+        let root = ScratchDir::new("symlinks");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("target.txt"), b"").unwrap();
+        fs::write(root.join("in/a.txt"), b"").unwrap();
+        std::os::unix::fs::symlink(root.join("target.txt"), root.join("in/link.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("gone.txt"), root.join("in/broken.txt")).unwrap();
+        let names = |symlinks| {
+            let mut config = SrcDstConfig::new("txt");
+            config.symlinks = symlinks;
+            let pairs = config.try_parse(root.join("in"), Some(root.join("out")))?;
+            let names = pairs.map(|(src, _)| match src {
+                Src::File(path) => path.file_name().unwrap().to_owned(),
+                _ => panic!("not a file"),
+            });
+            Ok::<_, ClarifyError>(names.collect::<Vec<_>>())
+        };
+
+        assert_eq!(names(SymlinkPolicy::Skip).unwrap(), ["a.txt"]);
+        assert_eq!(names(SymlinkPolicy::Follow).unwrap(), ["a.txt", "link.txt"]);
+        assert!(matches!(
+            names(SymlinkPolicy::Error),
+            Err(ClarifyError::Environment(_))
+        ));
+    }
+
+    #[test]
+    fn incremental() {
+        let root = ScratchDir::new("incremental");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        for (file, secs) in [("in/a.jpg", 10), ("out/a.png", 20), ("in/b.jpg", 30)] {
+            let file = fs::File::create(root.join(file)).unwrap();
+            file.set_modified(at(secs)).unwrap();
+        }
+        let mut config = SrcDstConfig::new("png");
+        config.output_extension = Some("png".into());
+        config.overwrite = OverwritePolicy::Error;
+        config.incremental = true;
+
+        let mut pairs = config
+            .try_parse(root.join("in"), Some(root.join("out")))
+            .unwrap();
+        let srcs: Vec<_> = pairs.by_ref().map(|(src, _)| src).collect();
+        assert_eq!(
+            srcs,
+            [Src::File(fs::canonicalize(root.join("in/b.jpg")).unwrap())]
+        );
+        assert_eq!(pairs.up_to_date(), 1);
+
+        let mut file = config
+            .try_parse(root.join("in/a.jpg"), Some(root.join("out/a.png")))
+            .unwrap();
+        assert_eq!(file.next(), None);
+        assert_eq!(file.up_to_date(), 1);
+        config.overwrite = OverwritePolicy::Overwrite;
+        let stale = config.try_parse(root.join("in/b.jpg"), Some(root.join("out/a.png")));
+        assert_eq!(stale.unwrap().count(), 1);
+    }
+
+    #[test]
+    fn probe() {
+        let root = ScratchDir::new("probe");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.bin"), b"\x89PNG\r\n").unwrap();
+        fs::write(root.join("in/b.bin"), b"text").unwrap();
+        let mut config = SrcDstConfig::new("bin");
+        config.probe = Some(probe::Probe::new(4, |_: &Path, head: &[u8]| match head {
+            b"\x89PNG" => ProbeDecision::Extension("png".into()),
+            _ => ProbeDecision::Skip,
+        }));
+
+        let dsts: Vec<_> = config
+            .try_parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .map(|(_, dst)| dst)
+            .collect();
+        assert_eq!(dsts, [Dst::File(root.join("out/a.png"))]);
+
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file_with("in/a.bin", b"\x89PNG")
+            .add_file("in/b.bin")
+            .add_dir("out");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        let plan = config.plan("in", Some("out")).unwrap().unwrap();
+        assert_eq!(plan.pairs[0].1, Dst::File("/work/out/a.png".into()));
+        assert_eq!(plan.pairs.len(), 1);
+    }
+
+    #[test]
+    fn placeholder_vfs() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file_with("in/a.txt", "a")
+            .add_file("out/a.txt")
+            .set_modified("out/a.txt", SystemTime::UNIX_EPOCH)
+            .add_file_with("out/b.txt", "b");
+        let mut config = SrcDstConfig::new("txt");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.overwrite = OverwritePolicy::Error;
+
+        let plan = config.plan("in/a.txt", Some("out/a.txt")).unwrap().unwrap();
+        assert_eq!(plan.pairs[0].1, Dst::File("/work/out/a.txt".into()));
+        assert!(config.try_parse("in/a.txt", Some("out/b.txt")).is_err());
+    }
+
+    #[test]
+    fn dst_buckets() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.txt")
+            .add_file("in/sub/b.txt")
+            .add_dir("out");
+        let mut config = SrcDstConfig::new("txt");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.max_depth = 1;
+        config.dst_buckets = Some(naming::Buckets {
+            levels: 2,
+            width: 1,
+            ..Default::default()
+        });
+
+        let plan = config.plan("in", Some("out")).unwrap().unwrap();
+        let bucket = |name: &str| {
+            let hex = digest::Digest::of_bytes(name.as_bytes()).to_string();
+            PathBuf::from(&hex[..1]).join(&hex[1..2])
+        };
+        assert_eq!(
+            plan.pairs[1].1,
+            Dst::File(
+                Path::new("/work/out/sub")
+                    .join(bucket("b.txt"))
+                    .join("b.txt")
+            )
+        );
+        let sub = Path::new("/work/out/sub").join(bucket("b.txt"));
+        assert!(plan.dst_dirs.contains(&sub));
+        assert!(plan
+            .dst_dirs
+            .contains(&Path::new("/work/out").join(bucket("a.txt"))));
+
+        let mut saved = Vec::new();
+        plan.write_to(&mut saved).unwrap();
+        let loaded = plan::Plan::read_from(&saved[..]).unwrap();
+        assert_eq!(loaded.config.dst_buckets, config.dst_buckets);
+
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory.add_file_with("in/a.txt", "hello").add_dir("out");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.dst_buckets = Some(naming::Buckets {
+            by: naming::BucketBy::Content,
+            ..config.dst_buckets.unwrap()
+        });
+        let plan = config.plan("in", Some("out")).unwrap().unwrap();
+        let dst = Path::new("/work/out").join(bucket("hello")).join("a.txt");
+        assert_eq!(plan.pairs[0].1, Dst::File(dst));
+    }
+
+    #[test]
+    fn conflict_resolver() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.txt")
+            .add_file("in/b.txt")
+            .add_file("out/a.txt")
+            .add_file("out/b.txt");
+        let mut config = SrcDstConfig::new("txt");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.overwrite = OverwritePolicy::Error;
+        config.conflict_resolver =
+            Some(Shared(std::sync::Arc::new(
+                |conflict: Conflict| match conflict.src {
+                    Some(src) if src.ends_with("a.txt") => Resolution::Skip,
+                    _ => Resolution::Rename(conflict.dst.with_extension("new")),
+                },
+            )));
+        let dsts: Vec<_> = config
+            .try_parse("in", Some("out"))
+            .unwrap()
+            .map(|(_, dst)| dst)
+            .collect();
+        assert_eq!(dsts, [Dst::File(PathBuf::from("/work/out/b.new"))]);
+
+        config.conflict_resolver = Some(Shared(std::sync::Arc::new(|_: Conflict| {
+            Resolution::Default
+        })));
+        assert!(config.try_parse("in", Some("out")).is_err());
+    }
+
+    #[test]
+    fn reprioritize() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.txt")
+            .add_file("in/b.txt")
+            .add_file("in/c.txt")
+            .add_dir("out");
+        let mut config = SrcDstConfig::new("txt");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        let mut pairs = config.try_parse("in", Some("out")).unwrap();
+        let name = |src: &Src| src.to_string().rsplit('/').next().unwrap().to_owned();
+        assert_eq!(name(&pairs.next().unwrap().0), "a.txt");
+
+        let wanted = Src::File(PathBuf::from("/work/in/c.txt"));
+        pairs
+            .reprioritize(|a, b| {
+                let key = |src: &Src| (src != &wanted, src.to_string());
+                key(a).cmp(&key(b))
+            })
+            .unwrap();
+        let rest: Vec<_> = pairs.map(|(src, _)| name(&src)).collect();
+        assert_eq!(rest, ["c.txt", "b.txt"]);
+    }
+
+    #[test]
+    fn descend() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.js")
+            .add_file("in/lib/b.js")
+            .add_file("in/node_modules/dep/c.js")
+            .add_dir("out");
+        let mut config = SrcDstConfig::new("js");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.max_depth = 3;
+        config.source_filter.descend = Some(Shared(std::sync::Arc::new(|dir: &Path| {
+            !dir.ends_with("node_modules")
+        })));
+        let dsts: Vec<_> = config
+            .try_parse("in", Some("out"))
+            .unwrap()
+            .map(|(_, dst)| dst.to_string())
+            .collect();
+        assert_eq!(dsts, ["/work/out/a.js", "/work/out/lib/b.js"]);
+    }
+
+    #[cfg(feature = "ignore")]
+    #[test]
+    fn ignore_files() {
+        let root = ScratchDir::new("ignore");
+        fs::create_dir_all(root.join("in/target/debug")).unwrap();
+        fs::create_dir_all(root.join("in/.git")).unwrap();
+        fs::create_dir_all(root.join("in/src")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in [
+            "in/.git/HEAD",
+            "in/target/debug/a.rs",
+            "in/src/b.rs",
+            "in/src/c.rs",
+            "in/main.rs",
+        ] {
+            fs::write(root.join(file), b"").unwrap();
+        }
+        fs::write(root.join("in/.gitignore"), "target/\n*.rs\n!main.rs\n").unwrap();
+        fs::write(root.join("in/src/.ignore"), "!*.rs\nc.rs\n").unwrap();
+
+        let mut config = SrcDstConfig::new("rs");
+        config.max_depth = 2;
+        config.source_filter.exclude.push(String::from(".*"));
+        let names = |config: &SrcDstConfig| -> Vec<_> {
+            config
+                .try_parse(root.join("in"), Some(root.join("out")))
+                .unwrap()
+                .map(|(src, _)| src.to_string().rsplit('/').next().unwrap().to_owned())
+                .collect()
+        };
+        assert_eq!(names(&config), ["main.rs", "b.rs"]);
+        config.ignore_files = false;
+        assert_eq!(names(&config), ["HEAD", "main.rs", "b.rs", "c.rs", "a.rs"]);
+    }
+
+    #[test]
+    fn sibling_suffix() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.jpg")
+            .add_file("in/b.jpg")
+            .add_file("in/a.converted.png");
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.output_extension = Some("png".into());
+        config.sibling_suffix = Some("converted".into());
+        config.auto_name = AutoNamePolicy::FilesOnly;
+        let dsts: Vec<_> = config
+            .try_parse("in", None)
+            .unwrap()
+            .map(|(_, dst)| dst.to_string())
+            .collect();
+        assert_eq!(
+            dsts,
+            ["/work/in/a.converted.png", "/work/in/b.converted.png"]
+        );
+        let pairs: Vec<_> = config.try_parse("in/b.jpg", None).unwrap().collect();
+        assert_eq!(
+            pairs[0].1,
+            Dst::File(PathBuf::from("/work/in/b.converted.png"))
+        );
+
+        let sibling = |name: &str, suffix: &str| is_sibling_output(name.as_ref(), suffix.as_ref());
+        assert!(
+            sibling("a.png.jpg", "png") && !sibling("a.png", "png") && !sibling("a.bak", "png")
+        );
+
+        config.sibling_suffix = Some(OsString::new());
+        config.output_extension = Some("jpg".into());
+        assert!(matches!(
+            config.try_parse("in", None),
+            Err(ClarifyError::Usage(SrcDstError::Inplaced))
+        ));
+        config.inplace = InplacePolicy::Atomic;
+        assert_eq!(config.try_parse("in", None).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn policies() {
+        for (a, b) in [(true, true), (true, false), (false, true), (false, false)] {
+            let stdio = StdioPolicy::of(a, b);
+            assert_eq!((stdio.from_stdin(), stdio.to_stdout()), (a, b));
+            let auto_name = AutoNamePolicy::of(a, b);
+            assert_eq!((auto_name.files(), auto_name.dirs()), (a, b));
+        }
+        assert!(!SrcDstConfig::new("txt").inplace.allows());
+        assert!(SrcDstConfig::new_with_allow_inplace("txt").inplace.allows());
+    }
+
+    #[test]
+    fn auto_name() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory.add_file("in/a.jpg");
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+
+        // 只许自动命名目录时，SRC 目录不受文件那一条的限制
+        config.auto_name = AutoNamePolicy::DirsOnly;
+        assert!(config.try_parse("in", None).unwrap().tnamed_dir().is_some());
+        assert!(matches!(
+            config.try_parse("in/a.jpg", None),
+            Err(ClarifyError::Usage(SrcDstError::ForbidAutoTnamedDstFile))
+        ));
+        config.auto_name = AutoNamePolicy::FilesOnly;
+        assert!(matches!(
+            config.try_parse("in", None),
+            Err(ClarifyError::Usage(SrcDstError::ForbidAutoTnamedDstDir))
+        ));
+        assert!(config.try_parse("in/a.jpg", None).is_ok());
+    }
+
+    #[test]
+    fn name_policy_collisions() {
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/Photo.png")
+            .add_file("in/photo.png")
+            .add_dir("out");
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.name_policy.lowercase = true;
+        let dsts: Vec<_> = config
+            .try_parse("in", Some("out"))
+            .unwrap()
+            .map(|(_, dst)| dst.to_string())
+            .collect();
+        assert_eq!(dsts, ["/work/out/photo.png", "/work/out/photo-1.png"]);
+
+        config.overwrite = OverwritePolicy::Error;
+        assert_eq!(config.try_parse("in", Some("out")).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn unreadable_subdir() {
+        struct Locked(vfs::MemoryFs);
+
+        impl vfs::FileSystem for Locked {
+            fn kind(&self, path: &Path) -> io::Result<vfs::EntryKind> {
+                self.0.kind(path)
+            }
+
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                self.0.canonicalize(path)
+            }
+
+            fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, vfs::EntryKind)>> {
+                match path.ends_with("locked") {
+                    true => Err(io::ErrorKind::PermissionDenied.into()),
+                    false => self.0.read_dir(path),
+                }
+            }
+
+            fn current_dir(&self) -> io::Result<PathBuf> {
+                self.0.current_dir()
+            }
+        }
+
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.jpg")
+            .add_file("in/locked/b.jpg")
+            .add_dir("out");
+        let collector = std::sync::Arc::new(warning::WarningCollector::default());
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(Shared(std::sync::Arc::new(Locked(memory))));
+        config.warnings = Some(Shared(collector.clone()));
+        config.max_depth = 1;
+        assert_eq!(config.try_parse("in", Some("out")).unwrap().count(), 1);
+        assert!(matches!(
+            &collector.take()[..],
+            [warning::Warning::UnreadableDirectory { path, .. }] if path.ends_with("locked")
+        ));
+    }
+
+    #[test]
+    fn capture_time_vfs() {
+        struct Dated(vfs::MemoryFs);
+
+        impl vfs::FileSystem for Dated {
+            fn kind(&self, path: &Path) -> io::Result<vfs::EntryKind> {
+                self.0.kind(path)
+            }
+
+            fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+                self.0.canonicalize(path)
+            }
+
+            fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, vfs::EntryKind)>> {
+                self.0.read_dir(path)
+            }
+
+            fn current_dir(&self) -> io::Result<PathBuf> {
+                self.0.current_dir()
+            }
+
+            fn modified(&self, _: &Path) -> io::Result<SystemTime> {
+                Ok(SystemTime::UNIX_EPOCH + Duration::from_secs(1625423405))
+            }
+
+            fn open(&self, _: &Path) -> io::Result<Box<dyn io::Read + Send>> {
+                Ok(Box::new(io::empty()))
+            }
+        }
+
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory.add_file("photo.jpg");
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(Shared(std::sync::Arc::new(Dated(memory))));
+        config.capture_time = true;
+        config.tnamed_file_template = Some("{datetime}.{default_ext}".into());
+        let mut file = config.try_parse("photo.jpg", None).unwrap();
+        let Some((_, Dst::File(dst))) = file.next() else {
+            panic!("not a file");
+        };
+        assert_eq!(dst, Path::new("/work/20210704T183005Z.png"));
+    }
+
+    #[test]
+    fn incremental_vfs() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut memory = vfs::MemoryFs::new("/work");
+        memory
+            .add_file("in/a.jpg")
+            .add_file("in/b.jpg")
+            .add_file("out/a.png")
+            .add_file("out/b.png")
+            .set_modified("in/a.jpg", at(1))
+            .set_modified("out/a.png", at(2))
+            .set_modified("in/b.jpg", at(3))
+            .set_modified("out/b.png", at(2));
+        let mut config = SrcDstConfig::new("png");
+        config.filesystem = Some(Shared(std::sync::Arc::new(memory)));
+        config.output_extension = Some("png".into());
+        config.overwrite = OverwritePolicy::Overwrite;
+        config.incremental = true;
+
+        let mut pairs = config.try_parse("in", Some("out")).unwrap();
+        let srcs: Vec<_> = pairs.by_ref().map(|(src, _)| src).collect();
+        assert_eq!(srcs, [Src::File("/work/in/b.jpg".into())]);
+        assert_eq!(pairs.up_to_date(), 1);
+        let file = config.try_parse("in/a.jpg", Some("out/a.png")).unwrap();
+        assert_eq!(file.count(), 0);
+    }
 }
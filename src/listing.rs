@@ -0,0 +1,177 @@
+//! On-disk cache of SRC directory listings, see [`SrcDstConfig::listing_cache`].
+//!
+//! A listing is reused as long as the modification time of its directory is unchanged,
+//! which is the case until an entry is added, removed or renamed.
+//!
+//! [`SrcDstConfig::listing_cache`]: crate::SrcDstConfig::listing_cache
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    audit::{self, Operation},
+    digest,
+    plan::{escape, unescape},
+    shallow_names, vfs, warning, SymlinkPolicy,
+};
+
+const HEADER: &str = "# src-dst-clarifier listing v1";
+/// A directory modified this recently may still change within the same timestamp.
+const RACY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub dir_modified: SystemTime,
+    /// In no particular order.
+    pub files: Vec<ListedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedFile {
+    pub name: OsString,
+    /// As of the scan, not kept up to date.
+    pub len: u64,
+    pub modified: SystemTime,
+}
+
+impl Listing {
+    /// Regular files only, as in batch mode.
+    pub fn scan<P: AsRef<Path>>(dir: P, warn: impl FnMut(warning::Warning)) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        let dir_modified = fs::metadata(dir)?.modified()?;
+        let mut files = Vec::new();
+        shallow_names(&vfs::StdFs, dir, SymlinkPolicy::Skip, warn, &mut |name| {
+            let meta = fs::symlink_metadata(dir.join(&name))?;
+            files.push(ListedFile {
+                name,
+                len: meta.len(),
+                modified: meta.modified()?,
+            });
+            Ok(())
+        })?;
+        Ok(Self {
+            dir_modified,
+            files,
+        })
+    }
+
+    /// The cached listing of `dir` in `cache`, `None` if there is none or it is outdated.
+    pub fn load<P: AsRef<Path>, D: AsRef<Path>>(cache: P, dir: D) -> io::Result<Option<Self>> {
+        let dir_modified = fs::metadata(dir.as_ref())?.modified()?;
+        let path = cache_file(cache.as_ref(), dir.as_ref());
+        let file = match fs::File::open(&path) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            file => audit::record(Operation::Open, &path, file)?,
+        };
+
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "bad listing cache");
+        let mut lines = BufReader::new(file).lines();
+        if lines.next().transpose()?.as_deref() != Some(HEADER) {
+            return Err(invalid());
+        }
+        let cached_modified = lines.next().transpose()?.ok_or_else(invalid)?;
+        if parse_time(&cached_modified).ok_or_else(invalid)? != dir_modified {
+            return Ok(None);
+        }
+        let mut files = Vec::new();
+        for line in lines {
+            let line = line?;
+            let mut fields = line.splitn(3, ' ');
+            let (Some(len), Some(modified), Some(name)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid());
+            };
+            files.push(ListedFile {
+                name: unescape(name)?,
+                len: len.parse().map_err(|_| invalid())?,
+                modified: parse_time(modified).ok_or_else(invalid)?,
+            });
+        }
+        Ok(Some(Self {
+            dir_modified,
+            files,
+        }))
+    }
+
+    /// Saves the listing of `dir` into `cache`, which must exist. Does nothing if `dir` was
+    /// modified too recently to tell later changes apart.
+    pub fn save<P: AsRef<Path>, D: AsRef<Path>>(&self, cache: P, dir: D) -> io::Result<()> {
+        if self
+            .dir_modified
+            .elapsed()
+            .is_ok_and(|elapsed| elapsed < RACY)
+        {
+            return Ok(());
+        }
+        let path = cache_file(cache.as_ref(), dir.as_ref());
+        let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+        let file = audit::record(Operation::Create, &temp, fs::File::create(&temp))?;
+        let mut w = BufWriter::new(file);
+        writeln!(w, "{HEADER}")?;
+        writeln!(w, "{}", format_time(self.dir_modified))?;
+        for file in &self.files {
+            let modified = format_time(file.modified);
+            writeln!(w, "{} {modified} {}", file.len, escape(&file.name))?;
+        }
+        w.into_inner().map_err(io::IntoInnerError::into_error)?;
+        audit::record_to(Operation::Rename, &temp, &path, fs::rename(&temp, &path))
+    }
+}
+
+/// Where the listing of `dir` is cached in `cache`.
+fn cache_file(cache: &Path, dir: &Path) -> PathBuf {
+    let mut hasher = digest::Sha256::new();
+    hasher.update(escape(dir.as_os_str()).as_bytes());
+    cache.join(format!("{}.listing", &hasher.finish().to_string()[..32]))
+}
+
+fn format_time(time: SystemTime) -> String {
+    let time = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    format!("{}.{:09}", time.as_secs(), time.subsec_nanos())
+}
+
+fn parse_time(value: &str) -> Option<SystemTime> {
+    let (secs, nanos) = value.split_once('.')?;
+    Some(SystemTime::UNIX_EPOCH + Duration::new(secs.parse().ok()?, nanos.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn reused_until_modified() {
+        let root = ScratchDir::new("listing");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("cache")).unwrap();
+        fs::write(root.join("src/a b.txt"), b"abc").unwrap();
+        let (src, cache) = (root.join("src"), root.join("cache"));
+        let age = |dir: &Path, secs| {
+            let modified = SystemTime::now() - Duration::from_secs(secs);
+            fs::File::open(dir).unwrap().set_modified(modified).unwrap();
+        };
+
+        let listing = Listing::scan(&src, |_| ()).unwrap();
+        listing.save(&cache, &src).unwrap();
+        assert_eq!(Listing::load(&cache, &src).unwrap(), None); // 刚刚修改过，不缓存
+
+        age(&src, 60);
+        let listing = Listing::scan(&src, |_| ()).unwrap();
+        assert_eq!(listing.files[0].len, 3);
+        listing.save(&cache, &src).unwrap();
+        assert_eq!(Listing::load(&cache, &src).unwrap(), Some(listing));
+
+        fs::write(root.join("src/b.txt"), b"").unwrap();
+        age(&src, 30);
+        assert_eq!(Listing::load(&cache, &src).unwrap(), None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Merging several SRC directories into one DST directory.
+//!
+//! ``` plaintext
+//! MergeLayout::Flat      a/0001.jpg b/0001.jpg  =>  dst/0001.jpg (twice!)
+//! MergeLayout::PerRoot   a/0001.jpg b/0001.jpg  =>  dst/a/0001.jpg dst/b/0001.jpg
+//! ```
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    check_writable, collapse_duplicate_extension, shallow_walk, ClarifyError, Dst, Src,
+    SrcDstConfig, SrcDstError, SrcDstPairs,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum MergeLayout {
+    /// Files of the same name from different roots end up at the same DST.
+    #[default]
+    Flat,
+    /// `dst/<root-name>/file`. Roots of the same name are suffixed with `-2`, `-3`...
+    PerRoot,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergedPair {
+    /// Index into [`Merge::roots`].
+    pub root: usize,
+    pub src: Src,
+    pub dst: Dst,
+}
+
+#[derive(Debug, Clone)]
+pub struct Merge {
+    /// Canonicalized SRC directories, in the order given.
+    pub roots: Vec<PathBuf>,
+    /// Grouped by root, sorted within each.
+    pub pairs: Vec<MergedPair>,
+    /// Per-root subdirectories of DST, empty for [`MergeLayout::Flat`].
+    pub subdirs: Vec<PathBuf>,
+}
+
+impl SrcDstConfig {
+    /// Every SRC must be an existing directory, and so must DST.
+    pub fn merge<P: AsRef<Path>>(
+        &self,
+        srcs: &[P],
+        dst: P,
+        layout: MergeLayout,
+    ) -> Result<Merge, ClarifyError> {
+        let dst = dst.as_ref();
+        if !dst.is_dir() {
+            return Err(SrcDstError::DstDirNotExist.into());
+        }
+        self.confine(dst)?;
+        let dst = fs::canonicalize(dst)?;
+
+        let mut merge = Merge {
+            roots: Vec::with_capacity(srcs.len()),
+            pairs: Vec::new(),
+            subdirs: Vec::new(),
+        };
+        let mut names = HashSet::new();
+        for (root, src) in srcs.iter().enumerate() {
+            let src = src.as_ref();
+            if !src.exists() {
+                return Err(SrcDstError::SrcNotExist.into());
+            }
+            let src = fs::canonicalize(src)?;
+            if !src.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a directory", src.display()),
+                )
+                .into());
+            }
+            let parent = match layout {
+                MergeLayout::Flat => dst.clone(),
+                MergeLayout::PerRoot => {
+                    let name = src.file_name().unwrap_or("root".as_ref()).to_owned();
+                    let mut unique = name.clone();
+                    for n in 2.. {
+                        if names.insert(unique.clone()) {
+                            break;
+                        }
+                        unique = OsString::from(format!("{}-{n}", name.to_string_lossy()));
+                    }
+                    let subdir = dst.join(unique);
+                    merge.subdirs.push(subdir.clone());
+                    subdir
+                }
+            };
+            if !self.inplace.allows() && self.same_path(&parent, &src)? {
+                return Err(SrcDstError::Inplaced.into());
+            }
+
+            let files = shallow_walk(&src, |w| self.warn(w))?;
+            for file in files.into_iter().rev() {
+                if !self.source_filter.accepts(&file) {
+                    continue;
+                }
+                let mut path = self
+                    .name_policy
+                    .apply(parent.join(file.file_name().unwrap()));
+                if self.collapse_duplicate_extensions {
+                    path = collapse_duplicate_extension(path);
+                }
+                merge.pairs.push(MergedPair {
+                    root,
+                    src: Src::File(file),
+                    dst: Dst::File(path),
+                });
+            }
+            merge.roots.push(src);
+        }
+
+        Ok(merge)
+    }
+}
+
+impl Merge {
+    /// **Call this before consuming the pairs**, unless the layout is flat.
+    pub fn create_subdirs(&self) -> io::Result<()> {
+        self.subdirs.iter().try_for_each(|subdir| {
+            check_writable(subdir)?;
+            crate::audit::record(
+                crate::audit::Operation::CreateDir,
+                subdir,
+                fs::create_dir_all(subdir),
+            )
+        })
+    }
+
+    pub fn root_of(&self, pair: &MergedPair) -> &Path {
+        &self.roots[pair.root]
+    }
+
+    /// Drops the root labels.
+    pub fn into_pairs(self) -> SrcDstPairs {
+        SrcDstPairs::planned(
+            self.pairs.into_iter().map(|p| (p.src, p.dst)).collect(),
+            None,
+        )
+    }
+}
@@ -0,0 +1,413 @@
+//! Normalization of DST file names in batch mode, for outputs published to URL-addressable storage.
+//!
+//! ``` plaintext
+//! Café Menu (2).PNG   =>   cafe-menu-(2).png      (all enabled)
+//! out/sub/photo.png   =>   out/sub/3f/a2/photo.png (bucketed)
+//! ```
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    audit::{self, Operation},
+    digest::Digest,
+    vfs::FileSystem,
+};
+
+/// Disabled entirely by default. Non-UTF-8 names are left untouched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NamePolicy {
+    pub lowercase: bool,
+    /// Every run of whitespace becomes a single `-`.
+    pub hyphenate_spaces: bool,
+    /// Only Latin letters are covered, `Æ`, `ß` and the like are spelled out.
+    pub strip_diacritics: bool,
+}
+
+impl NamePolicy {
+    pub fn is_noop(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Only the file name is touched.
+    pub fn apply(&self, mut path: PathBuf) -> PathBuf {
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            return path;
+        };
+        if self.is_noop() {
+            return path;
+        }
+
+        let mut normalized = String::with_capacity(name.len());
+        let mut in_space = false;
+        for c in name.chars() {
+            if self.hyphenate_spaces && c.is_whitespace() {
+                if !in_space {
+                    normalized.push('-');
+                }
+                in_space = true;
+                continue;
+            }
+            in_space = false;
+            match self.strip_diacritics.then(|| strip_diacritic(c)).flatten() {
+                Some(s) => normalized.push_str(s),
+                None => normalized.push(c),
+            }
+        }
+        if self.lowercase {
+            normalized = normalized.to_lowercase();
+        }
+
+        path.set_file_name(OsString::from(normalized));
+        path
+    }
+}
+
+/// Fans batch DSTs out into `ab/cd/` style subdirectories named by a SHA-256, so that huge
+/// batches do not end up with millions of entries in one directory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Buckets {
+    pub by: BucketBy,
+    /// Subdirectories nested.
+    pub levels: u8,
+    /// Hexadecimal digits per subdirectory name.
+    pub width: u8,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum BucketBy {
+    /// The file name of the SRC, so that DSTs can be found again by name alone.
+    #[default]
+    Name,
+    /// The whole SRC, read once for the bucket and once more when the DST directories are
+    /// listed. SRCs that cannot be read are bucketed by name.
+    Content,
+}
+
+impl Default for Buckets {
+    fn default() -> Self {
+        Self {
+            by: BucketBy::Name,
+            levels: 2,
+            width: 2,
+        }
+    }
+}
+
+impl Buckets {
+    /// Relative, e.g. `3f/a2`, for the SRC file `src` as read through `filesystem`.
+    pub fn of(&self, filesystem: &dyn FileSystem, src: &Path) -> PathBuf {
+        let by_name = || Digest::of_bytes(src.file_name().unwrap_or_default().as_encoded_bytes());
+        let digest = match self.by {
+            BucketBy::Name => by_name(),
+            BucketBy::Content => audit::record(Operation::Open, src, filesystem.open(src))
+                .and_then(Digest::of_reader)
+                .unwrap_or_else(|_| by_name()),
+        };
+        let hex = digest.to_string();
+        let levels = self.levels as usize;
+        let width = (self.width.max(1) as usize).min(hex.len() / levels.max(1));
+        (0..levels).map(|i| &hex[i * width..][..width]).collect()
+    }
+}
+
+/// How batch DSTs of the same file name from different SRC sub-directories are told apart
+/// by [`SrcDstConfig::flatten`](crate::SrcDstConfig::flatten). The first one yielded keeps it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Flatten {
+    /// `two-1.txt`, `two-2.txt`...
+    #[default]
+    Counter,
+    /// `two (2).txt`, `two (3).txt`...
+    Parenthesized,
+    /// By the sub-directories, `two-a-deep.txt` for `a/deep/two.txt`, then counted.
+    ParentDirs,
+}
+
+impl Flatten {
+    /// `dst` of the SRC `relative` to the SRC directory, if not `taken` by an earlier one.
+    pub(crate) fn free_name(
+        &self,
+        dst: &Path,
+        relative: &Path,
+        taken: impl Fn(&Path) -> bool,
+    ) -> PathBuf {
+        if !taken(dst) {
+            return dst.to_owned();
+        }
+        let mut base = dst.to_owned();
+        if *self == Flatten::ParentDirs {
+            let mut name = dst.file_stem().unwrap_or_default().to_owned();
+            for dir in relative.parent().into_iter().flat_map(Path::iter) {
+                name.push("-");
+                name.push(dir);
+            }
+            if let Some(ext) = dst.extension() {
+                name.push(".");
+                name.push(ext);
+            }
+            base.set_file_name(name);
+            if !taken(&base) {
+                return base;
+            }
+        }
+        (1..)
+            .map(|n| match self {
+                Flatten::Parenthesized => parenthesized(&base, n + 1),
+                _ => crate::numbered(&base, n, false),
+            })
+            .find(|dst| !taken(dst))
+            .unwrap()
+    }
+}
+
+/// Hands out `photo (2).png`, `photo (3).png`... for
+/// [`OverwritePolicy::RenameParenthesized`](crate::OverwritePolicy::RenameParenthesized),
+/// the directory is read once per base name and the numbers found there are skipped.
+#[derive(Debug, Default)]
+pub(crate) struct CopyNumbers(HashMap<PathBuf, Numbers>);
+
+#[derive(Debug)]
+struct Numbers {
+    used: HashSet<u32>,
+    next: u32,
+}
+
+impl CopyNumbers {
+    /// `dst` itself if it is not `taken`.
+    pub(crate) fn free_name(
+        &mut self,
+        filesystem: &dyn FileSystem,
+        dst: &Path,
+        taken: impl Fn(&Path) -> bool,
+    ) -> PathBuf {
+        if !taken(dst) {
+            return dst.to_owned();
+        }
+        let numbers = (self.0).entry(dst.to_owned()).or_insert_with(|| Numbers {
+            used: used_numbers(filesystem, dst),
+            next: 2,
+        });
+        loop {
+            let n = numbers.next;
+            numbers.next += 1;
+            if numbers.used.contains(&n) {
+                continue;
+            }
+            // 扫描之后才出现的也要避开
+            let candidate = parenthesized(dst, n);
+            if !taken(&candidate) {
+                return candidate;
+            }
+        }
+    }
+}
+
+/// `photo (2).png`.
+fn parenthesized(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!(" ({n})"));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+/// The `n` of every `<stem> (n).<ext>` beside `dst`. Non-UTF-8 names are not recognized.
+fn used_numbers(filesystem: &dyn FileSystem, dst: &Path) -> HashSet<u32> {
+    let parent = match dst.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let (Some(stem), ext) = (dst.file_stem().and_then(|s| s.to_str()), dst.extension()) else {
+        return HashSet::new();
+    };
+    let ext = match ext.map(|ext| ext.to_str()) {
+        Some(None) => return HashSet::new(),
+        Some(Some(ext)) => format!(".{ext}"),
+        None => String::new(),
+    };
+    let entries = filesystem.read_dir(parent).unwrap_or_default();
+    entries
+        .iter()
+        .filter_map(|(name, _)| {
+            let inner = name
+                .to_str()?
+                .strip_suffix(ext.as_str())?
+                .strip_prefix(stem)?
+                .strip_prefix(" (")?
+                .strip_suffix(')')?;
+            match inner.starts_with('0') {
+                true => None,
+                false => inner.parse().ok(),
+            }
+        })
+        .collect()
+}
+
+/// What to do with a DST name that would escape its output root, see [`join_contained`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Containment {
+    #[default]
+    Reject,
+    /// Drop the offending components (`..`, root, prefix) and keep the rest.
+    Sanitize,
+}
+
+/// Joins a possibly untrusted `name` (from a template, an archive entry...) onto `root`,
+/// lexically, so `root` itself need not exist. `None` if rejected.
+pub fn join_contained(root: &Path, name: &Path, containment: Containment) -> Option<PathBuf> {
+    let mut joined = root.to_path_buf();
+    let mut depth = 0;
+    for component in name.components() {
+        match component {
+            Component::Normal(part) => {
+                joined.push(part);
+                depth += 1;
+            }
+            Component::CurDir => (),
+            Component::ParentDir if depth > 0 => {
+                joined.pop();
+                depth -= 1;
+            }
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => match containment {
+                Containment::Reject => return None,
+                Containment::Sanitize => (),
+            },
+        }
+    }
+    match depth {
+        0 => None,
+        _ => Some(joined),
+    }
+}
+
+fn strip_diacritic(c: char) -> Option<&'static str> {
+    Some(match c {
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+        'à'..='å' | 'ā' | 'ă' | 'ą' => "a",
+        'Æ' => "AE",
+        'æ' => "ae",
+        'Ç' | 'Ć' | 'Ĉ' | 'Ċ' | 'Č' => "C",
+        'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+        'Ď' | 'Đ' | 'Ð' => "D",
+        'ď' | 'đ' | 'ð' => "d",
+        'È'..='Ë' | 'Ē' | 'Ĕ' | 'Ė' | 'Ę' | 'Ě' => "E",
+        'è'..='ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+        'Ĝ' | 'Ğ' | 'Ġ' | 'Ģ' => "G",
+        'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+        'Ĥ' | 'Ħ' => "H",
+        'ĥ' | 'ħ' => "h",
+        'Ì'..='Ï' | 'Ĩ' | 'Ī' | 'Ĭ' | 'Į' | 'İ' => "I",
+        'ì'..='ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+        'Ĵ' => "J",
+        'ĵ' => "j",
+        'Ķ' => "K",
+        'ķ' => "k",
+        'Ĺ' | 'Ļ' | 'Ľ' | 'Ŀ' | 'Ł' => "L",
+        'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+        'Ñ' | 'Ń' | 'Ņ' | 'Ň' => "N",
+        'ñ' | 'ń' | 'ņ' | 'ň' => "n",
+        'Ò'..='Ö' | 'Ø' | 'Ō' | 'Ŏ' | 'Ő' => "O",
+        'ò'..='ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+        'Œ' => "OE",
+        'œ' => "oe",
+        'Ŕ' | 'Ŗ' | 'Ř' => "R",
+        'ŕ' | 'ŗ' | 'ř' => "r",
+        'Ś' | 'Ŝ' | 'Ş' | 'Š' => "S",
+        'ś' | 'ŝ' | 'ş' | 'š' => "s",
+        'ß' => "ss",
+        'Ţ' | 'Ť' | 'Ŧ' => "T",
+        'ţ' | 'ť' | 'ŧ' => "t",
+        'Þ' => "TH",
+        'þ' => "th",
+        'Ù'..='Ü' | 'Ũ' | 'Ū' | 'Ŭ' | 'Ů' | 'Ű' | 'Ų' => "U",
+        'ù'..='ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+        'Ŵ' => "W",
+        'ŵ' => "w",
+        'Ý' | 'Ŷ' | 'Ÿ' => "Y",
+        'ý' | 'ÿ' | 'ŷ' => "y",
+        'Ź' | 'Ż' | 'Ž' => "Z",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply() {
+        let all = NamePolicy {
+            lowercase: true,
+            hyphenate_spaces: true,
+            strip_diacritics: true,
+        };
+        assert_eq!(
+            all.apply(PathBuf::from("Out Dir/Café  Menu (2).PNG")),
+            PathBuf::from("Out Dir/cafe-menu-(2).png")
+        );
+        assert_eq!(
+            NamePolicy::default().apply(PathBuf::from("Straße.png")),
+            PathBuf::from("Straße.png")
+        );
+    }
+
+    #[test]
+    fn contained() {
+        let root = Path::new("/out");
+        let join = |name: &str, c| join_contained(root, Path::new(name), c);
+        assert_eq!(
+            join("a/../b.png", Containment::Reject),
+            Some(PathBuf::from("/out/b.png"))
+        );
+        assert_eq!(join("../../etc/passwd", Containment::Reject), None);
+        assert_eq!(join("/etc/passwd", Containment::Reject), None);
+        assert_eq!(
+            join("../../etc/passwd", Containment::Sanitize),
+            Some(PathBuf::from("/out/etc/passwd"))
+        );
+        assert_eq!(join("..", Containment::Sanitize), None);
+    }
+
+    #[test]
+    fn copy_numbers() {
+        let mut filesystem = crate::vfs::MemoryFs::new("/");
+        for file in [
+            "a.txt",
+            "a (2).txt",
+            "a (4).txt",
+            "a (05).txt",
+            "a (3).png",
+            "b",
+        ] {
+            filesystem.add_file(Path::new("/out").join(file));
+        }
+        let taken = |p: &Path| filesystem.exists(p);
+        let mut numbers = CopyNumbers::default();
+        let mut next =
+            |name: &str| numbers.free_name(&filesystem, &Path::new("/out").join(name), taken);
+        assert_eq!(next("a.txt"), Path::new("/out/a (3).txt"));
+        assert_eq!(next("a.txt"), Path::new("/out/a (5).txt"));
+        assert_eq!(next("b"), Path::new("/out/b (2)"));
+        assert_eq!(next("c.txt"), Path::new("/out/c.txt"));
+    }
+
+    #[test]
+    fn flatten() {
+        let taken = |dst: &Path| dst == Path::new("/out/two.txt") || dst.ends_with("two-1.txt");
+        let relative = Path::new("a/deep/two.txt");
+        let named =
+            |flatten: Flatten| flatten.free_name(Path::new("/out/two.txt"), relative, taken);
+        assert_eq!(named(Flatten::Counter), Path::new("/out/two-2.txt"));
+        assert_eq!(named(Flatten::Parenthesized), Path::new("/out/two (2).txt"));
+        assert_eq!(named(Flatten::ParentDirs), Path::new("/out/two-a-deep.txt"));
+        let free = Flatten::Counter.free_name(Path::new("/out/one.txt"), relative, taken);
+        assert_eq!(free, Path::new("/out/one.txt"));
+    }
+}
@@ -0,0 +1,600 @@
+//! Fanning pairs out to worker threads, see [`SrcDstPairs::process_parallel`],
+//! [`SrcDstPairs::process_parallel_io`], [`SrcDstPairs::process_parallel_ordered`],
+//! [`SrcDstPairs::shared`] and [`SrcDstPairs::into_channel`].
+
+use std::{
+    collections::BTreeMap,
+    io,
+    num::NonZeroUsize,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+};
+
+use crate::{
+    descriptors,
+    ioers::{ClarifiedIo, IoFactory},
+    Dst, Src, SrcDstPairs,
+};
+
+impl SrcDstPairs {
+    /// For stdout and single-archive drains: `process` runs on `threads` workers (0 for one per
+    /// CPU) and produces each output in memory, while `commit` is called on the current thread
+    /// with the outputs in the order the pairs were yielded, e.g. writing them to stdout or
+    /// appending them to a `WriteArchive` (feature `tar`).
+    ///
+    /// Workers stop taking pairs while outputs of `window` bytes or more wait for their turn,
+    /// except for the very pair everything waits on, so memory stays bounded by about `window`
+    /// plus one output per worker.
+    ///
+    /// Pairs failed by `process` are not committed. An error of `commit`, or of reading back
+    /// spilled SRC names (see [`Self::spill_error`]), stops the processing and is returned; the
+    /// results so far are lost with it. A panic in `process` stops the other workers too, and
+    /// is propagated once they stopped.
+    ///
+    /// ```no_run
+    /// use std::io::Write;
+    /// use src_dst_clarifier::SrcDstConfig;
+    ///
+    /// let pairs = SrcDstConfig::new("txt").parse("logs", Some("-")).unwrap().unwrap();
+    /// let mut stdout = std::io::stdout().lock();
+    /// let results = pairs.process_parallel_ordered(
+    ///     0,
+    ///     64 << 20,
+    ///     |src, _| std::fs::read(src.to_string()),
+    ///     |_, _, output| stdout.write_all(&output),
+    /// );
+    /// ```
+    pub fn process_parallel_ordered<F, C>(
+        self,
+        threads: usize,
+        window: usize,
+        process: F,
+        mut commit: C,
+    ) -> io::Result<Vec<(Src, Dst, io::Result<()>)>>
+    where
+        F: Fn(&Src, &Dst) -> io::Result<Vec<u8>> + Sync,
+        C: FnMut(&Src, &Dst, Vec<u8>) -> io::Result<()>,
+    {
+        let threads = descriptors::global().workers(match threads {
+            0 => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            n => n,
+        });
+        let window = Window {
+            limit: window,
+            state: Mutex::new(WindowState {
+                pairs: self,
+                taken: 0,
+                committed: 0,
+                buffered: 0,
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        };
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let (state, process, tx) = (&window, &process, tx.clone());
+                scope.spawn(move || {
+                    while let Some((i, (src, dst))) = state.take() {
+                        let guard = StopOnPanic(state);
+                        let output = process(&src, &dst);
+                        std::mem::forget(guard);
+                        state.buffer(output.as_ref().map_or(0, Vec::len));
+                        if tx.send((i, (src, dst, output))).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(tx);
+
+            // 按顺序提交，先到的乱序结果在这里等着
+            let mut pending = BTreeMap::new();
+            let mut results = Vec::new();
+            for (i, result) in rx {
+                pending.insert(i, result);
+                while let Some(entry) = pending.first_entry() {
+                    if *entry.key() != results.len() {
+                        break;
+                    }
+                    let (src, dst, output) = entry.remove();
+                    let len = output.as_ref().map_or(0, Vec::len);
+                    let result = match output {
+                        Ok(output) => match commit(&src, &dst, output) {
+                            Ok(()) => Ok(()),
+                            Err(e) => {
+                                window.stop();
+                                return Err(e);
+                            }
+                        },
+                        Err(e) => Err(e),
+                    };
+                    results.push((src, dst, result));
+                    window.commit(len);
+                }
+            }
+            match window.lock().pairs.spill_error() {
+                Some(e) => Err(io::Error::new(e.kind(), e.to_string())),
+                None => Ok(results),
+            }
+        })
+    }
+
+    /// For handing the pairs to a thread pool without collecting them first:
+    /// every clone yields the next pair not yet taken by any of them.
+    /// Check [`Self::writes_to_stdout`] first, nothing here serializes the outputs.
+    ///
+    /// ```no_run
+    /// use src_dst_clarifier::SrcDstConfig;
+    ///
+    /// let pairs = SrcDstConfig::new("webp").parse("photos", Some("out")).unwrap().unwrap();
+    /// pairs.create_tnamed_dir().unwrap();
+    /// let shared = pairs.shared();
+    /// std::thread::scope(|scope| {
+    ///     for _ in 0..4 {
+    ///         let pairs = shared.clone();
+    ///         scope.spawn(move || {
+    ///             for (src, dst) in pairs {
+    ///                 // ...
+    ///             }
+    ///         });
+    ///     }
+    /// });
+    /// ```
+    pub fn shared(self) -> SharedPairs {
+        SharedPairs(Arc::new(Mutex::new(self)))
+    }
+
+    /// For streaming pipelines: the producer enumerates into a channel of at most `capacity`
+    /// pairs (at least 1), blocking while it is full, so a slow consumer throttles the walk and
+    /// memory stays flat. Run [`PairProducer::run`] on a thread of your own. With several
+    /// consumers, check [`Self::writes_to_stdout`] first.
+    ///
+    /// ```no_run
+    /// use src_dst_clarifier::SrcDstConfig;
+    ///
+    /// let pairs = SrcDstConfig::new("webp").parse("photos", Some("out")).unwrap().unwrap();
+    /// pairs.create_tnamed_dir().unwrap();
+    /// let (producer, receiver) = pairs.into_channel(64);
+    /// std::thread::scope(|scope| {
+    ///     scope.spawn(|| producer.run());
+    ///     for (src, dst) in receiver {
+    ///         // ...
+    ///     }
+    /// });
+    /// ```
+    pub fn into_channel(self, capacity: usize) -> (PairProducer, PairReceiver) {
+        let (tx, rx) = mpsc::sync_channel(capacity.max(1));
+        let producer = PairProducer { pairs: self, tx };
+        (producer, PairReceiver(Arc::new(Mutex::new(rx))))
+    }
+
+    /// Calls `process` on every pair from `threads` workers (0 for one per CPU), and returns
+    /// the results in the order the pairs were yielded.
+    ///
+    /// **Call [`Self::create_tnamed_dir`] (and [`Self::create_dst_dirs`]) first**, as when
+    /// consuming the pairs serially. A panic in `process` is propagated once all workers stopped.
+    ///
+    /// When [`Self::writes_to_stdout`], a single worker is used whatever `threads` says,
+    /// so that outputs are written one after another in order. See
+    /// [`Self::process_parallel_ordered`] for processing such pairs concurrently anyway.
+    ///
+    /// ```no_run
+    /// use src_dst_clarifier::{Dst, Src, SrcDstConfig};
+    ///
+    /// let pairs = SrcDstConfig::new("webp").parse("photos", Some("out")).unwrap().unwrap();
+    /// pairs.create_tnamed_dir().unwrap();
+    /// for (src, dst, result) in pairs.process_parallel(0, |src, dst| match (src, dst) {
+    ///     (Src::File(src), Dst::File(dst)) => std::fs::copy(src, dst),
+    ///     _ => unreachable!(), // 批量模式下都是文件
+    /// }) {
+    ///     if let Err(e) = result {
+    ///         eprintln!("{src} -> {dst}: {e}");
+    ///     }
+    /// }
+    /// ```
+    pub fn process_parallel<T, E, F>(
+        self,
+        threads: usize,
+        process: F,
+    ) -> Vec<(Src, Dst, Result<T, E>)>
+    where
+        F: Fn(Src, Dst) -> Result<T, E> + Sync,
+        T: Send,
+        E: Send,
+    {
+        let threads = descriptors::global().workers(match (threads, self.writes_to_stdout()) {
+            (_, true) => 1, // 并行写 stdout 只会搅成一团
+            (0, false) => thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            (n, false) => n,
+        });
+        let pairs = Mutex::new(self.enumerate());
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..threads {
+                let (pairs, process, tx) = (&pairs, &process, tx.clone());
+                scope.spawn(move || loop {
+                    // 取出下一对就立刻释放锁
+                    let next = pairs.lock().unwrap_or_else(|e| e.into_inner()).next();
+                    let Some((i, (src, dst))) = next else {
+                        break;
+                    };
+                    let result = process(src.clone(), dst.clone());
+                    if tx.send((i, (src, dst, result))).is_err() {
+                        break;
+                    }
+                });
+            }
+        });
+        drop(tx);
+
+        let mut results = rx.into_iter().collect::<Vec<_>>();
+        results.sort_unstable_by_key(|&(i, _)| i);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Like [`Self::process_parallel`], but `process` is given the [`ClarifiedIo`] made by
+    /// `factory` for the pair, which is then finished, or aborted if anything failed.
+    pub fn process_parallel_io<T, F>(
+        self,
+        threads: usize,
+        factory: &IoFactory,
+        process: F,
+    ) -> Vec<(Src, Dst, io::Result<T>)>
+    where
+        F: Fn(&mut ClarifiedIo) -> io::Result<T> + Sync,
+        T: Send,
+    {
+        self.process_parallel(threads, |src, dst| {
+            let mut io = factory.clarify(&src, &dst);
+            let result = process(&mut io).and_then(|value| {
+                io.input.finish()?;
+                Ok(value)
+            });
+            match result {
+                Ok(value) => io.output.finish().map(|()| value),
+                Err(e) => {
+                    let _ = io.output.abort(); // 原本的错误更重要
+                    Err(e)
+                }
+            }
+        })
+    }
+}
+
+/// The state of [`SrcDstPairs::process_parallel_ordered`].
+struct Window {
+    limit: usize,
+    state: Mutex<WindowState>,
+    changed: Condvar,
+}
+
+struct WindowState {
+    pairs: SrcDstPairs,
+    /// Index of the next pair to take.
+    taken: usize,
+    /// Index of the next pair to commit.
+    committed: usize,
+    /// Bytes of outputs not yet committed.
+    buffered: usize,
+    stopped: bool,
+}
+
+impl Window {
+    fn lock(&self) -> std::sync::MutexGuard<'_, WindowState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn take(&self) -> Option<(usize, (Src, Dst))> {
+        let mut state = self.lock();
+        // 窗口满了就等，除非下一对正是提交在等的那一对，不然会死锁
+        while !state.stopped && state.buffered >= self.limit && state.taken != state.committed {
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        if state.stopped {
+            return None;
+        }
+        let next = state.pairs.next()?;
+        state.taken += 1;
+        Some((state.taken - 1, next))
+    }
+
+    fn buffer(&self, len: usize) {
+        self.lock().buffered += len;
+    }
+
+    fn commit(&self, len: usize) {
+        let mut state = self.lock();
+        state.buffered -= len;
+        state.committed += 1;
+        self.changed.notify_all();
+    }
+
+    fn stop(&self) {
+        self.lock().stopped = true;
+        self.changed.notify_all();
+    }
+}
+
+/// Stops the other workers when `process` panics, the pair it had will never be committed
+/// and they would wait for it forever.
+struct StopOnPanic<'a>(&'a Window);
+
+impl Drop for StopOnPanic<'_> {
+    fn drop(&mut self) {
+        self.0.stop();
+    }
+}
+
+/// See [`SrcDstPairs::shared`].
+#[derive(Debug, Clone)]
+pub struct SharedPairs(Arc<Mutex<SrcDstPairs>>);
+
+impl Iterator for SharedPairs {
+    type Item = (Src, Dst);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // 别的线程 panic 了也继续给出剩下的
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).next()
+    }
+}
+
+/// See [`SrcDstPairs::into_channel`].
+pub struct PairProducer {
+    pairs: SrcDstPairs,
+    tx: mpsc::SyncSender<(Src, Dst)>,
+}
+
+impl PairProducer {
+    /// Sends every pair, returns how many were taken. Stops early once every
+    /// [`PairReceiver`] has been dropped. Fails when the spilled SRC names could not be read
+    /// back, after sending those that could, see [`SrcDstPairs::spill_error`].
+    pub fn run(mut self) -> io::Result<usize> {
+        let mut sent = 0;
+        for pair in self.pairs.by_ref() {
+            if self.tx.send(pair).is_err() {
+                return Ok(sent);
+            }
+            sent += 1;
+        }
+        match self.pairs.spill_error() {
+            Some(e) => Err(io::Error::new(e.kind(), e.to_string())),
+            None => Ok(sent),
+        }
+    }
+}
+
+/// See [`SrcDstPairs::into_channel`]. Clones share the channel, each pair goes to one of them.
+/// Ends once the producer finished and the channel has been drained.
+#[derive(Debug, Clone)]
+pub struct PairReceiver(Arc<Mutex<mpsc::Receiver<(Src, Dst)>>>);
+
+impl Iterator for PairReceiver {
+    type Item = (Src, Dst);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, fs, io, path::PathBuf};
+
+    use crate::{scratch::ScratchDir, SrcDstConfig};
+
+    use super::*;
+
+    #[test]
+    fn in_order() {
+        let root = ScratchDir::new("parallel");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("dst")).unwrap();
+        for n in 0..20 {
+            fs::write(root.join(format!("src/{n:02}.txt")), n.to_string()).unwrap();
+        }
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("src"), Some(root.join("dst")))
+            .unwrap()
+            .unwrap();
+        let results = pairs.process_parallel(4, |src, dst| match (src, dst) {
+            (Src::File(src), Dst::File(dst)) => fs::copy(src, dst).map(|_| ()),
+            _ => Err(io::Error::other("not a file")),
+        });
+
+        let names: Vec<_> = results
+            .iter()
+            .map(|(src, _, result)| {
+                assert!(result.is_ok());
+                PathBuf::from(src.to_string())
+                    .file_name()
+                    .unwrap()
+                    .to_owned()
+            })
+            .collect();
+        let expected: Vec<OsString> = (0..20).map(|n| format!("{n:02}.txt").into()).collect();
+        assert_eq!(names, expected);
+        assert_eq!(fs::read_to_string(root.join("dst/13.txt")).unwrap(), "13");
+    }
+
+    #[test]
+    fn shared() {
+        let root = ScratchDir::new("shared");
+        fs::create_dir_all(root.join("src")).unwrap();
+        for n in 0..50 {
+            fs::write(root.join(format!("src/{n:02}.txt")), b"").unwrap();
+        }
+
+        let shared = SrcDstConfig::new("txt")
+            .parse(root.join("src"), Some(root.clone()))
+            .unwrap()
+            .unwrap()
+            .shared();
+        let mut taken: Vec<_> = thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    let pairs = shared.clone();
+                    scope.spawn(move || pairs.map(|(src, _)| src.to_string()).collect::<Vec<_>>())
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap())
+                .collect()
+        });
+        taken.sort();
+        taken.dedup();
+        assert_eq!(taken.len(), 50);
+    }
+
+    #[test]
+    fn factory() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use crate::ioers::{DefaultIo, Output, OutputFactory};
+        let root = ScratchDir::new("factory");
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::create_dir_all(root.join("dst")).unwrap();
+        for n in 0..10 {
+            fs::write(root.join(format!("src/{n}.txt")), n.to_string()).unwrap();
+        }
+
+        let made = Arc::new(AtomicUsize::new(0));
+        let counter = made.clone();
+        let factory = IoFactory {
+            output: Arc::new(move |src: &Src, dst: &Dst| -> Box<dyn Output> {
+                counter.fetch_add(1, Ordering::Relaxed);
+                DefaultIo.output(src, dst)
+            }),
+            ..IoFactory::default()
+        };
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("src"), Some(root.join("dst")))
+            .unwrap()
+            .unwrap();
+        let results = pairs.process_parallel_io(3, &factory, |io| {
+            io::copy(&mut io.input.reader()?, &mut io.output.writer()?)
+        });
+
+        assert!(results.iter().all(|(_, _, result)| result.is_ok()));
+        assert_eq!(made.load(Ordering::Relaxed), 10);
+        assert_eq!(fs::read_to_string(root.join("dst/7.txt")).unwrap(), "7");
+    }
+
+    #[test]
+    fn channel() {
+        let root = ScratchDir::new("channel");
+        fs::create_dir_all(root.join("src")).unwrap();
+        for n in 0..10 {
+            fs::write(root.join(format!("src/{n}.txt")), b"").unwrap();
+        }
+
+        let (producer, receiver) = SrcDstConfig::new("txt")
+            .parse(root.join("src"), Some(root.clone()))
+            .unwrap()
+            .unwrap()
+            .into_channel(2);
+        let (sent, received) = thread::scope(|scope| {
+            let producer = scope.spawn(|| producer.run());
+            let received = receiver.clone().take(3).count();
+            drop(receiver); // 没人收了，生产者就该停下
+            (producer.join().unwrap().unwrap(), received)
+        });
+        // 最多多走出通道容量那么多
+        assert_eq!(received, 3);
+        assert!((3..=5).contains(&sent));
+    }
+
+    #[test]
+    fn stdout_serialized() {
+        use crate::builder::{PairDrain, PairSource};
+
+        let pairs = SrcDstConfig::new("txt")
+            .builder()
+            .source(PairSource::Files {
+                root: PathBuf::from("/in"),
+                names: (0..20).map(|n| PathBuf::from(format!("{n}.txt"))).collect(),
+            })
+            .drain(PairDrain::Stdout)
+            .build()
+            .unwrap();
+        assert!(pairs.writes_to_stdout());
+        let results = pairs.process_parallel(4, |_, _| Ok::<_, ()>(thread::current().id()));
+        let workers = (results.iter())
+            .map(|(_, _, id)| id.unwrap())
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(workers.len(), 1);
+    }
+
+    #[test]
+    fn ordered_commit() {
+        use crate::builder::{PairDrain, PairSource};
+
+        let pairs = SrcDstConfig::new("txt")
+            .builder()
+            .source(PairSource::Files {
+                root: PathBuf::from("/in"),
+                names: (0..30).map(|n| PathBuf::from(format!("{n:02}"))).collect(),
+            })
+            .drain(PairDrain::Stdout)
+            .build()
+            .unwrap();
+        let mut committed = Vec::new();
+        let results = pairs
+            .process_parallel_ordered(
+                4,
+                8,
+                |src, _| {
+                    let name = src.to_string();
+                    // 让后面的先做完，考验重排
+                    let n: u64 = name[name.len() - 2..].parse().unwrap();
+                    thread::sleep(std::time::Duration::from_millis((30 - n) % 7));
+                    match n {
+                        13 => Err(io::Error::other("unlucky")),
+                        _ => Ok(name.into_bytes()),
+                    }
+                },
+                |_, _, output| {
+                    committed.push(String::from_utf8(output).unwrap());
+                    Ok(())
+                },
+            )
+            .unwrap();
+        assert_eq!(results.len(), 30);
+        assert!(results[13].2.is_err());
+        let expected: Vec<_> = (0..30)
+            .filter(|&n| n != 13)
+            .map(|n| format!("/in/{n:02}"))
+            .collect();
+        assert_eq!(committed, expected);
+    }
+
+    #[test]
+    fn ordered_panic() {
+        use crate::builder::{PairDrain, PairSource};
+
+        let pairs = SrcDstConfig::new("txt")
+            .builder()
+            .source(PairSource::Files {
+                root: PathBuf::from("/in"),
+                names: (0..30).map(|n| PathBuf::from(format!("{n:02}"))).collect(),
+            })
+            .drain(PairDrain::Stdout)
+            .build()
+            .unwrap();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            // 窗口很小，其他 worker 都会等着第 0 对
+            pairs.process_parallel_ordered(
+                4,
+                1,
+                |src, _| match src.to_string().ends_with("00") {
+                    true => panic!("worker failed"),
+                    false => Ok(vec![0; 8]),
+                },
+                |_, _, _| Ok(()),
+            )
+        }));
+        assert!(panicked.is_err());
+    }
+}
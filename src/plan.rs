@@ -0,0 +1,1008 @@
+//! Resolved pairs that can be saved, inspected and executed later (or elsewhere).
+//!
+//! # The `.sdplan` Format
+//!
+//! A UTF-8 text file, one record per line, fields separated by a single space:
+//!
+//! ``` plaintext
+//! sdplan 1
+//! created 1697000000.000000000
+//! env.crate_version 0.2.0
+//! env.platform linux x86_64
+//! env.cwd /home/user
+//! config.default_extension png
+//! config.inplace deny
+//! tnamed_dir /home/user/frames-A01123-0456-0789
+//! dst_dir /home/user/frames-A01123-0456-0789/closeups
+//! pair /home/user/frames/0001.jpg /home/user/frames-A01123-0456-0789/0001.jpg
+//! pair <stdin> <stdout>
+//! pair <ssh>user@host:/srv/input.jpg /home/user/input.jpg
+//! pair /home/user/frames/0001.jpg <entry>/home/user/frames.tar 0001.jpg
+//! overwrite /home/user/input.jpg -512 86400000000000 differs
+//! ```
+//!
+//! Paths are percent-escaped (`%`, whitespace, `<`, `>`, control characters and bytes that are
+//! not valid UTF-8), so that non-UTF-8 paths round-trip on Unix.
+//! Unknown keys are ignored; the version number is bumped whenever that would not be sound.
+//! Remote and socket pairs can only be read back with the feature that produced them.
+
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    audit::{self, Operation},
+    collation::{Collation, Traversal},
+    digest::Digest,
+    environment::Environment,
+    naming::{BucketBy, Buckets, Containment, Flatten},
+    ArchiveEntry, AutoNamePolicy, CwdFallback, Dst, DstKind, InplacePolicy, OverwritePolicy, Src,
+    SrcDstConfig, SrcDstError, SrcDstPairs, StdioPolicy, SymlinkPolicy,
+};
+
+pub const PLAN_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub config: SrcDstConfig,
+    pub created: SystemTime,
+    pub environment: Environment,
+    /// The time-based named directory that has to be created before execution.
+    pub tnamed_dir: Option<PathBuf>,
+    /// Sub-directories that have to be created before execution, after [`Self::tnamed_dir`],
+    /// shallowest first, see [`SrcDstPairs::create_dst_dirs`].
+    pub dst_dirs: Vec<PathBuf>,
+    pub pairs: Vec<(Src, Dst)>,
+    /// Existing DSTs the pairs would replace, empty until [`Self::summarize_overwrites`].
+    pub overwrites: Vec<Overwrite>,
+}
+
+impl SrcDstConfig {
+    /// Like [`Self::parse`], but collects every pair up front. Nothing is created, and with a
+    /// [`vfs::MemoryFs`](crate::vfs::MemoryFs) as [`Self::filesystem`] nothing is read either.
+    pub fn plan<P: AsRef<Path>>(
+        &self,
+        src: P,
+        dst: Option<P>,
+    ) -> io::Result<Result<Plan, SrcDstError>> {
+        Ok(self.parse(src, dst)?.map(|pairs| Plan {
+            config: self.clone(),
+            created: match self.deterministic {
+                true => self.fixed_time.unwrap_or(SystemTime::UNIX_EPOCH),
+                false => SystemTime::now(),
+            },
+            environment: Environment::capture_in(self.fs()),
+            tnamed_dir: pairs.tnamed_dir().map(Path::to_path_buf),
+            dst_dirs: (pairs.dst_dirs().into_iter())
+                .filter(|dir| !self.fs().is_dir(dir))
+                .collect(),
+            pairs: pairs.collect(),
+            overwrites: Vec::new(),
+        }))
+    }
+}
+
+impl Plan {
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let created = self
+            .created
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        let config = &self.config;
+
+        writeln!(w, "sdplan {PLAN_VERSION}")?;
+        writeln!(
+            w,
+            "created {}.{:09}",
+            created.as_secs(),
+            created.subsec_nanos()
+        )?;
+        let environment = &self.environment;
+        writeln!(
+            w,
+            "env.crate_version {}",
+            escape(environment.crate_version.as_ref())
+        )?;
+        writeln!(
+            w,
+            "env.platform {} {}",
+            escape(environment.os.as_ref()),
+            escape(environment.arch.as_ref())
+        )?;
+        if let Some(cwd) = &environment.cwd {
+            writeln!(w, "env.cwd {}", escape(cwd.as_os_str()))?;
+        }
+        let stdio = match config.stdio {
+            StdioPolicy::StdinOnly => "stdin-only",
+            StdioPolicy::StdoutOnly => "stdout-only",
+            StdioPolicy::Deny => "deny",
+            StdioPolicy::Allow => "allow",
+        };
+        writeln!(w, "config.stdio {stdio}")?;
+        writeln!(w, "config.deny_stdout_tty {}", config.deny_stdout_tty)?;
+        writeln!(w, "config.dash_is_stdio {}", config.dash_is_stdio)?;
+        let auto_name = match config.auto_name {
+            AutoNamePolicy::FilesOnly => "files-only",
+            AutoNamePolicy::DirsOnly => "dirs-only",
+            AutoNamePolicy::Never => "never",
+            AutoNamePolicy::FilesAndDirs => "files-and-dirs",
+        };
+        writeln!(w, "config.auto_name {auto_name}")?;
+        writeln!(
+            w,
+            "config.default_extension {}",
+            escape(&config.default_extension)
+        )?;
+        if let Some(extension) = &config.output_extension {
+            writeln!(w, "config.output_extension {}", escape(extension))?;
+        }
+        for pattern in &config.passthrough {
+            writeln!(w, "config.passthrough {}", escape(pattern.as_ref()))?;
+        }
+        if let Some(suffix) = &config.sibling_suffix {
+            writeln!(w, "config.sibling_suffix {}", escape(suffix))?;
+        }
+        let inplace = match config.inplace {
+            InplacePolicy::Atomic => "atomic",
+            InplacePolicy::Deny => "deny",
+        };
+        writeln!(w, "config.inplace {inplace}")?;
+        writeln!(
+            w,
+            "config.collapse_duplicate_extensions {}",
+            config.collapse_duplicate_extensions
+        )?;
+        writeln!(w, "config.deterministic {}", config.deterministic)?;
+        writeln!(w, "config.capture_time {}", config.capture_time)?;
+        writeln!(w, "config.sidecar_tokens {}", config.sidecar_tokens)?;
+        writeln!(w, "config.hold_src_dir {}", config.hold_src_dir)?;
+        writeln!(w, "config.max_depth {}", config.max_depth)?;
+        let symlinks = match config.symlinks {
+            SymlinkPolicy::Follow => "follow",
+            SymlinkPolicy::Skip => "skip",
+            SymlinkPolicy::Error => "error",
+        };
+        writeln!(w, "config.symlinks {symlinks}")?;
+        writeln!(w, "config.ignore_files {}", config.ignore_files)?;
+        writeln!(
+            w,
+            "config.sort_spill_threshold {}",
+            config.sort_spill_threshold
+        )?;
+        let filter = &config.source_filter;
+        for extension in &filter.extensions {
+            writeln!(w, "config.source_filter.extension {}", escape(extension))?;
+        }
+        for extension in &filter.excluded_extensions {
+            writeln!(
+                w,
+                "config.source_filter.excluded_extension {}",
+                escape(extension)
+            )?;
+        }
+        for pattern in &filter.include {
+            writeln!(
+                w,
+                "config.source_filter.include {}",
+                escape(pattern.as_ref())
+            )?;
+        }
+        for pattern in &filter.exclude {
+            writeln!(
+                w,
+                "config.source_filter.exclude {}",
+                escape(pattern.as_ref())
+            )?;
+        }
+        let containment = match config.containment {
+            Containment::Reject => "reject",
+            Containment::Sanitize => "sanitize",
+        };
+        writeln!(w, "config.containment {containment}")?;
+        let overwrite = match config.overwrite {
+            OverwritePolicy::Error => "error",
+            OverwritePolicy::Overwrite => "overwrite",
+            OverwritePolicy::Skip => "skip",
+            OverwritePolicy::RenameWithCounter => "rename",
+            OverwritePolicy::RenameParenthesized => "rename-parenthesized",
+        };
+        writeln!(w, "config.overwrite {overwrite}")?;
+        writeln!(w, "config.incremental {}", config.incremental)?;
+        match &config.collation {
+            Collation::Bytewise => writeln!(w, "config.collation <bytewise>")?,
+            Collation::Locale(tag) => writeln!(w, "config.collation {}", escape(tag.as_ref()))?,
+        }
+        let traversal = match config.traversal {
+            Traversal::DepthFirst => "depth-first",
+            Traversal::BreadthFirst => "breadth-first",
+            Traversal::DirsFirst => "dirs-first",
+        };
+        writeln!(w, "config.traversal {traversal}")?;
+        match &config.cwd_fallback {
+            CwdFallback::Fail => writeln!(w, "config.cwd_fallback <fail>")?,
+            CwdFallback::TempDir => writeln!(w, "config.cwd_fallback <temp>")?,
+            CwdFallback::Path(path) => {
+                writeln!(w, "config.cwd_fallback {}", escape(path.as_os_str()))?
+            }
+        }
+        if let Some(root) = &config.confine_to {
+            writeln!(w, "config.confine_to {}", escape(root.as_os_str()))?;
+        }
+        if let Some(cache) = &config.listing_cache {
+            writeln!(w, "config.listing_cache {}", escape(cache.as_os_str()))?;
+        }
+        if let Some(template) = &config.tnamed_file_template {
+            writeln!(w, "config.tnamed_file_template {}", escape(template))?;
+        }
+        if let Some(template) = &config.tnamed_dir_template {
+            writeln!(w, "config.tnamed_dir_template {}", escape(template))?;
+        }
+        if let Some(time) = config.fixed_time {
+            let time = time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default();
+            writeln!(
+                w,
+                "config.fixed_time {}.{:09}",
+                time.as_secs(),
+                time.subsec_nanos()
+            )?;
+        }
+        let dst_kind = match config.dst_kind {
+            DstKind::Guess => "guess",
+            DstKind::File => "file",
+            DstKind::Dir => "dir",
+        };
+        writeln!(w, "config.dst_kind {dst_kind}")?;
+        writeln!(w, "config.allow_many_to_one {}", config.allow_many_to_one)?;
+        let policy = &config.name_policy;
+        writeln!(w, "config.name_policy.lowercase {}", policy.lowercase)?;
+        writeln!(
+            w,
+            "config.name_policy.hyphenate_spaces {}",
+            policy.hyphenate_spaces
+        )?;
+        writeln!(
+            w,
+            "config.name_policy.strip_diacritics {}",
+            policy.strip_diacritics
+        )?;
+        if let Some(buckets) = &config.dst_buckets {
+            let by = match buckets.by {
+                BucketBy::Name => "name",
+                BucketBy::Content => "content",
+            };
+            writeln!(
+                w,
+                "config.dst_buckets {by} {} {}",
+                buckets.levels, buckets.width
+            )?;
+        }
+        if let Some(flatten) = config.flatten {
+            let flatten = match flatten {
+                Flatten::Counter => "counter",
+                Flatten::Parenthesized => "parenthesized",
+                Flatten::ParentDirs => "parent-dirs",
+            };
+            writeln!(w, "config.flatten {flatten}")?;
+        }
+        if let Some(dir) = &self.tnamed_dir {
+            writeln!(w, "tnamed_dir {}", escape(dir.as_os_str()))?;
+        }
+        for dir in &self.dst_dirs {
+            writeln!(w, "dst_dir {}", escape(dir.as_os_str()))?;
+        }
+        for (src, dst) in &self.pairs {
+            let src = match src {
+                Src::Stdin => String::from("<stdin>"),
+                Src::File(path) => escape(path.as_os_str()),
+                #[cfg(feature = "sftp")]
+                Src::Remote(remote) => format!("<ssh>{}", escape(remote.to_string().as_ref())),
+                #[cfg(all(unix, feature = "unix-socket"))]
+                Src::UnixSocket(socket) => format!("<unix>{}", escape(socket.to_string().as_ref())),
+            };
+            let dst = match dst {
+                Dst::Stdout => String::from("<stdout>"),
+                Dst::File(path) => escape(path.as_os_str()),
+                #[cfg(feature = "sftp")]
+                Dst::Remote(remote) => format!("<ssh>{}", escape(remote.to_string().as_ref())),
+                #[cfg(all(unix, feature = "unix-socket"))]
+                Dst::UnixSocket(socket) => format!("<unix>{}", escape(socket.to_string().as_ref())),
+                Dst::Entry(ArchiveEntry { archive, name }) => {
+                    let archive = archive
+                        .as_ref()
+                        .map_or_else(|| String::from("<stdout>"), |path| escape(path.as_os_str()));
+                    format!("<entry>{archive} {}", escape(name.as_os_str()))
+                }
+            };
+            writeln!(w, "pair {src} {dst}")?;
+        }
+        for overwrite in &self.overwrites {
+            let content = match overwrite.same_content {
+                None => "-",
+                Some(true) => "same",
+                Some(false) => "differs",
+            };
+            writeln!(
+                w,
+                "overwrite {} {} {} {content}",
+                escape(overwrite.dst.as_os_str()),
+                overwrite.size_delta,
+                overwrite.mtime_delta
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: BufRead>(r: R) -> io::Result<Self> {
+        fn invalid(msg: String) -> io::Error {
+            io::Error::new(io::ErrorKind::InvalidData, msg)
+        }
+        fn parse_time(value: &str) -> io::Result<SystemTime> {
+            let (secs, nanos) = value.split_once('.').unwrap_or((value, "0"));
+            let secs = secs
+                .parse()
+                .map_err(|_| invalid(format!("bad time '{value}'")))?;
+            let nanos = nanos
+                .parse()
+                .map_err(|_| invalid(format!("bad time '{value}'")))?;
+            Ok(SystemTime::UNIX_EPOCH + Duration::new(secs, nanos))
+        }
+        fn parse_bool(value: &str) -> io::Result<bool> {
+            value
+                .parse()
+                .map_err(|_| invalid(format!("expect `true` or `false`, found '{value}'")))
+        }
+
+        let mut lines = r.lines();
+        match lines.next().transpose()? {
+            Some(header) if header == format!("sdplan {PLAN_VERSION}") => (),
+            Some(header) if header.starts_with("sdplan ") => {
+                return Err(invalid(format!(
+                    "unsupported plan version '{}'",
+                    &header[7..]
+                )))
+            }
+            _ => return Err(invalid(String::from("not a plan file"))),
+        }
+
+        let mut plan = Plan {
+            config: SrcDstConfig::new(""),
+            created: SystemTime::UNIX_EPOCH,
+            environment: Environment::default(),
+            tnamed_dir: None,
+            dst_dirs: Vec::new(),
+            pairs: Vec::new(),
+            overwrites: Vec::new(),
+        };
+
+        for line in lines {
+            let line = line?;
+            let (key, value) = line.split_once(' ').unwrap_or((&line, ""));
+            let config = &mut plan.config;
+            match key {
+                "created" => plan.created = parse_time(value)?,
+                "env.crate_version" => plan.environment.crate_version = unescape_str(value)?,
+                "env.platform" => {
+                    let (os, arch) = value
+                        .split_once(' ')
+                        .ok_or_else(|| invalid(format!("bad platform '{value}'")))?;
+                    plan.environment.os = unescape_str(os)?;
+                    plan.environment.arch = unescape_str(arch)?;
+                }
+                "env.cwd" => plan.environment.cwd = Some(unescape(value)?.into()),
+                "config.deterministic" => config.deterministic = parse_bool(value)?,
+                "config.capture_time" => config.capture_time = parse_bool(value)?,
+                "config.sidecar_tokens" => config.sidecar_tokens = parse_bool(value)?,
+                "config.hold_src_dir" => config.hold_src_dir = parse_bool(value)?,
+                "config.source_filter.extension" => {
+                    config.source_filter.extensions.push(unescape(value)?)
+                }
+                "config.source_filter.excluded_extension" => config
+                    .source_filter
+                    .excluded_extensions
+                    .push(unescape(value)?),
+                "config.source_filter.include" => {
+                    config.source_filter.include.push(unescape_str(value)?)
+                }
+                "config.source_filter.exclude" => {
+                    config.source_filter.exclude.push(unescape_str(value)?)
+                }
+                "config.max_depth" => {
+                    config.max_depth = value
+                        .parse()
+                        .map_err(|_| invalid(format!("bad max_depth '{value}'")))?
+                }
+                "config.symlinks" => {
+                    config.symlinks = match value {
+                        "follow" => SymlinkPolicy::Follow,
+                        "skip" => SymlinkPolicy::Skip,
+                        "error" => SymlinkPolicy::Error,
+                        _ => return Err(invalid(format!("bad symlink policy '{value}'"))),
+                    }
+                }
+                "config.ignore_files" => config.ignore_files = parse_bool(value)?,
+                "config.sort_spill_threshold" => {
+                    config.sort_spill_threshold = value
+                        .parse()
+                        .map_err(|_| invalid(format!("bad sort_spill_threshold '{value}'")))?
+                }
+                "config.containment" => {
+                    config.containment = match value {
+                        "reject" => Containment::Reject,
+                        "sanitize" => Containment::Sanitize,
+                        _ => return Err(invalid(format!("bad containment '{value}'"))),
+                    }
+                }
+                "config.incremental" => config.incremental = parse_bool(value)?,
+                "config.overwrite" => {
+                    config.overwrite = match value {
+                        "error" => OverwritePolicy::Error,
+                        "overwrite" => OverwritePolicy::Overwrite,
+                        "skip" => OverwritePolicy::Skip,
+                        "rename" => OverwritePolicy::RenameWithCounter,
+                        "rename-parenthesized" => OverwritePolicy::RenameParenthesized,
+                        _ => return Err(invalid(format!("bad overwrite policy '{value}'"))),
+                    }
+                }
+                "config.collation" => {
+                    config.collation = match value {
+                        "<bytewise>" => Collation::Bytewise,
+                        tag => Collation::Locale(unescape_str(tag)?),
+                    }
+                }
+                "config.traversal" => {
+                    config.traversal = match value {
+                        "depth-first" => Traversal::DepthFirst,
+                        "breadth-first" => Traversal::BreadthFirst,
+                        "dirs-first" => Traversal::DirsFirst,
+                        _ => return Err(invalid(format!("bad traversal '{value}'"))),
+                    }
+                }
+                "config.cwd_fallback" => {
+                    config.cwd_fallback = match value {
+                        "<fail>" => CwdFallback::Fail,
+                        "<temp>" => CwdFallback::TempDir,
+                        path => CwdFallback::Path(unescape(path)?.into()),
+                    }
+                }
+                "config.fixed_time" => config.fixed_time = Some(parse_time(value)?),
+                "config.confine_to" => config.confine_to = Some(unescape(value)?.into()),
+                "config.listing_cache" => config.listing_cache = Some(unescape(value)?.into()),
+                "config.tnamed_file_template" => {
+                    config.tnamed_file_template = Some(unescape(value)?)
+                }
+                "config.tnamed_dir_template" => config.tnamed_dir_template = Some(unescape(value)?),
+                "config.stdio" => {
+                    config.stdio = match value {
+                        "allow" => StdioPolicy::Allow,
+                        "stdin-only" => StdioPolicy::StdinOnly,
+                        "stdout-only" => StdioPolicy::StdoutOnly,
+                        "deny" => StdioPolicy::Deny,
+                        _ => return Err(invalid(format!("bad stdio policy '{value}'"))),
+                    }
+                }
+                // Plans written before the typed policies
+                "config.allow_from_stdin" => {
+                    let to_stdout = config.stdio.to_stdout();
+                    config.stdio = StdioPolicy::of(parse_bool(value)?, to_stdout)
+                }
+                "config.allow_to_stdout" => {
+                    let from_stdin = config.stdio.from_stdin();
+                    config.stdio = StdioPolicy::of(from_stdin, parse_bool(value)?)
+                }
+                "config.deny_stdout_tty" => config.deny_stdout_tty = parse_bool(value)?,
+                "config.dash_is_stdio" => config.dash_is_stdio = parse_bool(value)?,
+                "config.auto_name" => {
+                    config.auto_name = match value {
+                        "files-and-dirs" => AutoNamePolicy::FilesAndDirs,
+                        "files-only" => AutoNamePolicy::FilesOnly,
+                        "dirs-only" => AutoNamePolicy::DirsOnly,
+                        "never" => AutoNamePolicy::Never,
+                        _ => return Err(invalid(format!("bad auto name policy '{value}'"))),
+                    }
+                }
+                "config.auto_tnamed_dst_file" => {
+                    let dirs = config.auto_name.dirs();
+                    config.auto_name = AutoNamePolicy::of(parse_bool(value)?, dirs)
+                }
+                "config.auto_tnamed_dst_dir" => {
+                    let files = config.auto_name.files();
+                    config.auto_name = AutoNamePolicy::of(files, parse_bool(value)?)
+                }
+                "config.default_extension" => config.default_extension = unescape(value)?,
+                "config.output_extension" => config.output_extension = Some(unescape(value)?),
+                "config.passthrough" => config.passthrough.push(unescape_str(value)?),
+                "config.sibling_suffix" => config.sibling_suffix = Some(unescape(value)?),
+                "config.inplace" => {
+                    config.inplace = match value {
+                        "deny" => InplacePolicy::Deny,
+                        "atomic" => InplacePolicy::Atomic,
+                        _ => return Err(invalid(format!("bad inplace policy '{value}'"))),
+                    }
+                }
+                "config.allow_inplace" => {
+                    config.inplace = match parse_bool(value)? {
+                        true => InplacePolicy::Atomic,
+                        false => InplacePolicy::Deny,
+                    }
+                }
+                "config.collapse_duplicate_extensions" => {
+                    config.collapse_duplicate_extensions = parse_bool(value)?
+                }
+                "config.dst_kind" => {
+                    config.dst_kind = match value {
+                        "guess" => DstKind::Guess,
+                        "file" => DstKind::File,
+                        "dir" => DstKind::Dir,
+                        _ => return Err(invalid(format!("bad DST kind '{value}'"))),
+                    }
+                }
+                "config.allow_many_to_one" => config.allow_many_to_one = parse_bool(value)?,
+                "config.name_policy.lowercase" => config.name_policy.lowercase = parse_bool(value)?,
+                "config.name_policy.hyphenate_spaces" => {
+                    config.name_policy.hyphenate_spaces = parse_bool(value)?
+                }
+                "config.name_policy.strip_diacritics" => {
+                    config.name_policy.strip_diacritics = parse_bool(value)?
+                }
+                "config.dst_buckets" => {
+                    let bad = || invalid(format!("bad DST buckets '{value}'"));
+                    let mut fields = value.split(' ');
+                    let by = match fields.next() {
+                        Some("name") => BucketBy::Name,
+                        Some("content") => BucketBy::Content,
+                        _ => return Err(bad()),
+                    };
+                    let mut number = || fields.next().and_then(|n| n.parse().ok()).ok_or_else(bad);
+                    config.dst_buckets = Some(Buckets {
+                        by,
+                        levels: number()?,
+                        width: number()?,
+                    });
+                }
+                "config.flatten" => {
+                    config.flatten = Some(match value {
+                        "counter" => Flatten::Counter,
+                        "parenthesized" => Flatten::Parenthesized,
+                        "parent-dirs" => Flatten::ParentDirs,
+                        _ => return Err(invalid(format!("bad flatten '{value}'"))),
+                    })
+                }
+                "tnamed_dir" => plan.tnamed_dir = Some(unescape(value)?.into()),
+                "dst_dir" => plan.dst_dirs.push(unescape(value)?.into()),
+                "pair" => {
+                    let (src, dst) = value
+                        .split_once(' ')
+                        .ok_or_else(|| invalid(format!("bad pair '{value}'")))?;
+                    let src = match src {
+                        "<stdin>" => Src::Stdin,
+                        src => match (src.strip_prefix("<ssh>"), src.strip_prefix("<unix>")) {
+                            #[cfg(feature = "sftp")]
+                            (Some(remote), _) => Src::Remote(unescape_remote(remote)?),
+                            #[cfg(all(unix, feature = "unix-socket"))]
+                            (_, Some(socket)) => Src::UnixSocket(unescape_socket(socket)?),
+                            #[cfg(not(feature = "sftp"))]
+                            (Some(_), _) => return Err(remote_unsupported()),
+                            #[cfg(not(all(unix, feature = "unix-socket")))]
+                            (_, Some(_)) => return Err(socket_unsupported()),
+                            _ => Src::File(unescape(src)?.into()),
+                        },
+                    };
+                    let dst = match dst {
+                        "<stdout>" => Dst::Stdout,
+                        dst if dst.starts_with("<entry>") => {
+                            let (archive, name) = dst[7..]
+                                .split_once(' ')
+                                .ok_or_else(|| invalid(format!("bad archive entry '{dst}'")))?;
+                            let archive = match archive {
+                                "<stdout>" => None,
+                                archive => Some(unescape(archive)?.into()),
+                            };
+                            let name = unescape(name)?.into();
+                            Dst::Entry(ArchiveEntry { archive, name })
+                        }
+                        dst => match (dst.strip_prefix("<ssh>"), dst.strip_prefix("<unix>")) {
+                            #[cfg(feature = "sftp")]
+                            (Some(remote), _) => Dst::Remote(unescape_remote(remote)?),
+                            #[cfg(all(unix, feature = "unix-socket"))]
+                            (_, Some(socket)) => Dst::UnixSocket(unescape_socket(socket)?),
+                            #[cfg(not(feature = "sftp"))]
+                            (Some(_), _) => return Err(remote_unsupported()),
+                            #[cfg(not(all(unix, feature = "unix-socket")))]
+                            (_, Some(_)) => return Err(socket_unsupported()),
+                            _ => Dst::File(unescape(dst)?.into()),
+                        },
+                    };
+                    plan.pairs.push((src, dst));
+                }
+                "overwrite" => {
+                    let bad = || invalid(format!("bad overwrite '{value}'"));
+                    let mut fields = value.split(' ');
+                    let mut next = || fields.next().ok_or_else(bad);
+                    let dst = unescape(next()?)?.into();
+                    let size_delta = next()?.parse().map_err(|_| bad())?;
+                    let mtime_delta = next()?.parse().map_err(|_| bad())?;
+                    let same_content = match next()? {
+                        "-" => None,
+                        "same" => Some(true),
+                        "differs" => Some(false),
+                        _ => return Err(bad()),
+                    };
+                    plan.overwrites.push(Overwrite {
+                        dst,
+                        size_delta,
+                        mtime_delta,
+                        same_content,
+                    });
+                }
+                _ => (),
+            }
+        }
+
+        Ok(plan)
+    }
+}
+
+impl Plan {
+    /// What changes when going from `self` to `newer`, pairs are matched by their SRC.
+    ///
+    /// Note that time-based named DSTs differ between any two parses,
+    /// so such pairs always come out as retargeted.
+    pub fn diff(&self, newer: &Plan) -> PlanDiff {
+        let old = self.pairs.iter().cloned().collect::<HashMap<_, _>>();
+        let new = newer.pairs.iter().cloned().collect::<HashMap<_, _>>();
+
+        let mut diff = PlanDiff::default();
+        for (src, dst) in &self.pairs {
+            match new.get(src) {
+                None => diff.removed.push((src.clone(), dst.clone())),
+                Some(to) if to != dst => diff.retargeted.push(Retargeted {
+                    src: src.clone(),
+                    from: dst.clone(),
+                    to: to.clone(),
+                }),
+                Some(_) => (),
+            }
+        }
+        for (src, dst) in &newer.pairs {
+            if !old.contains_key(src) {
+                diff.added.push((src.clone(), dst.clone()));
+            }
+        }
+        diff
+    }
+}
+
+impl Plan {
+    /// Fills [`Self::overwrites`] for the pairs from a file SRC to an existing file DST, by their
+    /// metadata, and with `hash` also by their content when the sizes are equal. So that users can be
+    /// shown what would change before anything runs. Read through [`SrcDstConfig::filesystem`].
+    ///
+    /// Note that the SRC is compared as is, whatever the processing would turn it into.
+    pub fn summarize_overwrites(&mut self, hash: bool) -> io::Result<()> {
+        self.overwrites.clear();
+        let filesystem = crate::vfs::or_std(&self.config.filesystem);
+        for (src, dst) in &self.pairs {
+            let (Src::File(src), Dst::File(dst)) = (src, dst) else {
+                continue;
+            };
+            if !filesystem.is_file(dst) {
+                continue;
+            }
+            let src_len = audit::record(Operation::Open, src, filesystem.len(src))?;
+            fn nanos(time: io::Result<SystemTime>) -> io::Result<i128> {
+                Ok(match time?.duration_since(SystemTime::UNIX_EPOCH) {
+                    Ok(since) => since.as_nanos() as i128,
+                    Err(before) => -(before.duration().as_nanos() as i128),
+                })
+            }
+            let mtime_delta = nanos(filesystem.modified(src))? - nanos(filesystem.modified(dst))?;
+            let size_delta = src_len as i128 - filesystem.len(dst)? as i128;
+            let same_content = match (hash, size_delta) {
+                (false, _) => None,
+                (true, 0) => {
+                    let digest = |path: &Path| {
+                        let file = audit::record(Operation::Open, path, filesystem.open(path))?;
+                        Digest::of_reader(io::BufReader::new(file))
+                    };
+                    Some(digest(src)? == digest(dst)?)
+                }
+                (true, _) => Some(false),
+            };
+            self.overwrites.push(Overwrite {
+                dst: dst.clone(),
+                size_delta: size_delta as i64,
+                mtime_delta: mtime_delta as i64,
+                same_content,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// An existing DST and how its SRC differs from it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overwrite {
+    pub dst: PathBuf,
+    /// SRC size minus DST size, in bytes.
+    pub size_delta: i64,
+    /// SRC mtime minus DST mtime, in nanoseconds. Positive when the SRC is newer.
+    pub mtime_delta: i64,
+    /// `None` when not hashed.
+    pub same_content: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PlanDiff {
+    pub added: Vec<(Src, Dst)>,
+    pub removed: Vec<(Src, Dst)>,
+    pub retargeted: Vec<Retargeted>,
+}
+
+impl PlanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.retargeted.is_empty()
+    }
+}
+
+/// The same SRC which goes to another DST.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Retargeted {
+    pub src: Src,
+    pub from: Dst,
+    pub to: Dst,
+}
+
+pub fn save_plan<P: AsRef<Path>>(plan: &Plan, path: P) -> io::Result<()> {
+    crate::check_writable(path.as_ref())?;
+    let path = path.as_ref();
+    let file = audit::record(Operation::Create, path, fs::File::create(path))?;
+    let mut file = io::BufWriter::new(file);
+    plan.write_to(&mut file)?;
+    file.flush()
+}
+
+pub fn load_plan<P: AsRef<Path>>(path: P) -> io::Result<Plan> {
+    let path = path.as_ref();
+    let file = audit::record(Operation::Open, path, fs::File::open(path))?;
+    Plan::read_from(io::BufReader::new(file))
+}
+
+impl From<Plan> for SrcDstPairs {
+    fn from(plan: Plan) -> Self {
+        let mut pairs = SrcDstPairs::planned(plan.pairs, plan.tnamed_dir);
+        pairs.planned_dirs = plan.dst_dirs;
+        pairs
+    }
+}
+
+pub(crate) fn escape(s: &std::ffi::OsStr) -> String {
+    fn escape_str(out: &mut String, s: &str) {
+        for c in s.chars() {
+            match c {
+                '%' | '<' | '>' => out.push_str(&format!("%{:02X}", c as u32)),
+                c if c.is_whitespace() || c.is_control() => {
+                    let mut buf = [0; 4];
+                    for b in c.encode_utf8(&mut buf).bytes() {
+                        out.push_str(&format!("%{b:02X}"));
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        for chunk in s.as_bytes().utf8_chunks() {
+            escape_str(&mut out, chunk.valid());
+            for b in chunk.invalid() {
+                out.push_str(&format!("%{b:02X}"));
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    escape_str(&mut out, &s.to_string_lossy());
+    out
+}
+
+pub(crate) fn unescape(s: &str) -> io::Result<OsString> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut iter = s.bytes();
+    while let Some(b) = iter.next() {
+        if b != b'%' {
+            bytes.push(b);
+            continue;
+        }
+        let hex = [iter.next().unwrap_or(0), iter.next().unwrap_or(0)];
+        let b = std::str::from_utf8(&hex)
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("bad escape in '{s}'"))
+            })?;
+        bytes.push(b);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        Ok(OsString::from_vec(bytes))
+    }
+    #[cfg(not(unix))]
+    String::from_utf8(bytes)
+        .map(OsString::from)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn unescape_str(s: &str) -> io::Result<String> {
+    unescape(s)?
+        .into_string()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("bad pattern '{s}'")))
+}
+
+#[cfg(feature = "sftp")]
+fn unescape_remote(s: &str) -> io::Result<crate::remote::RemotePath> {
+    crate::remote::RemotePath::parse(unescape(s)?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad remote path '{s}'")))
+}
+
+#[cfg(all(unix, feature = "unix-socket"))]
+fn unescape_socket(s: &str) -> io::Result<crate::socket::SocketPath> {
+    crate::socket::SocketPath::parse(unescape(s)?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("bad socket path '{s}'")))
+}
+
+#[cfg(not(feature = "sftp"))]
+fn remote_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "remote paths require the `sftp` feature",
+    )
+}
+
+#[cfg(not(all(unix, feature = "unix-socket")))]
+fn socket_unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "unix sockets require the `unix-socket` feature on Unix",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn round_trip() {
+        let mut config = SrcDstConfig::new_with_allow_inplace("png");
+        config.max_depth = 3;
+        config.sort_spill_threshold = 1000;
+        config.tnamed_file_template = Some("{stem} #{counter}.{ext}".into());
+        config.source_filter.extensions.push("jpg".into());
+        config.source_filter.exclude.push(String::from("100%*"));
+        config.passthrough.push(String::from("*.md"));
+        let plan = Plan {
+            config,
+            created: SystemTime::UNIX_EPOCH + Duration::new(1697000000, 42),
+            environment: Environment {
+                cwd: Some(PathBuf::from("/tmp/work dir")),
+                ..Environment::capture()
+            },
+            tnamed_dir: Some(PathBuf::from("/tmp/out dir")),
+            dst_dirs: vec![PathBuf::from("/tmp/out dir/sub")],
+            pairs: vec![
+                (Src::Stdin, Dst::Stdout),
+                (
+                    Src::File(PathBuf::from("/tmp/in/100%.jpg")),
+                    Dst::File(PathBuf::from("/tmp/out dir/<1>.jpg")),
+                ),
+            ],
+            overwrites: vec![Overwrite {
+                dst: PathBuf::from("/tmp/out dir/<1>.jpg"),
+                size_delta: -42,
+                mtime_delta: 1_000_000_000,
+                same_content: Some(false),
+            }],
+        };
+
+        let mut buf = Vec::new();
+        plan.write_to(&mut buf).unwrap();
+        assert_eq!(Plan::read_from(&buf[..]).unwrap(), plan);
+    }
+
+    #[test]
+    fn diff() {
+        let file = |p: &str| PathBuf::from(p);
+        let plan = |pairs: &[(&str, &str)]| Plan {
+            config: SrcDstConfig::new("png"),
+            created: SystemTime::UNIX_EPOCH,
+            environment: Environment::default(),
+            tnamed_dir: None,
+            dst_dirs: Vec::new(),
+            overwrites: Vec::new(),
+            pairs: pairs
+                .iter()
+                .map(|(s, d)| (Src::File(file(s)), Dst::File(file(d))))
+                .collect(),
+        };
+
+        let old = plan(&[("/a", "/o/a"), ("/b", "/o/b"), ("/c", "/o/c")]);
+        let new = plan(&[("/a", "/o/a"), ("/b", "/p/b"), ("/d", "/o/d")]);
+        let diff = old.diff(&new);
+
+        assert_eq!(
+            diff.added,
+            [(Src::File(file("/d")), Dst::File(file("/o/d")))]
+        );
+        assert_eq!(
+            diff.removed,
+            [(Src::File(file("/c")), Dst::File(file("/o/c")))]
+        );
+        assert_eq!(diff.retargeted.len(), 1);
+        assert_eq!(diff.retargeted[0].to, Dst::File(file("/p/b")));
+        assert!(old.diff(&old).is_empty());
+    }
+
+    #[test]
+    fn summarize_overwrites() {
+        let root = ScratchDir::new("overwrites");
+        let file = |name: &str, content: &str| {
+            let path = root.join(name);
+            fs::write(&path, content).unwrap();
+            path
+        };
+        let mut plan = Plan {
+            config: SrcDstConfig::new("txt"),
+            created: SystemTime::UNIX_EPOCH,
+            environment: Environment::default(),
+            tnamed_dir: None,
+            dst_dirs: Vec::new(),
+            pairs: vec![
+                (
+                    Src::File(file("a", "same")),
+                    Dst::File(file("a.out", "same")),
+                ),
+                (
+                    Src::File(file("b", "longer")),
+                    Dst::File(file("b.out", "b")),
+                ),
+                (Src::File(file("c", "c")), Dst::File(root.join("c.out"))),
+                (Src::Stdin, Dst::File(file("d.out", "d"))),
+            ],
+            overwrites: Vec::new(),
+        };
+
+        plan.summarize_overwrites(false).unwrap();
+        assert_eq!(plan.overwrites.len(), 2);
+        assert_eq!(plan.overwrites[1].size_delta, 5);
+        assert_eq!(plan.overwrites[1].same_content, None);
+
+        plan.summarize_overwrites(true).unwrap();
+        let content = plan.overwrites.iter().map(|o| o.same_content);
+        assert_eq!(content.collect::<Vec<_>>(), [Some(true), Some(false)]);
+
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut memory = crate::vfs::MemoryFs::new("/work");
+        memory
+            .add_file_with("a", "same")
+            .add_file_with("a.out", "same")
+            .set_modified("a", at(2))
+            .set_modified("a.out", at(1));
+        plan.config.filesystem = Some(crate::shared::Shared(std::sync::Arc::new(memory)));
+        plan.pairs = vec![(Src::File("/work/a".into()), Dst::File("/work/a.out".into()))];
+        plan.summarize_overwrites(true).unwrap();
+        let overwrite = &plan.overwrites[0];
+        assert_eq!(overwrite.mtime_delta, 1_000_000_000);
+        assert_eq!(overwrite.same_content, Some(true));
+    }
+}
@@ -0,0 +1,116 @@
+//! Opening SRC files ahead of their processing, for huge batches of tiny files.
+//!
+//! ```no_run
+//! use src_dst_clarifier::{ioers::Input, prefetch::Prefetch, SrcDstConfig};
+//!
+//! let pairs = SrcDstConfig::new("txt").parse("photos", Some("out")).unwrap().unwrap();
+//! pairs.create_tnamed_dir().unwrap();
+//! for (src, dst, input) in Prefetch::new(pairs, 64) {
+//!     if let Some(mut input) = input {
+//!         let reader = input.reader().unwrap(); // 已经打开了
+//!     }
+//! }
+//! ```
+//!
+//! Files opened ahead count against the [`descriptors`](crate::descriptors) budget, but never
+//! take the last [`PREFETCH_RESERVE`](descriptors::PREFETCH_RESERVE) of it: when the budget is
+//! that tight, SRCs are handed over unopened and opened once processed.
+
+use std::{fs, sync::mpsc, thread};
+
+use crate::{
+    audit::{self, Operation},
+    descriptors,
+    ioers::{advise, Advice, ReadFile},
+    Dst, Src,
+};
+
+/// Yields pairs with their SRC file already opened by a background thread, which stays up to
+/// `depth` pairs ahead, and asks the kernel to start reading it (Linux). A SRC that failed to open is retried, and reports its error, on
+/// [`Input::reader`](crate::ioers::Input::reader). `None` for SRCs that are not files.
+#[derive(Debug)]
+pub struct Prefetch {
+    ready: mpsc::Receiver<(Src, Dst, Option<ReadFile>)>,
+}
+
+impl Prefetch {
+    /// A `depth` of 0 is taken as 1.
+    pub fn new<I>(pairs: I, depth: usize) -> Self
+    where
+        I: IntoIterator<Item = (Src, Dst)>,
+        I::IntoIter: Send + 'static,
+    {
+        let pairs = pairs.into_iter();
+        let (tx, rx) = mpsc::sync_channel(depth.max(1));
+        thread::spawn(move || {
+            for (src, dst) in pairs {
+                let input = match &src {
+                    Src::File(path) => Some({
+                        let budget = descriptors::global();
+                        match budget.try_acquire_leaving(descriptors::PREFETCH_RESERVE) {
+                            Some(permit) => {
+                                match audit::record(Operation::Open, path, fs::File::open(path)) {
+                                    Ok(file) => {
+                                        advise(&file, Advice::WillNeed);
+                                        ReadFile::held(path, file, permit)
+                                    }
+                                    Err(_) => ReadFile::new(path),
+                                }
+                            }
+                            None => ReadFile::new(path), // 等到处理时再打开，免得写入时没有描述符
+                        }
+                    }),
+                    _ => None,
+                };
+                if tx.send((src, dst, input)).is_err() {
+                    break; // 迭代器已经被丢弃了
+                }
+            }
+        });
+        Self { ready: rx }
+    }
+}
+
+impl Iterator for Prefetch {
+    type Item = (Src, Dst, Option<ReadFile>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.ready.recv().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, path::PathBuf};
+
+    use super::*;
+    use crate::{ioers::Input, scratch::ScratchDir};
+
+    #[test]
+    fn in_order() {
+        let root = ScratchDir::new("prefetch");
+        let pairs: Vec<_> = (0..10)
+            .map(|n| {
+                let src = root.join(format!("{n}.txt"));
+                fs::write(&src, n.to_string()).unwrap();
+                (Src::File(src), Dst::File(PathBuf::from(format!("{n}.out"))))
+            })
+            .chain([(Src::Stdin, Dst::Stdout)])
+            .collect();
+
+        let mut prefetch = Prefetch::new(pairs, 3);
+        for n in 0..10 {
+            let (_, _, input) = prefetch.next().unwrap();
+            let mut content = String::new();
+            input
+                .unwrap()
+                .reader()
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, n.to_string());
+        }
+        assert!(prefetch.next().unwrap().2.is_none());
+        assert!(prefetch.next().is_none());
+    }
+}
@@ -0,0 +1,204 @@
+//! Looking at the start of every batch SRC before its DST is named, see [`SrcDstConfig::probe`](crate::SrcDstConfig::probe).
+//!
+//! ```
+//! use src_dst_clarifier::{probe::{Probe, ProbeDecision}, SrcDstConfig};
+//!
+//! let mut config = SrcDstConfig::new("bin");
+//! config.probe = Some(Probe::new(8, |_: &std::path::Path, head: &[u8]| match head {
+//!     [0x89, b'P', b'N', b'G', ..] => ProbeDecision::Extension("png".into()),
+//!     [0xff, 0xd8, ..] => ProbeDecision::Extension("jpg".into()),
+//!     _ => ProbeDecision::Skip,
+//! }));
+//! ```
+//!
+//! The SRC is opened again for the processing, so nothing of it is consumed by the probe.
+//!
+//! To dispatch a mixed directory to several backends in one pass, pairs can also be tagged
+//! while iterating, see [`SrcDstPairs::annotate`].
+
+use std::{
+    ffi::OsString,
+    io::{self, Read},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::{
+    audit::{self, Operation},
+    shared::Shared,
+    vfs::{self, FileSystem},
+    Dst, Src, SrcDstPairs,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProbeDecision {
+    /// The DST is named as without the probe.
+    Keep,
+    /// The DST gets this extension, instead of [`SrcDstConfig::output_extension`](crate::SrcDstConfig::output_extension).
+    Extension(OsString),
+    /// No pair for this SRC.
+    Skip,
+}
+
+pub trait ProbeHook: Send + Sync {
+    /// `head` is shorter than asked for when the file is.
+    fn probe(&self, src: &Path, head: &[u8]) -> ProbeDecision;
+}
+
+impl<F> ProbeHook for F
+where
+    F: Fn(&Path, &[u8]) -> ProbeDecision + Send + Sync,
+{
+    fn probe(&self, src: &Path, head: &[u8]) -> ProbeDecision {
+        self(src, head)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    /// Bytes read from the start of each SRC.
+    pub len: usize,
+    pub hook: Shared<dyn ProbeHook>,
+}
+
+impl Probe {
+    pub fn new<H: ProbeHook + 'static>(len: usize, hook: H) -> Self {
+        Self {
+            len,
+            hook: Shared(Arc::new(hook)),
+        }
+    }
+
+    /// A SRC that cannot be read is kept, its error comes up again when it is processed.
+    pub(crate) fn decide(&self, filesystem: &dyn FileSystem, src: &Path) -> ProbeDecision {
+        match read_head(filesystem, src, self.len) {
+            Ok(head) => self.hook.probe(src, &head),
+            Err(_) => ProbeDecision::Keep,
+        }
+    }
+}
+
+fn read_head(filesystem: &dyn FileSystem, src: &Path, len: usize) -> io::Result<Vec<u8>> {
+    let file = audit::record(Operation::Open, src, filesystem.open(src))?;
+    let mut head = Vec::with_capacity(len);
+    file.take(len as u64).read_to_end(&mut head)?;
+    Ok(head)
+}
+
+impl SrcDstPairs {
+    /// Every pair with a tag of the caller's own type, decided from the first `len` bytes of
+    /// the SRC, `None` drops the pair. The head is empty for SRCs other than local files, and
+    /// for those that cannot be read.
+    ///
+    /// ```no_run
+    /// use src_dst_clarifier::SrcDstConfig;
+    ///
+    /// enum Backend { Image, Video }
+    ///
+    /// let pairs = SrcDstConfig::new("out").try_parse("media", Some("out")).unwrap();
+    /// for (src, dst, backend) in pairs.annotate(12, |_, head| match head {
+    ///     [0x89, b'P', b'N', b'G', ..] => Some(Backend::Image),
+    ///     [_, _, _, _, b'f', b't', b'y', b'p', ..] => Some(Backend::Video),
+    ///     _ => None,
+    /// }) {
+    ///     match backend {
+    ///         Backend::Image => { /* .. */ }
+    ///         Backend::Video => { /* .. */ }
+    ///     }
+    /// }
+    /// ```
+    pub fn annotate<T, F>(self, len: usize, tag: F) -> Annotated<F>
+    where
+        F: FnMut(&Src, &[u8]) -> Option<T>,
+    {
+        Annotated {
+            pairs: self,
+            len,
+            tag,
+        }
+    }
+}
+
+/// See [`SrcDstPairs::annotate`].
+#[derive(Debug)]
+pub struct Annotated<F> {
+    pairs: SrcDstPairs,
+    len: usize,
+    tag: F,
+}
+
+impl<T, F> Iterator for Annotated<F>
+where
+    F: FnMut(&Src, &[u8]) -> Option<T>,
+{
+    type Item = (Src, Dst, T);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let (src, dst) = self.pairs.next()?;
+            let head = match &src {
+                Src::File(path) => {
+                    let filesystem = vfs::or_std(&self.pairs.filesystem);
+                    read_head(filesystem, path, self.len).unwrap_or_default()
+                }
+                Src::Stdin => Vec::new(),
+                #[cfg(feature = "sftp")]
+                Src::Remote(_) => Vec::new(),
+                #[cfg(all(unix, feature = "unix-socket"))]
+                Src::UnixSocket(_) => Vec::new(),
+            };
+            if let Some(tag) = (self.tag)(&src, &head) {
+                return Some((src, dst, tag));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+    use crate::{
+        builder::{PairDrain, PairSource},
+        SrcDstConfig,
+    };
+    use std::fs;
+
+    #[derive(Debug, PartialEq)]
+    enum Kind {
+        Image,
+        Text,
+    }
+
+    #[test]
+    fn annotate() {
+        let root = ScratchDir::new("annotate");
+        fs::write(root.join("a"), b"\x89PNG").unwrap();
+        fs::write(root.join("b"), b"text").unwrap();
+        fs::write(root.join("c"), [0; 4]).unwrap();
+        let pairs = SrcDstConfig::new("out")
+            .builder()
+            .source(PairSource::Files {
+                root: root.clone(),
+                names: vec!["a".into(), "b".into(), "c".into()],
+            })
+            .drain(PairDrain::Stdout)
+            .build()
+            .unwrap();
+        let kinds: Vec<_> = pairs
+            .annotate(4, |_, head| match head {
+                b"\x89PNG" => Some(Kind::Image),
+                _ if head.is_ascii() && head != [0; 4] => Some(Kind::Text),
+                _ => None,
+            })
+            .map(|(src, _, kind)| (src, kind))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (Src::File(root.join("a")), Kind::Image),
+                (Src::File(root.join("b")), Kind::Text),
+            ]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+//! Complete SRC/DST behaviors under a name, e.g. one per subcommand.
+//!
+//! ```
+//! use src_dst_clarifier::{profile::{Profile, Profiles}, run::StaleOutput, SrcDstConfig};
+//!
+//! let mut profiles = Profiles::new();
+//! let mut thumbnail = Profile::new(SrcDstConfig::new("jpg"));
+//! thumbnail.config.name_policy.lowercase = true;
+//! thumbnail.runner.exclude.push(String::from("*.tmp"));
+//! thumbnail.runner.stale_output = StaleOutput::MoveToOldDir;
+//! profiles.register("thumbnail", thumbnail);
+//!
+//! assert!(profiles.get("thumbnail").is_some());
+//! ```
+
+use std::{collections::BTreeMap, io, path::Path};
+
+use crate::{
+    run::{RunReport, Runner, Task},
+    ClarifyError, SrcDstConfig, SrcDstPairs,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profile {
+    /// Including the naming, see [`SrcDstConfig::name_policy`].
+    pub config: SrcDstConfig,
+    /// Filters ([`Runner::exclude`], [`Runner::skip_up_to_date`]...) and cleanup
+    /// ([`Runner::stale_output`], [`Runner::consume_src`], [`Runner::failed_src_dir`]...).
+    pub runner: Runner,
+}
+
+impl Profile {
+    pub fn new(config: SrcDstConfig) -> Self {
+        Self {
+            config,
+            runner: Runner::new(),
+        }
+    }
+
+    pub fn parse<P: AsRef<Path>>(
+        &self,
+        src: P,
+        dst: Option<P>,
+    ) -> Result<SrcDstPairs, ClarifyError> {
+        self.config.try_parse(src, dst)
+    }
+
+    /// [`Self::parse`], then [`Runner::run`].
+    pub fn run<P, F>(&self, src: P, dst: Option<P>, process: F) -> Result<RunReport, ClarifyError>
+    where
+        P: AsRef<Path>,
+        F: FnMut(&mut Task) -> io::Result<()>,
+    {
+        let pairs = self.parse(src, dst)?;
+        Ok(self.runner.run(pairs, process)?)
+    }
+}
+
+/// Looked up by exact name.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profiles {
+    by_name: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the profile previously registered under `name`, if any.
+    pub fn register<S: Into<String>>(&mut self, name: S, profile: Profile) -> Option<Profile> {
+        self.by_name.insert(name.into(), profile)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Profile> {
+        self.by_name.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Profile> {
+        self.by_name.get_mut(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Profile> {
+        self.by_name.remove(name)
+    }
+
+    /// Sorted, e.g. for listing subcommands.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.by_name.keys().map(String::as_str)
+    }
+}
@@ -0,0 +1,283 @@
+//! Remote SRC and DST in the scp-like form of `user@host:/path`, or `sftp://user@host:port/path`
+//! for another port than 22.
+//!
+//! The host key is verified against `~/.ssh/known_hosts`, unknown hosts are refused.
+//!
+//! Only recognized by [`SrcDstConfig::parse`] when the `sftp` feature is enabled.
+//! A remote path is always treated as a file, unless it ends with `/`, then it is
+//! a directory the files would be put into (it must exist). Remote directories can not be SRC.
+
+use std::{ffi::OsStr, fmt, path::Path};
+
+#[cfg(feature = "sftp")]
+pub(crate) use sftp::parse_remote;
+#[cfg(feature = "sftp")]
+pub use sftp::{SftpInput, SftpOutput};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RemotePath {
+    pub user: String,
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl RemotePath {
+    pub const DEFAULT_PORT: u16 = 22;
+
+    pub fn parse<S: AsRef<OsStr>>(s: S) -> Option<Self> {
+        let s = s.as_ref().to_str()?;
+        let (user, host, port, path) = match s.strip_prefix("sftp://") {
+            Some(url) => {
+                let (user, rest) = url.split_once('@')?;
+                let slash = rest.find('/')?;
+                let (authority, path) = rest.split_at(slash);
+                let (host, port) = match authority.split_once(':') {
+                    Some((host, port)) => (host, port.parse().ok()?),
+                    None => (authority, Self::DEFAULT_PORT),
+                };
+                (user, host, port, path)
+            }
+            None => {
+                let (user, rest) = s.split_once('@')?;
+                let (host, path) = rest.split_once(':')?;
+                (user, host, Self::DEFAULT_PORT, path)
+            }
+        };
+        if user.is_empty() || host.is_empty() || user.contains(['/', ':']) || host.contains('/') {
+            return None;
+        }
+        Some(Self {
+            user: user.to_owned(),
+            host: host.to_owned(),
+            port,
+            path: path.to_owned(),
+        })
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.path.ends_with('/')
+    }
+
+    pub fn file_name(&self) -> Option<&OsStr> {
+        Path::new(&self.path).file_name()
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, name: P) -> Self {
+        Self {
+            path: Path::new(&self.path)
+                .join(name)
+                .to_string_lossy()
+                .into_owned(),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for RemotePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.port == Self::DEFAULT_PORT {
+            true => write!(f, "{}@{}:{}", self.user, self.host, self.path),
+            // 路径要是绝对的才能写成 URL
+            false => write!(
+                f,
+                "sftp://{}@{}:{}{}",
+                self.user, self.host, self.port, self.path
+            ),
+        }
+    }
+}
+
+#[cfg(feature = "sftp")]
+mod sftp {
+    use std::{
+        ffi::OsStr,
+        fs,
+        io::{self, Read, Write},
+        net::TcpStream,
+        path::{Path, PathBuf},
+    };
+
+    use super::RemotePath;
+    use crate::{
+        ioers::{Input, Output},
+        shallow_walk, ClarifyError, Dst, Src, SrcDstConfig, SrcDstError, SrcDstPairs,
+    };
+
+    /// `Ok(None)` if neither SRC nor DST is remote.
+    pub(crate) fn parse_remote(
+        config: &SrcDstConfig,
+        src: &Path,
+        dst: Option<&Path>,
+    ) -> Result<Option<SrcDstPairs>, ClarifyError> {
+        let remote_src = RemotePath::parse(src);
+        let remote_dst = dst.and_then(RemotePath::parse);
+
+        let pairs = match (remote_src, remote_dst) {
+            (None, None) => return Ok(None),
+
+            (Some(src), remote_dst) => {
+                let name = src.file_name().unwrap_or(OsStr::new("remote")).to_owned();
+                let dst = match (remote_dst, dst) {
+                    (Some(dst), _) if dst.is_dir() => Dst::Remote(dst.join(name)),
+                    (Some(dst), _) => Dst::Remote(dst),
+                    (None, None) if !config.auto_name.files() => {
+                        return Err(SrcDstError::ForbidAutoTnamedDstFile.into())
+                    }
+                    (None, None) => {
+                        let cwd = config.output_root()?;
+                        Dst::File(config.tnamed_file(cwd.join(name), None)?)
+                    }
+                    (None, Some(dst)) if config.is_stdio(dst) => {
+                        config.check_stdout()?;
+                        Dst::Stdout
+                    }
+                    (None, Some(dst)) => match dst.is_dir() {
+                        true => Dst::File(config.normalize(fs::canonicalize(dst)?.join(name))),
+                        false => Dst::File(dst.to_owned()),
+                    },
+                };
+                vec![(Src::Remote(src), dst)]
+            }
+
+            (None, Some(dst)) => {
+                if config.is_stdio(src) {
+                    if !config.stdio.from_stdin() {
+                        return Err(SrcDstError::DisallowFromStdin.into());
+                    }
+                    let dst = match dst.is_dir() {
+                        true => dst.join("stdin"),
+                        false => dst,
+                    };
+                    vec![(Src::Stdin, Dst::Remote(dst))]
+                } else if !src.exists() {
+                    return Err(SrcDstError::SrcNotExist.into());
+                } else if src.is_file() {
+                    let src = fs::canonicalize(src)?;
+                    let dst = match dst.is_dir() {
+                        true => dst.join(src.file_name().unwrap()),
+                        false => dst,
+                    };
+                    vec![(Src::File(src), Dst::Remote(dst))]
+                } else if !dst.is_dir() {
+                    return Err(SrcDstError::ManyToOne.into());
+                } else {
+                    shallow_walk(fs::canonicalize(src)?, |w| config.warn(w))?
+                        .into_iter()
+                        .rev()
+                        .map(|src| {
+                            let dst = dst.join(src.file_name().unwrap());
+                            (Src::File(src), Dst::Remote(dst))
+                        })
+                        .collect()
+                }
+            }
+        };
+
+        Ok(Some(SrcDstPairs::planned(pairs, None)))
+    }
+
+    fn connect(remote: &RemotePath) -> io::Result<ssh2::Sftp> {
+        let tcp = TcpStream::connect((remote.host.as_str(), remote.port))?;
+        let mut session = ssh2::Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        verify_host_key(&session, remote)?;
+        session.userauth_agent(&remote.user)?;
+        Ok(session.sftp()?)
+    }
+
+    /// Against `~/.ssh/known_hosts`, before anything is sent to the host.
+    fn verify_host_key(session: &ssh2::Session, remote: &RemotePath) -> io::Result<()> {
+        let refused = |why: &str| {
+            io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("host key of '{}' {why}", remote.host),
+            )
+        };
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+        let Some(home) = home else {
+            return Err(refused("can not be verified without a home directory"));
+        };
+        let mut known_hosts = session.known_hosts()?;
+        let file = PathBuf::from(home).join(".ssh").join("known_hosts");
+        known_hosts.read_file(&file, ssh2::KnownHostFileKind::OpenSSH)?;
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| refused("was not received"))?;
+        match known_hosts.check_port(&remote.host, remote.port, key) {
+            ssh2::CheckResult::Match => Ok(()),
+            ssh2::CheckResult::NotFound => Err(refused("is unknown, see ~/.ssh/known_hosts")),
+            ssh2::CheckResult::Mismatch => Err(refused("does not match ~/.ssh/known_hosts")),
+            ssh2::CheckResult::Failure => Err(refused("could not be checked")),
+        }
+    }
+
+    /// Authenticates through the SSH agent.
+    #[derive(Debug, Clone)]
+    pub struct SftpInput {
+        remote: RemotePath,
+    }
+
+    impl SftpInput {
+        pub fn new(remote: RemotePath) -> Self {
+            Self { remote }
+        }
+    }
+
+    impl Input for SftpInput {
+        fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+            let sftp = connect(&self.remote)?;
+            Ok(Box::new(sftp.open(Path::new(&self.remote.path))?))
+        }
+    }
+
+    /// Authenticates through the SSH agent.
+    pub struct SftpOutput {
+        remote: RemotePath,
+        sftp: Option<ssh2::Sftp>,
+    }
+
+    impl SftpOutput {
+        pub fn new(remote: RemotePath) -> Self {
+            Self { remote, sftp: None }
+        }
+    }
+
+    impl Output for SftpOutput {
+        fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+            let sftp = connect(&self.remote)?;
+            let file = sftp.create(Path::new(&self.remote.path))?;
+            self.sftp = Some(sftp);
+            Ok(Box::new(file))
+        }
+
+        fn abort(&mut self) -> io::Result<()> {
+            if let Some(sftp) = self.sftp.take() {
+                sftp.unlink(Path::new(&self.remote.path))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let remote = RemotePath::parse("alice@example.com:/srv/frames/").unwrap();
+        assert_eq!(remote.to_string(), "alice@example.com:/srv/frames/");
+        assert!(remote.is_dir());
+        assert_eq!(remote.join("0001.jpg").path, "/srv/frames/0001.jpg");
+
+        let remote = RemotePath::parse("sftp://alice@example.com:2222/srv/a.jpg").unwrap();
+        assert_eq!((remote.port, remote.path.as_str()), (2222, "/srv/a.jpg"));
+        assert_eq!(RemotePath::parse(remote.to_string()), Some(remote));
+        assert_eq!(RemotePath::parse("sftp://alice@example.com:ssh/a"), None);
+
+        assert_eq!(RemotePath::parse("./a@b:c"), None);
+        assert_eq!(RemotePath::parse("C:\\frames"), None);
+    }
+}
@@ -0,0 +1,50 @@
+//! Deciding on a DST when none is provided, e.g. by prompting the user.
+//!
+//! ```
+//! use std::{io, sync::Arc};
+//! use src_dst_clarifier::{resolver::DstRequest, shared::Shared, SrcDstConfig};
+//!
+//! let mut config = SrcDstConfig::new("png");
+//! config.dst_resolver = Some(Shared(Arc::new(|request: &DstRequest| {
+//!     // Ask the user here, offering `request.suggested`.
+//!     Ok::<_, io::Error>(request.suggested.clone())
+//! })));
+//! ```
+
+use std::{io, path::PathBuf};
+
+/// A file, ready to be written, or a directory, which will be created by
+/// [`SrcDstPairs::create_tnamed_dir`](crate::SrcDstPairs::create_tnamed_dir) if it does not exist.
+pub trait DstResolver: Send + Sync {
+    /// An error aborts the parsing, e.g. when the user cancels.
+    fn resolve(&self, request: &DstRequest) -> io::Result<PathBuf>;
+}
+
+impl<F> DstResolver for F
+where
+    F: Fn(&DstRequest) -> io::Result<PathBuf> + Send + Sync,
+{
+    fn resolve(&self, request: &DstRequest) -> io::Result<PathBuf> {
+        self(request)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DstRequest {
+    /// `None` for stdin.
+    pub src: Option<PathBuf>,
+    /// SRC is a directory, so a directory is expected.
+    pub batch: bool,
+    /// What the time-based naming would produce.
+    pub suggested: PathBuf,
+}
+
+/// The default: time-based naming, just as if no resolver is set.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TnamedResolver;
+
+impl DstResolver for TnamedResolver {
+    fn resolve(&self, request: &DstRequest) -> io::Result<PathBuf> {
+        Ok(request.suggested.clone())
+    }
+}
@@ -0,0 +1,3567 @@
+//! Drive a [`SrcDstPairs`] to completion and collect what happened to every pair.
+
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    fmt, fs,
+    hash::Hasher,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use kalavor::Katetime;
+use thiserror::Error;
+
+use crate::{
+    audit::{self, Operation},
+    cleanup::TempGuard,
+    digest::{ChecksumManifest, Digest, Sha256},
+    environment::Environment,
+    ioers::{
+        FifoOutput, GoldenDiff, GoldenOutput, HardenedWriteFile, Input, LinkStyle, Output,
+        ReadFile, ReadStdin, RecordingOutput, SymlinkOutput, WriteFile, WriteFileAtomic,
+    },
+    l10n::Message,
+    plan::escape,
+    prefetch::Prefetch,
+    shared::Shared,
+    size_class::{SizeClass, SizeClasses},
+    undo::{KnownDirs, Mutation, UndoLog},
+    units::{Bytes, Elapsed, Rate},
+    vfs,
+    wrap::WrapperRegistry,
+    Dst, HeldSrcDir, Src, SrcDstPairError, SrcDstPairs,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Runner {
+    /// When writing to the primary DST fails (read-only mount, quota exceeded...),
+    /// the pair is processed again with its DST relocated into this directory.
+    ///
+    /// Only takes effect for file SRCs, stdin can not be read twice.
+    pub fallback_dst: Option<PathBuf>,
+
+    /// What to do with an existing file that is about to be overwritten.
+    pub stale_output: StaleOutput,
+
+    /// What to do with the partial output a crashed run left beside a file DST, i.e. the
+    /// temporary sibling of [`WriteFileAtomic`]. See [`Self::preflight`] for what will be found.
+    pub stale_partial: StalePartial,
+
+    /// Hash outputs as they are written, and deal with files identical to an earlier output.
+    pub dedup: Dedup,
+
+    /// Once this many bytes have been written, the remaining pairs are skipped.
+    ///
+    /// The pair crossing the budget is still completed, so no output is cut off mid-write.
+    pub byte_budget: Option<u64>,
+
+    /// File SRCs whose name matches any of these globs (`*` and `?` only) are skipped.
+    pub exclude: Vec<String>,
+    /// File SRCs whose name matches any of these globs are copied to their DST as is, see
+    /// [`Task::copy_through`], without calling the closure of [`Self::run`]. The DST is the
+    /// planned one, give [`SrcDstConfig::passthrough`](crate::SrcDstConfig::passthrough) the
+    /// same globs to have it named with the extension of SRC.
+    pub passthrough: Vec<String>,
+    /// Skip pairs whose file DST was modified after their file SRC, like `make` does.
+    pub skip_up_to_date: bool,
+    /// What to do with zero-byte file SRCs of a batch, which many converters choke on.
+    /// A single SRC given explicitly is always processed.
+    pub empty_src: EmptySrc,
+
+    /// Fail stdin SRCs exceeding this many bytes, see [`ReadStdin`].
+    pub max_stdin_bytes: Option<u64>,
+    /// Fail stdin SRCs when no data arrives for this long, see [`ReadStdin`].
+    pub stdin_read_timeout: Option<Duration>,
+
+    /// Abort a pair after processing it for this long, see [`Outcome::TimedOut`].
+    ///
+    /// Enforced on every read and write through the [`Task`]; processing code that neither reads
+    /// nor writes can not be interrupted.
+    pub pair_timeout: Option<Duration>,
+
+    /// Once cancelled, the pair in progress fails on its next read or write through the [`Task`]
+    /// (see [`Outcome::Cancelled`]) and the remaining pairs are skipped. The report is still
+    /// persisted, see [`CancelToken::on_ctrl_c`] for interrupting a run with Ctrl-C.
+    pub cancel: Option<CancelToken>,
+
+    /// Verify file SRCs against these checksums before processing them.
+    pub verify_sources: Option<ChecksumManifest>,
+    /// What to do with SRCs which do not match [`Self::verify_sources`] or are absent from it.
+    pub on_unverified: OnUnverified,
+
+    /// Create named pipes at file DSTs instead of regular files, see [`FifoOutput`].
+    pub fifo_dst: bool,
+
+    /// Substitute [`RecordingOutput`] for every DST: the processing code runs for real,
+    /// but nothing is written, moved or created. [`PairReport::written`] is still accurate.
+    pub dry_run: bool,
+
+    /// Compare every output against the existing file DST instead of writing it,
+    /// see [`PairReport::golden_diff`]. Otherwise behaves like [`Self::dry_run`].
+    pub golden: bool,
+
+    /// Lay out the run without processing anything: DST directories are created, and with
+    /// [`StructureOnly::Placeholders`] empty DST files too, but no SRC is read. Every pair is
+    /// reported as skipped for [`SkipReason::StructureOnly`].
+    pub structure_only: Option<StructureOnly>,
+
+    /// Write [`REPORT_FILE_NAME`] into the DST directory after the run, see [`RunReport::write_json`].
+    /// The directory is the time-based named one if any, otherwise that of the first file DST.
+    ///
+    /// Also enables SHA-256 of outputs, see [`PairReport::sha256`]. Never done in simulated runs.
+    pub persist_report: bool,
+    /// Write [`MANIFEST_FILE_NAME`] into the directory [`Self::persist_report`] would, see
+    /// [`RunReport::write_manifest`], e.g. to map [`SrcDstConfig::flatten`]ed DSTs back to
+    /// their SRCs. Never in simulated runs.
+    ///
+    /// [`SrcDstConfig::flatten`]: crate::SrcDstConfig::flatten
+    pub manifest: bool,
+    /// SHA-256 of stdin SRCs, which have no path to tell where an output came from,
+    /// see [`PairReport::src_sha256`].
+    pub hash_stdin: bool,
+
+    /// For external supervisors of long runs, see [`ProgressFile`]. Never in simulated runs.
+    pub progress_file: Option<ProgressFile>,
+    /// Kept up to date during the run, simulated runs included.
+    pub status: Option<StatusBoard>,
+
+    /// Asked first for every DST outside simulated runs, e.g. to substitute an encrypting writer.
+    /// Writers of file DSTs are refused in [read-only mode](crate::set_read_only) before the
+    /// returned [`Output`] is asked for one.
+    pub make_output: Option<Shared<dyn MakeOutput>>,
+
+    /// Layers composed from the extensions of file DSTs around whatever [`Output`] was picked,
+    /// simulated runs included, so that [`PairReport::written`] counts the layered bytes.
+    pub wrappers: WrapperRegistry,
+
+    /// For organizing tools: file DSTs of file SRCs become symlinks to them, see [`SymlinkOutput`].
+    /// The processing code still runs, but its output is discarded.
+    pub symlink_dst: Option<LinkStyle>,
+
+    /// For ingest tools: file SRCs are removed once their pair is done and the file DST
+    /// has been synced to disk, or moved into [`Self::processed_dir`] if set, numbered like
+    /// `<stem>-1.<ext>` when the name is taken. Never in simulated runs.
+    pub consume_src: bool,
+    pub processed_dir: Option<PathBuf>,
+
+    /// Move file SRCs of failed (or timed-out) pairs aside for a later retry, numbered like
+    /// [`Self::processed_dir`], see [`PairReport::set_aside`]. Those that can not be moved
+    /// stay where they are. Never in simulated runs.
+    pub failed_src_dir: Option<FailedSrcDir>,
+
+    /// Called after a pair was processed successfully, right before its output is finalized,
+    /// e.g. to record it in a database. A [`Veto`] fails the pair as if processing had failed.
+    /// Never in simulated runs.
+    pub on_before_finalize: Option<Shared<dyn BeforeFinalize>>,
+
+    /// Check every pair with [`revalidate`] right before processing it, for plans executed
+    /// long after they were made.
+    pub revalidate: bool,
+
+    /// Write file DSTs through [`HardenedWriteFile`], for tools running in shared directories.
+    pub hardened_dst: bool,
+
+    /// Open up to this many SRC files ahead on a background thread, see [`crate::prefetch`].
+    /// 0 (the default) disables it. Not used for SRC directories held by
+    /// [`SrcDstConfig::hold_src_dir`](crate::SrcDstConfig::hold_src_dir).
+    ///
+    /// Prefetched SRCs are read through [`ReadFile`], so [`Self::run`] refuses to combine it
+    /// with [`Self::io_uring`] ([`io::ErrorKind::InvalidInput`]).
+    pub prefetch: usize,
+
+    /// Read SRC files and write file DSTs through [`crate::uring`]. Ignored without the
+    /// `io-uring` feature or off Linux.
+    pub io_uring: bool,
+
+    /// Write file DSTs through [`WriteFile::new_direct`], bypassing the page cache.
+    pub direct_dst: bool,
+
+    /// For copy-like tools: [`Task::expect_len`] the length of file SRCs.
+    pub preallocate_src_len: bool,
+
+    /// File DSTs of file SRCs get the permissions and timestamps of their SRC once finished,
+    /// see [`WriteFile::preserve_metadata_of`]. Also applies to [`Self::direct_dst`] and
+    /// [`Self::io_uring`], overridden by the other kinds of DST above.
+    pub preserve_metadata: bool,
+
+    /// Thresholds for [`Task::size_class`].
+    pub size_classes: SizeClasses,
+}
+
+pub trait MakeOutput: Send + Sync {
+    /// `None` keeps the default [`Output`] for that DST.
+    fn make_output(&self, dst: &Dst) -> Option<Box<dyn Output>>;
+}
+
+impl<F> MakeOutput for F
+where
+    F: Fn(&Dst) -> Option<Box<dyn Output>> + Send + Sync,
+{
+    fn make_output(&self, dst: &Dst) -> Option<Box<dyn Output>> {
+        self(dst)
+    }
+}
+
+pub trait BeforeFinalize: Send + Sync {
+    fn before_finalize(&self, task: &Task) -> Result<(), Veto>;
+}
+
+impl<F> BeforeFinalize for F
+where
+    F: Fn(&Task) -> Result<(), Veto> + Send + Sync,
+{
+    fn before_finalize(&self, task: &Task) -> Result<(), Veto> {
+        self(task)
+    }
+}
+
+/// Shared by its clones, see [`Shared`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CancelToken(Shared<AtomicBool>);
+
+/// What reads and writes through a [`Task`] fail with once [`Runner::cancel`] is cancelled,
+/// inside an [`io::Error`]. Processing that returns it unchanged gets [`Outcome::Cancelled`].
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("run cancelled")]
+pub struct CancelledError;
+
+impl CancelledError {
+    pub fn is(e: &io::Error) -> bool {
+        e.get_ref().is_some_and(|inner| inner.is::<Self>())
+    }
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Cancelled on the first Ctrl-C (SIGINT, and SIGTERM with the `termination` feature of
+    /// `ctrlc`), while a second one exits the process with status 130 right away, after
+    /// [`remove_pending`](crate::cleanup::remove_pending).
+    ///
+    /// Fails if a handler has been installed already, there can be only one per process.
+    #[cfg(feature = "ctrlc")]
+    pub fn on_ctrl_c() -> Result<Self, ctrlc::Error> {
+        let token = Self::new();
+        let cancel = token.clone();
+        ctrlc::set_handler(move || match cancel.is_cancelled() {
+            true => {
+                crate::cleanup::remove_pending();
+                std::process::exit(130)
+            }
+            false => cancel.cancel(),
+        })?;
+        Ok(token)
+    }
+}
+
+/// Retrieve it from the failure with [`io::Error::get_ref`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+#[error("finalization vetoed: {reason}")]
+pub struct Veto {
+    pub reason: String,
+}
+
+impl From<Veto> for io::Error {
+    fn from(veto: Veto) -> Self {
+        io::Error::other(veto)
+    }
+}
+
+/// A pair that failed to write its DST, and could not be relocated to [`Runner::fallback_dst`]
+/// either. Retrieve it from the failure with [`io::Error::get_ref`], which has the kind of `primary`.
+#[derive(Error, Debug)]
+#[error("{primary}, and the fallback DST {} is not usable: {error}", .fallback.display())]
+pub struct FallbackFailed {
+    pub primary: io::Error,
+    pub fallback: PathBuf,
+    pub error: io::Error,
+}
+
+impl From<FallbackFailed> for io::Error {
+    fn from(failed: FallbackFailed) -> Self {
+        io::Error::new(failed.primary.kind(), failed)
+    }
+}
+
+pub const REPORT_FILE_NAME: &str = "run-report.json";
+pub const PROGRESS_FILE_NAME: &str = "run-progress.json";
+pub const MANIFEST_FILE_NAME: &str = "run-manifest.txt";
+
+/// A tiny JSON object, rewritten before a pair starts once `interval` has passed, and after the run:
+///
+/// ``` plaintext
+/// {"done":41,"failed":1,"skipped":0,"total":120,"current":"/in/0043.jpg","finished":false}
+/// ```
+///
+/// `total` is `null` if unknown (see [`SrcDstPairs::remaining`]), `current` is `null` between pairs.
+/// Replaced by renaming, so it is never seen half-written, not even after a kill.
+/// Failing to write it does not fail the run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressFile {
+    /// `None` for [`PROGRESS_FILE_NAME`] in the DST directory, chosen like for
+    /// [`Runner::persist_report`] once the first output is known.
+    pub path: Option<PathBuf>,
+    pub interval: Duration,
+}
+
+struct ProgressWriter<'a> {
+    config: &'a ProgressFile,
+    path: Option<PathBuf>,
+    total: Option<usize>,
+    last: Option<Instant>,
+    /// Recorded in the undo log once, when first written.
+    written: bool,
+}
+
+impl ProgressWriter<'_> {
+    fn update(&mut self, report: &mut RunReport, current: Option<(&Src, &Dst)>, finished: bool) {
+        let interval = self.config.interval;
+        let due = !matches!(self.last, Some(last) if last.elapsed() < interval);
+        if !(due || finished) {
+            return;
+        }
+        if self.path.is_none() {
+            let dir = match current {
+                Some((_, Dst::File(dst))) => dst.parent(),
+                _ => None,
+            };
+            self.path = self.config.path.clone().or_else(|| {
+                let dir = dir.filter(|dir| !dir.as_os_str().is_empty())?;
+                Some(dir.join(PROGRESS_FILE_NAME))
+            });
+        }
+        let Some(path) = &self.path else {
+            return;
+        };
+        self.last = Some(Instant::now());
+        let json = report.progress_json(self.total, current.map(|(src, _)| src), finished);
+        // 只是给外部看的，写不了也不影响处理
+        if write_replacing(path, json.as_bytes()).is_ok() && !self.written {
+            self.written = true;
+            report.undo.push(Mutation::CreatedFile(path.clone()));
+        }
+    }
+}
+
+/// The latest progress of a run, and its report once finished, for pollers like
+/// [`StatusServer`](crate::status::StatusServer) (feature `status-server`).
+///
+/// Shared by its clones, see [`Shared`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatusBoard(Shared<Mutex<Status>>);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    /// Like the content of a [`ProgressFile`], empty before the run starts.
+    pub progress: String,
+    /// [`RunReport::write_json`], once the run has finished.
+    pub report: Option<String>,
+}
+
+impl StatusBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn status(&self) -> Status {
+        self.lock().clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Status> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// How often [`Runner::status`] is refreshed at most, counting the pairs is not free.
+const STATUS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Through a temporary sibling `<path>.<pid>-<n>.tmp` renamed over `path`, so that concurrent
+/// writers of the same file never share one. Synced before the rename, so that not even a
+/// crash leaves `path` half-written.
+fn write_replacing(path: &Path, content: &[u8]) -> io::Result<()> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut temp = path.as_os_str().to_owned();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    temp.push(format!(".{}-{n}.tmp", std::process::id()));
+    let temp = PathBuf::from(temp);
+    let written = fs::File::create_new(&temp).and_then(|mut file| {
+        file.write_all(content)?;
+        file.sync_all()
+    });
+    let renamed = audit::record(Operation::Create, &temp, written)
+        .and_then(|()| audit::record(Operation::Rename, &temp, fs::rename(&temp, path)));
+    if renamed.is_err() {
+        let _ = fs::remove_file(&temp); // 原本的错误更重要
+    }
+    renamed
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StaleOutput {
+    #[default]
+    Overwrite,
+    /// Move it into a `.old/` directory beside it, numbered like `<stem>-1.<ext>` when one of
+    /// an earlier run is there.
+    MoveToOldDir,
+    /// Rename it to `<stem>-<datetime>.<ext>` beside it.
+    RenameWithDatetime,
+    /// Leave it alone and skip the pair.
+    Keep,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StalePartial {
+    /// Leave it for [`cleanup_stale`](crate::cleanup::cleanup_stale).
+    #[default]
+    Ignore,
+    Delete,
+    /// Append to the longest one (removing the others), then rename it over DST as usual.
+    /// Only for processing that can skip what was already written, see [`Task::resume_offset`].
+    /// A partial older than SRC, or of a SRC not on disk, is removed instead.
+    /// Output layers of [`Runner::wrappers`] are not applied.
+    Resume,
+    /// Fail the pair, the others still run.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailedSrcDir {
+    /// A `failed/` directory beside each SRC.
+    BesideSrc,
+    Path(PathBuf),
+}
+
+/// For [`Runner::retry`], the delay doubles after every attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Runs at most, the first one included.
+    pub attempts: u32,
+    /// Before the first attempt.
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            initial_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Before the attempt numbered from 0.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EmptySrc {
+    #[default]
+    Process,
+    /// See [`SkipReason::EmptySrc`].
+    Skip,
+    /// The pair fails with [`io::ErrorKind::InvalidData`].
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StructureOnly {
+    Dirs,
+    /// Zero-byte files at file DSTs which do not exist yet, dated to the Unix epoch. Later runs
+    /// take them for missing DSTs: never up to date (see [`Runner::skip_up_to_date`] and
+    /// [`SrcDstConfig::incremental`](crate::SrcDstConfig::incremental)), nor in the way of
+    /// [`SrcDstConfig::overwrite`](crate::SrcDstConfig::overwrite) or [`Runner::stale_output`].
+    Placeholders,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnUnverified {
+    #[default]
+    Skip,
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dedup {
+    #[default]
+    Off,
+    /// Replace the duplicate with a hard link to the first identical output, the duplicate is
+    /// kept when linking fails.
+    HardLink,
+    /// Remove the duplicate, see [`PairReport::duplicate_of`] for where the content is.
+    Remove,
+}
+
+impl Runner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the time-based named directory if needed, then calls `process` for each pair.
+    ///
+    /// Failures of individual pairs are recorded in the report rather than returned, unlike
+    /// failing to read back spilled SRC names, see [`SrcDstPairs::spill_error`].
+    pub fn run<F>(&self, mut pairs: SrcDstPairs, mut process: F) -> io::Result<RunReport>
+    where
+        F: FnMut(&mut Task) -> io::Result<()>,
+    {
+        let started = Instant::now();
+        let mut report = RunReport {
+            environment: Environment::capture(),
+            batch: pairs.is_batch(),
+            ..RunReport::default()
+        };
+        let mut tnamed_guard = None;
+        let mut known = KnownDirs::default();
+
+        if self.prefetch > 0 && self.uring() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "prefetch can not be combined with io_uring",
+            ));
+        }
+
+        if !self.simulated() {
+            pairs.allocate_tnamed()?;
+            if let Some(dir) = pairs.tnamed_dir() {
+                report.undo.push(Mutation::CreatedDir(dir.to_owned()));
+                tnamed_guard = Some(TempGuard::empty_dir(dir)); // 中途退出时若还是空的就删掉
+            }
+            if let Some(file) = pairs.tnamed_file() {
+                report.undo.push(Mutation::CreatedFile(file.to_owned()));
+                if self.hardened_dst {
+                    audit::record(Operation::Remove, file, fs::remove_file(file))?;
+                    // 否则 O_EXCL 会失败；被人抢先占用也只会失败
+                }
+            }
+            for dir in pairs.create_dst_dirs()? {
+                report.undo.push(Mutation::CreatedDir(dir));
+            }
+            pairs
+                .tnamed_dir()
+                .into_iter()
+                .for_each(|dir| known.mark(dir));
+            pairs.dst_dirs().iter().for_each(|dir| known.mark(dir));
+        }
+
+        let pairs_tnamed_dir = pairs.tnamed_dir().map(Path::to_path_buf);
+        let total = pairs.remaining();
+        let mut progress = match (&self.progress_file, self.simulated()) {
+            (Some(config), false) => Some(ProgressWriter {
+                config,
+                path: config
+                    .path
+                    .clone()
+                    .or_else(|| Some(pairs_tnamed_dir.as_ref()?.join(PROGRESS_FILE_NAME))),
+                total,
+                last: None,
+                written: false,
+            }),
+            _ => None,
+        };
+        let mut status_updated = None;
+        #[cfg(unix)]
+        let held = pairs.held_dir().cloned();
+        #[cfg(not(unix))]
+        let held = ();
+        #[cfg(unix)]
+        let holding = held.is_some();
+        #[cfg(not(unix))]
+        let holding = false;
+        let mut state = PairState {
+            held,
+            batch: pairs.is_batch(),
+            // 模拟运行时不会创建 tnamed 目录和子目录
+            unborn: self.simulated()
+                && (pairs.tnamed_dir().is_some() || !pairs.dst_dirs().is_empty()),
+            // 预留的空文件不算旧的输出
+            reserved: pairs.tnamed_file().map(Path::to_path_buf),
+            known,
+            seen: HashMap::new(),
+            total_written: 0,
+        };
+        let listing = Arc::new(Mutex::new(None));
+        let unsettled = Arc::new(Mutex::new(Vec::new()));
+        let drain_unsettled = |report: &mut RunReport| {
+            let mut failed = unsettled.lock().unwrap_or_else(|e| e.into_inner());
+            report.pairs.append(&mut failed);
+        };
+        let pairs = Listed {
+            pairs,
+            error: listing.clone(),
+            unsettled: unsettled.clone(),
+        };
+        let pairs: Box<dyn Iterator<Item = (Src, Dst, Option<ReadFile>)>> =
+            match self.prefetch > 0 && !holding && self.structure_only.is_none() {
+                true => Box::new(Prefetch::new(pairs, self.prefetch)),
+                false => Box::new(pairs.map(|(src, dst)| (src, dst, None))),
+            };
+        for (src, dst, prefetched) in pairs {
+            drain_unsettled(&mut report);
+            if let Some(progress) = &mut progress {
+                progress.update(&mut report, Some((&src, &dst)), false);
+            }
+            if let Some(board) = &self.status {
+                if !matches!(status_updated, Some(at) if Instant::elapsed(&at) < STATUS_INTERVAL) {
+                    status_updated = Some(Instant::now());
+                    board.lock().progress = report.progress_json(total, Some(&src), false);
+                }
+            }
+            let pair = self.run_pair(
+                &mut state,
+                src,
+                dst,
+                prefetched,
+                &mut report.undo,
+                &mut process,
+            );
+            report.pairs.push(pair);
+        }
+
+        drain_unsettled(&mut report);
+        // 没读完的名字不能当作已经处理完了
+        if let Some(e) = listing.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            return Err(e);
+        }
+        if let Some(progress) = &mut progress {
+            progress.update(&mut report, None, true);
+        }
+        if let Some(guard) = tnamed_guard {
+            guard.keep();
+        }
+        report.elapsed = started.elapsed();
+        let dir = pairs_tnamed_dir.or_else(|| {
+            let first = report.pairs.iter().find_map(PairReport::output_path)?;
+            first.parent().map(Path::to_path_buf)
+        });
+        if let (Some(dir), false) = (&dir, self.simulated()) {
+            if self.persist_report {
+                let path = dir.join(REPORT_FILE_NAME);
+                let file = audit::record(Operation::Create, &path, fs::File::create(&path))?;
+                report.write_json(self, io::BufWriter::new(file))?;
+                report.undo.push(Mutation::CreatedFile(path));
+            }
+            if self.manifest {
+                let path = dir.join(MANIFEST_FILE_NAME);
+                let file = audit::record(Operation::Create, &path, fs::File::create(&path))?;
+                report.write_manifest(dir, io::BufWriter::new(file))?;
+                report.undo.push(Mutation::CreatedFile(path));
+            }
+        }
+        if let Some(board) = &self.status {
+            let mut json = Vec::new();
+            report.write_json(self, &mut json)?;
+            let mut status = board.lock();
+            status.progress = report.progress_json(total, None, true);
+            status.report = Some(String::from_utf8_lossy(&json).into_owned());
+        }
+        Ok(report)
+    }
+
+    /// One pair of [`Self::run`] and [`Self::retry`], what is not about the run as a whole.
+    fn run_pair<F>(
+        &self,
+        state: &mut PairState,
+        src: Src,
+        dst: Dst,
+        prefetched: Option<ReadFile>,
+        undo: &mut UndoLog,
+        process: &mut F,
+    ) -> PairReport
+    where
+        F: FnMut(&mut Task) -> io::Result<()>,
+    {
+        if self.cancelled() {
+            return PairReport::skipped(src, dst, SkipReason::Cancelled);
+        }
+        if let Some(structure) = self.structure_only {
+            let laid_out = match (structure, &dst) {
+                (StructureOnly::Placeholders, Dst::File(path))
+                    if !self.simulated() && !path.exists() =>
+                {
+                    create_placeholder(path, undo)
+                }
+                _ => Ok(()),
+            };
+            return match laid_out {
+                Ok(()) => PairReport::skipped(src, dst, SkipReason::StructureOnly),
+                Err(e) => PairReport::failed(src, dst, e),
+            };
+        }
+        if self
+            .byte_budget
+            .is_some_and(|budget| state.total_written >= budget)
+        {
+            return PairReport::skipped(src, dst, SkipReason::BudgetExhausted);
+        }
+
+        if self.revalidate {
+            let reason = revalidate(&src, &dst)
+                .filter(|reason| !(state.unborn && *reason == SkipReason::DstDirGone));
+            if let Some(reason) = reason {
+                return PairReport::skipped(src, dst, reason);
+            }
+        }
+
+        // 原地编辑时 DST 就是 SRC，也不算旧的输出
+        let fresh = matches!((&dst, &state.reserved), (Dst::File(dst), Some(r)) if dst == r)
+            || matches!((&src, &dst), (Src::File(src), Dst::File(dst)) if src == dst);
+        let filtered = match self.filter(&src, &dst) {
+            Ok(filtered) => filtered.filter(|_| !fresh),
+            Err(e) => return PairReport::failed(src, dst, e),
+        };
+        if let Some(reason) = filtered {
+            return PairReport::skipped(src, dst, reason);
+        }
+
+        let empty =
+            matches!(&src, Src::File(path) if fs::metadata(path).is_ok_and(|m| m.len() == 0));
+        if state.batch && empty && self.empty_src != EmptySrc::Process {
+            return match self.empty_src {
+                EmptySrc::Fail => PairReport::failed(
+                    src,
+                    dst,
+                    io::Error::new(io::ErrorKind::InvalidData, SkipReason::EmptySrc.to_string()),
+                ),
+                _ => PairReport::skipped(src, dst, SkipReason::EmptySrc),
+            };
+        }
+
+        // 读不了的 SRC 只算这一对失败
+        let unverified = match self.verify(&src) {
+            Ok(unverified) => unverified,
+            Err(e) => return PairReport::failed(src, dst, e),
+        };
+        if let Some(reason) = unverified {
+            return match self.on_unverified {
+                OnUnverified::Skip => PairReport::skipped(src, dst, reason),
+                OnUnverified::Fail => PairReport::failed(
+                    src,
+                    dst,
+                    io::Error::new(io::ErrorKind::InvalidData, reason.to_string()),
+                ),
+            };
+        }
+
+        let mut resumed = None;
+        let partials = match (&dst, self.stale_partial, self.simulated()) {
+            (Dst::File(dst), policy, false) if policy != StalePartial::Ignore => {
+                self.stale_partials(dst)
+            }
+            _ => Ok(Vec::new()),
+        };
+        let partials = match partials {
+            Ok(partials) => partials,
+            Err(e) => return PairReport::failed(src, dst, e),
+        };
+        if let Some(found) = partials.iter().find(|f| f.action == StalePartial::Error) {
+            let e = io::Error::new(io::ErrorKind::AlreadyExists, found.to_string());
+            return PairReport::failed(src, dst, e);
+        }
+        let removed = partials
+            .into_iter()
+            .try_for_each(|found| match found.action {
+                StalePartial::Resume if resumable(&src, &found) => {
+                    resumed = Some(found);
+                    Ok(())
+                }
+                _ => audit::record(
+                    Operation::Remove,
+                    &found.partial,
+                    fs::remove_file(&found.partial),
+                ),
+            });
+        if let Err(e) = removed {
+            return PairReport::failed(src, dst, e);
+        }
+
+        let pair_started = Instant::now();
+        let mut task = Task::new(self, &state.held, src.clone(), dst.clone());
+        if let Some(found) = resumed {
+            task.output = Box::new(WriteFileAtomic::resume(&found.dst, found.partial.clone()));
+            task.resume_offset = found.len;
+            task.resumed_from = Some(found.partial);
+        }
+        if let Some(input) = prefetched {
+            task.input = Box::new(input);
+        }
+        if fresh {
+            task.stale_output = StaleOutput::Overwrite;
+        }
+        let mut result = task.execute(process);
+        let mut substituted = None;
+        undo.mutations.append(&mut task.undo.mutations);
+
+        if result.is_err() {
+            if let Some(fallback) = self.fallback_for(&task) {
+                // 备用目录也建不了的话，只有这一对失败
+                let ensured = match fallback.parent() {
+                    Some(parent) => state.known.ensure(parent, |dir| undo.create_dir_all(dir)),
+                    None => Err(io::ErrorKind::InvalidInput.into()),
+                };
+                match ensured {
+                    Ok(()) => {
+                        task =
+                            Task::new(self, &state.held, src.clone(), Dst::File(fallback.clone()));
+                        result = task.execute(process);
+                        substituted = Some(fallback);
+                        undo.mutations.append(&mut task.undo.mutations);
+                    }
+                    Err(error) => {
+                        result = result.map_err(|primary| {
+                            FallbackFailed {
+                                primary,
+                                fallback,
+                                error,
+                            }
+                            .into()
+                        });
+                    }
+                }
+            }
+        }
+
+        state.total_written += task.written;
+        let timed_out = task.timed_out();
+        let cancelled = self.cancelled();
+
+        let mut duplicate_of = None;
+        if result.is_ok() {
+            // 去重不了就留着副本，输出本身没有问题
+            duplicate_of =
+                (self.dedup(&task, substituted.as_ref(), &mut state.seen)).unwrap_or(None);
+            // 去掉的副本的内容在第一份那里
+            let output = match (&duplicate_of, &substituted, &dst) {
+                (Some(path), _, _) | (None, Some(path), _) | (None, None, Dst::File(path)) => {
+                    Some(path.as_path())
+                }
+                _ => None,
+            };
+            if let Err(e) = self.consume(&src, output, undo, &mut state.known) {
+                result = Err(e);
+            }
+        }
+
+        // 挪不走就留在原处，这一对本来就失败了
+        let set_aside = match result.is_err() && !cancelled {
+            true => (self.set_aside(&src, undo, &mut state.known)).unwrap_or(None),
+            false => None,
+        };
+
+        PairReport {
+            src,
+            dst,
+            substituted,
+            quarantined: task.quarantined.map(|(_, backup)| backup),
+            set_aside,
+            written: task.written,
+            elapsed: pair_started.elapsed(),
+            duplicate_of,
+            golden_diff: task.golden.as_ref().and_then(GoldenOutput::diff),
+            sha256: task.sha256,
+            src_sha256: task.src_sha256,
+            outcome: match result {
+                Ok(()) => Outcome::Done,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut && timed_out => Outcome::TimedOut,
+                Err(e) if cancelled && CancelledError::is(&e) => Outcome::Cancelled,
+                Err(e) => Outcome::Failed(e),
+            },
+        }
+    }
+
+    /// The stale partial outputs of `pairs` (e.g. [`Plan::pairs`](crate::plan::Plan::pairs)),
+    /// and what [`Self::run`] will do with them. Nothing is touched.
+    ///
+    /// Also the SRC files that are not readable and the DST directories that are not writable,
+    /// instead of the run failing on them halfway through, as far as `filesystem` can tell,
+    /// see [`vfs::FileSystem::accessible`]. [`vfs::StdFs`] unless the pairs were parsed with
+    /// [`SrcDstConfig::filesystem`](crate::SrcDstConfig::filesystem).
+    pub fn preflight(&self, pairs: &[(Src, Dst)], filesystem: &dyn vfs::FileSystem) -> Preflight {
+        let mut preflight = Preflight::default();
+        let mut denied = std::collections::BTreeMap::new();
+        for (src, dst) in pairs {
+            if let Src::File(src) = src {
+                if !filesystem.accessible(src, Access::Read) {
+                    *denied.entry((src.clone(), Access::Read)).or_insert(0) += 1;
+                }
+            }
+            if let Dst::File(dst) = dst {
+                // 列不出来的话，run 时只有这一对会失败
+                let partials = self.stale_partials(dst).unwrap_or_default();
+                preflight.stale_partials.extend(partials);
+                // 还不存在的目录由最近的已有祖先决定
+                let dir = (dst.ancestors().skip(1))
+                    .find(|dir| dir.as_os_str().is_empty() || filesystem.is_dir(dir))
+                    .map(|dir| match dir.as_os_str().is_empty() {
+                        true => Path::new("."),
+                        false => dir,
+                    });
+                if let Some(dir) = dir.filter(|dir| !filesystem.accessible(dir, Access::WriteDir)) {
+                    *denied
+                        .entry((dir.to_owned(), Access::WriteDir))
+                        .or_insert(0) += 1;
+                }
+                if filesystem.is_file(dst) && !filesystem.accessible(dst, Access::Write) {
+                    *denied.entry((dst.clone(), Access::Write)).or_insert(0) += 1;
+                }
+            }
+        }
+        preflight.denied = (denied.into_iter())
+            .map(|((path, access), pairs)| Denied {
+                path,
+                access,
+                pairs,
+            })
+            .collect();
+        preflight
+    }
+
+    fn stale_partials(&self, dst: &Path) -> io::Result<Vec<FoundPartial>> {
+        let mut found = Vec::new();
+        for partial in crate::cleanup::stale_partials(dst)? {
+            let len = fs::symlink_metadata(&partial)?.len();
+            found.push(FoundPartial {
+                dst: dst.to_owned(),
+                partial,
+                len,
+                action: self.stale_partial,
+            });
+        }
+        if self.stale_partial == StalePartial::Resume {
+            let longest = (0..found.len()).max_by_key(|&i| found[i].len);
+            for (i, found) in found.iter_mut().enumerate() {
+                if Some(i) != longest {
+                    found.action = StalePartial::Delete;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Runs just the [`RunReport::failed_pairs`] of `report` again, until every pair either
+    /// succeeds or has used up [`RetryPolicy::attempts`]. They are still treated as pairs of a
+    /// batch or not, like in the run. The report has the last outcome of every pair, retried
+    /// ones last, and the undo log of all attempts.
+    ///
+    /// Only the pairs are run again: the progress file, the persisted report and manifest and
+    /// the status board are left as [`Self::run`] wrote them.
+    pub fn retry<F>(&self, report: &RunReport, policy: &RetryPolicy, mut process: F) -> RunReport
+    where
+        F: FnMut(&mut Task) -> io::Result<()>,
+    {
+        let started = Instant::now();
+        let batch = report.batch;
+        let mut state = PairState {
+            held: HeldSrcDir::default(),
+            batch,
+            unborn: false,
+            reserved: None,
+            known: KnownDirs::default(),
+            seen: HashMap::new(),
+            total_written: 0,
+        };
+        let mut pending = report.failed_pairs();
+        let mut report = RunReport {
+            environment: Environment::capture(),
+            batch,
+            ..Default::default()
+        };
+        let mut still_failed = Vec::new();
+        for attempt in 0..policy.attempts {
+            if pending.is_empty() || self.cancelled() {
+                break;
+            }
+            std::thread::sleep(policy.delay(attempt));
+            let attempted: Vec<_> = (pending.into_iter())
+                .map(|(src, dst)| {
+                    self.run_pair(&mut state, src, dst, None, &mut report.undo, &mut process)
+                })
+                .collect();
+            let (failed, settled): (Vec<_>, _) =
+                (attempted.into_iter()).partition(PairReport::retriable);
+            pending = (failed.iter())
+                .filter(|p| p.src != Src::Stdin)
+                .map(|p| (p.src.clone(), p.dst.clone()))
+                .collect();
+            report.pairs.extend(settled);
+            still_failed = failed;
+        }
+        report.pairs.extend(still_failed);
+        report.elapsed = started.elapsed();
+        report
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.as_ref().is_some_and(CancelToken::is_cancelled)
+    }
+
+    fn input_for(&self, held: &HeldSrcDir, src: &Src) -> Box<dyn Input> {
+        #[cfg(unix)]
+        if let (Some(dir), Src::File(path)) = (held, src) {
+            if path.parent() == Some(dir.path()) {
+                let name = path.file_name().unwrap();
+                return Box::new(crate::snapshot::HeldInput::new(dir.clone(), name));
+            }
+        }
+        #[cfg(not(unix))]
+        let () = *held;
+        match src {
+            Src::Stdin => Box::new(ReadStdin {
+                max_bytes: self.max_stdin_bytes,
+                read_timeout: self.stdin_read_timeout,
+            }),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            Src::File(path) if self.io_uring => Box::new(crate::uring::UringReadFile::new(path)),
+            src => src.input(),
+        }
+    }
+
+    fn output_for(&self, src: &Src, dst: &Dst) -> Box<dyn Output> {
+        let output: Box<dyn Output> = match dst {
+            dst if self.simulated() => Box::new(RecordingOutput::new(dst.clone())),
+            dst => match self.make_output.as_ref().and_then(|o| o.0.make_output(dst)) {
+                Some(inner) => Box::new(Overridden {
+                    dst: dst.clone(),
+                    inner,
+                }),
+                None => self.default_output_for(src, dst),
+            },
+        };
+        self.wrappers.wrap(dst, output)
+    }
+
+    fn default_output_for(&self, src: &Src, dst: &Dst) -> Box<dyn Output> {
+        match (src, dst) {
+            (Src::File(src), Dst::File(path)) if src == path => {
+                Box::new(WriteFileAtomic::new(path))
+            }
+            (Src::File(src), Dst::File(path)) if self.symlink_dst.is_some() => Box::new(
+                SymlinkOutput::new(src, path, self.symlink_dst.unwrap_or_default()),
+            ),
+            (_, Dst::File(path)) if self.fifo_dst => Box::new(FifoOutput::new(path)),
+            (_, Dst::File(path)) if self.hardened_dst => Box::new(HardenedWriteFile::new(path)),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            (Src::File(src), Dst::File(path))
+                if self.preserve_metadata && self.uring() && !self.direct_dst =>
+            {
+                Box::new(crate::uring::UringWriteFile::new(path).preserve_metadata_of(src))
+            }
+            (Src::File(src), Dst::File(path)) if self.preserve_metadata => {
+                let output = match self.direct_dst {
+                    true => WriteFile::new_direct(path),
+                    false => WriteFile::new(path),
+                };
+                Box::new(output.preserve_metadata_of(src))
+            }
+            (_, Dst::File(path)) if self.direct_dst => Box::new(WriteFile::new_direct(path)),
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            (_, Dst::File(path)) if self.io_uring => {
+                Box::new(crate::uring::UringWriteFile::new(path))
+            }
+            (_, dst) => dst.output(),
+        }
+    }
+
+    /// [`Self::io_uring`] is in effect.
+    fn uring(&self) -> bool {
+        cfg!(all(target_os = "linux", feature = "io-uring")) && self.io_uring
+    }
+
+    /// Nothing is written, moved or created.
+    fn simulated(&self) -> bool {
+        self.dry_run || self.golden || crate::is_read_only()
+    }
+
+    fn is_passthrough(&self, src: &Src) -> bool {
+        let Src::File(src) = src else {
+            return false;
+        };
+        match src.file_name().and_then(|name| name.to_str()) {
+            Some(name) => self
+                .passthrough
+                .iter()
+                .any(|pattern| glob_match(pattern, name)),
+            None => false,
+        }
+    }
+
+    fn filter(&self, src: &Src, dst: &Dst) -> io::Result<Option<SkipReason>> {
+        let Src::File(src) = src else {
+            return Ok(None);
+        };
+        if let Some(name) = src.file_name().and_then(|name| name.to_str()) {
+            if self.exclude.iter().any(|pattern| glob_match(pattern, name)) {
+                return Ok(Some(SkipReason::FilteredByGlob));
+            }
+        }
+
+        let Dst::File(dst) = dst else {
+            return Ok(None);
+        };
+        let Ok(dst_meta) = fs::metadata(dst) else {
+            return Ok(None);
+        };
+        if crate::is_placeholder(&vfs::StdFs, dst) {
+            return Ok(None);
+        }
+        if self.stale_output == StaleOutput::Keep {
+            return Ok(Some(SkipReason::WouldOverwrite));
+        }
+        if self.skip_up_to_date && dst_meta.modified()? >= fs::metadata(src)?.modified()? {
+            return Ok(Some(SkipReason::UpToDate));
+        }
+        Ok(None)
+    }
+
+    fn verify(&self, src: &Src) -> io::Result<Option<SkipReason>> {
+        let (Some(manifest), Src::File(path)) = (&self.verify_sources, src) else {
+            return Ok(None);
+        };
+        Ok(match manifest.verify(path)? {
+            None => Some(SkipReason::NotInManifest),
+            Some(false) => Some(SkipReason::ChecksumMismatch),
+            Some(true) => None,
+        })
+    }
+
+    fn dedup(
+        &self,
+        task: &Task,
+        substituted: Option<&PathBuf>,
+        seen: &mut HashMap<(u64, u64), PathBuf>,
+    ) -> io::Result<Option<PathBuf>> {
+        let (Some(digest), Dst::File(dst)) = (task.digest, &task.dst) else {
+            return Ok(None);
+        };
+        let path = substituted.unwrap_or(dst);
+
+        match seen.get(&(digest, task.written)) {
+            None => {
+                seen.insert((digest, task.written), path.to_owned());
+                Ok(None)
+            }
+            Some(first) => {
+                if !same_content(first, path)? {
+                    return Ok(None); // 哈希碰撞
+                }
+                match self.dedup {
+                    Dedup::HardLink => {
+                        link_over(first, path, |first, temp| fs::hard_link(first, temp))?
+                    }
+                    _ => audit::record(Operation::Remove, path, fs::remove_file(path))?,
+                }
+                Ok(Some(first.to_owned()))
+            }
+        }
+    }
+
+    fn set_aside(
+        &self,
+        src: &Src,
+        undo: &mut UndoLog,
+        known: &mut KnownDirs,
+    ) -> io::Result<Option<PathBuf>> {
+        let (Src::File(src), Some(failed), false) = (src, &self.failed_src_dir, self.simulated())
+        else {
+            return Ok(None);
+        };
+        let dir = match failed {
+            FailedSrcDir::BesideSrc => src.with_file_name("failed"),
+            FailedSrcDir::Path(dir) => dir.clone(),
+        };
+        crate::check_writable(src)?;
+        known.ensure(&dir, |dir| undo.create_dir_all(dir))?;
+        let moved = unique_in(&dir, src.file_name().unwrap());
+        audit::record_to(Operation::Rename, src, &moved, fs::rename(src, &moved))?;
+        undo.push(Mutation::Replaced {
+            dst: src.clone(),
+            backup: moved.clone(),
+        });
+        Ok(Some(moved))
+    }
+
+    /// DST (and its directory) first, so that a crash in between leaves both rather than neither.
+    /// SRC is kept when that fails.
+    fn consume(
+        &self,
+        src: &Src,
+        output: Option<&Path>,
+        undo: &mut UndoLog,
+        known: &mut KnownDirs,
+    ) -> io::Result<()> {
+        let Src::File(src) = src else {
+            return Ok(());
+        };
+        if !self.consume_src || self.simulated() || output == Some(src.as_path()) {
+            return Ok(());
+        }
+        if let Some(path) = output {
+            audit::record(Operation::Open, path, fs::File::open(path))?.sync_all()?;
+            sync_dir(path.parent())?;
+        }
+
+        crate::check_writable(src)?;
+        match &self.processed_dir {
+            None => {
+                audit::record(Operation::Remove, src, fs::remove_file(src))?;
+                undo.push(Mutation::Removed(src.clone()));
+            }
+            Some(dir) => {
+                known.ensure(dir, |dir| undo.create_dir_all(dir))?;
+                let archived = unique_in(dir, src.file_name().unwrap());
+                let renamed = fs::rename(src, &archived);
+                match audit::record_to(Operation::Rename, src, &archived, renamed) {
+                    Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                        let copied = fs::copy(src, &archived);
+                        audit::record_to(Operation::Copy, src, &archived, copied)?;
+                        let file = fs::File::open(&archived);
+                        audit::record(Operation::Open, &archived, file)?.sync_all()?;
+                        audit::record(Operation::Remove, src, fs::remove_file(src))?;
+                    }
+                    result => result?,
+                }
+                undo.push(Mutation::Replaced {
+                    dst: src.clone(),
+                    backup: archived,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    fn fallback_for(&self, task: &Task) -> Option<PathBuf> {
+        let dir = self.fallback_dst.as_ref()?;
+        match (&task.src, &task.dst) {
+            (Src::File(_), Dst::File(dst)) if task.write_failed => Some(dir.join(dst.file_name()?)),
+            _ => None,
+        }
+    }
+}
+
+/// The pair currently being processed, handed to the closure of [`Runner::run`].
+pub struct Task {
+    src: Src,
+    dst: Dst,
+    input: Box<dyn Input>,
+    output: Box<dyn Output>,
+    reader: Option<DeadlineReader>,
+    writer: Option<TrackedWriter>,
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+    write_failed: bool,
+    stale_output: StaleOutput,
+    resume_offset: u64,
+    resumed_from: Option<PathBuf>,
+    hash_output: bool,
+    sha256_output: bool,
+    sha256_input: bool,
+    simulated: bool,
+    golden: Option<GoldenOutput>,
+    written: u64,
+    digest: Option<u64>,
+    sha256: Option<Digest>,
+    src_sha256: Option<Digest>,
+    /// (DST, backup)
+    quarantined: Option<(PathBuf, PathBuf)>,
+    scratch: Option<TempGuard>,
+    undo: UndoLog,
+    before_finalize: Option<Shared<dyn BeforeFinalize>>,
+    size_class: SizeClass,
+    passthrough: bool,
+}
+
+impl Task {
+    fn new(runner: &Runner, held: &HeldSrcDir, src: Src, dst: Dst) -> Self {
+        let golden = match &dst {
+            Dst::File(path) if runner.golden => Some(GoldenOutput::new(path)),
+            _ => None,
+        };
+        let mut output: Box<dyn Output> = match &golden {
+            Some(golden) => Box::new(golden.clone()),
+            None => runner.output_for(&src, &dst),
+        };
+        let passthrough = runner.is_passthrough(&src);
+        let stdin = src == Src::Stdin;
+        let mut size_class = SizeClass::Unknown;
+        if let Src::File(path) = &src {
+            if let Ok(meta) = fs::metadata(path) {
+                if meta.is_file() {
+                    size_class = runner.size_classes.classify(meta.len());
+                }
+                if runner.preallocate_src_len {
+                    output.expect_len(meta.len());
+                }
+            }
+        }
+        Self {
+            input: runner.input_for(held, &src),
+            output,
+            src,
+            dst,
+            reader: None,
+            writer: None,
+            deadline: runner.pair_timeout.map(|timeout| Instant::now() + timeout),
+            cancel: runner.cancel.clone(),
+            write_failed: false,
+            stale_output: runner.stale_output,
+            resume_offset: 0,
+            resumed_from: None,
+            hash_output: runner.dedup != Dedup::Off && !runner.simulated(),
+            sha256_output: runner.persist_report && !runner.simulated(),
+            sha256_input: runner.hash_stdin && stdin,
+            simulated: runner.simulated(),
+            golden,
+            written: 0,
+            digest: None,
+            sha256: None,
+            src_sha256: None,
+            quarantined: None,
+            scratch: None,
+            undo: UndoLog::default(),
+            before_finalize: match runner.simulated() {
+                true => None,
+                false => runner.on_before_finalize.clone(),
+            },
+            size_class,
+            passthrough,
+        }
+    }
+
+    pub fn src(&self) -> &Src {
+        &self.src
+    }
+
+    pub fn dst(&self) -> &Dst {
+        &self.dst
+    }
+
+    /// Of the SRC when the task was made, by [`Runner::size_classes`].
+    pub fn size_class(&self) -> SizeClass {
+        self.size_class
+    }
+
+    /// Before [`Self::writer`]: about `len` bytes will be written, so that DST can be
+    /// preallocated, see [`Output::expect_len`].
+    pub fn expect_len(&mut self, len: u64) {
+        self.output.expect_len(len);
+    }
+
+    /// Bytes of output already in DST before [`Self::writer`], which continues after them,
+    /// see [`StalePartial::Resume`]. 0 unless resuming.
+    pub fn resume_offset(&self) -> u64 {
+        self.resume_offset
+    }
+
+    /// Bytes written so far, final once processing is done. With those of a resumed partial,
+    /// see [`Self::resume_offset`].
+    pub fn written(&self) -> u64 {
+        match &self.writer {
+            Some(w) => w.written,
+            None => self.written,
+        }
+    }
+
+    /// An empty directory for intermediate files, created on first call in
+    /// [`std::env::temp_dir`] (also in simulated runs), removed with its content once the pair
+    /// is done, whatever the outcome.
+    pub fn scratch_dir(&mut self) -> io::Result<&Path> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        if self.scratch.is_none() {
+            let path = std::env::temp_dir().join(format!(
+                "sdc-scratch-{}-{}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            audit::record(Operation::CreateDir, &path, fs::create_dir(&path))?;
+            self.scratch = Some(TempGuard::tree(path));
+        }
+        Ok(self.scratch.as_ref().unwrap().path())
+    }
+
+    /// Streams SRC to DST unchanged, for tools passing some files through untouched.
+    /// Returns the bytes copied.
+    ///
+    /// Between local files that nothing else has to see the bytes of, this is left to the OS
+    /// (`copy_file_range` on Linux), see [`Input::file`] and [`Output::file`].
+    pub fn copy_through(&mut self) -> io::Result<u64> {
+        if let Some(copied) = self.copy_file() {
+            return copied;
+        }
+        self.reader()?;
+        self.writer()?;
+        let (reader, writer) = (self.reader.as_mut().unwrap(), self.writer.as_mut().unwrap());
+        io::copy(reader, writer)
+    }
+
+    /// Opens SRC on first call.
+    pub fn reader(&mut self) -> io::Result<&mut dyn Read> {
+        if self.reader.is_none() {
+            self.reader = Some(DeadlineReader {
+                inner: self.input.reader()?,
+                sha256: self.sha256_input.then(Sha256::new),
+                deadline: self.deadline,
+                cancel: self.cancel.clone(),
+            });
+        }
+        Ok(self.reader.as_mut().unwrap())
+    }
+
+    /// `None` when the bytes have to go through [`Self::reader`] and [`Self::writer`], to be
+    /// hashed, checked against a deadline, recorded...
+    fn copy_file(&mut self) -> Option<io::Result<u64>> {
+        let plain = (self.reader.is_none() && self.writer.is_none())
+            && !(self.simulated || self.hash_output || self.sha256_output || self.sha256_input)
+            && (self.deadline.is_none() && self.cancel.is_none() && self.resumed_from.is_none());
+        if !plain {
+            return None;
+        }
+        let mut from = match self.input.file()? {
+            Ok(from) => from,
+            Err(e) => return Some(Err(e)),
+        };
+        let existed = matches!(&self.dst, Dst::File(dst) if dst.is_file());
+        // 不支持的话 writer() 会再来一次，那时已经没有要挪走的了
+        let to = self.quarantine().map(|()| self.output.file());
+        let copied = match to {
+            Ok(None) => return None,
+            Ok(Some(Ok(mut to))) => {
+                self.record_created(existed);
+                io::copy(&mut from, &mut to)
+            }
+            Ok(Some(Err(e))) | Err(e) => Err(e),
+        };
+        match &copied {
+            Ok(n) => self.written = *n,
+            Err(_) => self.write_failed = true,
+        }
+        Some(copied)
+    }
+
+    fn record_created(&mut self, existed: bool) {
+        if let (Dst::File(dst), false) = (&self.dst, self.simulated) {
+            self.undo.push(match existed && self.quarantined.is_none() {
+                true => Mutation::Overwritten(dst.to_owned()),
+                false => Mutation::CreatedFile(dst.to_owned()),
+            });
+        }
+    }
+
+    /// Opens (creates) DST on first call.
+    pub fn writer(&mut self) -> io::Result<&mut dyn Write> {
+        if self.writer.is_none() {
+            let existed = matches!(&self.dst, Dst::File(dst) if dst.is_file());
+            let quarantined = match self.simulated {
+                true => Ok(()),
+                false => self.quarantine(),
+            };
+            match quarantined.and_then(|()| self.output.writer()) {
+                Ok(inner) => {
+                    self.record_created(existed);
+                    let mut writer = TrackedWriter {
+                        inner,
+                        failed: false,
+                        written: 0,
+                        hasher: self.hash_output.then(DefaultHasher::new),
+                        sha256: self.sha256_output.then(Sha256::new),
+                        deadline: self.deadline,
+                        cancel: self.cancel.clone(),
+                    };
+                    if let Some(partial) = &self.resumed_from {
+                        writer.skip_prefix(partial)?;
+                    }
+                    self.writer = Some(writer);
+                }
+                Err(e) => {
+                    self.write_failed = true;
+                    return Err(e);
+                }
+            }
+        }
+        Ok(self.writer.as_mut().unwrap())
+    }
+
+    fn execute<F>(&mut self, process: &mut F) -> io::Result<()>
+    where
+        F: FnMut(&mut Task) -> io::Result<()>,
+    {
+        let processed = match self.passthrough {
+            true => self.copy_through().map(|_| ()),
+            false => process(self),
+        };
+        let result = processed.and_then(|()| match self.writer.as_mut() {
+            Some(w) => w.flush(),
+            None => Ok(()),
+        });
+        // 没读完的部分也要算进摘要
+        let result = match (result, self.reader.take()) {
+            (Ok(()), Some(mut r)) if r.sha256.is_some() => {
+                let drained = io::copy(&mut r, &mut io::sink()).map(drop);
+                self.src_sha256 = r.sha256.map(Sha256::finish);
+                drained
+            }
+            (result, _) => result,
+        };
+        if let Some(w) = self.writer.take() {
+            self.write_failed |= w.failed;
+            self.written = w.written;
+            self.digest = w.hasher.map(|h| h.finish());
+            self.sha256 = w.sha256.map(Sha256::finish);
+        }
+        let result =
+            result
+                .and_then(|()| self.input.finish())
+                .and_then(|()| match &self.before_finalize {
+                    Some(hook) => hook.0.before_finalize(self).map_err(io::Error::from),
+                    None => Ok(()),
+                });
+        if let Some(scratch) = self.scratch.take() {
+            let _ = scratch.remove(); // 删不掉也不影响这一对的结果，之后 cleanup_stale 会处理
+        }
+        match result {
+            Ok(()) => self.output.finish(),
+            Err(e) => {
+                let _ = self.output.abort(); // 原本的错误更重要
+                if let Some((dst, backup)) = self.quarantined.take() {
+                    let _ = audit::record_to(
+                        Operation::Rename,
+                        &backup,
+                        &dst,
+                        fs::rename(&backup, &dst),
+                    );
+                }
+                // 只有新建的目录还留着
+                self.undo
+                    .mutations
+                    .retain(|m| matches!(m, Mutation::CreatedDir(_)));
+                Err(e)
+            }
+        }
+    }
+
+    fn timed_out(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn quarantine(&mut self) -> io::Result<()> {
+        let Dst::File(dst) = &self.dst else {
+            return Ok(());
+        };
+        if !dst.is_file() || crate::is_placeholder(&vfs::StdFs, dst) {
+            return Ok(());
+        }
+
+        let parent = dst.parent().unwrap();
+        let backup = match self.stale_output {
+            StaleOutput::Overwrite | StaleOutput::Keep => return Ok(()),
+            StaleOutput::MoveToOldDir => {
+                let old = parent.join(".old");
+                self.undo.create_dir_all(&old)?;
+                unique_in(&old, dst.file_name().unwrap())
+            }
+            StaleOutput::RenameWithDatetime => {
+                let mut name = dst.file_stem().unwrap().to_owned();
+                name.push(format!("-{}", Katetime::now_datetime()));
+                if let Some(ext) = dst.extension() {
+                    name.push(".");
+                    name.push(ext);
+                }
+                unique_in(parent, &name) // 同一秒里的第二次
+            }
+        };
+
+        audit::record_to(Operation::Rename, dst, &backup, fs::rename(dst, &backup))?;
+        self.undo.push(Mutation::Replaced {
+            dst: dst.to_owned(),
+            backup: backup.clone(),
+        });
+        self.quarantined = Some((dst.to_owned(), backup));
+        Ok(())
+    }
+}
+
+/// `name` in `dir`, or `<stem>-1.<ext>`, `<stem>-2.<ext>`... if taken.
+fn unique_in(dir: &Path, name: &std::ffi::OsStr) -> PathBuf {
+    let path = dir.join(name);
+    if fs::symlink_metadata(&path).is_err() {
+        return path;
+    }
+    let name = Path::new(name);
+    (1..)
+        .map(|n| {
+            let mut numbered = name.file_stem().unwrap_or_default().to_owned();
+            numbered.push(format!("-{n}"));
+            if let Some(ext) = name.extension() {
+                numbered.push(".");
+                numbered.push(ext);
+            }
+            dir.join(numbered)
+        })
+        .find(|path| fs::symlink_metadata(path).is_err())
+        .unwrap()
+}
+
+/// So that the entries just made in it survive a crash. Nothing to do but on Unix.
+fn sync_dir(dir: Option<&Path>) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = dir {
+        let dir = match dir.as_os_str().is_empty() {
+            true => Path::new("."),
+            false => dir,
+        };
+        audit::record(Operation::Open, dir, fs::File::open(dir))?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+/// Compared in chunks, so that large outputs are not read into memory.
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    fn fill(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match r.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(filled)
+    }
+
+    let open = |path: &Path| audit::record(Operation::Open, path, fs::File::open(path));
+    let (mut a, mut b) = (open(a)?, open(b)?);
+    let (mut buf_a, mut buf_b) = (vec![0; 64 * 1024], vec![0; 64 * 1024]);
+    loop {
+        let (n, m) = (fill(&mut a, &mut buf_a)?, fill(&mut b, &mut buf_b)?);
+        if buf_a[..n] != buf_b[..m] {
+            return Ok(false);
+        }
+        if n == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Links `first` beside `path` then renames the link over it, so that `path` keeps its content
+/// when linking fails, e.g. across devices or at the link limit.
+fn link_over<F>(first: &Path, path: &Path, link: F) -> io::Result<()>
+where
+    F: FnOnce(&Path, &Path) -> io::Result<()>,
+{
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(
+        ".{}-{}.link",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let temp = path.with_file_name(name);
+    audit::record_to(Operation::HardLink, &temp, first, link(first, &temp))?;
+    let renamed = fs::rename(&temp, path);
+    if renamed.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    audit::record_to(Operation::Rename, &temp, path, renamed)
+}
+
+/// See [`StructureOnly::Placeholders`].
+fn create_placeholder(path: &Path, undo: &mut UndoLog) -> io::Result<()> {
+    crate::check_writable(path)?;
+    let file = audit::record(Operation::Create, path, fs::File::create(path))?;
+    undo.push(Mutation::CreatedFile(path.to_owned()));
+    file.set_modified(std::time::UNIX_EPOCH)
+}
+
+/// Ends like the pairs, keeping the error that ended them early, see [`SrcDstPairs::spill_error`].
+struct Listed {
+    pairs: SrcDstPairs,
+    error: Arc<Mutex<Option<io::Error>>>,
+    /// See [`SrcDstPairs::take_unsettled`], reported as failed pairs.
+    unsettled: Arc<Mutex<Vec<PairReport>>>,
+}
+
+impl Iterator for Listed {
+    type Item = (Src, Dst);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.pairs.next();
+        let unsettled = self.pairs.take_unsettled();
+        if !unsettled.is_empty() {
+            let mut failed = self.unsettled.lock().unwrap_or_else(|e| e.into_inner());
+            // 被略过的只有 DST 定不下来的
+            for e in unsettled {
+                if let SrcDstPairError::Dst { src, dst, error } = e {
+                    let error = io::Error::new(io::ErrorKind::AlreadyExists, error);
+                    failed.push(PairReport::failed(Src::File(src), Dst::File(dst), error));
+                }
+            }
+        }
+        if let (None, Some(e)) = (&next, self.pairs.spill_error()) {
+            let e = io::Error::new(e.kind(), e.to_string());
+            *self.error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e);
+        }
+        next
+    }
+}
+
+/// What [`Runner::run_pair`] carries from one pair to the next.
+struct PairState {
+    held: HeldSrcDir,
+    batch: bool,
+    unborn: bool,
+    reserved: Option<PathBuf>,
+    known: KnownDirs,
+    seen: HashMap<(u64, u64), PathBuf>,
+    total_written: u64,
+}
+
+/// Cheap existence checks of a pair planned earlier: the file SRC, and the directory of the file DST.
+pub fn revalidate(src: &Src, dst: &Dst) -> Option<SkipReason> {
+    if let Src::File(src) = src {
+        if !src.is_file() {
+            return Some(SkipReason::SrcGone);
+        }
+    }
+    if let Dst::File(dst) = dst {
+        if dst.parent().is_some_and(|dir| !dir.is_dir()) {
+            return Some(SkipReason::DstDirGone);
+        }
+    }
+    None
+}
+
+/// Remembers whether an error came from the DST side, to decide about fallback.
+struct TrackedWriter {
+    inner: Box<dyn Write + Send>,
+    failed: bool,
+    written: u64,
+    hasher: Option<DefaultHasher>,
+    sha256: Option<Sha256>,
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+}
+
+impl TrackedWriter {
+    /// Counts and hashes what a resumed partial already has, so that the output is
+    /// described as a whole.
+    fn skip_prefix(&mut self, partial: &Path) -> io::Result<()> {
+        let mut prefix = audit::record(Operation::Open, partial, fs::File::open(partial))?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            let n = prefix.read(&mut buf)?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.written += n as u64;
+            if let Some(hasher) = &mut self.hasher {
+                hasher.write(&buf[..n]);
+            }
+            if let Some(sha256) = &mut self.sha256 {
+                sha256.update(&buf[..n]);
+            }
+        }
+    }
+}
+
+impl Write for TrackedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        check_deadline(self.deadline, &self.cancel)?; // 超时不算写入失败，不触发 fallback
+        let result = self.inner.write(buf);
+        match &result {
+            Ok(n) => {
+                self.written += *n as u64;
+                if let Some(hasher) = &mut self.hasher {
+                    hasher.write(&buf[..*n]);
+                }
+                if let Some(sha256) = &mut self.sha256 {
+                    sha256.update(&buf[..*n]);
+                }
+            }
+            Err(_) => self.failed = true,
+        }
+        result
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let result = self.inner.flush();
+        self.failed |= result.is_err();
+        result
+    }
+}
+
+/// What [`Runner::make_output`] returned, kept to [`crate::set_read_only`] like the defaults
+/// even if the override does not check it itself.
+struct Overridden {
+    dst: Dst,
+    inner: Box<dyn Output>,
+}
+
+impl Output for Overridden {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        if let Dst::File(path) = &self.dst {
+            crate::check_writable(path)?;
+        }
+        self.inner.writer()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.inner.finish()
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        self.inner.abort()
+    }
+
+    fn expect_len(&mut self, len: u64) {
+        self.inner.expect_len(len)
+    }
+}
+
+struct DeadlineReader {
+    inner: Box<dyn Read + Send>,
+    sha256: Option<Sha256>,
+    deadline: Option<Instant>,
+    cancel: Option<CancelToken>,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        check_deadline(self.deadline, &self.cancel)?;
+        let n = self.inner.read(buf)?;
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// The partial still is as [`Runner::stale_partials`] found it, and was written after the
+/// last change of SRC.
+fn resumable(src: &Src, found: &FoundPartial) -> bool {
+    let Src::File(src) = src else {
+        return false; // 没法知道 SRC 变了没有
+    };
+    let modified = |path: &Path| fs::metadata(path).and_then(|meta| meta.modified());
+    match (fs::symlink_metadata(&found.partial), modified(src)) {
+        (Ok(partial), Ok(src)) => {
+            partial.len() == found.len && partial.modified().is_ok_and(|at| at >= src)
+        }
+        _ => false,
+    }
+}
+
+fn check_deadline(deadline: Option<Instant>, cancel: &Option<CancelToken>) -> io::Result<()> {
+    if cancel.as_ref().is_some_and(CancelToken::is_cancelled) {
+        return Err(io::Error::other(CancelledError));
+    }
+    match deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        true => Err(io::Error::new(io::ErrorKind::TimedOut, "pair timed out")),
+        false => Ok(()),
+    }
+}
+
+/// See [`Runner::preflight`].
+#[derive(Debug, Default)]
+pub struct Preflight {
+    pub stale_partials: Vec<FoundPartial>,
+    /// One per path, sorted by it.
+    pub denied: Vec<Denied>,
+}
+
+/// One line per finding.
+impl fmt::Display for Preflight {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for found in &self.stale_partials {
+            writeln!(f, "{found}")?;
+        }
+        for denied in &self.denied {
+            writeln!(f, "{denied}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Access {
+    Read,
+    /// Of an existing DST file.
+    Write,
+    /// Creating entries in a directory, i.e. write and search permission.
+    WriteDir,
+}
+
+/// A permission the run will need but does not have.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Denied {
+    pub path: PathBuf,
+    pub access: Access,
+    /// That need it.
+    pub pairs: usize,
+}
+
+/// `'/out' is not writable (120 pairs)`
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let what = match self.access {
+            Access::Read => "readable",
+            Access::Write | Access::WriteDir => "writable",
+        };
+        write!(
+            f,
+            "'{}' is not {what} ({} pairs)",
+            self.path.display(),
+            self.pairs
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FoundPartial {
+    pub dst: PathBuf,
+    pub partial: PathBuf,
+    pub len: u64,
+    /// Only one of those of a DST is resumed.
+    pub action: StalePartial,
+}
+
+/// `stale partial output '/out/.a.png.4242-0.tmp' of '/out/a.png' (1.5 MiB): resumed`
+impl fmt::Display for FoundPartial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "stale partial output '{}' of '{}' ({}): {}",
+            self.partial.display(),
+            self.dst.display(),
+            Bytes(self.len),
+            match self.action {
+                StalePartial::Ignore => "ignored",
+                StalePartial::Delete => "removed",
+                StalePartial::Resume => "resumed",
+                StalePartial::Error => "fails the pair",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RunReport {
+    pub pairs: Vec<PairReport>,
+    pub undo: UndoLog,
+    pub elapsed: Duration,
+    /// Captured when the run started.
+    pub environment: Environment,
+    /// The pairs came from a directory, see [`SrcDstPairs::is_batch`].
+    pub batch: bool,
+}
+
+/// `processed 1.2 GiB in 34 s, 36 MiB/s (9 done, 1 failed, 0 skipped)`
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "processed {} in {}, {} ({} done, {} failed, {} skipped)",
+            self.total_written(),
+            Elapsed(self.elapsed),
+            self.rate(),
+            self.succeeded().count(),
+            self.failed().count(),
+            self.skipped().count(),
+        )
+    }
+}
+
+impl RunReport {
+    /// Restores the filesystem to the state before the run, as far as [`UndoLog`] allows.
+    pub fn revert(&self) -> io::Result<()> {
+        self.undo.revert()
+    }
+
+    pub fn succeeded(&self) -> impl Iterator<Item = &PairReport> {
+        self.pairs
+            .iter()
+            .filter(|p| matches!(p.outcome, Outcome::Done))
+    }
+
+    pub fn failed(&self) -> impl Iterator<Item = &PairReport> {
+        self.pairs.iter().filter(|p| {
+            matches!(
+                p.outcome,
+                Outcome::Failed(_) | Outcome::TimedOut | Outcome::Cancelled
+            )
+        })
+    }
+
+    /// Failed and timed-out pairs to be run again, see [`Runner::retry`]. Neither cancelled
+    /// pairs nor those reading stdin, which can not be read twice.
+    pub fn failed_pairs(&self) -> Vec<(Src, Dst)> {
+        (self.pairs.iter())
+            .filter(|p| p.retriable() && p.src != Src::Stdin)
+            .map(|p| (p.src.clone(), p.dst.clone()))
+            .collect()
+    }
+
+    /// Also counted in [`Self::failed`].
+    pub fn timed_out(&self) -> impl Iterator<Item = &PairReport> {
+        self.pairs
+            .iter()
+            .filter(|p| matches!(p.outcome, Outcome::TimedOut))
+    }
+
+    pub fn skipped(&self) -> impl Iterator<Item = &PairReport> {
+        self.pairs
+            .iter()
+            .filter(|p| matches!(p.outcome, Outcome::Skipped(_)))
+    }
+
+    /// Pairs whose output ended up in [`Runner::fallback_dst`].
+    pub fn substituted(&self) -> impl Iterator<Item = &PairReport> {
+        self.pairs.iter().filter(|p| p.substituted.is_some())
+    }
+
+    /// See [`ProgressFile`].
+    fn progress_json(&self, total: Option<usize>, current: Option<&Src>, finished: bool) -> String {
+        format!(
+            r#"{{"done":{},"failed":{},"skipped":{},"total":{},"current":{},"finished":{finished}}}"#,
+            self.succeeded().count(),
+            self.failed().count(),
+            self.skipped().count(),
+            total.map_or(String::from("null"), |total| total.to_string()),
+            current.map_or(String::from("null"), |src| json_str(&src.to_string())),
+        )
+    }
+
+    pub fn total_written(&self) -> Bytes {
+        Bytes(self.pairs.iter().map(|p| p.written).sum())
+    }
+
+    pub fn rate(&self) -> Rate {
+        Rate::new(self.total_written().0, self.elapsed)
+    }
+
+    /// Pairs whose output differs from the golden DST, see [`Runner::golden`].
+    pub fn mismatched(&self) -> impl Iterator<Item = &PairReport> {
+        self.pairs.iter().filter(|p| p.golden_diff.is_some())
+    }
+
+    /// Bytes not stored twice thanks to [`Runner::dedup`].
+    pub fn dedup_savings(&self) -> u64 {
+        self.pairs
+            .iter()
+            .filter(|p| p.duplicate_of.is_some())
+            .map(|p| p.written)
+            .sum()
+    }
+
+    /// One line per output written, `<DST> <SRC>` percent-escaped like in a
+    /// [`Plan`](crate::plan::Plan), DSTs relative to `dir` if inside it:
+    ///
+    /// ``` plaintext
+    /// two.txt /in/a/deep/two.txt
+    /// two-1.txt /in/b/two.txt
+    /// ```
+    pub fn write_manifest<W: Write>(&self, dir: &Path, mut w: W) -> io::Result<()> {
+        for pair in self.succeeded() {
+            let Some(output) = pair.output_path() else {
+                continue;
+            };
+            let output = output.strip_prefix(dir).unwrap_or(output);
+            let src = match &pair.src {
+                Src::File(path) => escape(path.as_os_str()),
+                Src::Stdin => String::from("<stdin>"),
+                #[cfg(feature = "sftp")]
+                Src::Remote(remote) => escape(remote.to_string().as_ref()),
+                #[cfg(all(unix, feature = "unix-socket"))]
+                Src::UnixSocket(socket) => escape(socket.to_string().as_ref()),
+            };
+            writeln!(w, "{} {src}", escape(output.as_os_str()))?;
+        }
+        w.flush()
+    }
+
+    /// One JSON object, `runner` being the one that produced this report:
+    ///
+    /// ``` plaintext
+    /// {"version":1,"elapsed_ms":34012,"written":1288490189,
+    ///  "environment":{"crate_version":"0.3.0","os":"linux","arch":"x86_64","cwd":"/home/user"},
+    ///  "config":{"fallback_dst":null,"stale_output":"Overwrite","dedup":"Off","byte_budget":null,...},
+    ///  "pairs":[{"src":"/in/0001.jpg","dst":"/out/0001.jpg","substituted":null,"written":421,
+    ///            "elapsed_ms":12,"sha256":"e3b0...","src_sha256":null,"outcome":"done","detail":null}, ...]}
+    /// ```
+    ///
+    /// `outcome` is one of [`Outcome`], with `detail`:
+    ///
+    /// ``` plaintext
+    /// done        null
+    /// failed      the error
+    /// timed_out   null
+    /// cancelled   null
+    /// skipped     the Message::key of the SkipReason, e.g. "skip-empty-src"
+    /// ```
+    pub fn write_json<W: Write>(&self, runner: &Runner, mut w: W) -> io::Result<()> {
+        let mut report = JsonObject::new(&mut w)?;
+        report.raw("version", 1)?;
+        report.raw("elapsed_ms", self.elapsed.as_millis())?;
+        report.raw("written", self.total_written().0)?;
+
+        let environment = &self.environment;
+        let mut object = report.object("environment")?;
+        object.string("crate_version", &environment.crate_version)?;
+        object.string("os", &environment.os)?;
+        object.string("arch", &environment.arch)?;
+        object.opt_string("cwd", environment.cwd.as_ref().map(|cwd| cwd.display()))?;
+        object.end()?;
+
+        let mut config = report.object("config")?;
+        let fallback_dst = runner.fallback_dst.as_ref().map(|p| p.display());
+        config.opt_string("fallback_dst", fallback_dst)?;
+        config.debug("stale_output", runner.stale_output)?;
+        config.debug("stale_partial", runner.stale_partial)?;
+        config.debug("dedup", runner.dedup)?;
+        config.opt_raw("byte_budget", runner.byte_budget)?;
+        config.strings("exclude", &runner.exclude)?;
+        config.strings("passthrough", &runner.passthrough)?;
+        config.raw("skip_up_to_date", runner.skip_up_to_date)?;
+        config.debug("empty_src", runner.empty_src)?;
+        config.raw("verify_sources", runner.verify_sources.is_some())?;
+        config.debug("on_unverified", runner.on_unverified)?;
+        config.raw("fifo_dst", runner.fifo_dst)?;
+        config.opt_string("symlink_dst", runner.symlink_dst.map(|s| format!("{s:?}")))?;
+        config.raw("consume_src", runner.consume_src)?;
+        config.raw("revalidate", runner.revalidate)?;
+        config.raw("hardened_dst", runner.hardened_dst)?;
+        config.raw("prefetch", runner.prefetch)?;
+        config.raw("io_uring", runner.io_uring)?;
+        config.raw("direct_dst", runner.direct_dst)?;
+        config.raw("preallocate_src_len", runner.preallocate_src_len)?;
+        config.raw("preserve_metadata", runner.preserve_metadata)?;
+        let classes = &runner.size_classes;
+        config.raw(
+            "size_classes",
+            format!("[{},{}]", classes.tiny, classes.small),
+        )?;
+        config.raw("progress_file", runner.progress_file.is_some())?;
+        config.raw("hash_stdin", runner.hash_stdin)?;
+        config.opt_string(
+            "structure_only",
+            runner.structure_only.map(|s| format!("{s:?}")),
+        )?;
+        config.raw("dry_run", runner.dry_run)?;
+        config.raw("golden", runner.golden)?;
+        config.end()?;
+
+        report.objects("pairs", &self.pairs, |object, pair| {
+            let (outcome, detail) = match &pair.outcome {
+                Outcome::Done => ("done", None),
+                Outcome::Failed(e) => ("failed", Some(e.to_string())),
+                Outcome::TimedOut => ("timed_out", None),
+                Outcome::Cancelled => ("cancelled", None),
+                Outcome::Skipped(reason) => ("skipped", Some(reason.key().to_owned())),
+            };
+            object.string("src", &pair.src)?;
+            object.string("dst", &pair.dst)?;
+            object.opt_string(
+                "substituted",
+                pair.substituted.as_ref().map(|p| p.display()),
+            )?;
+            object.raw("written", pair.written)?;
+            object.raw("elapsed_ms", pair.elapsed.as_millis())?;
+            object.opt_string("sha256", pair.sha256)?;
+            object.opt_string("src_sha256", pair.src_sha256)?;
+            object.string("outcome", outcome)?;
+            object.opt_string("detail", detail)
+        })?;
+        report.end()?;
+        w.write_all(b"\n")?;
+        w.flush()
+    }
+
+    /// Removes zero-byte outputs produced by this run, then every empty subdirectory of `dst_root`
+    /// (`dst_root` itself is kept). Returns the removed paths.
+    ///
+    /// Files that were not written by this run are never touched.
+    pub fn prune<P: AsRef<Path>>(&self, dst_root: P) -> io::Result<Vec<PathBuf>> {
+        fn prune_dirs(dir: &Path, removed: &mut Vec<PathBuf>) -> io::Result<bool> {
+            let mut empty = true;
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() && prune_dirs(&entry.path(), removed)? {
+                    let path = entry.path();
+                    audit::record(Operation::RemoveDir, &path, fs::remove_dir(&path))?;
+                    removed.push(entry.path());
+                } else {
+                    empty = false;
+                }
+            }
+            Ok(empty)
+        }
+
+        crate::check_writable(dst_root.as_ref())?;
+        let mut removed = Vec::new();
+        for path in self.succeeded().filter_map(PairReport::output_path) {
+            if fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() == 0) {
+                audit::record(Operation::Remove, path, fs::remove_file(path))?;
+                removed.push(path.to_owned());
+            }
+        }
+
+        prune_dirs(dst_root.as_ref(), &mut removed)?;
+        Ok(removed)
+    }
+}
+
+/// `*` matches any run of characters, `?` matches exactly one.
+pub(crate) fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The members of a JSON object being written, strings escaped by [`json_str`].
+struct JsonObject<'a> {
+    w: &'a mut dyn Write,
+    empty: bool,
+}
+
+impl<'a> JsonObject<'a> {
+    fn new(w: &'a mut dyn Write) -> io::Result<Self> {
+        w.write_all(b"{")?;
+        Ok(Self { w, empty: true })
+    }
+
+    /// Writes `"key":`, the value is up to the caller.
+    fn key(&mut self, key: &str) -> io::Result<&mut dyn Write> {
+        if !std::mem::take(&mut self.empty) {
+            self.w.write_all(b",")?;
+        }
+        write!(self.w, "{}:", json_str(key))?;
+        Ok(&mut *self.w)
+    }
+
+    /// A number or a boolean, written as is.
+    fn raw(&mut self, key: &str, value: impl fmt::Display) -> io::Result<()> {
+        write!(self.key(key)?, "{value}")
+    }
+
+    fn opt_raw(&mut self, key: &str, value: Option<impl fmt::Display>) -> io::Result<()> {
+        match value {
+            Some(value) => self.raw(key, value),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    fn string(&mut self, key: &str, value: impl fmt::Display) -> io::Result<()> {
+        let value = json_str(&value.to_string());
+        self.key(key)?.write_all(value.as_bytes())
+    }
+
+    fn opt_string(&mut self, key: &str, value: Option<impl fmt::Display>) -> io::Result<()> {
+        match value {
+            Some(value) => self.string(key, value),
+            None => self.raw(key, "null"),
+        }
+    }
+
+    /// The `Debug` of an enum without fields, i.e. its variant name.
+    fn debug(&mut self, key: &str, value: impl fmt::Debug) -> io::Result<()> {
+        self.string(key, format!("{value:?}"))
+    }
+
+    fn strings(&mut self, key: &str, values: &[String]) -> io::Result<()> {
+        let values = values.iter().map(|v| json_str(v)).collect::<Vec<_>>();
+        write!(self.key(key)?, "[{}]", values.join(","))
+    }
+
+    fn object(&mut self, key: &str) -> io::Result<JsonObject<'_>> {
+        JsonObject::new(self.key(key)?)
+    }
+
+    /// An array with an object for each of `items`, its members written by `each`.
+    fn objects<T>(
+        &mut self,
+        key: &str,
+        items: impl IntoIterator<Item = T>,
+        mut each: impl FnMut(&mut JsonObject<'_>, T) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let w = self.key(key)?;
+        w.write_all(b"[")?;
+        for (i, item) in items.into_iter().enumerate() {
+            if i > 0 {
+                w.write_all(b",")?;
+            }
+            let mut object = JsonObject::new(&mut *w)?;
+            each(&mut object, item)?;
+            object.end()?;
+        }
+        w.write_all(b"]")
+    }
+
+    fn end(self) -> io::Result<()> {
+        self.w.write_all(b"}")
+    }
+}
+
+pub(crate) fn json_str(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[derive(Debug)]
+pub struct PairReport {
+    pub src: Src,
+    /// DST as planned. See `substituted` for where the output actually went.
+    pub dst: Dst,
+    pub substituted: Option<PathBuf>,
+    /// Where the previous content of DST was moved to, see [`Runner::stale_output`].
+    pub quarantined: Option<PathBuf>,
+    /// Where the SRC of a failed pair was moved to, see [`Runner::failed_src_dir`].
+    pub set_aside: Option<PathBuf>,
+    /// Bytes written to DST.
+    pub written: u64,
+    pub elapsed: Duration,
+    /// The earlier output with identical content, see [`Runner::dedup`].
+    pub duplicate_of: Option<PathBuf>,
+    /// How the output differs from the existing DST, see [`Runner::golden`].
+    pub golden_diff: Option<GoldenDiff>,
+    /// Of the output, see [`Runner::persist_report`].
+    pub sha256: Option<Digest>,
+    /// Of the whole stdin SRC of a successful pair, see [`Runner::hash_stdin`].
+    pub src_sha256: Option<Digest>,
+    pub outcome: Outcome,
+}
+
+impl Message for SkipReason {
+    fn key(&self) -> &'static str {
+        match self {
+            SkipReason::BudgetExhausted => "skip-budget-exhausted",
+            SkipReason::ChecksumMismatch => "skip-checksum-mismatch",
+            SkipReason::NotInManifest => "skip-not-in-manifest",
+            SkipReason::UpToDate => "skip-up-to-date",
+            SkipReason::FilteredByGlob => "skip-filtered-by-glob",
+            SkipReason::WouldOverwrite => "skip-would-overwrite",
+            SkipReason::SrcGone => "skip-src-gone",
+            SkipReason::DstDirGone => "skip-dst-dir-gone",
+            SkipReason::Cancelled => "skip-cancelled",
+            SkipReason::EmptySrc => "skip-empty-src",
+            SkipReason::StructureOnly => "skip-structure-only",
+        }
+    }
+}
+
+impl PairReport {
+    fn retriable(&self) -> bool {
+        matches!(self.outcome, Outcome::Failed(_) | Outcome::TimedOut)
+    }
+
+    fn skipped(src: Src, dst: Dst, reason: SkipReason) -> Self {
+        Self::not_run(src, dst, Outcome::Skipped(reason))
+    }
+
+    fn failed(src: Src, dst: Dst, err: io::Error) -> Self {
+        Self::not_run(src, dst, Outcome::Failed(err))
+    }
+
+    fn not_run(src: Src, dst: Dst, outcome: Outcome) -> Self {
+        Self {
+            src,
+            dst,
+            substituted: None,
+            quarantined: None,
+            set_aside: None,
+            written: 0,
+            elapsed: Duration::ZERO,
+            duplicate_of: None,
+            golden_diff: None,
+            sha256: None,
+            src_sha256: None,
+            outcome,
+        }
+    }
+
+    /// The local file actually written.
+    pub fn output_path(&self) -> Option<&Path> {
+        match (&self.substituted, &self.dst) {
+            (Some(path), _) | (None, Dst::File(path)) => Some(path),
+            (None, _) => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Outcome {
+    Done,
+    Failed(io::Error),
+    /// See [`Runner::pair_timeout`]. Partial output has been dealt with like any failure.
+    TimedOut,
+    /// See [`Runner::cancel`]. Partial output has been dealt with like any failure,
+    /// but SRC is never set aside.
+    Cancelled,
+    Skipped(SkipReason),
+}
+
+#[non_exhaustive]
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SkipReason {
+    /// [`Runner::byte_budget`] was used up by earlier pairs.
+    #[error("byte budget exhausted")]
+    BudgetExhausted,
+    /// See [`Runner::verify_sources`].
+    #[error("SRC does not match its checksum")]
+    ChecksumMismatch,
+    #[error("SRC is not listed in the checksum manifest")]
+    NotInManifest,
+    /// See [`Runner::skip_up_to_date`].
+    #[error("DST is up to date")]
+    UpToDate,
+    /// See [`Runner::exclude`].
+    #[error("SRC is excluded by a glob")]
+    FilteredByGlob,
+    /// See [`StaleOutput::Keep`].
+    #[error("DST exists and would be overwritten")]
+    WouldOverwrite,
+    /// See [`Runner::revalidate`].
+    #[error("SRC no longer exists")]
+    SrcGone,
+    #[error("the directory of DST no longer exists")]
+    DstDirGone,
+    /// See [`Runner::cancel`].
+    #[error("run cancelled")]
+    Cancelled,
+    /// See [`Runner::empty_src`].
+    #[error("SRC is empty")]
+    EmptySrc,
+    /// See [`Runner::structure_only`].
+    #[error("only the structure of the run is laid out")]
+    StructureOnly,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::{copy_all, ScratchDir};
+    use crate::SrcDstConfig;
+
+    #[test]
+    fn fallback_dst() {
+        let root = ScratchDir::new("fallback-dst");
+        fs::write(root.join("input.txt"), b"hello").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(
+                root.join("input.txt"),
+                Some(root.join("missing/output.txt")),
+            )
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            fallback_dst: Some(root.join("fallback")),
+            ..Runner::new()
+        };
+        let report = runner.run(pairs, copy_all).unwrap();
+
+        assert_eq!(report.failed().count(), 0);
+        assert_eq!(report.substituted().count(), 1);
+        assert_eq!(
+            fs::read(root.join("fallback/output.txt")).unwrap(),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn fallback_dst_unusable() {
+        let root = ScratchDir::new("fallback-dst-unusable");
+        fs::write(root.join("input.txt"), b"hello").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(
+                root.join("input.txt"),
+                Some(root.join("missing/output.txt")),
+            )
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            fallback_dst: Some(root.join("input.txt/fallback")), // 建不了目录
+            ..Runner::new()
+        };
+        let report = runner
+            .run(pairs, |task| task.writer()?.write_all(b"hello"))
+            .unwrap();
+
+        let Outcome::Failed(e) = &report.pairs[0].outcome else {
+            panic!("{:?}", report.pairs[0].outcome);
+        };
+        let failed = e
+            .get_ref()
+            .unwrap()
+            .downcast_ref::<FallbackFailed>()
+            .unwrap();
+        assert_eq!(failed.fallback, root.join("input.txt/fallback/output.txt"));
+        assert_eq!(report.substituted().count(), 0);
+    }
+
+    #[test]
+    fn unsettled_dst() {
+        let root = ScratchDir::new("unsettled-dst");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for file in ["a.txt", "b.txt"] {
+            fs::write(root.join("in").join(file), b"new").unwrap();
+        }
+
+        let mut config = SrcDstConfig::new("txt");
+        config.overwrite = crate::OverwritePolicy::Error;
+        let pairs = config
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        fs::write(root.join("out/b.txt"), b"old").unwrap(); // 解析之后才有的
+        let report = Runner::new()
+            .run(pairs, |task| task.copy_through().map(drop))
+            .unwrap();
+
+        assert_eq!(report.pairs.len(), 2);
+        assert_eq!(report.succeeded().count(), 1);
+        let failed: Vec<_> = report.failed().collect();
+        assert_eq!(failed.len(), 1);
+        assert_eq!(failed[0].dst, Dst::File(root.join("out/b.txt")));
+        assert_eq!(fs::read(root.join("out/b.txt")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn quarantine_and_revert() {
+        let root = ScratchDir::new("quarantine-and-revert");
+        fs::write(root.join("input.txt"), b"new").unwrap();
+        fs::write(root.join("output.txt"), b"old").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("input.txt"), Some(root.join("output.txt")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            stale_output: StaleOutput::MoveToOldDir,
+            ..Runner::new()
+        };
+        let report = runner.run(pairs, copy_all).unwrap();
+
+        assert_eq!(fs::read(root.join("output.txt")).unwrap(), b"new");
+        assert_eq!(fs::read(root.join(".old/output.txt")).unwrap(), b"old");
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("input.txt"), Some(root.join("output.txt")))
+            .unwrap()
+            .unwrap();
+        let again = runner.run(pairs, |task| task.copy_through().map(drop));
+        assert_eq!(fs::read(root.join(".old/output.txt")).unwrap(), b"old");
+        assert_eq!(fs::read(root.join(".old/output-1.txt")).unwrap(), b"new");
+
+        again.unwrap().revert().unwrap();
+        report.revert().unwrap();
+        assert_eq!(fs::read(root.join("output.txt")).unwrap(), b"old");
+        assert!(!root.join(".old").exists());
+    }
+
+    #[test]
+    fn golden() {
+        let root = ScratchDir::new("golden");
+        fs::write(root.join("input.txt"), b"hello world").unwrap();
+        fs::write(root.join("output.txt"), b"hello there").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("input.txt"), Some(root.join("output.txt")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            golden: true,
+            ..Runner::new()
+        };
+        let report = runner.run(pairs, copy_all).unwrap();
+
+        assert_eq!(
+            report.pairs[0].golden_diff,
+            Some(GoldenDiff::Differs { offset: 6 })
+        );
+        assert_eq!(fs::read(root.join("output.txt")).unwrap(), b"hello there");
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("*.jpg", "0001.jpg"));
+        assert!(glob_match("00?1*", "0001.jpg"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("*.jpg", "0001.jpeg"));
+        assert!(!glob_match("?", ""));
+    }
+
+    #[test]
+    fn make_output() {
+        let root = ScratchDir::new("make-output");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"plain").unwrap();
+        fs::write(root.join("in/b.key"), b"secret").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            make_output: Some(Shared(Arc::new(|dst: &Dst| match dst {
+                Dst::File(path) if path.extension() == Some("key".as_ref()) => {
+                    Some(Box::new(RecordingOutput::new(dst.clone())) as Box<dyn Output>)
+                }
+                _ => None,
+            }))),
+            ..Runner::new()
+        };
+        let report = runner.run(pairs, copy_all).unwrap();
+
+        assert_eq!(report.succeeded().count(), 2);
+        assert_eq!(fs::read(root.join("out/a.txt")).unwrap(), b"plain");
+        assert!(!root.join("out/b.key").exists());
+    }
+
+    #[test]
+    fn consume_src() {
+        let root = ScratchDir::new("consume-src");
+        fs::write(root.join("input.txt"), b"hello").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("input.txt"), Some(root.join("output.txt")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            consume_src: true,
+            processed_dir: Some(root.join("processed")),
+            ..Runner::new()
+        };
+        let report = runner.run(pairs, copy_all).unwrap();
+
+        assert_eq!(report.succeeded().count(), 1);
+        assert!(!root.join("input.txt").exists());
+        assert_eq!(
+            fs::read(root.join("processed/input.txt")).unwrap(),
+            b"hello"
+        );
+        assert_eq!(fs::read(root.join("output.txt")).unwrap(), b"hello");
+        let created = Mutation::CreatedDir(root.join("processed"));
+        assert!(report.undo.mutations.contains(&created));
+
+        // 同名的 SRC 不会覆盖之前处理过的
+        fs::write(root.join("input.txt"), b"again").unwrap();
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("input.txt"), Some(root.join("second.txt")))
+            .unwrap()
+            .unwrap();
+        let report = runner
+            .run(pairs, |task| task.copy_through().map(drop))
+            .unwrap();
+        assert_eq!(
+            fs::read(root.join("processed/input-1.txt")).unwrap(),
+            b"again"
+        );
+        report.revert().unwrap();
+        assert_eq!(fs::read(root.join("input.txt")).unwrap(), b"again");
+
+        let runner = Runner {
+            consume_src: true,
+            ..Runner::new()
+        };
+        let parse = |dst: &str| {
+            SrcDstConfig::new("txt")
+                .parse(root.join("input.txt"), Some(root.join(dst)))
+                .unwrap()
+                .unwrap()
+        };
+        // DST 打不开时 SRC 留着
+        let report = runner
+            .run(parse("gone.txt"), |task| {
+                task.copy_through()?;
+                fs::remove_file(root.join("gone.txt"))
+            })
+            .unwrap();
+        assert_eq!(report.failed().count(), 1);
+        assert!(root.join("input.txt").exists());
+
+        let report = runner
+            .run(parse("third.txt"), |task| task.copy_through().map(drop))
+            .unwrap();
+        assert!(!root.join("input.txt").exists());
+        assert_eq!(
+            report.undo.mutations.last(),
+            Some(&Mutation::Removed(root.join("input.txt")))
+        );
+    }
+
+    #[test]
+    fn consume_deduplicated() {
+        let root = ScratchDir::new("consume-deduplicated");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"same").unwrap();
+        fs::write(root.join("in/b.txt"), b"same").unwrap();
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            consume_src: true,
+            dedup: Dedup::Remove,
+            ..Runner::new()
+        };
+        let report = runner.run(pairs, copy_all).unwrap();
+
+        assert_eq!(report.succeeded().count(), 2);
+        assert_eq!(report.pairs[1].duplicate_of, Some(root.join("out/a.txt")));
+        assert!(!root.join("out/b.txt").exists());
+        assert_eq!(fs::read_dir(root.join("in")).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn inplace() {
+        let root = ScratchDir::new("inplace");
+        fs::write(root.join("notes.txt"), b"hello").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("notes.txt"), Some(root.join("./notes.txt")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            stale_output: StaleOutput::Keep,
+            ..Runner::new()
+        };
+        let report = runner
+            .run(pairs, |task| {
+                let mut buf = Vec::new();
+                task.reader()?.read_to_end(&mut buf)?;
+                task.writer()?.write_all(&buf.to_ascii_uppercase())
+            })
+            .unwrap();
+
+        assert_eq!(report.succeeded().count(), 1);
+        assert_eq!(fs::read(root.join("notes.txt")).unwrap(), b"HELLO");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn cancel() {
+        let root = ScratchDir::new("cancel");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"a").unwrap();
+        fs::write(root.join("in/b.txt"), b"b").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let token = CancelToken::new();
+        let runner = Runner {
+            cancel: Some(token.clone()),
+            ..Runner::new()
+        };
+        let report = runner
+            .run(pairs, |task| {
+                task.writer()?.write_all(b"partial")?;
+                token.cancel(); // 处理到一半按下 Ctrl-C
+                task.writer()?.write_all(b"rest")
+            })
+            .unwrap();
+
+        assert!(matches!(report.pairs[0].outcome, Outcome::Cancelled));
+        assert!(matches!(
+            report.pairs[1].outcome,
+            Outcome::Skipped(SkipReason::Cancelled)
+        ));
+        assert!(!root.join("out/a.txt").exists());
+
+        // 取消的同时自己出错了，不算取消
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let token = CancelToken::new();
+        let runner = Runner {
+            cancel: Some(token.clone()),
+            ..Runner::new()
+        };
+        let report = runner
+            .run(pairs, |_| {
+                token.cancel();
+                Err(io::Error::other("unrelated"))
+            })
+            .unwrap();
+        assert!(matches!(report.pairs[0].outcome, Outcome::Failed(_)));
+    }
+
+    #[test]
+    fn stale_partial() {
+        let root = ScratchDir::new("stale-partial");
+        fs::write(root.join("input.txt"), b"hello").unwrap();
+        let partial = root.join(".output.txt.4294967295-0.tmp");
+        fs::write(&partial, b"hel").unwrap();
+        fs::write(root.join(".output.txt.4294967295-1.tmp"), b"h").unwrap();
+
+        let parse = || {
+            SrcDstConfig::new("txt")
+                .parse(root.join("input.txt"), Some(root.join("output.txt")))
+                .unwrap()
+                .unwrap()
+        };
+        let mut runner = Runner {
+            stale_partial: StalePartial::Error,
+            ..Runner::new()
+        };
+        let preflight = runner.preflight(&parse().collect::<Vec<_>>(), &vfs::StdFs);
+        assert_eq!(preflight.stale_partials.len(), 2);
+        let copy = |task: &mut Task| {
+            let mut buf = Vec::new();
+            task.reader()?.read_to_end(&mut buf)?;
+            let offset = task.resume_offset() as usize;
+            task.writer()?.write_all(&buf[offset..])
+        };
+        assert_eq!(runner.run(parse(), copy).unwrap().failed().count(), 1);
+
+        runner.stale_partial = StalePartial::Resume;
+        let preflight = runner.preflight(&parse().collect::<Vec<_>>(), &vfs::StdFs);
+        let actions: Vec<_> = preflight.stale_partials.iter().map(|f| f.action).collect();
+        assert_eq!(actions, [StalePartial::Resume, StalePartial::Delete]);
+        let report = runner.run(parse(), copy).unwrap();
+        assert_eq!(report.succeeded().count(), 1);
+        assert_eq!(fs::read(root.join("output.txt")).unwrap(), b"hello");
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 2);
+
+        // 比 SRC 旧的不续写
+        fs::write(&partial, b"xx").unwrap();
+        let old = fs::File::options().write(true).open(&partial).unwrap();
+        old.set_modified(std::time::UNIX_EPOCH).unwrap();
+        runner.run(parse(), copy).unwrap();
+        assert_eq!(fs::read(root.join("output.txt")).unwrap(), b"hello");
+        assert!(!partial.exists());
+
+        // 摘要包括已经写了的部分
+        fs::write(&partial, b"hel").unwrap();
+        runner.persist_report = true;
+        let report = runner.run(parse(), copy).unwrap();
+        assert_eq!(report.pairs[0].written, 5);
+        assert_eq!(report.pairs[0].sha256, Some(Digest::of_bytes(b"hello")));
+    }
+
+    #[test]
+    fn scratch_dir() {
+        let root = ScratchDir::new("scratch-dir");
+        fs::write(root.join("input.txt"), b"hello").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("input.txt"), Some(root.join("output.txt")))
+            .unwrap()
+            .unwrap();
+        let mut scratch = PathBuf::new();
+        let report = Runner::new()
+            .run(pairs, |task| {
+                let dir = task.scratch_dir()?.to_owned();
+                assert_eq!(task.scratch_dir()?, dir);
+                fs::copy(root.join("input.txt"), dir.join("intermediate"))?;
+                let buf = fs::read(dir.join("intermediate"))?;
+                scratch = dir;
+                task.writer()?.write_all(&buf)
+            })
+            .unwrap();
+        assert_eq!(report.succeeded().count(), 1);
+        assert_eq!(fs::read(root.join("output.txt")).unwrap(), b"hello");
+        assert!(!scratch.as_os_str().is_empty() && !scratch.exists());
+    }
+
+    #[test]
+    fn progress_file() {
+        let root = ScratchDir::new("progress-file");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        for name in ["a.txt", "b.txt", "c.txt"] {
+            fs::write(root.join("in").join(name), b"x").unwrap();
+        }
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            progress_file: Some(ProgressFile {
+                path: None,
+                interval: Duration::ZERO,
+            }),
+            ..Runner::new()
+        };
+        let progress = root.join("out").join(PROGRESS_FILE_NAME);
+        let report = runner
+            .run(pairs, |task| {
+                let seen = fs::read_to_string(&progress)?;
+                assert!(seen.contains(r#""total":3,"current":"#) && seen.contains(".txt"));
+                task.writer()?.write_all(b"y")
+            })
+            .unwrap();
+        assert_eq!(report.succeeded().count(), 3);
+        assert_eq!(
+            fs::read_to_string(&progress).unwrap(),
+            r#"{"done":3,"failed":0,"skipped":0,"total":3,"current":null,"finished":true}"#
+        );
+        let created = (report.undo.mutations.iter())
+            .filter(|m| **m == Mutation::CreatedFile(progress.clone()))
+            .count();
+        assert_eq!(created, 1);
+    }
+
+    #[test]
+    fn copy_through() {
+        let root = ScratchDir::new("copy-through");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::write(root.join("in/a.txt"), b"as is").unwrap();
+        fs::write(root.join("in/b.txt"), b"convert me").unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let report = Runner::new()
+            .run(pairs, |task| {
+                match task.src().to_string().ends_with("a.txt") {
+                    true => task.copy_through().map(|_| ()),
+                    false => task.writer()?.write_all(b"converted"),
+                }
+            })
+            .unwrap();
+        assert_eq!(report.total_written().0, 5 + 9);
+        assert_eq!(fs::read(root.join("out/a.txt")).unwrap(), b"as is");
+    }
+
+    #[test]
+    fn passthrough() {
+        let root = ScratchDir::new("passthrough");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"convert me").unwrap();
+        fs::write(root.join("in/b.md"), b"as is").unwrap();
+        let mut config = SrcDstConfig::new("txt");
+        config.output_extension = Some("html".into());
+        config.passthrough = vec![String::from("*.md")];
+        let pairs = config
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            passthrough: config.passthrough.clone(),
+            ..Runner::new()
+        };
+        let mut processed = 0;
+        let report = runner
+            .run(pairs, |task| {
+                processed += 1;
+                task.writer()?.write_all(b"converted")
+            })
+            .unwrap();
+        assert_eq!((processed, report.succeeded().count()), (1, 2));
+        assert_eq!(fs::read(root.join("out/b.md")).unwrap(), b"as is");
+        assert_eq!(fs::read(root.join("out/a.html")).unwrap(), b"converted");
+    }
+
+    #[test]
+    fn passthrough_collisions() {
+        let root = ScratchDir::new("passthrough-collisions");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/Notes.md"), b"upper").unwrap();
+        fs::write(root.join("in/notes.md"), b"lower").unwrap();
+        fs::write(root.join("out/notes.md"), b"old").unwrap();
+        let mut config = SrcDstConfig::new("txt");
+        config.output_extension = Some("html".into());
+        config.passthrough = vec![String::from("*.md")];
+        config.name_policy.lowercase = true;
+        config.overwrite = crate::OverwritePolicy::RenameWithCounter;
+        let pairs = config
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            passthrough: config.passthrough.clone(),
+            ..Runner::new()
+        };
+        let report = runner.run(pairs, |_| unreachable!()).unwrap();
+
+        assert_eq!(report.succeeded().count(), 2);
+        assert_eq!(fs::read(root.join("out/notes.md")).unwrap(), b"old");
+        assert_eq!(fs::read(root.join("out/notes-1.md")).unwrap(), b"upper");
+        assert_eq!(fs::read(root.join("out/notes-2.md")).unwrap(), b"lower");
+    }
+
+    #[test]
+    fn empty_src() {
+        let root = ScratchDir::new("empty-src");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"a").unwrap();
+        fs::write(root.join("in/b.txt"), b"").unwrap();
+        let parse = || {
+            SrcDstConfig::new("txt")
+                .parse(root.join("in"), Some(root.join("out")))
+                .unwrap()
+                .unwrap()
+        };
+        let copy = |task: &mut Task| task.copy_through().map(drop);
+        for (empty_src, failed) in [(EmptySrc::Skip, 0), (EmptySrc::Fail, 1)] {
+            let runner = Runner {
+                empty_src,
+                ..Runner::new()
+            };
+            let report = runner.run(parse(), copy).unwrap();
+            assert_eq!(report.succeeded().count(), 1);
+            assert_eq!(report.failed().count(), failed);
+            assert_eq!(report.skipped().count(), 1 - failed);
+        }
+        let single = SrcDstConfig::new("txt")
+            .parse(root.join("in/b.txt"), Some(root.join("out/b.txt")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            empty_src: EmptySrc::Skip,
+            ..Runner::new()
+        };
+        assert_eq!(runner.run(single, copy).unwrap().succeeded().count(), 1);
+    }
+
+    #[test]
+    fn retry() {
+        let root = ScratchDir::new("retry");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"a").unwrap();
+        fs::write(root.join("in/b.txt"), b"b").unwrap();
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            persist_report: true,
+            ..Runner::new()
+        };
+        let mut flaky = 2;
+        let mut process = |task: &mut Task| match task.src() {
+            Src::File(path) if path.ends_with("b.txt") && flaky > 0 => {
+                flaky -= 1;
+                Err(io::Error::other("flaky"))
+            }
+            _ => task.copy_through().map(drop),
+        };
+        let report = runner.run(pairs, &mut process).unwrap();
+        assert_eq!(report.failed_pairs().len(), 1);
+
+        let policy = RetryPolicy {
+            attempts: 2,
+            initial_delay: Duration::ZERO,
+            ..RetryPolicy::default()
+        };
+        let retried = runner.retry(&report, &policy, &mut process);
+        assert_eq!(
+            (retried.succeeded().count(), retried.failed().count()),
+            (1, 0)
+        );
+        assert_eq!(fs::read(root.join("out/b.txt")).unwrap(), b"b");
+        // 重试的只是这一对，报告还是 run 写的那份
+        assert_eq!(
+            retried.undo.mutations,
+            [Mutation::CreatedFile(root.join("out/b.txt"))]
+        );
+
+        // 只剩一对也还是批量里的
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let failing = |task: &mut Task| match task.src() {
+            Src::File(path) if path.ends_with("b.txt") => Err(io::Error::other("down")),
+            _ => task.copy_through().map(drop),
+        };
+        let report = runner.run(pairs, failing).unwrap();
+        fs::write(root.join("in/b.txt"), b"").unwrap();
+        let runner = Runner {
+            empty_src: EmptySrc::Skip,
+            ..runner
+        };
+        let retried = runner.retry(&report, &policy, &mut process);
+        assert_eq!(retried.skipped().count(), 1);
+        let default = RetryPolicy::default();
+        assert_eq!(default.delay(1), Duration::from_millis(400));
+        assert_eq!(default.delay(30), default.max_delay);
+    }
+
+    #[test]
+    fn report_json() {
+        let mut report = RunReport::default();
+        let src = Src::File(PathBuf::from("in/\"a\".txt"));
+        let failed = io::Error::other("bad\nthing");
+        report
+            .pairs
+            .push(PairReport::failed(src, Dst::Stdout, failed));
+        (report.pairs).push(PairReport::not_run(
+            Src::Stdin,
+            Dst::Stdout,
+            Outcome::Cancelled,
+        ));
+        let mut json = Vec::new();
+        report.write_json(&Runner::new(), &mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+
+        assert!(json.starts_with(r#"{"version":1,"elapsed_ms":0,"written":0,"environment":{"#));
+        assert!(json.contains(r#""src":"in/\"a\".txt","dst":"-","#));
+        assert!(json.contains(r#""outcome":"failed","detail":"bad\nthing"}"#));
+        assert!(json.ends_with("\"outcome\":\"cancelled\",\"detail\":null}]}\n"));
+    }
+
+    #[test]
+    fn hash_stdin() {
+        let root = ScratchDir::new("hash-stdin");
+        let path = root.join("stdin");
+        fs::write(&path, b"piped in").unwrap();
+        let runner = Runner {
+            hash_stdin: true,
+            ..Runner::new()
+        };
+        let mut task = Task::new(&runner, &HeldSrcDir::default(), Src::Stdin, Dst::Stdout);
+        task.input = Box::new(ReadFile::new(&path)); // 代替真正的 stdin
+        task.execute(&mut |task: &mut Task| task.reader()?.read_exact(&mut [0; 5]))
+            .unwrap();
+        assert_eq!(task.src_sha256, Some(Digest::of_bytes(b"piped in")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn preflight_access() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use crate::vfs::FileSystem;
+        let root = ScratchDir::new("preflight-access");
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        fs::set_permissions(root.join("out"), fs::Permissions::from_mode(0o500)).unwrap();
+        let pairs = [
+            (
+                Src::File(root.join("a.txt")),
+                Dst::File(root.join("out/a.txt")),
+            ),
+            (
+                Src::File(root.join("a.txt")),
+                Dst::File(root.join("out/sub/b.txt")),
+            ),
+        ];
+        let preflight = Runner::new().preflight(&pairs, &vfs::StdFs);
+        // root 无视权限位
+        if !vfs::StdFs.accessible(&root.join("out"), Access::WriteDir) {
+            assert_eq!(
+                preflight.denied,
+                [Denied {
+                    path: root.join("out"),
+                    access: Access::WriteDir,
+                    pairs: 2
+                }]
+            );
+        }
+        fs::set_permissions(root.join("out"), fs::Permissions::from_mode(0o700)).unwrap();
+        assert!(Runner::new()
+            .preflight(&pairs, &vfs::StdFs)
+            .denied
+            .is_empty());
+    }
+
+    #[test]
+    fn structure_only() {
+        let root = ScratchDir::new("structure-only");
+        fs::create_dir_all(root.join("in/sub")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/sub/a.txt"), b"a").unwrap();
+        let mut config = SrcDstConfig::new("txt");
+        config.max_depth = 1;
+        let runner = Runner {
+            structure_only: Some(StructureOnly::Placeholders),
+            ..Runner::new()
+        };
+        let pairs = config
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let report = runner
+            .run(pairs, |_| panic!("nothing is processed"))
+            .unwrap();
+        assert_eq!(report.skipped().count(), 1);
+        assert_eq!(fs::read(root.join("out/sub/a.txt")).unwrap(), b"");
+
+        // 占位文件不算已经有的输出
+        config.overwrite = crate::OverwritePolicy::Skip;
+        let runner = Runner {
+            skip_up_to_date: true,
+            stale_output: StaleOutput::Keep,
+            ..Runner::new()
+        };
+        let pairs = config
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let real = runner
+            .run(pairs, |task| task.copy_through().map(drop))
+            .unwrap();
+        assert_eq!(real.succeeded().count(), 1);
+        assert_eq!(fs::read(root.join("out/sub/a.txt")).unwrap(), b"a");
+
+        report.revert().unwrap();
+        assert!(!root.join("out/sub").exists());
+    }
+
+    #[test]
+    fn flatten_manifest() {
+        let root = ScratchDir::new("flatten");
+        fs::create_dir_all(root.join("in/a")).unwrap();
+        fs::create_dir_all(root.join("in/b")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a/two.txt"), b"a").unwrap();
+        fs::write(root.join("in/b/two.txt"), b"b").unwrap();
+        let mut config = SrcDstConfig::new("txt");
+        config.max_depth = 1;
+        config.flatten = Some(crate::naming::Flatten::Counter);
+        let runner = Runner {
+            manifest: true,
+            ..Runner::new()
+        };
+        let pairs = config
+            .try_parse(root.join("in"), Some(root.join("out")))
+            .unwrap();
+        assert!(pairs.dst_dirs().is_empty());
+        let report = runner
+            .run(pairs, |task| task.copy_through().map(drop))
+            .unwrap();
+        assert_eq!(report.succeeded().count(), 2);
+        assert_eq!(fs::read(root.join("out/two-1.txt")).unwrap(), b"b");
+        let src = fs::canonicalize(root.join("in")).unwrap();
+        assert_eq!(
+            fs::read_to_string(root.join("out").join(MANIFEST_FILE_NAME)).unwrap(),
+            format!(
+                "two.txt {}\ntwo-1.txt {}\n",
+                escape(src.join("a/two.txt").as_os_str()),
+                escape(src.join("b/two.txt").as_os_str())
+            )
+        );
+    }
+
+    #[test]
+    fn dedup_hard_link() {
+        let root = ScratchDir::new("dedup-hard-link");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"same").unwrap();
+        fs::write(root.join("in/b.txt"), b"same").unwrap();
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            dedup: Dedup::HardLink,
+            ..Runner::new()
+        };
+        let report = runner
+            .run(pairs, |task| task.copy_through().map(drop))
+            .unwrap();
+        assert_eq!(report.dedup_savings(), 4);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let ino = |name: &str| fs::metadata(root.join("out").join(name)).unwrap().ino();
+            assert_eq!(ino("a.txt"), ino("b.txt"));
+        }
+
+        fs::write(root.join("out/c.txt"), b"copy").unwrap();
+        let failed = link_over(&root.join("out/a.txt"), &root.join("out/c.txt"), |_, _| {
+            Err(io::ErrorKind::CrossesDevices.into())
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read(root.join("out/c.txt")).unwrap(), b"copy");
+        assert_eq!(fs::read_dir(root.join("out")).unwrap().count(), 3);
+        assert!(same_content(&root.join("out/a.txt"), &root.join("in/b.txt")).unwrap());
+        assert!(!same_content(&root.join("out/a.txt"), &root.join("out/c.txt")).unwrap());
+    }
+
+    #[test]
+    fn verify_unreadable() {
+        let root = ScratchDir::new("verify-unreadable");
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        let digest = crate::digest::Digest::of_reader(&b"a"[..]).unwrap();
+        let manifest = crate::digest::ChecksumManifest {
+            entries: [root.join("a.txt"), root.join("gone.txt")]
+                .into_iter()
+                .map(|path| (path, digest))
+                .collect(),
+        };
+        let runner = Runner {
+            verify_sources: Some(manifest),
+            ..Runner::new()
+        };
+        let planned = ["gone", "a"]
+            .map(|name| {
+                let src = Src::File(root.join(format!("{name}.txt")));
+                (src, Dst::File(root.join(format!("{name}.out"))))
+            })
+            .to_vec();
+        let report = runner
+            .run(SrcDstPairs::planned(planned, None), |task| {
+                task.copy_through().map(drop)
+            })
+            .unwrap();
+        assert_eq!(
+            (report.failed().count(), report.succeeded().count()),
+            (1, 1)
+        );
+        assert_eq!(fs::read(root.join("a.out")).unwrap(), b"a");
+    }
+
+    #[test]
+    fn up_to_date_gone() {
+        let root = ScratchDir::new("up-to-date-gone");
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        fs::write(root.join("gone.out"), b"old").unwrap();
+        let runner = Runner {
+            skip_up_to_date: true,
+            ..Runner::new()
+        };
+        let planned = ["gone", "a"]
+            .map(|name| {
+                let src = Src::File(root.join(format!("{name}.txt")));
+                (src, Dst::File(root.join(format!("{name}.out"))))
+            })
+            .to_vec();
+        let report = runner
+            .run(SrcDstPairs::planned(planned, None), |task| {
+                task.copy_through().map(drop)
+            })
+            .unwrap();
+        assert_eq!(
+            (report.failed().count(), report.succeeded().count()),
+            (1, 1)
+        );
+        assert_eq!(fs::read(root.join("gone.out")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn failed_src_dir() {
+        let root = ScratchDir::new("failed-src-dir");
+        fs::create_dir_all(root.join("in")).unwrap();
+        let mut runner = Runner {
+            failed_src_dir: Some(FailedSrcDir::BesideSrc),
+            ..Runner::new()
+        };
+        let run = |runner: &Runner| {
+            fs::write(root.join("in/a.txt"), b"a").unwrap();
+            let planned = vec![(
+                Src::File(root.join("in/a.txt")),
+                Dst::File(root.join("a.out")),
+            )];
+            let pairs = SrcDstPairs::planned(planned, None);
+            let report = runner.run(pairs, |_| Err(io::Error::other("nope")));
+            report.unwrap().pairs.remove(0).set_aside
+        };
+        assert_eq!(run(&runner), Some(root.join("in/failed/a.txt")));
+        assert_eq!(run(&runner), Some(root.join("in/failed/a-1.txt")));
+
+        // 挪不走的留在原处
+        fs::write(root.join("blocked"), b"").unwrap();
+        runner.failed_src_dir = Some(FailedSrcDir::Path(root.join("blocked/failed")));
+        assert_eq!(run(&runner), None);
+        assert!(root.join("in/a.txt").exists());
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    fn prefetch_with_uring() {
+        let runner = Runner {
+            prefetch: 4,
+            io_uring: true,
+            ..Runner::new()
+        };
+        let e = runner
+            .run(SrcDstPairs::planned(vec![], None), |_| Ok(()))
+            .unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn symlink_dst() {
+        let root = ScratchDir::new("symlink-dst");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"a").unwrap();
+        let root = fs::canonicalize(&root).unwrap();
+
+        for (style, target) in [
+            (LinkStyle::Relative, PathBuf::from("../in/a.txt")),
+            (LinkStyle::Absolute, root.join("in/a.txt")),
+        ] {
+            let _ = fs::remove_file(root.join("out/a.txt"));
+            let pairs = SrcDstConfig::new("txt")
+                .parse(root.join("in"), Some(root.join("out")))
+                .unwrap()
+                .unwrap();
+            let runner = Runner {
+                symlink_dst: Some(style),
+                stale_output: StaleOutput::Overwrite,
+                ..Runner::new()
+            };
+            let report = runner
+                .run(pairs, |task| task.copy_through().map(drop))
+                .unwrap();
+            assert_eq!(report.succeeded().count(), 1);
+            assert_eq!(fs::read_link(root.join("out/a.txt")).unwrap(), target);
+            assert_eq!(fs::read(root.join("out/a.txt")).unwrap(), b"a");
+        }
+    }
+
+    #[test]
+    fn before_finalize() {
+        let root = ScratchDir::new("before-finalize");
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.txt"), b"a").unwrap();
+        fs::write(root.join("in/b.txt"), b"b").unwrap();
+
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in"), Some(root.join("out")))
+            .unwrap()
+            .unwrap();
+        let vetoed = fs::canonicalize(root.join("out")).unwrap().join("b.txt");
+        let runner = Runner {
+            on_before_finalize: Some(Shared(Arc::new(move |task: &Task| {
+                match task.written() == 1 && task.dst() == &Dst::File(vetoed.clone()) {
+                    true => Err(Veto {
+                        reason: "not today".into(),
+                    }),
+                    false => Ok(()),
+                }
+            }))),
+            ..Runner::new()
+        };
+        let report = runner
+            .run(pairs, |task| task.copy_through().map(drop))
+            .unwrap();
+
+        assert_eq!(report.succeeded().count(), 1);
+        let Outcome::Failed(e) = &report.pairs[1].outcome else {
+            panic!("not vetoed");
+        };
+        let veto = e.get_ref().and_then(|e| e.downcast_ref::<Veto>());
+        assert_eq!(veto.map(|veto| veto.reason.as_str()), Some("not today"));
+        assert_eq!(fs::read(root.join("out/a.txt")).unwrap(), b"a");
+        assert!(!root.join("out/b.txt").exists());
+    }
+
+    #[test]
+    fn copy_file() {
+        let root = ScratchDir::new("copy-file");
+        let content: Vec<u8> = (0..300_000).map(|n| n as u8).collect();
+        fs::write(root.join("a.bin"), &content).unwrap();
+
+        let planned = vec![
+            (Src::File(root.join("a.bin")), Dst::File(root.join("b.bin"))),
+            (Src::File(root.join("a.bin")), Dst::File(root.join("a.bin"))),
+        ];
+        for hash in [false, true] {
+            let runner = Runner {
+                persist_report: hash,
+                ..Runner::new()
+            };
+            let report = runner
+                .run(SrcDstPairs::planned(planned.clone(), None), |task| {
+                    task.copy_through().map(drop)
+                })
+                .unwrap();
+            let written: Vec<_> = report.pairs.iter().map(|p| p.written).collect();
+            assert_eq!(written, [content.len() as u64; 2]);
+            let hashed = report.pairs.iter().all(|p| p.sha256.is_some());
+            assert_eq!(hashed, hash);
+            assert_eq!(fs::read(root.join("b.bin")).unwrap(), content);
+            assert_eq!(fs::read(root.join("a.bin")).unwrap(), content);
+            let mut names = fs::read_dir(&root).unwrap().map(|e| e.unwrap().file_name());
+            assert!(!names.any(|name| name.to_string_lossy().ends_with(".tmp")));
+        }
+    }
+
+    #[test]
+    fn write_replacing_concurrently() {
+        let root = ScratchDir::new("write-replacing");
+        let path = root.join("progress.json");
+        std::thread::scope(|scope| {
+            for i in 0..4 {
+                let path = &path;
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        write_replacing(path, format!("{i}").as_bytes()).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(fs::read(&path).unwrap().len(), 1);
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+    }
+}
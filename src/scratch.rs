@@ -0,0 +1,55 @@
+//! Fixtures shared by the tests of every module.
+
+use std::{
+    fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::run::Task;
+
+/// A directory of its own under the temporary directory, removed with everything in it when
+/// dropped. Unique to the process and the call, so that tests running concurrently (even in
+/// several `cargo test` at once) never share one.
+#[derive(Debug)]
+pub(crate) struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    pub(crate) fn new(name: &str) -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let pid = std::process::id();
+        let path = std::env::temp_dir().join(format!("sdc-test-{name}-{pid}-{n}"));
+        let _ = fs::remove_dir_all(&path); // 同一个 pid 以前留下的
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for ScratchDir {
+    type Target = PathBuf;
+
+    fn deref(&self) -> &PathBuf {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for ScratchDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The SRC read whole, then written as is.
+pub(crate) fn copy_all(task: &mut Task) -> io::Result<()> {
+    let mut buf = Vec::new();
+    task.reader()?.read_to_end(&mut buf)?;
+    task.writer()?.write_all(&buf)
+}
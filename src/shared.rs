@@ -0,0 +1,64 @@
+//! How [`SrcDstConfig`](crate::SrcDstConfig) and [`Runner`](crate::run::Runner) hold their hooks.
+//!
+//! ```
+//! use std::{path::Path, sync::Arc};
+//! use src_dst_clarifier::{shared::Shared, SrcDstConfig};
+//!
+//! let mut config = SrcDstConfig::new("png");
+//! let hidden = |path: &Path| path.file_name().is_some_and(|n| n.as_encoded_bytes()[0] == b'.');
+//! config.source_filter.predicate = Some(Shared(Arc::new(move |path: &Path| !hidden(path))));
+//! assert_eq!(config.clone(), config);
+//! ```
+
+use std::{fmt, ops::Deref, sync::Arc};
+
+/// Shared by its clones. Compared by identity, so that what holds one stays comparable.
+pub struct Shared<T: ?Sized>(pub Arc<T>);
+
+impl<T: ?Sized> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Shared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Shared(..)")
+    }
+}
+
+impl<T: ?Sized> PartialEq for Shared<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<T: ?Sized> Eq for Shared<T> {}
+
+impl<T: Default> Default for Shared<T> {
+    fn default() -> Self {
+        Self(Arc::default())
+    }
+}
+
+impl<T: ?Sized> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identity() {
+        let shared: Shared<dyn Fn() -> u8> = Shared(Arc::new(|| 1));
+        assert_eq!(shared.clone(), shared);
+        assert_ne!(Shared(Arc::new(|| 1u8) as Arc<dyn Fn() -> u8>), shared);
+        assert_eq!((*shared)(), 1);
+        assert_eq!(format!("{shared:?}"), "Shared(..)");
+    }
+}
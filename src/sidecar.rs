@@ -0,0 +1,294 @@
+//! Companion metadata files of a SRC, for naming DSTs after their values.
+//!
+//! ``` plaintext
+//! photo.jpg.json   {"title": "Lake", "photoTakenTime": {"timestamp": "1600000000"}}
+//! photo.xmp        <rdf:Description dc:title="Lake" xmp:Rating="5"/>
+//!
+//!   =>   {meta.title}  {meta.photoTakenTime.timestamp}  {meta.Rating}
+//! ```
+//!
+//! Only what names need is understood: scalars of JSON (nested keys joined with `.`, array items
+//! by their index), and the attributes and simple elements of XMP (namespace prefixes dropped,
+//! the first item of `rdf:Alt`, `rdf:Seq` and `rdf:Bag`).
+
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::audit::{self, Operation};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sidecar {
+    pub path: PathBuf,
+    pub fields: BTreeMap<String, String>,
+}
+
+impl Sidecar {
+    /// The first of `<src>.json`, `<src>.xmp` and `<src stem>.xmp` that exists.
+    pub fn find<P: AsRef<Path>>(src: P) -> io::Result<Option<Self>> {
+        let src = src.as_ref();
+        let mut candidates = Vec::new();
+        for extension in ["json", "xmp"] {
+            let mut path = src.as_os_str().to_owned();
+            path.push(".");
+            path.push(extension);
+            candidates.push(PathBuf::from(path));
+        }
+        if src.extension().is_some() {
+            candidates.push(src.with_extension("xmp"));
+        }
+        for path in candidates {
+            match audit::record(Operation::Open, &path, fs::read_to_string(&path)) {
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+                Ok(text) => return Self::parse(path, &text).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    /// By the extension of `path`, JSON unless it is `xmp`.
+    pub fn parse<P: Into<PathBuf>>(path: P, text: &str) -> io::Result<Self> {
+        let path = path.into();
+        let xmp = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("xmp"));
+        let fields = match xmp {
+            true => parse_xmp(text),
+            false => parse_json(text).map_err(|at| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("bad JSON in '{}' at byte {at}", path.display()),
+                )
+            })?,
+        };
+        Ok(Self { path, fields })
+    }
+}
+
+/// Err with where it went wrong.
+fn parse_json(text: &str) -> Result<BTreeMap<String, String>, usize> {
+    struct Parser<'a> {
+        text: &'a str,
+        at: usize,
+        fields: BTreeMap<String, String>,
+    }
+
+    impl Parser<'_> {
+        fn peek(&mut self) -> Option<u8> {
+            let rest = self.text[self.at..].trim_start();
+            self.at = self.text.len() - rest.len();
+            rest.bytes().next()
+        }
+
+        fn eat(&mut self, b: u8) -> Result<(), usize> {
+            if self.peek() != Some(b) {
+                return Err(self.at);
+            }
+            self.at += 1;
+            Ok(())
+        }
+
+        fn value(&mut self, key: &str) -> Result<(), usize> {
+            let join = |child: &str| match key.is_empty() {
+                true => child.to_owned(),
+                false => format!("{key}.{child}"),
+            };
+            match self.peek().ok_or(self.at)? {
+                b'{' => {
+                    self.at += 1;
+                    if self.peek() == Some(b'}') {
+                        return self.eat(b'}');
+                    }
+                    loop {
+                        let child = self.string()?;
+                        self.eat(b':')?;
+                        self.value(&join(&child))?;
+                        match self.peek() {
+                            Some(b',') => self.at += 1,
+                            _ => return self.eat(b'}'),
+                        }
+                    }
+                }
+                b'[' => {
+                    self.at += 1;
+                    if self.peek() == Some(b']') {
+                        return self.eat(b']');
+                    }
+                    for i in 0.. {
+                        self.value(&join(&i.to_string()))?;
+                        match self.peek() {
+                            Some(b',') => self.at += 1,
+                            _ => break,
+                        }
+                    }
+                    self.eat(b']')
+                }
+                b'"' => {
+                    let value = self.string()?;
+                    self.fields.insert(key.to_owned(), value);
+                    Ok(())
+                }
+                _ => {
+                    let rest = &self.text[self.at..];
+                    let len = rest
+                        .find(|c: char| c == ',' || c == '}' || c == ']' || c.is_whitespace())
+                        .unwrap_or(rest.len());
+                    let literal = &rest[..len];
+                    let number = literal.parse::<f64>().is_ok();
+                    if len == 0 || !(number || ["true", "false", "null"].contains(&literal)) {
+                        return Err(self.at);
+                    }
+                    if literal != "null" {
+                        self.fields.insert(key.to_owned(), literal.to_owned());
+                    }
+                    self.at += len;
+                    Ok(())
+                }
+            }
+        }
+
+        fn string(&mut self) -> Result<String, usize> {
+            self.eat(b'"')?;
+            let mut value = String::new();
+            let mut chars = self.text[self.at..].char_indices();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        self.at += i + 1;
+                        return Ok(value);
+                    }
+                    '\\' => match chars.next().map(|(_, c)| c) {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('r') => value.push('\r'),
+                        Some('b') => value.push('\u{8}'),
+                        Some('f') => value.push('\u{c}'),
+                        Some('u') => {
+                            let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                            let code = u32::from_str_radix(&hex, 16).map_err(|_| self.at + i)?;
+                            // 代理对不拼了
+                            value.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                        }
+                        Some(c) => value.push(c),
+                        None => return Err(self.at + i),
+                    },
+                    c => value.push(c),
+                }
+            }
+            Err(self.text.len())
+        }
+    }
+
+    let mut parser = Parser {
+        text,
+        at: 0,
+        fields: BTreeMap::new(),
+    };
+    parser.value("")?;
+    match parser.peek() {
+        None => Ok(parser.fields),
+        Some(_) => Err(parser.at),
+    }
+}
+
+/// Lenient, malformed parts are just not understood.
+fn parse_xmp(text: &str) -> BTreeMap<String, String> {
+    let local = |name: &str| name.rsplit(':').next().unwrap_or(name).to_owned();
+    let mut fields = BTreeMap::new();
+    // 当前所在的简单元素，以及它已经拿到值了没有
+    let mut open: Option<(String, bool)> = None;
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        let content = unescape_xml(rest[..start].trim());
+        if let Some((name, done @ false)) = &mut open {
+            if !content.is_empty() {
+                fields.entry(name.clone()).or_insert(content);
+                *done = true;
+            }
+        }
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+        if tag.starts_with(['?', '!']) {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            if open
+                .as_ref()
+                .is_some_and(|(open, _)| *open == local(name.trim()))
+            {
+                open = None;
+            }
+            continue;
+        }
+
+        let (tag, empty) = match tag.strip_suffix('/') {
+            Some(tag) => (tag, true),
+            None => (tag, false),
+        };
+        let (name, mut attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        while let Some((key, value)) = attributes.split_once('=') {
+            let value = value.trim_start();
+            let Some(quote) = value.chars().next().filter(|q| *q == '"' || *q == '\'') else {
+                break;
+            };
+            let Some(len) = value[1..].find(quote) else {
+                break;
+            };
+            let key = key.trim();
+            if !key.starts_with("xmlns") && !key.starts_with("rdf:") && !key.starts_with("xml:") {
+                fields
+                    .entry(local(key))
+                    .or_insert_with(|| unescape_xml(&value[1..1 + len]));
+            }
+            attributes = &value[len + 2..];
+        }
+        let is_container = name.starts_with("rdf:") || name.starts_with("x:");
+        if !is_container && !empty && open.is_none() {
+            open = Some((local(name), false));
+        }
+    }
+    fields
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let json = r#"{"title": "Lake \"Bled\"", "photoTakenTime": {"timestamp": "1600000000"},
+                       "people": [{"name": "Ann"}], "rating": 5, "album": null}"#;
+        let json = Sidecar::parse("photo.jpg.json", json).unwrap().fields;
+        assert_eq!(json["title"], "Lake \"Bled\"");
+        assert_eq!(json["photoTakenTime.timestamp"], "1600000000");
+        assert_eq!(json["people.0.name"], "Ann");
+        assert_eq!(json["rating"], "5");
+        assert!(!json.contains_key("album"));
+        assert!(Sidecar::parse("a.json", r#"{"title": }"#).is_err());
+
+        let xmp = r#"<?xpacket begin=""?><x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF>
+            <rdf:Description rdf:about="" xmp:Rating="5" xmlns:dc="http://purl.org/dc/elements/1.1/">
+              <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Lake &amp; hills</rdf:li></rdf:Alt></dc:title>
+              <dc:creator><rdf:Seq><rdf:li>Ann</rdf:li><rdf:li>Bob</rdf:li></rdf:Seq></dc:creator>
+            </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        let xmp = Sidecar::parse("photo.XMP", xmp).unwrap().fields;
+        assert_eq!(xmp["Rating"], "5");
+        assert_eq!(xmp["title"], "Lake & hills");
+        assert_eq!(xmp["creator"], "Ann");
+        assert!(!xmp.contains_key("about") && !xmp.contains_key("lang"));
+    }
+}
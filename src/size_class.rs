@@ -0,0 +1,95 @@
+//! Rough SRC sizes for schedulers, see [`Src::size_class`] and [`Task::size_class`](crate::run::Task::size_class).
+//!
+//! ``` plaintext
+//!   0 B  ..  64 KiB  ..  64 MiB  ..
+//!    Tiny     Small       Large
+//! ```
+//!
+//! So that parallel runners can interleave a few large jobs with many small ones, instead of
+//! every worker ending up stuck on large files at once.
+
+use std::{fs, path::Path};
+
+use crate::Src;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SizeClass {
+    Tiny,
+    Small,
+    Large,
+    /// Stdin, remote and socket SRCs, directories, or when the size could not be read.
+    Unknown,
+}
+
+/// Upper bounds (exclusive) of [`SizeClass::Tiny`] and [`SizeClass::Small`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SizeClasses {
+    pub tiny: u64,
+    pub small: u64,
+}
+
+impl Default for SizeClasses {
+    fn default() -> Self {
+        Self {
+            tiny: 64 * 1024,
+            small: 64 * 1024 * 1024,
+        }
+    }
+}
+
+impl SizeClasses {
+    pub fn classify(&self, len: u64) -> SizeClass {
+        match len {
+            len if len < self.tiny => SizeClass::Tiny,
+            len if len < self.small => SizeClass::Small,
+            _ => SizeClass::Large,
+        }
+    }
+
+    /// Of a local file, by its metadata.
+    pub fn of_path(&self, path: &Path) -> SizeClass {
+        match fs::metadata(path) {
+            Ok(meta) if meta.is_file() => self.classify(meta.len()),
+            _ => SizeClass::Unknown,
+        }
+    }
+}
+
+impl Src {
+    pub fn size_class(&self, classes: &SizeClasses) -> SizeClass {
+        match self {
+            Src::File(path) => classes.of_path(path),
+            Src::Stdin => SizeClass::Unknown,
+            #[cfg(feature = "sftp")]
+            Src::Remote(_) => SizeClass::Unknown,
+            #[cfg(all(unix, feature = "unix-socket"))]
+            Src::UnixSocket(_) => SizeClass::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn classify() {
+        let classes = SizeClasses {
+            tiny: 10,
+            small: 100,
+        };
+        assert_eq!(classes.classify(0), SizeClass::Tiny);
+        assert_eq!(classes.classify(10), SizeClass::Small);
+        assert_eq!(classes.classify(100), SizeClass::Large);
+        assert_eq!(Src::Stdin.size_class(&classes), SizeClass::Unknown);
+
+        let root = ScratchDir::new("size-class");
+        let path = root.join("a.bin");
+        fs::write(&path, [0; 42]).unwrap();
+        assert_eq!(
+            Src::File(path.clone()).size_class(&classes),
+            SizeClass::Small
+        );
+    }
+}
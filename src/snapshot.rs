@@ -0,0 +1,189 @@
+//! Batch SRCs enumerated and opened relative to a directory handle held since parsing, Unix only.
+//!
+//! Renaming or replacing an ancestor of the SRC directory afterwards can not redirect which files
+//! are read, see [`SrcDstConfig::hold_src_dir`](crate::SrcDstConfig::hold_src_dir).
+
+use std::{
+    ffi::{CStr, CString, OsStr, OsString},
+    fs,
+    io::{self, Read},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd},
+    },
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    audit::{self, Operation},
+    ioers::Input,
+    warning::Warning,
+};
+
+#[derive(Debug)]
+pub struct HeldDir {
+    dir: fs::File,
+    path: PathBuf,
+}
+
+impl HeldDir {
+    /// Symlinks are not followed, so canonicalize `path` first.
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let dir = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_DIRECTORY | libc::O_NOFOLLOW)
+            .open(&path);
+        let dir = audit::record(Operation::Open, &path, dir)?;
+        Ok(Self { dir, path })
+    }
+
+    /// Where the directory was when it was opened.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Names of the regular files directly inside, unsorted.
+    pub fn file_names(&self, mut warn: impl FnMut(Warning)) -> io::Result<Vec<OsString>> {
+        let mut names = Vec::new();
+        for name in self.entries()? {
+            match self.open_entry(&name).and_then(|file| file.metadata()) {
+                Ok(meta) if meta.is_file() => names.push(name),
+                Ok(meta) if meta.is_dir() => {
+                    warn(Warning::IgnoredSubdirectory(self.path.join(&name)))
+                }
+                Ok(_) => (),
+                Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+                    warn(Warning::SkippedSymlink(self.path.join(&name)))
+                }
+                Err(_) => (),
+            }
+        }
+        Ok(names)
+    }
+
+    /// `name` must be a plain file name, and refer to a regular file that is not a symlink.
+    pub fn open_file<S: AsRef<OsStr>>(&self, name: S) -> io::Result<fs::File> {
+        let name = name.as_ref();
+        let file = self.open_entry(name)?;
+        match file.metadata()?.is_file() {
+            true => Ok(file),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a regular file", self.path.join(name).display()),
+            )),
+        }
+    }
+
+    fn open_entry(&self, name: &OsStr) -> io::Result<fs::File> {
+        if name.is_empty() || name == "." || name == ".." || name.as_bytes().contains(&b'/') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is not a file name", name.to_string_lossy()),
+            ));
+        }
+        let c_name = CString::new(name.as_bytes())?;
+        let flags = libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_NONBLOCK | libc::O_CLOEXEC;
+        // SAFETY: `c_name` is a valid NUL-terminated string, and the fd is owned by `self.dir`.
+        let fd = unsafe { libc::openat(self.dir.as_raw_fd(), c_name.as_ptr(), flags) };
+        let result = match fd < 0 {
+            true => Err(io::Error::last_os_error()),
+            // SAFETY: `fd` was just opened and is owned by nobody else.
+            false => Ok(unsafe { fs::File::from_raw_fd(fd) }),
+        };
+        audit::record(Operation::Open, &self.path.join(name), result)
+    }
+
+    fn entries(&self) -> io::Result<Vec<OsString>> {
+        // SAFETY: the duplicate is handed over to `fdopendir`, and closed by `closedir`.
+        let fd = unsafe { libc::dup(self.dir.as_raw_fd()) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a valid directory fd.
+        let dirp = unsafe { libc::fdopendir(fd) };
+        if dirp.is_null() {
+            let err = io::Error::last_os_error();
+            // SAFETY: `fdopendir` failed, so `fd` is still ours.
+            drop(unsafe { fs::File::from_raw_fd(fd) });
+            return Err(err);
+        }
+
+        let mut entries = Vec::new();
+        loop {
+            // SAFETY: `dirp` is open until `closedir` below.
+            let entry = unsafe { libc::readdir(dirp) };
+            if entry.is_null() {
+                break;
+            }
+            // SAFETY: `d_name` is NUL-terminated, and valid until the next `readdir`.
+            let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+            let name = OsStr::from_bytes(name.to_bytes());
+            if name != "." && name != ".." {
+                entries.push(name.to_owned());
+            }
+        }
+        // SAFETY: `dirp` is not used afterwards.
+        unsafe { libc::closedir(dirp) };
+        Ok(entries)
+    }
+}
+
+/// Opens a file of a [`HeldDir`] on [`Input::reader`].
+#[derive(Debug, Clone)]
+pub struct HeldInput {
+    dir: Arc<HeldDir>,
+    name: OsString,
+}
+
+impl HeldInput {
+    pub fn new<S: Into<OsString>>(dir: Arc<HeldDir>, name: S) -> Self {
+        Self {
+            dir,
+            name: name.into(),
+        }
+    }
+}
+
+impl Input for HeldInput {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(io::BufReader::new(
+            self.dir.open_file(&self.name)?,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn survives_rename() {
+        let root = ScratchDir::new("snapshot");
+        fs::create_dir_all(root.join("in/sub")).unwrap();
+        fs::write(root.join("in/a.txt"), b"held").unwrap();
+        std::os::unix::fs::symlink(root.join("in/a.txt"), root.join("in/link.txt")).unwrap();
+
+        let dir = Arc::new(HeldDir::open(root.join("in")).unwrap());
+        let mut warnings = Vec::new();
+        assert_eq!(
+            dir.file_names(|w| warnings.push(w)).unwrap(),
+            vec![OsString::from("a.txt")]
+        );
+        assert_eq!(warnings.len(), 2);
+
+        fs::rename(root.join("in"), root.join("moved")).unwrap();
+        fs::create_dir_all(root.join("in")).unwrap();
+        fs::write(root.join("in/a.txt"), b"swapped").unwrap();
+        let mut content = String::new();
+        HeldInput::new(dir, "a.txt")
+            .reader()
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "held");
+    }
+}
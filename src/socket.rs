@@ -0,0 +1,193 @@
+//! Unix domain socket SRC and DST, for local IPC endpoints.
+//!
+//! Only recognized by [`SrcDstConfig::parse`] on Unix with the `unix-socket` feature enabled.
+//! `unix:/path.sock` connects to a listening socket, `unix+listen:/path.sock` binds one,
+//! accepts exactly one connection, and removes the socket file afterwards.
+//! A socket carries a single stream, so it can not be paired with a directory of files.
+
+use std::{ffi::OsStr, fmt, path::PathBuf};
+
+#[cfg(all(unix, feature = "unix-socket"))]
+pub(crate) use unix::parse_socket;
+#[cfg(all(unix, feature = "unix-socket"))]
+pub use unix::{UnixSocketInput, UnixSocketOutput};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SocketPath {
+    pub path: PathBuf,
+    /// Bind and accept one connection, instead of connecting.
+    pub listen: bool,
+}
+
+impl SocketPath {
+    pub fn parse<S: AsRef<OsStr>>(s: S) -> Option<Self> {
+        let s = s.as_ref().to_str()?;
+        let (path, listen) = match s.strip_prefix("unix+listen:") {
+            Some(path) => (path, true),
+            None => (s.strip_prefix("unix:")?, false),
+        };
+        match path.is_empty() {
+            true => None,
+            false => Some(Self {
+                path: path.into(),
+                listen,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SocketPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.listen {
+            true => write!(f, "unix+listen:{}", self.path.display()),
+            false => write!(f, "unix:{}", self.path.display()),
+        }
+    }
+}
+
+#[cfg(all(unix, feature = "unix-socket"))]
+mod unix {
+    use std::{
+        fs,
+        io::{self, Read, Write},
+        os::unix::net::{UnixListener, UnixStream},
+        path::Path,
+    };
+
+    use super::SocketPath;
+    use crate::{
+        audit::{self, Operation},
+        ioers::{Input, Output},
+        ClarifyError, Dst, Src, SrcDstConfig, SrcDstError, SrcDstPairs,
+    };
+
+    /// `Ok(None)` if neither SRC nor DST is a socket.
+    pub(crate) fn parse_socket(
+        config: &SrcDstConfig,
+        src: &Path,
+        dst: Option<&Path>,
+    ) -> Result<Option<SrcDstPairs>, ClarifyError> {
+        let socket_src = SocketPath::parse(src);
+        let socket_dst = dst.and_then(SocketPath::parse);
+
+        let pair = match (socket_src, socket_dst) {
+            (None, None) => return Ok(None),
+
+            (Some(src), Some(dst)) => (Src::UnixSocket(src), Dst::UnixSocket(dst)),
+
+            (Some(src), None) => match dst {
+                Some(dst) if config.is_stdio(dst) => {
+                    config.check_stdout()?;
+                    (Src::UnixSocket(src), Dst::Stdout)
+                }
+                Some(dst) => {
+                    let name = src.path.file_stem().unwrap_or("socket".as_ref());
+                    let dst = match dst.is_dir() {
+                        true => fs::canonicalize(dst)?.join(name),
+                        false => dst.to_owned(),
+                    };
+                    (Src::UnixSocket(src), Dst::File(dst))
+                }
+                None if !config.auto_name.files() => {
+                    return Err(SrcDstError::ForbidAutoTnamedDstFile.into())
+                }
+                None => {
+                    let cwd = config.output_root()?;
+                    let name = src.path.file_stem().unwrap_or("socket".as_ref());
+                    let dst = config.tnamed_file(cwd.join(name), None)?;
+                    (Src::UnixSocket(src), Dst::File(dst))
+                }
+            },
+
+            (None, Some(dst)) => {
+                if config.is_stdio(src) {
+                    match config.stdio.from_stdin() {
+                        true => (Src::Stdin, Dst::UnixSocket(dst)),
+                        false => return Err(SrcDstError::DisallowFromStdin.into()),
+                    }
+                } else if !src.exists() {
+                    return Err(SrcDstError::SrcNotExist.into());
+                } else if !src.is_file() {
+                    return Err(SrcDstError::ManyToOne.into());
+                } else {
+                    (Src::File(fs::canonicalize(src)?), Dst::UnixSocket(dst))
+                }
+            }
+        };
+
+        Ok(Some(SrcDstPairs::planned(vec![pair], None)))
+    }
+
+    fn open(socket: &SocketPath) -> io::Result<UnixStream> {
+        match socket.listen {
+            false => UnixStream::connect(&socket.path),
+            true => {
+                crate::check_writable(&socket.path)?;
+                let listener = audit::record(
+                    Operation::Bind,
+                    &socket.path,
+                    UnixListener::bind(&socket.path),
+                )?;
+                let accepted = listener.accept().map(|(stream, _)| stream);
+                drop(listener);
+                audit::record(
+                    Operation::Remove,
+                    &socket.path,
+                    fs::remove_file(&socket.path),
+                )?;
+                accepted
+            }
+        }
+    }
+
+    /// Connects or accepts on [`Input::reader`], see [`SocketPath`].
+    #[derive(Debug, Clone)]
+    pub struct UnixSocketInput {
+        socket: SocketPath,
+    }
+
+    impl UnixSocketInput {
+        pub fn new(socket: SocketPath) -> Self {
+            Self { socket }
+        }
+    }
+
+    impl Input for UnixSocketInput {
+        fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+            Ok(Box::new(open(&self.socket)?))
+        }
+    }
+
+    /// Connects or accepts on [`Output::writer`], see [`SocketPath`].
+    #[derive(Debug, Clone)]
+    pub struct UnixSocketOutput {
+        socket: SocketPath,
+    }
+
+    impl UnixSocketOutput {
+        pub fn new(socket: SocketPath) -> Self {
+            Self { socket }
+        }
+    }
+
+    impl Output for UnixSocketOutput {
+        fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+            Ok(Box::new(open(&self.socket)?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let socket = SocketPath::parse("unix+listen:/run/frames.sock").unwrap();
+        assert!(socket.listen);
+        assert_eq!(socket.to_string(), "unix+listen:/run/frames.sock");
+        assert!(!SocketPath::parse("unix:/run/frames.sock").unwrap().listen);
+        assert_eq!(SocketPath::parse("unix:"), None);
+        assert_eq!(SocketPath::parse("./unix:/a"), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! External merge sort of batch SRC names, see [`SrcDstConfig::sort_spill_threshold`].
+//!
+//! [`SrcDstConfig::sort_spill_threshold`]: crate::SrcDstConfig::sort_spill_threshold
+
+use std::{
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    audit::{self, Operation},
+    cleanup::TempGuard,
+    collation::Order,
+};
+
+/// Collects names as they are enumerated, writing every `threshold` of them out as a sorted run.
+pub(crate) struct Spiller {
+    threshold: usize,
+    order: Order,
+    buf: Vec<OsString>,
+    runs: Vec<TempGuard>,
+}
+
+impl Spiller {
+    /// A `threshold` of 0 never spills.
+    pub(crate) fn new(threshold: usize, order: Order) -> Self {
+        Self {
+            threshold,
+            order,
+            buf: Vec::new(),
+            runs: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, name: OsString) -> io::Result<()> {
+        self.buf.push(name);
+        if self.threshold > 0 && self.buf.len() >= self.threshold {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        static RUNS: AtomicU64 = AtomicU64::new(0);
+
+        let order = &self.order;
+        self.buf
+            .sort_unstable_by(|a, b| order.tree(a.as_ref(), b.as_ref()));
+        let path = std::env::temp_dir().join(format!(
+            "sdc-sort-{}-{}",
+            std::process::id(),
+            RUNS.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path);
+        let mut w = BufWriter::new(audit::record(Operation::Create, &path, file)?);
+        self.runs.push(TempGuard::file(path)); // 先记下，出错时也能被删掉
+        for name in self.buf.drain(..) {
+            let bytes = name.as_encoded_bytes();
+            w.write_all(&(bytes.len() as u32).to_le_bytes())?;
+            w.write_all(bytes)?;
+        }
+        w.into_inner()
+            .map_err(io::IntoInnerError::into_error)?
+            .sync_all()
+    }
+
+    pub(crate) fn finish(mut self) -> io::Result<Names> {
+        if self.runs.is_empty() {
+            let mut names = std::mem::take(&mut self.buf);
+            let order = &self.order;
+            names.sort_unstable_by(|a, b| order.tree(b.as_ref(), a.as_ref()));
+            return Ok(Names::Memory(names));
+        }
+        if !self.buf.is_empty() {
+            self.spill()?;
+        }
+        let mut merge = Merge {
+            runs: std::mem::take(&mut self.runs),
+            order: self.order.clone(),
+            readers: Vec::new(),
+            heads: Vec::new(),
+            consumed: Vec::new(),
+            error: None,
+        };
+        for path in merge.runs.iter().map(TempGuard::path) {
+            let file = audit::record(Operation::Open, path, fs::File::open(path))?;
+            merge.readers.push(BufReader::new(file));
+        }
+        for reader in &mut merge.readers {
+            let head = read_name(reader)?;
+            merge.consumed.push(head.as_deref().map_or(0, encoded_len));
+            merge.heads.push(head);
+        }
+        Ok(Names::Spilled(merge))
+    }
+}
+
+/// The names of [`FileList`](crate::FileList).
+#[derive(Debug)]
+pub(crate) enum Names {
+    /// 倒序，见 [`crate::Source::Files`]。
+    Memory(Vec<OsString>),
+    Spilled(Merge),
+}
+
+impl Names {
+    /// The next name in the order of the [`Spiller`].
+    pub(crate) fn pop(&mut self) -> Option<OsString> {
+        match self {
+            Names::Memory(names) => names.pop(),
+            Names::Spilled(merge) => merge.pop(),
+        }
+    }
+
+    /// How many are not popped yet, unknown when spilled.
+    pub(crate) fn len(&self) -> Option<usize> {
+        match self {
+            Names::Memory(names) => Some(names.len()),
+            Names::Spilled(_) => None,
+        }
+    }
+
+    /// Every name not popped yet, in no particular order when spilled.
+    pub(crate) fn each(&self, mut f: impl FnMut(&OsStr)) -> io::Result<()> {
+        let merge = match self {
+            Names::Memory(names) => {
+                names.iter().for_each(|name| f(name));
+                return Ok(());
+            }
+            Names::Spilled(merge) => merge,
+        };
+        merge.heads.iter().flatten().for_each(|name| f(name));
+        for (run, &consumed) in merge.runs.iter().zip(&merge.consumed) {
+            let path = run.path();
+            // 从头重新读一遍这个 run，跳过已经读过的部分
+            let file = audit::record(Operation::Open, path, fs::File::open(path))?;
+            let mut r = BufReader::new(file);
+            let mut offset = 0;
+            while let Some(name) = read_name(&mut r)? {
+                offset += encoded_len(&name);
+                if offset > consumed {
+                    f(&name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The error that ended [`Self::pop`] early, if any.
+    pub(crate) fn error(&self) -> Option<&io::Error> {
+        match self {
+            Names::Memory(_) => None,
+            Names::Spilled(merge) => merge.error.as_ref(),
+        }
+    }
+}
+
+/// Sorted runs on disk, merged lazily.
+#[derive(Debug)]
+pub(crate) struct Merge {
+    runs: Vec<TempGuard>,
+    order: Order,
+    readers: Vec<BufReader<fs::File>>,
+    /// 每个 run 的下一个名字，读完了就是 `None`。
+    heads: Vec<Option<OsString>>,
+    /// Bytes of each run read so far, through its head.
+    consumed: Vec<u64>,
+    error: Option<io::Error>,
+}
+
+impl Merge {
+    fn pop(&mut self) -> Option<OsString> {
+        if self.error.is_some() {
+            return None;
+        }
+        let (i, _) = self
+            .heads
+            .iter()
+            .enumerate()
+            .filter_map(|(i, head)| Some((i, head.as_ref()?)))
+            .min_by(|(_, a), (_, b)| self.order.tree(a.as_ref(), b.as_ref()))?;
+        match read_name(&mut self.readers[i]) {
+            Ok(next) => {
+                self.consumed[i] += next.as_deref().map_or(0, encoded_len);
+                std::mem::replace(&mut self.heads[i], next)
+            }
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// `None` at the end of the run.
+fn read_name(r: &mut impl BufRead) -> io::Result<Option<OsString>> {
+    if r.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut len = [0; 4];
+    r.read_exact(&mut len)?;
+    let mut bytes = vec![0; u32::from_le_bytes(len) as usize];
+    r.read_exact(&mut bytes)?;
+    // SAFETY: written by `Spiller::spill` from `as_encoded_bytes`, in this same process.
+    Ok(Some(unsafe {
+        OsString::from_encoded_bytes_unchecked(bytes)
+    }))
+}
+
+fn encoded_len(name: &OsStr) -> u64 {
+    4 + name.as_encoded_bytes().len() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    #[test]
+    fn merged_in_order() {
+        let mut spiller = Spiller::new(3, Order::default());
+        for name in ["b", "sub/a", "a10", "c", "a2", "B", "sub/0", "d", "é", "a"] {
+            spiller.push(name.into()).unwrap();
+        }
+        let mut names = spiller.finish().unwrap();
+        let Names::Spilled(merge) = &names else {
+            panic!("not spilled");
+        };
+        let runs: Vec<_> = merge.runs.iter().map(|run| run.path().to_owned()).collect();
+        assert_eq!(runs.len(), 4);
+
+        let mut popped = Vec::new();
+        for _ in 0..4 {
+            popped.push(names.pop().unwrap());
+        }
+        let mut rest = BTreeSet::new();
+        names
+            .each(|name| {
+                rest.insert(name.to_owned());
+            })
+            .unwrap();
+        assert_eq!(rest.len(), 6);
+        popped.extend(std::iter::from_fn(|| names.pop()));
+        assert!(names.error().is_none());
+
+        let mut sorted = popped.clone();
+        sorted.sort_by(|a, b| Order::default().tree(a.as_ref(), b.as_ref()));
+        assert_eq!(popped, sorted);
+        assert!(popped[4..].iter().all(|name| rest.contains(name)));
+
+        drop(names);
+        assert!(runs.iter().all(|run| !run.exists()));
+    }
+}
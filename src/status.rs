@@ -0,0 +1,161 @@
+//! A tiny HTTP endpoint for polling long runs, feature `status-server`.
+//!
+//! ``` plaintext
+//! GET /          the progress, see run::ProgressFile       (`null` before the run starts)
+//! GET /report    the run report once finished, see run::RunReport::write_json   (404 until then)
+//! ```
+//!
+//! Only loopback addresses are bound, there is no authentication. One request per connection.
+
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::run::StatusBoard;
+
+/// Serves a [`StatusBoard`] on a background thread until dropped.
+#[derive(Debug)]
+pub struct StatusServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl StatusServer {
+    /// On `127.0.0.1:port`, any free port for 0 (see [`Self::local_addr`]).
+    pub fn bind(port: u16, board: StatusBoard) -> io::Result<Self> {
+        Self::bind_addr(SocketAddr::from((Ipv4Addr::LOCALHOST, port)), board)
+    }
+
+    /// [`io::ErrorKind::PermissionDenied`] for addresses other than loopback ones.
+    pub fn bind_addr(addr: SocketAddr, board: StatusBoard) -> io::Result<Self> {
+        if !addr.ip().is_loopback() {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("refuse to serve the status on non-loopback address {addr}"),
+            ));
+        }
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::Builder::new()
+            .name(String::from("sdc-status"))
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if stopped.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        let _ = serve(stream, &board); // 客户端断了就算了
+                    }
+                }
+            })?;
+        Ok(Self {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _ = TcpStream::connect(self.addr); // 叫醒阻塞着的 accept
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn serve(stream: TcpStream, board: &StatusBoard) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.by_ref().take(8192).read_line(&mut request)?;
+    // 头部读掉就好，用不上
+    let mut header = String::new();
+    while reader.by_ref().take(8192).read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next(), parts.next());
+    let status = board.status();
+    let (code, body) = match (method, target) {
+        (Some("GET"), Some("/")) if status.progress.is_empty() => ("200 OK", String::from("null")),
+        (Some("GET"), Some("/")) => ("200 OK", status.progress),
+        (Some("GET"), Some("/report")) => match status.report {
+            Some(report) => ("200 OK", report),
+            None => (
+                "404 Not Found",
+                String::from(r#"{"error":"the run has not finished"}"#),
+            ),
+        },
+        (Some("GET"), _) => ("404 Not Found", String::from(r#"{"error":"not found"}"#)),
+        _ => (
+            "405 Method Not Allowed",
+            String::from(r#"{"error":"only GET is supported"}"#),
+        ),
+    };
+    let mut w = &stream;
+    write!(
+        w,
+        "HTTP/1.1 {code}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    w.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+    use crate::{run::Runner, SrcDstConfig};
+
+    fn get(addr: SocketAddr, target: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serve() {
+        let any = SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0));
+        assert!(StatusServer::bind_addr(any, StatusBoard::new()).is_err());
+
+        let board = StatusBoard::new();
+        let server = StatusServer::bind(0, board.clone()).unwrap();
+        assert!(get(server.local_addr(), "/").ends_with("\r\n\r\nnull"));
+        assert!(get(server.local_addr(), "/report").starts_with("HTTP/1.1 404"));
+        let root = ScratchDir::new("status");
+        std::fs::write(root.join("in.txt"), b"x").unwrap();
+        let pairs = SrcDstConfig::new("txt")
+            .parse(root.join("in.txt"), Some(root.join("out.txt")))
+            .unwrap()
+            .unwrap();
+        let runner = Runner {
+            status: Some(board),
+            ..Runner::new()
+        };
+        runner
+            .run(pairs, |task| task.writer()?.write_all(b"y"))
+            .unwrap();
+        assert!(get(server.local_addr(), "/").contains(r#""done":1"#));
+        assert!(get(server.local_addr(), "/report").contains(r#""outcome":"done""#));
+    }
+}
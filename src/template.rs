@@ -0,0 +1,124 @@
+//! `{token}` interpolation for DST names, lossless for non-UTF-8 paths.
+//!
+//! ``` plaintext
+//! {stem}-small.{ext}   +   photos/IMG_01.jpg   =>   IMG_01-small.jpg
+//! ```
+//!
+//! Expanded names may come from anywhere, join them with [`SrcDstConfig::join_dst`](crate::SrcDstConfig::join_dst).
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    io,
+    path::Path,
+};
+
+use crate::sidecar::Sidecar;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tokens(BTreeMap<String, OsString>);
+
+impl Tokens {
+    /// `{name}`, `{stem}` and `{ext}` of SRC, empty if it has none.
+    pub fn of_src<P: AsRef<Path>>(src: P) -> Self {
+        let src = src.as_ref();
+        let mut tokens = Self::default();
+        tokens
+            .insert("name", src.file_name().unwrap_or_default())
+            .insert("stem", src.file_stem().unwrap_or_default())
+            .insert("ext", src.extension().unwrap_or_default());
+        tokens
+    }
+
+    /// `{meta.<field>}` for every field of the [`Sidecar`] of `src`, if it has one.
+    /// Path separators and control characters in values become `_`.
+    pub fn insert_sidecar<P: AsRef<Path>>(&mut self, src: P) -> io::Result<&mut Self> {
+        if let Some(sidecar) = Sidecar::find(src)? {
+            for (field, value) in sidecar.fields {
+                let value: String = value
+                    .chars()
+                    .map(|c| match c {
+                        '/' | '\\' => '_',
+                        c if c.is_control() => '_',
+                        c => c,
+                    })
+                    .collect();
+                self.insert(format!("meta.{field}"), value);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Replaces the value of an existing token.
+    pub fn insert<K: Into<String>, V: Into<OsString>>(&mut self, token: K, value: V) -> &mut Self {
+        self.0.insert(token.into(), value.into());
+        self
+    }
+
+    pub fn get(&self, token: &str) -> Option<&OsStr> {
+        self.0.get(token).map(OsString::as_os_str)
+    }
+
+    /// Unknown tokens are kept as is, `{{` and `}}` are literal braces.
+    pub fn expand<S: AsRef<OsStr>>(&self, template: S) -> OsString {
+        let template = template.as_ref().as_encoded_bytes();
+        let mut expanded = Vec::with_capacity(template.len());
+        let mut rest = template;
+        while let Some((&b, tail)) = rest.split_first() {
+            match (b, tail.first()) {
+                (b'{', Some(b'{')) | (b'}', Some(b'}')) => {
+                    expanded.push(b);
+                    rest = &tail[1..];
+                }
+                (b'{', _) => {
+                    let value = tail
+                        .iter()
+                        .position(|&b| b == b'}')
+                        .and_then(|end| Some((std::str::from_utf8(&tail[..end]).ok()?, end)))
+                        .and_then(|(token, end)| Some((self.get(token)?, end)));
+                    match value {
+                        Some((value, end)) => {
+                            expanded.extend_from_slice(value.as_encoded_bytes());
+                            rest = &tail[end + 1..];
+                        }
+                        None => {
+                            expanded.push(b);
+                            rest = tail;
+                        }
+                    }
+                }
+                _ => {
+                    expanded.push(b);
+                    rest = tail;
+                }
+            }
+        }
+        // SAFETY: only whole encoded `OsStr`s and pieces split at ASCII braces are concatenated.
+        unsafe { OsString::from_encoded_bytes_unchecked(expanded) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expand() {
+        let mut tokens = Tokens::of_src("photos/IMG_01.jpg");
+        tokens.insert("size", "small");
+        assert_eq!(tokens.expand("{stem}-{size}.{ext}"), "IMG_01-small.jpg");
+        assert_eq!(tokens.expand("{{stem}} {nope} {"), "{stem} {nope} {");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+
+            let stem = OsStr::from_bytes(b"caf\xe9");
+            tokens.insert("stem", stem);
+            assert_eq!(
+                tokens.expand("{stem}.png").as_bytes(),
+                b"caf\xe9.png".as_slice()
+            );
+        }
+    }
+}
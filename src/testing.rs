@@ -0,0 +1,233 @@
+//! Randomized SRC and DST combinations with the outcome the table of [`SrcDstConfig::parse`]
+//! promises for them, enabled by the `test-util` feature. For crates that build on the config
+//! to check it still follows the table:
+//!
+//! ```
+//! use src_dst_clarifier::{testing::Scenarios, SrcDstConfig};
+//!
+//! let mut config = SrcDstConfig::new("png");
+//! config.output_extension = Some("png".into()); // 下游自己的策略
+//!
+//! for scenario in Scenarios::new(42).take(500) {
+//!     if let Err(mismatch) = scenario.check(&config) {
+//!         panic!("{mismatch}");
+//!     }
+//! }
+//! ```
+//!
+//! Everything runs in a [`MemoryFs`], nothing on disk is touched.
+
+use std::{fmt, path::PathBuf, sync::Arc};
+
+use crate::{
+    shared::Shared, vfs::MemoryFs, AutoNamePolicy, Dst, DstKind, OverwritePolicy, SrcDstConfig,
+    SrcDstError, StdioPolicy,
+};
+
+/// A row of the table. `File` and `Dir` SRCs exist, so do `File` and `Dir` DSTs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SrcKind {
+    Stdin,
+    File,
+    Dir,
+}
+
+/// A column of the table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DstKindOf {
+    Stdout,
+    File,
+    Dir,
+    NotProvided,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Scenario {
+    pub src: SrcKind,
+    pub dst: DstKindOf,
+    pub allow_from_stdin: bool,
+    pub allow_to_stdout: bool,
+    pub auto_tnamed_dst_file: bool,
+    pub auto_tnamed_dst_dir: bool,
+}
+
+impl Scenario {
+    const CWD: &'static str = "/work";
+
+    /// What the table says, in the order its footnotes are checked.
+    pub fn expected(&self) -> Result<(), SrcDstError> {
+        use {DstKindOf as D, SrcKind as S};
+
+        if self.src == S::Stdin && !self.allow_from_stdin {
+            return Err(SrcDstError::DisallowFromStdin); // 1
+        }
+        if self.dst == D::Stdout && !self.allow_to_stdout {
+            return Err(SrcDstError::DisallowToStdout); // 2
+        }
+        match (self.src, self.dst) {
+            (S::Dir, D::Stdout | D::File) => Err(SrcDstError::ManyToOne),
+            (S::Dir, D::NotProvided) if !self.auto_tnamed_dst_dir => {
+                Err(SrcDstError::ForbidAutoTnamedDstDir) // 4
+            }
+            (S::Stdin | S::File, D::NotProvided) if !self.auto_tnamed_dst_file => {
+                Err(SrcDstError::ForbidAutoTnamedDstFile) // 3
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// `base` with the flags of this scenario, and the few options that change the table
+    /// itself reset, e.g. [`SrcDstConfig::allow_many_to_one`] and [`SrcDstConfig::dst_kind`].
+    pub fn config(&self, base: &SrcDstConfig) -> SrcDstConfig {
+        let mut memory = MemoryFs::new(Self::CWD);
+        memory
+            .add_file("in/a.jpg")
+            .add_file("in/b.jpg")
+            .add_file("out.png")
+            .add_dir("out");
+
+        let mut config = base.clone();
+        config.stdio = StdioPolicy::of(self.allow_from_stdin, self.allow_to_stdout);
+        config.auto_name = AutoNamePolicy::of(self.auto_tnamed_dst_file, self.auto_tnamed_dst_dir);
+        config.deny_stdout_tty = false;
+        config.dash_is_stdio = true;
+        config.allow_many_to_one = false;
+        config.dst_kind = DstKind::Guess;
+        config.dst_resolver = None;
+        config.sibling_suffix = None;
+        config.confine_to = None;
+        config.overwrite = OverwritePolicy::Overwrite;
+        config.conflict_resolver = None;
+        config.incremental = false;
+        config.filesystem = Some(Shared(Arc::new(memory)));
+        config
+    }
+
+    pub fn paths(&self) -> (PathBuf, Option<PathBuf>) {
+        let src = match self.src {
+            SrcKind::Stdin => "-",
+            SrcKind::File => "in/a.jpg",
+            SrcKind::Dir => "in",
+        };
+        let dst = match self.dst {
+            DstKindOf::Stdout => Some("-"),
+            DstKindOf::File => Some("out.png"),
+            DstKindOf::Dir => Some("out"),
+            DstKindOf::NotProvided => None,
+        };
+        (src.into(), dst.map(PathBuf::from))
+    }
+
+    /// Parses with [`Self::config`] and compares against [`Self::expected`]. On success, the
+    /// DSTs must also be of the column: stdout, the given file, or files.
+    pub fn check(&self, base: &SrcDstConfig) -> Result<(), Mismatch> {
+        let (src, dst) = self.paths();
+        let parsed = self.config(base).parse(src, dst).map_err(|e| Mismatch {
+            scenario: *self,
+            found: format!("IO error: {e}"),
+        })?;
+        let mismatch = |found: String| Mismatch {
+            scenario: *self,
+            found,
+        };
+        match (self.expected(), parsed) {
+            (Ok(()), Ok(pairs)) => {
+                for (_, dst) in pairs {
+                    let fits = match (self.dst, &dst) {
+                        (DstKindOf::Stdout, Dst::Stdout) => true,
+                        (DstKindOf::File, Dst::File(path)) => path.ends_with("out.png"),
+                        (DstKindOf::Dir | DstKindOf::NotProvided, Dst::File(_)) => true,
+                        _ => false,
+                    };
+                    if !fits {
+                        return Err(mismatch(format!("DST {dst}")));
+                    }
+                }
+                Ok(())
+            }
+            (Err(expected), Err(e)) if expected == e => Ok(()),
+            (_, Ok(_)) => Err(mismatch(String::from("pairs"))),
+            (_, Err(e)) => Err(mismatch(format!("{e:?}"))),
+        }
+    }
+}
+
+/// A [`Scenario`] parsed differently than [`Scenario::expected`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub scenario: Scenario,
+    pub found: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} expected {:?}, found {}",
+            self.scenario,
+            self.scenario.expected(),
+            self.found
+        )
+    }
+}
+
+impl std::error::Error for Mismatch {}
+
+/// Endless, reproducible from the seed.
+#[derive(Debug, Clone)]
+pub struct Scenarios {
+    state: u64,
+}
+
+impl Scenarios {
+    pub fn new(seed: u64) -> Self {
+        // xorshift 的状态不能为零
+        Self {
+            state: seed ^ 0x9e37_79b9_7f4a_7c15 | 1,
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+impl Iterator for Scenarios {
+    type Item = Scenario;
+
+    fn next(&mut self) -> Option<Scenario> {
+        let bits = self.next_u64();
+        let bit = |i: u32| bits >> i & 1 == 1;
+        Some(Scenario {
+            src: [SrcKind::Stdin, SrcKind::File, SrcKind::Dir][(bits >> 8) as usize % 3],
+            dst: [
+                DstKindOf::Stdout,
+                DstKindOf::File,
+                DstKindOf::Dir,
+                DstKindOf::NotProvided,
+            ][(bits >> 16) as usize % 4],
+            allow_from_stdin: bit(0),
+            allow_to_stdout: bit(1),
+            auto_tnamed_dst_file: bit(2),
+            auto_tnamed_dst_dir: bit(3),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table() {
+        let config = SrcDstConfig::new("png");
+        let scenarios: Vec<_> = Scenarios::new(7).take(300).collect();
+        assert!(scenarios.iter().any(|s| s.expected().is_ok()));
+        for scenario in scenarios {
+            scenario.check(&config).unwrap();
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Record of filesystem mutations made by a run, so that they can be reverted.
+
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::audit::{self, Operation};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    CreatedFile(PathBuf),
+    CreatedDir(PathBuf),
+    /// An existing file was moved away to `backup` before `dst` was recreated.
+    Replaced {
+        dst: PathBuf,
+        backup: PathBuf,
+    },
+    /// An existing file was overwritten without backup, it can not be restored.
+    Overwritten(PathBuf),
+    /// An existing file was removed, it can not be restored either.
+    Removed(PathBuf),
+}
+
+impl Mutation {
+    /// The path that was mutated, `dst` for [`Mutation::Replaced`].
+    pub fn path(&self) -> &Path {
+        match self {
+            Mutation::CreatedFile(path)
+            | Mutation::CreatedDir(path)
+            | Mutation::Overwritten(path)
+            | Mutation::Removed(path) => path,
+            Mutation::Replaced { dst, .. } => dst,
+        }
+    }
+}
+
+/// Directories known to exist during a run, so that each one is created (or looked up) once,
+/// not again for every file in it.
+#[derive(Debug, Default)]
+pub(crate) struct KnownDirs(HashSet<PathBuf>);
+
+impl KnownDirs {
+    /// `dir` and its ancestors exist.
+    pub(crate) fn mark(&mut self, dir: &Path) {
+        for ancestor in dir.ancestors() {
+            // 祖先一定已经记过了
+            if !self.0.insert(ancestor.to_owned()) {
+                break;
+            }
+        }
+    }
+
+    /// `create` (e.g. [`UndoLog::create_dir_all`]) `dir`, unless known to exist.
+    pub(crate) fn ensure(
+        &mut self,
+        dir: &Path,
+        create: impl FnOnce(&Path) -> io::Result<()>,
+    ) -> io::Result<()> {
+        if !self.0.contains(dir) {
+            create(dir)?;
+            self.mark(dir);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UndoLog {
+    pub mutations: Vec<Mutation>,
+}
+
+impl UndoLog {
+    pub fn push(&mut self, mutation: Mutation) {
+        self.mutations.push(mutation);
+    }
+
+    /// Like [`fs::create_dir_all`], but records every directory actually created.
+    pub fn create_dir_all<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        crate::check_writable(path)?;
+        let missing = path
+            .ancestors()
+            .take_while(|p| !p.as_os_str().is_empty() && !p.exists())
+            .map(Path::to_path_buf)
+            .collect::<Vec<_>>();
+        audit::record(Operation::CreateDir, path, fs::create_dir_all(path))?;
+        self.mutations
+            .extend(missing.into_iter().rev().map(Mutation::CreatedDir));
+        Ok(())
+    }
+
+    /// Undoes recorded mutations in reverse order.
+    ///
+    /// Directories are only removed if they are empty, [`Mutation::Overwritten`] and
+    /// [`Mutation::Removed`] files are left as is.
+    pub fn revert(&self) -> io::Result<()> {
+        for mutation in self.mutations.iter().rev() {
+            crate::check_writable(mutation.path())?;
+            match mutation {
+                Mutation::CreatedFile(path) => {
+                    match audit::record(Operation::Remove, path, fs::remove_file(path)) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                        _ => (),
+                    }
+                }
+                Mutation::CreatedDir(path) => {
+                    let _ = audit::record(Operation::RemoveDir, path, fs::remove_dir(path));
+                }
+                Mutation::Replaced { dst, backup } => {
+                    audit::record_to(Operation::Rename, backup, dst, fs::rename(backup, dst))?
+                }
+                Mutation::Overwritten(_) | Mutation::Removed(_) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_dirs() {
+        let mut known = KnownDirs::default();
+        let mut created = Vec::new();
+        for dir in ["/out/a/b", "/out/a/b", "/out/a", "/out/c"] {
+            let dir = Path::new(dir);
+            let create = |dir: &Path| {
+                created.push(dir.to_owned());
+                Ok(())
+            };
+            known.ensure(dir, create).unwrap();
+        }
+        assert_eq!(created, [Path::new("/out/a/b"), Path::new("/out/c")]);
+    }
+}
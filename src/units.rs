@@ -0,0 +1,95 @@
+//! Human-friendly but locale-independent rendering of sizes, durations and rates.
+//!
+//! ``` plaintext
+//! Bytes(1288490189)                  1.2 GiB
+//! Elapsed(Duration::from_secs(34))   34 s
+//! Rate::new(1288490189, 34 s)        36 MiB/s
+//! ```
+
+use std::{fmt, time::Duration};
+
+/// Binary units, one decimal below 10, none above.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Bytes(pub u64);
+
+impl fmt::Display for Bytes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut value = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while value >= 1024.0 && unit < UNITS.len() - 1 {
+            value /= 1024.0;
+            unit += 1;
+        }
+        write_scaled(f, value, UNITS[unit])
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Elapsed(pub Duration);
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        match secs {
+            0 => write!(f, "{} ms", self.0.as_millis()),
+            1..=59 => write_scaled(f, self.0.as_secs_f64(), "s"),
+            60..=3599 => write!(f, "{} min {} s", secs / 60, secs % 60),
+            _ => write!(f, "{} h {} min", secs / 3600, secs % 3600 / 60),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Rate {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Rate {
+    pub fn new(bytes: u64, elapsed: Duration) -> Self {
+        Self { bytes, elapsed }
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        match self.elapsed.is_zero() {
+            true => 0.0,
+            false => self.bytes as f64 / self.elapsed.as_secs_f64(),
+        }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/s", Bytes(self.bytes_per_sec() as u64))
+    }
+}
+
+fn write_scaled(f: &mut fmt::Formatter<'_>, value: f64, unit: &str) -> fmt::Result {
+    match value < 10.0 {
+        true => write!(f, "{:.1} {unit}", (value * 10.0).floor() / 10.0),
+        false => write!(f, "{} {unit}", value as u64),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display() {
+        assert_eq!(Bytes(512).to_string(), "512 B");
+        assert_eq!(Bytes(1288490189).to_string(), "1.2 GiB");
+        assert_eq!(Elapsed(Duration::from_millis(340)).to_string(), "340 ms");
+        assert_eq!(Elapsed(Duration::from_secs(34)).to_string(), "34 s");
+        assert_eq!(Elapsed(Duration::from_secs(303)).to_string(), "5 min 3 s");
+        assert_eq!(
+            Rate::new(1288490189, Duration::from_secs(34)).to_string(),
+            "36 MiB/s"
+        );
+    }
+}
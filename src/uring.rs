@@ -0,0 +1,253 @@
+//! io_uring-backed file IO, Linux only, behind the `io-uring` feature.
+//!
+//! Selected by [`Runner::io_uring`](crate::run::Runner::io_uring), processing code keeps using
+//! [`Input`] and [`Output`] as usual.
+//!
+//! Where the kernel refuses to set up a ring (`kernel.io_uring_disabled`, seccomp filters of
+//! containers...), the same files are read and written through std IO instead.
+
+use std::{
+    fs,
+    io::{self, Read, Write},
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+use io_uring::{cqueue, opcode, squeue, types, IoUring};
+
+use crate::{
+    audit::{self, Operation},
+    ioers::{Input, Output},
+};
+
+const CHUNK: usize = 256 * 1024;
+/// Chunks in flight during [`copy`].
+const DEPTH: usize = 8;
+
+/// Like [`ReadFile`](crate::ioers::ReadFile).
+#[derive(Debug, Clone)]
+pub struct UringReadFile {
+    path: PathBuf,
+}
+
+impl UringReadFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl Input for UringReadFile {
+    fn reader(&mut self) -> io::Result<Box<dyn Read + Send>> {
+        let file = audit::record(Operation::Open, &self.path, fs::File::open(&self.path))?;
+        Ok(match Ring::new(file) {
+            Ok(ring) => Box::new(io::BufReader::with_capacity(CHUNK, ring)),
+            Err(file) => Box::new(io::BufReader::with_capacity(CHUNK, file)),
+        })
+    }
+}
+
+/// Like [`WriteFile`](crate::ioers::WriteFile).
+#[derive(Debug, Clone)]
+pub struct UringWriteFile {
+    path: PathBuf,
+    created: bool,
+    metadata_of: Option<PathBuf>,
+}
+
+impl UringWriteFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            created: false,
+            metadata_of: None,
+        }
+    }
+
+    /// Like [`WriteFile::preserve_metadata_of`](crate::ioers::WriteFile::preserve_metadata_of).
+    pub fn preserve_metadata_of<P: Into<PathBuf>>(mut self, src: P) -> Self {
+        self.metadata_of = Some(src.into());
+        self
+    }
+}
+
+impl Output for UringWriteFile {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        let file = create(&self.path)?;
+        self.created = true;
+        Ok(match Ring::new(file) {
+            Ok(ring) => Box::new(io::BufWriter::with_capacity(CHUNK, ring)),
+            Err(file) => Box::new(io::BufWriter::with_capacity(CHUNK, file)),
+        })
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        match (&self.metadata_of, self.created) {
+            (Some(src), true) => crate::ioers::copy_metadata(src, &self.path),
+            _ => Ok(()),
+        }
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        if self.created {
+            self.created = false;
+            audit::record(Operation::Remove, &self.path, fs::remove_file(&self.path))?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies `src` to `dst` without processing, reading [`DEPTH`] chunks per submission.
+/// Returns the number of bytes copied.
+pub fn copy(src: &Path, dst: &Path) -> io::Result<u64> {
+    let mut from = audit::record(Operation::Open, src, fs::File::open(src))?;
+    let mut ring = match Ring::new(create(dst)?) {
+        Ok(ring) => ring,
+        Err(mut to) => return io::copy(&mut from, &mut to),
+    };
+    let mut buffers = vec![vec![0; CHUNK]; DEPTH];
+    let mut copied = 0;
+    loop {
+        let reads = buffers.iter_mut().enumerate().map(|(i, buf)| {
+            let offset = copied + (i * CHUNK) as u64;
+            opcode::Read::new(types::Fd(from.as_raw_fd()), buf.as_mut_ptr(), CHUNK as u32)
+                .offset(offset)
+                .build()
+        });
+        let lens = ring.batch(reads.collect())?;
+
+        // 遇到第一个不满的块就停下，之后的块下一轮再读
+        let full = lens.iter().take_while(|&&len| len == CHUNK).count();
+        let batch = &buffers[..(full + 1).min(DEPTH)];
+        let lens = &lens[..batch.len()];
+        if lens.iter().sum::<usize>() == 0 {
+            return Ok(copied);
+        }
+        for (buf, &len) in batch.iter().zip(lens) {
+            ring.write_all(&buf[..len])?;
+        }
+        copied = ring.offset;
+    }
+}
+
+fn create(path: &Path) -> io::Result<fs::File> {
+    crate::check_writable(path)?;
+    audit::record(Operation::Create, path, fs::File::create(path))
+}
+
+/// A file with its own ring, read or written sequentially.
+struct Ring {
+    ring: IoUring,
+    file: fs::File,
+    offset: u64,
+}
+
+impl Ring {
+    /// Gives `file` back if no ring can be set up.
+    fn new(file: fs::File) -> Result<Self, fs::File> {
+        match IoUring::new(DEPTH as u32) {
+            Ok(ring) => Ok(Self {
+                ring,
+                file,
+                offset: 0,
+            }),
+            Err(_) => Err(file),
+        }
+    }
+
+    /// Submits `entries` at once and waits for all of them, returning their results in order.
+    fn batch(&mut self, entries: Vec<squeue::Entry>) -> io::Result<Vec<usize>> {
+        let count = entries.len();
+        for (i, entry) in entries.into_iter().enumerate() {
+            // SAFETY: the buffers of `entries` outlive the wait below.
+            unsafe { self.ring.submission().push(&entry.user_data(i as u64)) }
+                .map_err(io::Error::other)?;
+        }
+        self.ring.submit_and_wait(count)?;
+        let mut results = vec![0; count];
+        let completed: Vec<cqueue::Entry> = self.ring.completion().collect();
+        for cqe in completed {
+            results[cqe.user_data() as usize] = match cqe.result() {
+                n if n < 0 => return Err(io::Error::from_raw_os_error(-n)),
+                n => n as usize,
+            };
+        }
+        Ok(results)
+    }
+
+    fn complete(&mut self, entry: squeue::Entry) -> io::Result<usize> {
+        Ok(self.batch(vec![entry])?[0])
+    }
+}
+
+impl Read for Ring {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let entry = opcode::Read::new(types::Fd(self.file.as_raw_fd()), buf.as_mut_ptr(), len)
+            .offset(self.offset)
+            .build();
+        let n = self.complete(entry)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl Write for Ring {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(u32::MAX as usize) as u32;
+        let entry = opcode::Write::new(types::Fd(self.file.as_raw_fd()), buf.as_ptr(), len)
+            .offset(self.offset)
+            .build();
+        let n = self.complete(entry)?;
+        self.offset += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+
+    #[test]
+    fn round_trip() {
+        let root = ScratchDir::new("uring");
+        let content: Vec<u8> = (0..CHUNK * DEPTH + 12345).map(|n| n as u8).collect();
+
+        let mut output = UringWriteFile::new(root.join("a.bin"));
+        output.writer().unwrap().write_all(&content).unwrap();
+        output.finish().unwrap();
+        assert_eq!(
+            copy(&root.join("a.bin"), &root.join("b.bin")).unwrap(),
+            content.len() as u64
+        );
+
+        let mut read = Vec::new();
+        let mut input = UringReadFile::new(root.join("b.bin"));
+        input.reader().unwrap().read_to_end(&mut read).unwrap();
+        assert_eq!(read, content);
+    }
+
+    #[test]
+    fn preserve_metadata() {
+        let root = ScratchDir::new("uring-meta");
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        let modified = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1 << 30);
+        fs::File::options()
+            .write(true)
+            .open(root.join("a.txt"))
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+
+        let mut output =
+            UringWriteFile::new(root.join("b.txt")).preserve_metadata_of(root.join("a.txt"));
+        output.writer().unwrap().write_all(b"a").unwrap();
+        output.finish().unwrap();
+        let meta = fs::metadata(root.join("b.txt")).unwrap();
+        assert_eq!(meta.modified().unwrap(), modified);
+    }
+}
@@ -0,0 +1,323 @@
+//! What [`SrcDstConfig::parse`] reads of the filesystem, see [`SrcDstConfig::filesystem`].
+//!
+//! Creating directories and the IO of [`ioers`](crate::ioers) always use [`std::fs`].
+//!
+//! ```
+//! use std::{path::PathBuf, sync::Arc};
+//! use src_dst_clarifier::{shared::Shared, vfs::MemoryFs, Dst, SrcDstConfig};
+//!
+//! let mut fs = MemoryFs::new("/home/user");
+//! fs.add_file("photos/a.jpg").add_file("photos/b.jpg").add_dir("out");
+//! let mut config = SrcDstConfig::new("png");
+//! config.filesystem = Some(Shared(Arc::new(fs)));
+//!
+//! let plan = config.plan("photos", Some("out")).unwrap().unwrap();
+//! assert_eq!(plan.pairs[1].1, Dst::File(PathBuf::from("/home/user/out/b.jpg")));
+//! ```
+//!
+//! [`SrcDstConfig::parse`]: crate::SrcDstConfig::parse
+//! [`SrcDstConfig::filesystem`]: crate::SrcDstConfig::filesystem
+
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{run::Access, shared::Shared};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    File,
+    Dir,
+    Symlink,
+    /// Sockets, FIFOs, devices...
+    Other,
+}
+
+pub trait FileSystem: Send + Sync {
+    /// Symlinks are followed.
+    fn kind(&self, path: &Path) -> io::Result<EntryKind>;
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// The entries of the directory `path` with their kind, symlinks not followed.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, EntryKind)>>;
+
+    fn current_dir(&self) -> io::Result<PathBuf>;
+
+    /// Of a file, symlinks followed. [`io::ErrorKind::Unsupported`] unless implemented.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        let _ = path;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// The length of a file, symlinks followed. [`io::ErrorKind::Unsupported`] unless implemented.
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        let _ = path;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// The content of a file. [`io::ErrorKind::Unsupported`] unless implemented.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let _ = path;
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.kind(path).is_ok()
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        matches!(self.kind(path), Ok(EntryKind::File))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(self.kind(path), Ok(EntryKind::Dir))
+    }
+
+    /// Whether `path` allows `access`, see [`Runner::preflight`]. `true` unless implemented.
+    ///
+    /// [`Runner::preflight`]: crate::run::Runner::preflight
+    fn accessible(&self, path: &Path, access: Access) -> bool {
+        let _ = (path, access);
+        true
+    }
+}
+
+/// The default, [`std::fs`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StdFs;
+
+impl FileSystem for StdFs {
+    fn kind(&self, path: &Path) -> io::Result<EntryKind> {
+        Ok(kind_of(fs::metadata(path)?.file_type()))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, EntryKind)>> {
+        Ok(fs::read_dir(path)?
+            .filter_map(Result::ok)
+            .filter_map(|entry| Some((entry.file_name(), kind_of(entry.file_type().ok()?))))
+            .collect())
+    }
+
+    fn current_dir(&self) -> io::Result<PathBuf> {
+        std::env::current_dir()
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs::metadata(path)?.modified()
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        Ok(fs::metadata(path)?.len())
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    /// By the effective user, so that setuid tools are judged right. Always `true` except on Unix.
+    fn accessible(&self, path: &Path, access: Access) -> bool {
+        #[cfg(unix)]
+        {
+            use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+            let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+                return false;
+            };
+            let mode = match access {
+                Access::Read => libc::R_OK,
+                Access::Write => libc::W_OK,
+                Access::WriteDir => libc::W_OK | libc::X_OK,
+            };
+            unsafe { libc::faccessat(libc::AT_FDCWD, c_path.as_ptr(), mode, libc::AT_EACCESS) == 0 }
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = (path, access);
+            true
+        }
+    }
+}
+
+fn kind_of(file_type: fs::FileType) -> EntryKind {
+    if file_type.is_symlink() {
+        EntryKind::Symlink
+    } else if file_type.is_dir() {
+        EntryKind::Dir
+    } else if file_type.is_file() {
+        EntryKind::File
+    } else {
+        EntryKind::Other
+    }
+}
+
+/// Files and directories that exist only in memory, for tests and previews. No symlinks,
+/// so paths are canonicalized lexically. Files are empty unless added with content.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryFs {
+    cwd: PathBuf,
+    entries: BTreeMap<PathBuf, EntryKind>,
+    modified: BTreeMap<PathBuf, SystemTime>,
+    contents: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFs {
+    /// `cwd` must be absolute, it is created with its ancestors.
+    pub fn new<P: Into<PathBuf>>(cwd: P) -> Self {
+        let mut fs = Self {
+            cwd: cwd.into(),
+            entries: BTreeMap::new(),
+            modified: BTreeMap::new(),
+            contents: BTreeMap::new(),
+        };
+        fs.add_dir(fs.cwd.clone());
+        fs
+    }
+
+    /// Relative to the current directory, missing ancestors are created.
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.add(path.as_ref(), EntryKind::File)
+    }
+
+    /// See [`Self::add_file`].
+    pub fn add_file_with<P: AsRef<Path>, C: Into<Vec<u8>>>(
+        &mut self,
+        path: P,
+        content: C,
+    ) -> &mut Self {
+        let absolute = self.absolute(path.as_ref());
+        self.contents.insert(absolute, content.into());
+        self.add_file(path)
+    }
+
+    /// See [`Self::add_file`].
+    pub fn add_dir<P: AsRef<Path>>(&mut self, path: P) -> &mut Self {
+        self.add(path.as_ref(), EntryKind::Dir)
+    }
+
+    /// Of an added file, [`FileSystem::modified`] is unsupported for the others.
+    pub fn set_modified<P: AsRef<Path>>(&mut self, path: P, time: SystemTime) -> &mut Self {
+        self.modified.insert(self.absolute(path.as_ref()), time);
+        self
+    }
+
+    fn add(&mut self, path: &Path, kind: EntryKind) -> &mut Self {
+        let path = self.absolute(path);
+        for dir in path.ancestors().skip(1) {
+            self.entries.insert(dir.to_owned(), EntryKind::Dir);
+        }
+        self.entries.insert(path, kind);
+        self
+    }
+
+    fn absolute(&self, path: &Path) -> PathBuf {
+        let mut absolute = PathBuf::new();
+        for component in self.cwd.join(path).components() {
+            match component {
+                Component::CurDir => (),
+                Component::ParentDir => {
+                    absolute.pop();
+                }
+                component => absolute.push(component),
+            }
+        }
+        absolute
+    }
+
+    fn content(&self, path: &Path) -> io::Result<&[u8]> {
+        match self.kind(path)? {
+            EntryKind::File => {
+                Ok((self.contents.get(&self.absolute(path))).map_or(&[], Vec::as_slice))
+            }
+            _ => Err(io::Error::other("not a file")),
+        }
+    }
+}
+
+impl FileSystem for MemoryFs {
+    fn kind(&self, path: &Path) -> io::Result<EntryKind> {
+        self.entries
+            .get(&self.absolute(path))
+            .copied()
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.kind(path)?;
+        Ok(self.absolute(path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<(OsString, EntryKind)>> {
+        if self.kind(path)? != EntryKind::Dir {
+            return Err(io::Error::other("not a directory"));
+        }
+        let dir = self.absolute(path);
+        Ok((self.entries.range(dir.clone()..))
+            .skip(1)
+            .take_while(|(entry, _)| entry.starts_with(&dir))
+            .filter(|(entry, _)| entry.parent() == Some(&dir))
+            .map(|(entry, &kind)| (entry.file_name().unwrap().to_owned(), kind))
+            .collect())
+    }
+
+    fn current_dir(&self) -> io::Result<PathBuf> {
+        Ok(self.cwd.clone())
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        self.kind(path)?;
+        (self.modified.get(&self.absolute(path)).copied())
+            .ok_or_else(|| io::ErrorKind::Unsupported.into())
+    }
+
+    fn len(&self, path: &Path) -> io::Result<u64> {
+        Ok(self.content(path)?.len() as u64)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        Ok(Box::new(io::Cursor::new(self.content(path)?.to_vec())))
+    }
+}
+
+/// The filesystem of an optional [`Shared<dyn FileSystem>`].
+pub(crate) fn or_std(fs: &Option<Shared<dyn FileSystem>>) -> &dyn FileSystem {
+    match fs {
+        Some(fs) => fs.0.as_ref(),
+        None => &StdFs,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory() {
+        let mut fs = MemoryFs::new("/work");
+        fs.add_file("in/a.txt").add_file("in/sub/b.txt");
+
+        assert!(fs.is_dir(Path::new("/work/in/sub")));
+        assert!(fs.is_file(Path::new("in/../in/./a.txt")));
+        assert!(!fs.exists(Path::new("/work/out")));
+        assert_eq!(
+            fs.canonicalize(Path::new("in/sub/..")).unwrap(),
+            PathBuf::from("/work/in")
+        );
+        assert_eq!(
+            fs.read_dir(Path::new("in")).unwrap(),
+            [
+                (OsString::from("a.txt"), EntryKind::File),
+                (OsString::from("sub"), EntryKind::Dir),
+            ]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Non-fatal conditions, reported to a sink instead of being printed.
+//!
+//! ```
+//! use std::sync::Arc;
+//! use src_dst_clarifier::{shared::Shared, warning::WarningCollector, SrcDstConfig};
+//!
+//! let collector = Arc::new(WarningCollector::default());
+//! let mut config = SrcDstConfig::new("png");
+//! config.warnings = Some(Shared(collector.clone()));
+//! // ... parse, then show `collector.take()` at the chosen verbosity.
+//! ```
+
+use std::{fmt, path::PathBuf, sync::Mutex};
+
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /// Symlinks in a SRC directory are not followed.
+    SkippedSymlink(PathBuf),
+    /// Only the top level of a SRC directory is walked.
+    IgnoredSubdirectory(PathBuf),
+    /// See [`naming::Containment::Sanitize`](crate::naming::Containment::Sanitize).
+    SanitizedName { name: PathBuf, dst: PathBuf },
+    /// See [`SrcDstConfig::ignore_files`](crate::SrcDstConfig::ignore_files).
+    BadIgnoreFile { path: PathBuf, error: String },
+    /// A sub-directory of a SRC directory that could not be listed is left out.
+    UnreadableDirectory { path: PathBuf, error: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Warning::SkippedSymlink(path) => write!(f, "skipped symlink '{}'", path.display()),
+            Warning::IgnoredSubdirectory(path) => {
+                write!(f, "ignored subdirectory '{}'", path.display())
+            }
+            Warning::SanitizedName { name, dst } => write!(
+                f,
+                "sanitized DST name '{}' to '{}'",
+                name.display(),
+                dst.display()
+            ),
+            Warning::BadIgnoreFile { path, error } => {
+                write!(f, "bad ignore file '{}': {error}", path.display())
+            }
+            Warning::UnreadableDirectory { path, error } => {
+                write!(
+                    f,
+                    "skipped unreadable directory '{}': {error}",
+                    path.display()
+                )
+            }
+        }
+    }
+}
+
+pub trait WarningSink: Send + Sync {
+    fn warn(&self, warning: Warning);
+}
+
+impl<F> WarningSink for F
+where
+    F: Fn(Warning) + Send + Sync,
+{
+    fn warn(&self, warning: Warning) {
+        self(warning)
+    }
+}
+
+/// Keeps every warning, in the order reported.
+#[derive(Debug, Default)]
+pub struct WarningCollector(Mutex<Vec<Warning>>);
+
+impl WarningCollector {
+    pub fn take(&self) -> Vec<Warning> {
+        std::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+impl WarningSink for WarningCollector {
+    fn warn(&self, warning: Warning) {
+        self.0.lock().unwrap().push(warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::ScratchDir;
+    use crate::{shared::Shared, SrcDstConfig};
+    use std::{fs, sync::Arc};
+
+    #[test]
+    fn collect() {
+        let root = ScratchDir::new("warning");
+        fs::create_dir_all(root.join("in/sub")).unwrap();
+        fs::create_dir_all(root.join("out")).unwrap();
+        fs::write(root.join("in/a.png"), b"").unwrap();
+
+        let collector = Arc::new(WarningCollector::default());
+        let mut config = SrcDstConfig::new("png");
+        config.warnings = Some(Shared(collector.clone()));
+        let pairs = config
+            .try_parse(root.join("in"), Some(root.join("out")))
+            .unwrap();
+        assert_eq!(pairs.count(), 1);
+        let sub = fs::canonicalize(root.join("in/sub")).unwrap();
+        assert_eq!(collector.take(), vec![Warning::IgnoredSubdirectory(sub)]);
+    }
+}
@@ -0,0 +1,248 @@
+//! Layered output handling composed from the DST extensions, e.g. compression or encryption.
+//!
+//! ``` plaintext
+//! frames.tar.gz.age   =>   processing -> gz -> age -> file      (both registered)
+//! ```
+//!
+//! Only trailing extensions are considered, and only as long as each one is registered,
+//! so `frames.gz.tar` gets no layer unless `tar` is registered too.
+
+use std::{
+    ffi::{OsStr, OsString},
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use crate::{ioers::Output, shared::Shared, Dst};
+
+pub trait WriteWrapper: Send + Sync {
+    fn wrap(&self, inner: Box<dyn Layer>) -> io::Result<Box<dyn Layer>>;
+}
+
+impl<F> WriteWrapper for F
+where
+    F: Fn(Box<dyn Layer>) -> io::Result<Box<dyn Layer>> + Send + Sync,
+{
+    fn wrap(&self, inner: Box<dyn Layer>) -> io::Result<Box<dyn Layer>> {
+        self(inner)
+    }
+}
+
+/// A writer of [`WriteWrapper::wrap`].
+pub trait Layer: Write + Send {
+    /// Completes the framing (trailers, padding...), then finishes the inner layer. Called on
+    /// [`Output::finish`], so that a failure fails the pair. A layer dropped unfinished, e.g.
+    /// on [`Output::abort`], should still try as best it can.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// The writer of the wrapped output, under all layers.
+struct Base(Box<dyn Write + Send>);
+
+impl Write for Base {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Layer for Base {
+    fn finish(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Extensions are matched ASCII case-insensitively.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WrapperRegistry {
+    by_extension: Vec<(OsString, Shared<dyn WriteWrapper>)>,
+}
+
+impl WrapperRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the wrapper already registered for `extension`, if any.
+    pub fn register<E: AsRef<OsStr>>(
+        &mut self,
+        extension: E,
+        wrapper: Shared<dyn WriteWrapper>,
+    ) -> &mut Self {
+        let extension = extension.as_ref();
+        self.by_extension
+            .retain(|(registered, _)| !registered.eq_ignore_ascii_case(extension));
+        self.by_extension.push((extension.to_owned(), wrapper));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_extension.is_empty()
+    }
+
+    /// The outermost (last extension) first.
+    pub fn layers<P: AsRef<Path>>(&self, path: P) -> Vec<&Shared<dyn WriteWrapper>> {
+        let mut layers = Vec::new();
+        let mut name = match path.as_ref().file_name() {
+            Some(name) => Path::new(name),
+            None => return layers,
+        };
+        while let Some(extension) = name.extension() {
+            match self.get(extension) {
+                Some(wrapper) => layers.push(wrapper),
+                None => break,
+            }
+            name = Path::new(name.file_stem().unwrap());
+        }
+        layers
+    }
+
+    /// `output` is returned as is if no layer applies, or DST is not a file.
+    pub fn wrap(&self, dst: &Dst, output: Box<dyn Output>) -> Box<dyn Output> {
+        let layers = match dst {
+            Dst::File(path) => self.layers(path),
+            _ => Vec::new(),
+        };
+        match layers.is_empty() {
+            true => output,
+            false => Box::new(WrappedOutput {
+                inner: output,
+                layers: layers.into_iter().cloned().collect(),
+                stack: Arc::default(),
+            }),
+        }
+    }
+
+    fn get(&self, extension: &OsStr) -> Option<&Shared<dyn WriteWrapper>> {
+        self.by_extension
+            .iter()
+            .find(|(registered, _)| registered.eq_ignore_ascii_case(extension))
+            .map(|(_, wrapper)| wrapper)
+    }
+}
+
+/// See [`WrapperRegistry::wrap`].
+pub struct WrappedOutput {
+    inner: Box<dyn Output>,
+    /// The outermost first.
+    layers: Vec<Shared<dyn WriteWrapper>>,
+    /// Written through by the handed out writer, kept to be finished.
+    stack: Arc<Mutex<Option<Box<dyn Layer>>>>,
+}
+
+impl WrappedOutput {
+    fn take(&self) -> Option<Box<dyn Layer>> {
+        self.stack
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+}
+
+impl Output for WrappedOutput {
+    fn writer(&mut self) -> io::Result<Box<dyn Write + Send>> {
+        let mut writer: Box<dyn Layer> = Box::new(Base(self.inner.writer()?));
+        for layer in &self.layers {
+            writer = layer.0.wrap(writer)?;
+        }
+        *self.stack.lock().unwrap_or_else(PoisonError::into_inner) = Some(writer);
+        Ok(Box::new(Stacked(self.stack.clone())))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(mut stack) = self.take() {
+            stack.finish()?;
+        }
+        self.inner.finish()
+    }
+
+    fn abort(&mut self) -> io::Result<()> {
+        drop(self.take());
+        self.inner.abort()
+    }
+}
+
+struct Stacked(Arc<Mutex<Option<Box<dyn Layer>>>>);
+
+impl Stacked {
+    fn with<T>(&self, f: impl FnOnce(&mut dyn Layer) -> io::Result<T>) -> io::Result<T> {
+        match &mut *self.0.lock().unwrap_or_else(PoisonError::into_inner) {
+            Some(stack) => f(&mut **stack),
+            None => Err(io::Error::other("output already finished")),
+        }
+    }
+}
+
+impl Write for Stacked {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with(|stack| stack.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with(|stack| stack.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ioers::BytesOutput;
+
+    struct Tag(&'static [u8], Box<dyn Layer>);
+
+    impl Write for Tag {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.1.flush()
+        }
+    }
+
+    impl Layer for Tag {
+        fn finish(&mut self) -> io::Result<()> {
+            match std::mem::take(&mut self.0) {
+                b"[fail]" => Err(io::Error::other("trailer")),
+                tag => self.1.write_all(tag).and_then(|()| self.1.finish()),
+            }
+        }
+    }
+
+    impl Drop for Tag {
+        fn drop(&mut self) {
+            let _ = self.finish();
+        }
+    }
+
+    #[test]
+    fn layers() {
+        let tag = |tag: &'static [u8]| -> Shared<dyn WriteWrapper> {
+            Shared(Arc::new(move |inner: Box<dyn Layer>| {
+                Ok(Box::new(Tag(tag, inner)) as Box<dyn Layer>)
+            }))
+        };
+        let mut registry = WrapperRegistry::new();
+        registry
+            .register("gz", tag(b"[gz]"))
+            .register("age", tag(b"[age]"));
+        assert_eq!(registry.layers("a.tar.GZ.age").len(), 2);
+        assert_eq!(registry.layers("a.gz.tar").len(), 0);
+
+        let bytes = BytesOutput::default();
+        let mut output = registry.wrap(&Dst::File("frames.gz.age".into()), Box::new(bytes.clone()));
+        output.writer().unwrap().write_all(b"data").unwrap();
+        output.finish().unwrap();
+        assert_eq!(bytes.spool().as_bytes().unwrap(), b"data[gz][age]");
+
+        // 收尾失败要报出来
+        registry.register("age", tag(b"[fail]"));
+        let mut output = registry.wrap(&Dst::File("frames.age".into()), Box::new(bytes));
+        output.writer().unwrap().write_all(b"data").unwrap();
+        assert!(output.finish().is_err());
+    }
+}